@@ -61,33 +61,6 @@ fn set_build_info() -> Result<()> {
     }
     Ok(())
 }
-fn make_pulsar_proto()->Result<()>{
-    tonic_build::configure()
-        .field_attribute(".", "#[serde(skip_serializing_if = \"Option::is_none\")]")
-        .type_attribute(".", "#[derive(serde::Serialize,serde::Deserialize)]")
-        .build_server(false)
-        .emit_rerun_if_changed(false)
-        .out_dir("src/flow_generator/protocol_logs/mq")
-        .compile(
-            &["src/flow_generator/protocol_logs/mq/PulsarApi.proto"],
-            &["src/flow_generator/protocol_logs/mq"],
-        )?;
-    println!("cargo:rerun-if-changed=src/flow_generator/protocol_logs/mq/PulsarApi.proto");
-    // remove `#[serde(skip_serializing_if = "Option::is_none")]` for non-optional fields
-    let filename = "src/flow_generator/protocol_logs/mq/pulsar.proto.rs";
-    let content = std::fs::read_to_string(filename)?;
-    let lines = content.lines().collect::<Vec<_>>();
-    let mut new_lines = Vec::new();
-    new_lines.push(*lines.get(0).unwrap());
-    for a in lines.windows(2) {
-        if a[1].contains("skip_serializing_if") && !a[0].contains("optional") {
-            continue;
-        }
-        new_lines.push(a[1]);
-    }
-    std::fs::write(filename, new_lines.join("\n"))?;
-    Ok(())
-}
 fn main()-> Result<()> {
     set_build_info()?;
     /*
@@ -99,6 +72,6 @@ fn main()-> Result<()> {
     *
    compile_wasm_plugin_proto()?;
     */
-    make_pulsar_proto()?;
+    // pulsar.proto.rs is generated by `cargo run -p proto-gen` without protoc
     Ok(())
 }
\ No newline at end of file
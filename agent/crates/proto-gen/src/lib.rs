@@ -0,0 +1,75 @@
+mod parser;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use prost_build::{Config, Module};
+
+pub use parser::parse;
+
+// relative to the agent directory
+pub const PULSAR_PROTO: &str = "src/flow_generator/protocol_logs/mq/PulsarApi.proto";
+pub const PULSAR_PROTO_RS: &str = "src/flow_generator/protocol_logs/mq/pulsar.proto.rs";
+
+const SKIP_NONE: &str = "#[serde(skip_serializing_if = \"Option::is_none\")]";
+
+pub fn agent_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+// build.rs added skip_serializing_if to every field, then dropped it unless the line
+// above, the end of the prost attribute, said optional. Doing the same keeps the
+// output identical to the code it generated with protoc
+fn drop_non_optional_skips(code: &str) -> String {
+    let lines = code.lines().collect::<Vec<_>>();
+    let mut kept = lines.first().into_iter().copied().collect::<Vec<_>>();
+    for w in lines.windows(2) {
+        if w[1].contains("skip_serializing_if") && !w[0].contains("optional") {
+            continue;
+        }
+        kept.push(w[1]);
+    }
+    kept.join("\n")
+}
+
+// generates the rust code of a proto2 file without protoc, so the output only
+// depends on the proto file and the prost-build version
+pub fn generate(proto: &Path) -> Result<String> {
+    let source = fs::read_to_string(proto)?;
+    let name = proto
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid proto path {}", proto.display()))?
+        .to_string_lossy();
+    let file = parse(&name, &source)?;
+    let package = file.package().to_owned();
+
+    let mut config = Config::new();
+    config
+        .field_attribute(".", SKIP_NONE)
+        .type_attribute(".", "#[derive(serde::Serialize,serde::Deserialize)]");
+
+    let module = Module::from_protobuf_package_name(&package);
+    let mut modules = config.generate(vec![(module.clone(), file)])?;
+    let code = modules
+        .remove(&module)
+        .ok_or_else(|| anyhow!("nothing generated from {}", proto.display()))?;
+    Ok(drop_non_optional_skips(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulsar_proto_up_to_date() {
+        let dir = agent_dir();
+        let generated = generate(&dir.join(PULSAR_PROTO)).unwrap();
+        let committed = fs::read_to_string(dir.join(PULSAR_PROTO_RS)).unwrap();
+        assert!(
+            generated == committed,
+            "{} is out of date, regenerate it with `cargo run -p proto-gen`",
+            PULSAR_PROTO_RS
+        );
+    }
+}
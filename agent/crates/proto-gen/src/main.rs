@@ -0,0 +1,14 @@
+use std::fs;
+
+use anyhow::Result;
+
+use proto_gen::{agent_dir, generate, PULSAR_PROTO, PULSAR_PROTO_RS};
+
+// rewrites the committed protobuf code, run after changing a proto file
+fn main() -> Result<()> {
+    let dir = agent_dir();
+    let code = generate(&dir.join(PULSAR_PROTO))?;
+    fs::write(dir.join(PULSAR_PROTO_RS), code)?;
+    println!("generated {}", PULSAR_PROTO_RS);
+    Ok(())
+}
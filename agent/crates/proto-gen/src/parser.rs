@@ -0,0 +1,888 @@
+use std::collections::HashMap;
+use std::mem;
+
+use anyhow::{anyhow, bail, Result};
+use prost_types::{
+    field_descriptor_proto::{Label, Type},
+    source_code_info::Location,
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, EnumValueOptions,
+    FieldDescriptorProto, FieldOptions, FileDescriptorProto, SourceCodeInfo,
+};
+
+// field numbers in descriptor.proto, used to build source code info paths
+const FILE_MESSAGE_TYPE: i32 = 4;
+const FILE_ENUM_TYPE: i32 = 5;
+const MESSAGE_FIELD: i32 = 2;
+const MESSAGE_NESTED_TYPE: i32 = 3;
+const MESSAGE_ENUM_TYPE: i32 = 4;
+const ENUM_VALUE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Start,
+    End,
+    Identifier,
+    Number,
+    String,
+    Symbol,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    line: usize,
+}
+
+enum CommentStart {
+    Line,
+    Block,
+    None,
+}
+
+// comment attribution follows protoc's Tokenizer::NextWithComments, so that
+// prost sees the same leading and trailing comments as with protoc descriptors
+#[derive(Default)]
+struct CommentCollector {
+    prev_trailing: String,
+    has_trailing: bool,
+    detached: Vec<String>,
+    buffer: String,
+    has_comment: bool,
+    is_line_comment: bool,
+    can_attach_to_prev: bool,
+    num_comments: usize,
+}
+
+impl CommentCollector {
+    fn new() -> Self {
+        Self {
+            can_attach_to_prev: true,
+            ..Default::default()
+        }
+    }
+
+    // consecutive line comments are one comment, block comments are always on their own
+    fn buffer_for_line_comment(&mut self) -> &mut String {
+        if self.has_comment && !self.is_line_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = true;
+        &mut self.buffer
+    }
+
+    fn buffer_for_block_comment(&mut self) -> &mut String {
+        if self.has_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = false;
+        &mut self.buffer
+    }
+
+    fn clear_buffer(&mut self) {
+        self.buffer.clear();
+        self.has_comment = false;
+    }
+
+    // the buffered comment is complete and not attached to the next token
+    fn flush(&mut self) {
+        if !self.has_comment {
+            return;
+        }
+        if self.can_attach_to_prev {
+            self.prev_trailing.push_str(&self.buffer);
+            self.has_trailing = true;
+            self.can_attach_to_prev = false;
+        } else {
+            self.detached.push(mem::take(&mut self.buffer));
+        }
+        self.clear_buffer();
+        self.num_comments += 1;
+    }
+
+    // a single comment between two tokens on the same line belongs to neither
+    fn maybe_detach(&mut self) {
+        let count = self.num_comments + self.has_comment as usize;
+        if count != 1 {
+            return;
+        }
+        if self.has_trailing {
+            let trailing = mem::take(&mut self.prev_trailing);
+            self.detached.insert(0, trailing);
+        }
+        self.can_attach_to_prev = false;
+        self.flush();
+    }
+
+    // returns (trailing comments of the previous token, detached comments, leading comments of the next token)
+    fn finish(self) -> (String, Vec<String>, String) {
+        let leading = if self.has_comment {
+            self.buffer
+        } else {
+            String::new()
+        };
+        (self.prev_trailing, self.detached, leading)
+    }
+}
+
+struct Tokenizer<'a> {
+    input: &'a [u8],
+    pos: usize,
+    line: usize,
+    current: Token,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input: input.as_bytes(),
+            pos: 0,
+            line: 0,
+            current: Token {
+                kind: TokenKind::Start,
+                text: String::new(),
+                line: 0,
+            },
+        }
+    }
+
+    fn peek(&self) -> u8 {
+        self.input.get(self.pos).copied().unwrap_or(0)
+    }
+
+    fn advance(&mut self) {
+        if self.peek() == b'\n' {
+            self.line += 1;
+        }
+        self.pos += 1;
+    }
+
+    fn try_consume(&mut self, c: u8) -> bool {
+        if self.pos < self.input.len() && self.peek() == c {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c') {
+            self.advance();
+        }
+    }
+
+    fn try_consume_comment_start(&mut self) -> Result<CommentStart> {
+        if self.peek() != b'/' {
+            return Ok(CommentStart::None);
+        }
+        self.advance();
+        if self.try_consume(b'/') {
+            Ok(CommentStart::Line)
+        } else if self.try_consume(b'*') {
+            Ok(CommentStart::Block)
+        } else {
+            bail!("line {}: unexpected '/'", self.line + 1)
+        }
+    }
+
+    // content after `//` up to and including the newline
+    fn consume_line_comment(&mut self, content: Option<&mut String>) {
+        let start = self.pos;
+        while self.pos < self.input.len() && self.peek() != b'\n' {
+            self.advance();
+        }
+        self.try_consume(b'\n');
+        if let Some(content) = content {
+            content.push_str(&String::from_utf8_lossy(&self.input[start..self.pos]));
+        }
+    }
+
+    // content between `/*` and `*/`, without the leading `*` of continuation lines
+    fn consume_block_comment(&mut self, mut content: Option<&mut String>) -> Result<()> {
+        let mut start = self.pos;
+        loop {
+            while !matches!(self.peek(), 0 | b'*' | b'/' | b'\n') {
+                self.advance();
+            }
+            if self.try_consume(b'\n') {
+                if let Some(content) = content.as_mut() {
+                    content.push_str(&String::from_utf8_lossy(&self.input[start..self.pos]));
+                }
+                self.skip_spaces();
+                if self.try_consume(b'*') && self.try_consume(b'/') {
+                    return Ok(());
+                }
+                start = self.pos;
+            } else if self.try_consume(b'*') {
+                if self.try_consume(b'/') {
+                    if let Some(content) = content.as_mut() {
+                        content
+                            .push_str(&String::from_utf8_lossy(&self.input[start..self.pos - 2]));
+                    }
+                    return Ok(());
+                }
+            } else if self.try_consume(b'/') {
+                if self.peek() == b'*' {
+                    bail!("line {}: nested block comment", self.line + 1);
+                }
+            } else {
+                bail!("line {}: unterminated block comment", self.line + 1);
+            }
+        }
+    }
+
+    // reads the next token, dropping any comments before it
+    fn next(&mut self) -> Result<bool> {
+        loop {
+            while matches!(
+                self.peek(),
+                b' ' | b'\t' | b'\r' | b'\n' | b'\x0b' | b'\x0c'
+            ) {
+                self.advance();
+            }
+            match self.try_consume_comment_start()? {
+                CommentStart::Line => self.consume_line_comment(None),
+                CommentStart::Block => self.consume_block_comment(None)?,
+                CommentStart::None => break,
+            }
+        }
+
+        let line = self.line;
+        let start = self.pos;
+        let kind = match self.peek() {
+            0 if self.pos >= self.input.len() => {
+                self.current = Token {
+                    kind: TokenKind::End,
+                    text: String::new(),
+                    line,
+                };
+                return Ok(false);
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                while self.peek().is_ascii_alphanumeric() || self.peek() == b'_' {
+                    self.advance();
+                }
+                TokenKind::Identifier
+            }
+            c if c.is_ascii_digit() => {
+                while self.peek().is_ascii_alphanumeric() || self.peek() == b'.' {
+                    self.advance();
+                }
+                TokenKind::Number
+            }
+            quote @ (b'"' | b'\'') => {
+                self.advance();
+                while self.peek() != quote {
+                    match self.peek() {
+                        0 | b'\n' => bail!("line {}: unterminated string", line + 1),
+                        b'\\' => {
+                            self.advance();
+                            self.advance();
+                        }
+                        _ => self.advance(),
+                    }
+                }
+                self.advance();
+                TokenKind::String
+            }
+            _ => {
+                self.advance();
+                TokenKind::Symbol
+            }
+        };
+        self.current = Token {
+            kind,
+            text: String::from_utf8_lossy(&self.input[start..self.pos]).into_owned(),
+            line,
+        };
+        Ok(true)
+    }
+
+    fn next_with_comments(&mut self) -> Result<(String, Vec<String>, String)> {
+        let mut collector = CommentCollector::new();
+        let prev_line = self.line;
+        let mut trailing_end_line = None;
+
+        if self.current.kind == TokenKind::Start {
+            collector.can_attach_to_prev = false;
+        } else {
+            // a comment on the same line belongs to the previous token
+            self.skip_spaces();
+            match self.try_consume_comment_start()? {
+                CommentStart::Line => {
+                    trailing_end_line = Some(self.line);
+                    self.consume_line_comment(Some(collector.buffer_for_line_comment()));
+                    collector.flush();
+                }
+                CommentStart::Block => {
+                    self.consume_block_comment(Some(collector.buffer_for_block_comment()))?;
+                    trailing_end_line = Some(self.line);
+                    self.skip_spaces();
+                    if !self.try_consume(b'\n') {
+                        collector.clear_buffer();
+                        self.next()?;
+                        return Ok(collector.finish());
+                    }
+                    collector.flush();
+                }
+                CommentStart::None => {
+                    if !self.try_consume(b'\n') {
+                        self.next()?;
+                        return Ok(collector.finish());
+                    }
+                }
+            }
+        }
+
+        loop {
+            self.skip_spaces();
+            match self.try_consume_comment_start()? {
+                CommentStart::Line => {
+                    self.consume_line_comment(Some(collector.buffer_for_line_comment()))
+                }
+                CommentStart::Block => {
+                    self.consume_block_comment(Some(collector.buffer_for_block_comment()))?;
+                    self.skip_spaces();
+                    self.try_consume(b'\n');
+                }
+                CommentStart::None => {
+                    if self.try_consume(b'\n') {
+                        // a blank line separates comments from both sides
+                        collector.flush();
+                        collector.can_attach_to_prev = false;
+                        continue;
+                    }
+                    let result = self.next()?;
+                    if !result || matches!(self.current.text.as_str(), "}" | "]" | ")") {
+                        // nothing to attach to at the end of a scope
+                        collector.flush();
+                    }
+                    if result
+                        && (prev_line == self.current.line
+                            || trailing_end_line == Some(self.current.line))
+                    {
+                        collector.maybe_detach();
+                    }
+                    return Ok(collector.finish());
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokenizer: Tokenizer<'a>,
+    // leading comments of the declaration starting at the current token
+    upcoming_doc: String,
+    locations: Vec<Location>,
+}
+
+impl<'a> Parser<'a> {
+    fn current(&self) -> &Token {
+        &self.tokenizer.current
+    }
+
+    fn at(&self, text: &str) -> bool {
+        let t = self.current();
+        t.kind != TokenKind::String && t.text == text
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        let t = self.current();
+        bail!(
+            "line {}: expected {}, found {:?}",
+            t.line + 1,
+            expected,
+            t.text
+        )
+    }
+
+    fn consume(&mut self, text: &str) -> Result<()> {
+        if !self.at(text) {
+            return self.unexpected(&format!("{:?}", text));
+        }
+        self.tokenizer.next()?;
+        Ok(())
+    }
+
+    fn consume_identifier(&mut self) -> Result<String> {
+        if self.current().kind != TokenKind::Identifier {
+            return self.unexpected("identifier");
+        }
+        let text = self.current().text.clone();
+        self.tokenizer.next()?;
+        Ok(text)
+    }
+
+    // `a.b.c`, with a leading dot for fully qualified names
+    fn consume_full_name(&mut self) -> Result<String> {
+        let mut name = String::new();
+        if self.at(".") {
+            self.tokenizer.next()?;
+            name.push('.');
+        }
+        name.push_str(&self.consume_identifier()?);
+        while self.at(".") {
+            self.tokenizer.next()?;
+            name.push('.');
+            name.push_str(&self.consume_identifier()?);
+        }
+        Ok(name)
+    }
+
+    fn consume_integer(&mut self) -> Result<i64> {
+        let negative = self.at("-");
+        if negative {
+            self.tokenizer.next()?;
+        }
+        if self.current().kind != TokenKind::Number {
+            return self.unexpected("integer");
+        }
+        let text = self.current().text.clone();
+        let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None if text.len() > 1 && text.starts_with('0') => i64::from_str_radix(&text, 8),
+            None => text.parse(),
+        }
+        .map_err(|_| anyhow!("line {}: invalid integer {}", self.current().line + 1, text))?;
+        self.tokenizer.next()?;
+        Ok(if negative { -value } else { value })
+    }
+
+    fn consume_string(&mut self) -> Result<String> {
+        if self.current().kind != TokenKind::String {
+            return self.unexpected("string");
+        }
+        let text = self.current().text.clone();
+        self.tokenizer.next()?;
+        Ok(unescape(&text[1..text.len() - 1]))
+    }
+
+    fn consume_bool(&mut self) -> Result<bool> {
+        match self.current().text.as_str() {
+            "true" => {
+                self.tokenizer.next()?;
+                Ok(true)
+            }
+            "false" => {
+                self.tokenizer.next()?;
+                Ok(false)
+            }
+            _ => self.unexpected("bool"),
+        }
+    }
+
+    // default values are kept as text, the way protoc puts them in descriptors
+    fn consume_constant(&mut self) -> Result<String> {
+        let mut text = String::new();
+        if self.at("-") {
+            self.tokenizer.next()?;
+            text.push('-');
+        }
+        let t = self.current().clone();
+        match t.kind {
+            TokenKind::Identifier | TokenKind::Number => {
+                self.tokenizer.next()?;
+                text.push_str(&t.text);
+                Ok(text)
+            }
+            TokenKind::String if text.is_empty() => self.consume_string(),
+            _ => self.unexpected("constant"),
+        }
+    }
+
+    // the leading comments gathered before the declaration and the trailing comments
+    // after its end token go to the declaration's location
+    fn consume_end_of_declaration(&mut self, text: &str, path: Option<Vec<i32>>) -> Result<()> {
+        if !self.at(text) {
+            return self.unexpected(&format!("{:?}", text));
+        }
+        let (trailing, _, leading) = self.tokenizer.next_with_comments()?;
+        let leading = mem::replace(&mut self.upcoming_doc, leading);
+        if let Some(path) = path {
+            self.locations.push(Location {
+                path,
+                leading_comments: (!leading.is_empty()).then_some(leading),
+                trailing_comments: (!trailing.is_empty()).then_some(trailing),
+                ..Default::default()
+            });
+        }
+        Ok(())
+    }
+
+    fn skip_option(&mut self) -> Result<()> {
+        self.consume("option")?;
+        self.consume_full_name()?;
+        self.consume("=")?;
+        self.consume_constant()?;
+        self.consume_end_of_declaration(";", None)
+    }
+
+    fn parse_file(mut self, name: &str) -> Result<FileDescriptorProto> {
+        let (_, _, leading) = self.tokenizer.next_with_comments()?;
+        self.upcoming_doc = leading;
+
+        let mut file = FileDescriptorProto {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        };
+        while self.current().kind != TokenKind::End {
+            if self.at("syntax") {
+                self.tokenizer.next()?;
+                self.consume("=")?;
+                let syntax = self.consume_string()?;
+                if syntax != "proto2" {
+                    bail!("unsupported syntax {}", syntax);
+                }
+                self.consume_end_of_declaration(";", None)?;
+            } else if self.at("package") {
+                self.tokenizer.next()?;
+                file.package = Some(self.consume_full_name()?);
+                self.consume_end_of_declaration(";", None)?;
+            } else if self.at("option") {
+                self.skip_option()?;
+            } else if self.at("message") {
+                let path = vec![FILE_MESSAGE_TYPE, file.message_type.len() as i32];
+                file.message_type.push(self.parse_message(path)?);
+            } else if self.at("enum") {
+                let path = vec![FILE_ENUM_TYPE, file.enum_type.len() as i32];
+                file.enum_type.push(self.parse_enum(path)?);
+            } else if self.at(";") {
+                self.consume_end_of_declaration(";", None)?;
+            } else {
+                return self.unexpected("top-level statement");
+            }
+        }
+
+        file.source_code_info = Some(SourceCodeInfo {
+            location: self.locations,
+        });
+        resolve_types(&mut file)?;
+        Ok(file)
+    }
+
+    fn parse_message(&mut self, path: Vec<i32>) -> Result<DescriptorProto> {
+        self.consume("message")?;
+        let mut message = DescriptorProto {
+            name: Some(self.consume_identifier()?),
+            ..Default::default()
+        };
+        self.consume_end_of_declaration("{", Some(path.clone()))?;
+        while !self.at("}") {
+            let child = |kind: i32, index: usize| {
+                let mut p = path.clone();
+                p.extend([kind, index as i32]);
+                p
+            };
+            if self.current().kind == TokenKind::End {
+                return self.unexpected("\"}\"");
+            } else if self.at("message") {
+                let p = child(MESSAGE_NESTED_TYPE, message.nested_type.len());
+                message.nested_type.push(self.parse_message(p)?);
+            } else if self.at("enum") {
+                let p = child(MESSAGE_ENUM_TYPE, message.enum_type.len());
+                message.enum_type.push(self.parse_enum(p)?);
+            } else if self.at("option") {
+                self.skip_option()?;
+            } else if self.at(";") {
+                self.consume_end_of_declaration(";", None)?;
+            } else {
+                let p = child(MESSAGE_FIELD, message.field.len());
+                message.field.push(self.parse_field(p)?);
+            }
+        }
+        self.consume_end_of_declaration("}", None)?;
+        Ok(message)
+    }
+
+    fn parse_field(&mut self, path: Vec<i32>) -> Result<FieldDescriptorProto> {
+        let label = match self.current().text.as_str() {
+            "optional" => Label::Optional,
+            "required" => Label::Required,
+            "repeated" => Label::Repeated,
+            _ => return self.unexpected("field label"),
+        };
+        self.tokenizer.next()?;
+        let type_name = self.consume_full_name()?;
+        let mut field = FieldDescriptorProto {
+            label: Some(label as i32),
+            name: Some(self.consume_identifier()?),
+            ..Default::default()
+        };
+        self.consume("=")?;
+        field.number = Some(self.consume_integer()? as i32);
+        match scalar_type(&type_name) {
+            Some(t) => field.r#type = Some(t as i32),
+            // resolved once all types are known
+            None => field.type_name = Some(type_name),
+        }
+
+        if self.at("[") {
+            let mut options = FieldOptions::default();
+            loop {
+                self.tokenizer.next()?;
+                let option = self.consume_identifier()?;
+                self.consume("=")?;
+                match option.as_str() {
+                    "default" => field.default_value = Some(self.consume_constant()?),
+                    "packed" => options.packed = Some(self.consume_bool()?),
+                    "deprecated" => options.deprecated = Some(self.consume_bool()?),
+                    _ => bail!("unsupported field option {}", option),
+                }
+                if !self.at(",") {
+                    break;
+                }
+            }
+            self.consume("]")?;
+            if options != FieldOptions::default() {
+                field.options = Some(options);
+            }
+        }
+        self.consume_end_of_declaration(";", Some(path))?;
+        Ok(field)
+    }
+
+    fn parse_enum(&mut self, path: Vec<i32>) -> Result<EnumDescriptorProto> {
+        self.consume("enum")?;
+        let mut e = EnumDescriptorProto {
+            name: Some(self.consume_identifier()?),
+            ..Default::default()
+        };
+        self.consume_end_of_declaration("{", Some(path.clone()))?;
+        while !self.at("}") {
+            if self.current().kind == TokenKind::End {
+                return self.unexpected("\"}\"");
+            } else if self.at("option") {
+                self.skip_option()?;
+            } else if self.at(";") {
+                self.consume_end_of_declaration(";", None)?;
+            } else {
+                let mut value = EnumValueDescriptorProto {
+                    name: Some(self.consume_identifier()?),
+                    ..Default::default()
+                };
+                self.consume("=")?;
+                value.number = Some(self.consume_integer()? as i32);
+                if self.at("[") {
+                    self.tokenizer.next()?;
+                    if self.consume_identifier()? != "deprecated" {
+                        bail!("unsupported enum value option");
+                    }
+                    self.consume("=")?;
+                    value.options = Some(EnumValueOptions {
+                        deprecated: Some(self.consume_bool()?),
+                        ..Default::default()
+                    });
+                    self.consume("]")?;
+                }
+                let mut p = path.clone();
+                p.extend([ENUM_VALUE, e.value.len() as i32]);
+                self.consume_end_of_declaration(";", Some(p))?;
+                e.value.push(value);
+            }
+        }
+        self.consume_end_of_declaration("}", None)?;
+        Ok(e)
+    }
+}
+
+fn scalar_type(name: &str) -> Option<Type> {
+    let t = match name {
+        "double" => Type::Double,
+        "float" => Type::Float,
+        "int64" => Type::Int64,
+        "uint64" => Type::Uint64,
+        "int32" => Type::Int32,
+        "fixed64" => Type::Fixed64,
+        "fixed32" => Type::Fixed32,
+        "bool" => Type::Bool,
+        "string" => Type::String,
+        "bytes" => Type::Bytes,
+        "uint32" => Type::Uint32,
+        "sfixed32" => Type::Sfixed32,
+        "sfixed64" => Type::Sfixed64,
+        "sint32" => Type::Sint32,
+        "sint64" => Type::Sint64,
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn collect_types(scope: &str, message: &DescriptorProto, types: &mut HashMap<String, Type>) {
+    let name = format!("{}.{}", scope, message.name());
+    for e in message.enum_type.iter() {
+        types.insert(format!("{}.{}", name, e.name()), Type::Enum);
+    }
+    for m in message.nested_type.iter() {
+        collect_types(&name, m, types);
+    }
+    types.insert(name, Type::Message);
+}
+
+// names are looked up from the innermost scope outwards, like protoc does
+fn resolve(scope: &str, name: &str, types: &HashMap<String, Type>) -> Result<(String, Type)> {
+    if name.starts_with('.') {
+        return types
+            .get(name)
+            .map(|t| (name.to_owned(), *t))
+            .ok_or_else(|| anyhow!("unknown type {}", name));
+    }
+    let mut scope = scope.to_owned();
+    loop {
+        let candidate = format!("{}.{}", scope, name);
+        if let Some(t) = types.get(&candidate) {
+            return Ok((candidate, *t));
+        }
+        match scope.rfind('.') {
+            Some(i) => scope.truncate(i),
+            None => bail!("unknown type {}", name),
+        }
+    }
+}
+
+fn resolve_message(
+    scope: &str,
+    message: &mut DescriptorProto,
+    types: &HashMap<String, Type>,
+) -> Result<()> {
+    let name = format!("{}.{}", scope, message.name());
+    for field in message.field.iter_mut() {
+        if let Some(type_name) = field.type_name.as_ref() {
+            let (full_name, t) = resolve(&name, type_name, types)?;
+            field.type_name = Some(full_name);
+            field.r#type = Some(t as i32);
+        }
+    }
+    for m in message.nested_type.iter_mut() {
+        resolve_message(&name, m, types)?;
+    }
+    Ok(())
+}
+
+fn resolve_types(file: &mut FileDescriptorProto) -> Result<()> {
+    let package = match file.package() {
+        "" => String::new(),
+        p => format!(".{}", p),
+    };
+    let mut types = HashMap::new();
+    for e in file.enum_type.iter() {
+        types.insert(format!("{}.{}", package, e.name()), Type::Enum);
+    }
+    for m in file.message_type.iter() {
+        collect_types(&package, m, &mut types);
+    }
+    for m in file.message_type.iter_mut() {
+        resolve_message(&package, m, &types)?;
+    }
+    Ok(())
+}
+
+// parses a proto2 file into a descriptor with source code info, covering the
+// subset of the language used by the protocols we decode: no imports, services or oneofs
+pub fn parse(name: &str, source: &str) -> Result<FileDescriptorProto> {
+    let parser = Parser {
+        tokenizer: Tokenizer::new(source),
+        upcoming_doc: String::new(),
+        locations: vec![],
+    };
+    parser.parse_file(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTO: &str = r#"
+syntax = "proto2";
+package test.proto;
+
+// a message
+message Outer {
+    /* nested
+     * enum */
+    enum Kind {
+        A = 0; // first
+        B = -1 [deprecated = true];
+    }
+
+    message Inner {
+        optional Kind kind = 1 [default = B];
+    }
+
+    required Inner inner = 1; /* same line */
+    repeated uint64 ids = 2 [packed = true];
+
+    // detached
+
+    optional string name = 3 [default = "a \"b\""];
+    optional .test.proto.Outer.Kind kind = 4;
+}
+"#;
+
+    fn location(file: &FileDescriptorProto, path: &[i32]) -> Location {
+        file.source_code_info
+            .as_ref()
+            .unwrap()
+            .location
+            .iter()
+            .find(|l| l.path == path)
+            .cloned()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_proto2() {
+        let file = parse("test.proto", PROTO).unwrap();
+        assert_eq!(file.package(), "test.proto");
+        let outer = &file.message_type[0];
+        let inner = &outer.nested_type[0];
+        assert_eq!(inner.field[0].type_name(), ".test.proto.Outer.Kind");
+        assert_eq!(inner.field[0].r#type(), Type::Enum);
+        assert_eq!(inner.field[0].default_value(), "B");
+        assert_eq!(outer.field[0].type_name(), ".test.proto.Outer.Inner");
+        assert_eq!(outer.field[0].label(), Label::Required);
+        assert_eq!(outer.field[1].r#type(), Type::Uint64);
+        assert_eq!(outer.field[1].options.as_ref().unwrap().packed, Some(true));
+        assert_eq!(outer.field[2].default_value(), "a \"b\"");
+        assert_eq!(outer.field[3].r#type(), Type::Enum);
+        assert_eq!(outer.enum_type[0].value[1].number(), -1);
+
+        assert_eq!(location(&file, &[4, 0]).leading_comments(), " a message\n");
+        assert_eq!(
+            location(&file, &[4, 0, 4, 0]).leading_comments(),
+            " nested\n enum "
+        );
+        assert_eq!(
+            location(&file, &[4, 0, 4, 0, 2, 0]).trailing_comments(),
+            " first\n"
+        );
+        assert_eq!(
+            location(&file, &[4, 0, 2, 0]).trailing_comments(),
+            " same line "
+        );
+        assert_eq!(location(&file, &[4, 0, 2, 2]).leading_comments, None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum L7Protocol {
+    #[default]
+    Unknown = 0,
+
+    // HTTP
+    Http1 = 20,
+    Http2 = 21,
+
+    // RPC
+    Dubbo = 40,
+    Grpc = 41,
+    Thrift = 42,
+
+    // SQL
+    MySQL = 60,
+    PostgreSQL = 61,
+
+    // NoSQL
+    Redis = 80,
+    MongoDB = 81,
+    Memcached = 82,
+    ZooKeeper = 83,
+
+    // MQ
+    Kafka = 100,
+    MQTT = 101,
+    AMQP = 102,
+    NATS = 104,
+    Pulsar = 105,
+    RocketMQ = 107,
+
+    // Network
+    DNS = 120,
+    TLS = 121,
+}
+
+impl L7Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unknown => "Unknown",
+            Self::Http1 => "HTTP",
+            Self::Http2 => "HTTP2",
+            Self::Dubbo => "Dubbo",
+            Self::Grpc => "gRPC",
+            Self::Thrift => "Thrift",
+            Self::MySQL => "MySQL",
+            Self::PostgreSQL => "PostgreSQL",
+            Self::Redis => "Redis",
+            Self::MongoDB => "MongoDB",
+            Self::Memcached => "Memcached",
+            Self::ZooKeeper => "ZooKeeper",
+            Self::Kafka => "Kafka",
+            Self::MQTT => "MQTT",
+            Self::AMQP => "AMQP",
+            Self::NATS => "NATS",
+            Self::Pulsar => "Pulsar",
+            Self::RocketMQ => "RocketMQ",
+            Self::DNS => "DNS",
+            Self::TLS => "TLS",
+        }
+    }
+}
+
+impl From<u8> for L7Protocol {
+    fn from(v: u8) -> Self {
+        match v {
+            20 => Self::Http1,
+            21 => Self::Http2,
+            40 => Self::Dubbo,
+            41 => Self::Grpc,
+            42 => Self::Thrift,
+            60 => Self::MySQL,
+            61 => Self::PostgreSQL,
+            80 => Self::Redis,
+            81 => Self::MongoDB,
+            82 => Self::Memcached,
+            83 => Self::ZooKeeper,
+            100 => Self::Kafka,
+            101 => Self::MQTT,
+            102 => Self::AMQP,
+            104 => Self::NATS,
+            105 => Self::Pulsar,
+            107 => Self::RocketMQ,
+            120 => Self::DNS,
+            121 => Self::TLS,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for L7Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
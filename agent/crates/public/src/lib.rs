@@ -1,6 +1,7 @@
 pub mod consts;
+pub mod l7_protocol;
 pub mod utils;
-mod bytes;
+pub mod bytes;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
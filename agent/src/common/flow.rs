@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PacketDirection {
+    #[default]
+    ClientToServer = 0,
+    ServerToClient = 1,
+}
+
+impl PacketDirection {
+    pub fn reversed(&self) -> Self {
+        match self {
+            PacketDirection::ClientToServer => PacketDirection::ServerToClient,
+            PacketDirection::ServerToClient => PacketDirection::ClientToServer,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum L4Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
@@ -1,3 +1,4 @@
 mod consts;
+pub mod flow;
 
-pub use consts::*;
\ No newline at end of file
+pub use consts::*;
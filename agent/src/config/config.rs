@@ -44,6 +44,52 @@ pub enum ConfigError {
     YamlConfigInvalid(String),
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct L7LogConfig {
+    // header, property or attachment keys carrying trace context, tried in order
+    pub trace_id_keys: Vec<String>,
+    pub span_id_keys: Vec<String>,
+    // split batched mq messages into per-message records
+    pub message_batch_expansion: bool,
+    // compressed payloads larger than this once decompressed are not expanded, 0 disables decompression
+    pub max_decompressed_size: usize,
+    // request and response headers recorded in http logs besides the well-known ones
+    pub http_extra_headers: Vec<String>,
+    // statements in database logs are truncated to this many bytes
+    pub sql_max_length: usize,
+    // log only the first segment of redis keys, as in user:?:? for user:42:profile
+    pub redis_key_obfuscation: bool,
+    // log the top level of mongodb commands with nested documents reduced to their size,
+    // as in {find: "users", filter: {2}, limit: 10}
+    pub mongodb_command_summary: bool,
+}
+
+impl Default for L7LogConfig {
+    fn default() -> Self {
+        Self {
+            trace_id_keys: vec![
+                "traceparent".into(),
+                "sw8".into(),
+                "uber-trace-id".into(),
+                "X-B3-TraceId".into(),
+            ],
+            span_id_keys: vec![
+                "traceparent".into(),
+                "sw8".into(),
+                "uber-trace-id".into(),
+                "X-B3-SpanId".into(),
+            ],
+            message_batch_expansion: true,
+            max_decompressed_size: 1 << 20,
+            http_extra_headers: vec![],
+            sql_max_length: 1024,
+            redis_key_obfuscation: false,
+            mongodb_command_summary: false,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Config {
@@ -65,6 +111,7 @@ pub struct Config {
     pub pid_file: String,
     pub team_id: String,
     pub cgroups_disabled: bool,
+    pub l7_log: L7LogConfig,
 }
 
 impl Config{
@@ -135,6 +182,7 @@ impl Default for Config {
             pid_file: Default::default(),
             team_id: "".into(),
             cgroups_disabled: false,
+            l7_log: L7LogConfig::default(),
         }
     }
 }
@@ -0,0 +1,43 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("insufficient payload length")]
+    InsufficientPayloadLength,
+    #[error("http log parse failed: {0}")]
+    HttpLogParseFailed(String),
+    #[error("amqp log parse failed: {0}")]
+    AmqpLogParseFailed(String),
+    #[error("kafka log parse failed: {0}")]
+    KafkaLogParseFailed(String),
+    #[error("mqtt log parse failed: {0}")]
+    MqttLogParseFailed(String),
+    #[error("nats log parse failed: {0}")]
+    NatsLogParseFailed(String),
+    #[error("pulsar log parse failed: {0}")]
+    PulsarLogParseFailed(String),
+    #[error("rocketmq log parse failed: {0}")]
+    RocketmqLogParseFailed(String),
+    #[error("dns log parse failed: {0}")]
+    DnsLogParseFailed(String),
+    #[error("mysql log parse failed: {0}")]
+    MysqlLogParseFailed(String),
+    #[error("postgresql log parse failed: {0}")]
+    PostgresqlLogParseFailed(String),
+    #[error("redis log parse failed: {0}")]
+    RedisLogParseFailed(String),
+    #[error("mongodb log parse failed: {0}")]
+    MongodbLogParseFailed(String),
+    #[error("dubbo log parse failed: {0}")]
+    DubboLogParseFailed(String),
+    #[error("thrift log parse failed: {0}")]
+    ThriftLogParseFailed(String),
+    #[error("tls log parse failed: {0}")]
+    TlsLogParseFailed(String),
+    #[error("memcached log parse failed: {0}")]
+    MemcachedLogParseFailed(String),
+    #[error("zookeeper log parse failed: {0}")]
+    ZookeeperLogParseFailed(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
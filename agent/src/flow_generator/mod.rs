@@ -0,0 +1,4 @@
+mod error;
+pub mod protocol_logs;
+
+pub use error::{Error, Result};
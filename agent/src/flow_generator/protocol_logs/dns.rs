@@ -0,0 +1,702 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use log::debug;
+use public::bytes::{read_u16_be, read_u32_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{
+    expire_pending, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus,
+    LogMessageType, ParseParam, PerDirection, Stream, MAX_PENDING_SESSIONS,
+};
+use crate::common::flow::{L4Protocol, PacketDirection};
+use crate::flow_generator::{Error, Result};
+
+const HEADER_SIZE: usize = 12;
+const MAX_NAME_SIZE: usize = 255;
+// records kept per section, a response may carry hundreds of them
+const MAX_RECORDS: usize = 32;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_OPT: u16 = 41;
+
+fn type_name(rr_type: u16) -> String {
+    let name = match rr_type {
+        TYPE_A => "A",
+        TYPE_NS => "NS",
+        TYPE_CNAME => "CNAME",
+        TYPE_SOA => "SOA",
+        TYPE_PTR => "PTR",
+        TYPE_MX => "MX",
+        TYPE_TXT => "TXT",
+        TYPE_AAAA => "AAAA",
+        TYPE_SRV => "SRV",
+        TYPE_OPT => "OPT",
+        64 => "SVCB",
+        65 => "HTTPS",
+        255 => "ANY",
+        _ => return format!("TYPE{}", rr_type),
+    };
+    name.to_owned()
+}
+
+fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        _ => "UNKNOWN",
+    }
+}
+
+fn rcode_status(rcode: u16) -> L7ResponseStatus {
+    match rcode {
+        0 => L7ResponseStatus::Ok,
+        // the query is malformed or the name does not exist
+        1 | 3 => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rr_type: String,
+    pub ttl: u32,
+    // presentation format of the types known, absent for the others
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct DnsInfo {
+    pub msg_type: LogMessageType,
+    pub transaction_id: u16,
+    pub opcode: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_type: Option<String>,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rcode: Option<&'static str>,
+    // set when a UDP response is cut and the client should retry over TCP
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub answers: Vec<DnsRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authorities: Vec<DnsRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub additionals: Vec<DnsRecord>,
+    // A and AAAA answers, following CNAME chains
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resolved_addresses: Vec<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl DnsInfo {
+    fn merge(&mut self, response: DnsInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.rcode = response.rcode;
+        self.truncated = response.truncated;
+        self.answers = response.answers;
+        self.authorities = response.authorities;
+        self.additionals = response.additionals;
+        self.resolved_addresses = response.resolved_addresses;
+        self.status = response.status;
+    }
+}
+
+// names may point anywhere before them in the message, so reads keep the whole of it
+struct Reader<'a> {
+    message: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .message
+            .get(self.offset..self.offset + n)
+            .ok_or(Error::InsufficientPayloadLength)?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(read_u16_be(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(read_u32_be(self.take(4)?))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let mut name = String::new();
+        let mut offset = self.offset;
+        // the offset after the name, which ends at its first pointer
+        let mut end = None;
+        loop {
+            let len = *self
+                .message
+                .get(offset)
+                .ok_or(Error::InsufficientPayloadLength)? as usize;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    offset += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .message
+                        .get(offset + 1..offset + 1 + len)
+                        .ok_or(Error::InsufficientPayloadLength)?;
+                    if name.len() + len + 1 > MAX_NAME_SIZE {
+                        return Err(Error::DnsLogParseFailed("name too long".to_owned()));
+                    }
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&String::from_utf8_lossy(label));
+                    offset += 1 + len;
+                }
+                0xc0 => {
+                    let pointer = self
+                        .message
+                        .get(offset..offset + 2)
+                        .ok_or(Error::InsufficientPayloadLength)?;
+                    let target = read_u16_be(pointer) as usize & 0x3fff;
+                    end.get_or_insert(offset + 2);
+                    // pointing backwards only rules out loops
+                    if target >= offset {
+                        return Err(Error::DnsLogParseFailed(format!(
+                            "name pointer {} at {}",
+                            target, offset
+                        )));
+                    }
+                    offset = target;
+                }
+                _ => {
+                    return Err(Error::DnsLogParseFailed(format!(
+                        "invalid label length {:#x}",
+                        len
+                    )))
+                }
+            }
+        }
+        self.offset = end.unwrap_or(offset);
+        if name.is_empty() {
+            name.push('.');
+        }
+        Ok(name)
+    }
+
+    // [name][type u16][class u16][ttl u32][rdlength u16][rdata]
+    fn record(&mut self) -> Result<(u16, DnsRecord)> {
+        let name = self.name()?;
+        let rr_type = self.u16()?;
+        let _class = self.u16()?;
+        let ttl = self.u32()?;
+        let rdlength = self.u16()? as usize;
+        let start = self.offset;
+        let rdata = self.take(rdlength)?;
+        let data = match rr_type {
+            TYPE_A if rdlength == 4 => Some(Ipv4Addr::from(read_u32_be(rdata)).to_string()),
+            TYPE_AAAA if rdlength == 16 => {
+                Some(Ipv6Addr::from(<[u8; 16]>::try_from(rdata).unwrap()).to_string())
+            }
+            // the primary name server only for SOA
+            TYPE_CNAME | TYPE_NS | TYPE_PTR | TYPE_SOA => Some(self.rdata_name(start)?),
+            TYPE_MX if rdlength > 2 => Some(format!(
+                "{} {}",
+                read_u16_be(rdata),
+                self.rdata_name(start + 2)?
+            )),
+            // [priority u16][weight u16][port u16][target]
+            TYPE_SRV if rdlength > 6 => Some(format!(
+                "{} {} {} {}",
+                read_u16_be(rdata),
+                read_u16_be(&rdata[2..]),
+                read_u16_be(&rdata[4..]),
+                self.rdata_name(start + 6)?
+            )),
+            TYPE_TXT => {
+                let mut text = String::new();
+                let mut strings = rdata;
+                while let Some((&len, rest)) = strings.split_first() {
+                    let s = rest.get(..len as usize).unwrap_or(rest);
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    let _ = write!(text, "{:?}", String::from_utf8_lossy(s));
+                    strings = &rest[s.len()..];
+                }
+                Some(text)
+            }
+            _ => None,
+        };
+        let record = DnsRecord {
+            name,
+            rr_type: type_name(rr_type),
+            ttl,
+            data,
+        };
+        Ok((rr_type, record))
+    }
+
+    fn rdata_name(&self, offset: usize) -> Result<String> {
+        Reader {
+            message: self.message,
+            offset,
+        }
+        .name()
+    }
+}
+
+// [id u16][flags u16][qdcount u16][ancount u16][nscount u16][arcount u16]
+fn parse_message(message: &[u8]) -> Result<DnsInfo> {
+    let mut reader = Reader { message, offset: 0 };
+    let transaction_id = reader.u16()?;
+    let flags = reader.u16()?;
+    let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+    let mut info = DnsInfo {
+        msg_type: if flags & FLAG_RESPONSE == 0 {
+            LogMessageType::Request
+        } else {
+            LogMessageType::Response
+        },
+        transaction_id,
+        opcode: (flags >> 11) as u8 & 0xf,
+        ..Default::default()
+    };
+    for i in 0..counts[0] {
+        let name = reader.name()?;
+        let query_type = reader.u16()?;
+        let _class = reader.u16()?;
+        if i == 0 {
+            info.query_name = Some(name);
+            info.query_type = Some(type_name(query_type));
+        }
+    }
+    if info.msg_type == LogMessageType::Request {
+        return Ok(info);
+    }
+
+    let mut rcode = flags & 0xf;
+    info.truncated = flags & FLAG_TRUNCATED != 0;
+    let mut chain = info.query_name.clone().into_iter().collect::<Vec<_>>();
+    for (section, &count) in counts[1..].iter().enumerate() {
+        for _ in 0..count {
+            // a truncated response keeps the records before the cut
+            let (rr_type, record) = match reader.record() {
+                Ok(r) => r,
+                Err(Error::InsufficientPayloadLength) if info.truncated => break,
+                Err(e) => return Err(e),
+            };
+            if rr_type == TYPE_OPT {
+                // EDNS extends rcode with the high byte of the ttl
+                rcode |= ((record.ttl >> 24) as u16) << 4;
+                continue;
+            }
+            let records = match section {
+                0 => &mut info.answers,
+                1 => &mut info.authorities,
+                _ => &mut info.additionals,
+            };
+            if section == 0 && chain.iter().any(|n| n.eq_ignore_ascii_case(&record.name)) {
+                match (rr_type, record.data.as_ref()) {
+                    (TYPE_CNAME, Some(target)) => chain.push(target.clone()),
+                    (TYPE_A | TYPE_AAAA, Some(address)) => {
+                        info.resolved_addresses.push(address.clone())
+                    }
+                    _ => (),
+                }
+            }
+            if records.len() < MAX_RECORDS {
+                records.push(record);
+            }
+        }
+    }
+    info.rcode = Some(rcode_name(rcode));
+    info.status = rcode_status(rcode);
+    Ok(info)
+}
+
+pub struct DnsLog {
+    // messages over TCP are prefixed with their length
+    l4_protocol: L4Protocol,
+    streams: PerDirection<Stream>,
+    pending: HashMap<u16, DnsInfo>,
+}
+
+impl DnsLog {
+    pub fn new(l4_protocol: L4Protocol) -> Self {
+        Self {
+            l4_protocol,
+            streams: Default::default(),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn parse_messages(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<DnsInfo>> {
+        if self.l4_protocol == L4Protocol::Udp {
+            return Ok(vec![parse_message(payload)?]);
+        }
+        // messages are at most 64 KiB, so they are buffered whole
+        let data = self.streams[param.direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= 2 {
+            let size = read_u16_be(remain) as usize;
+            if size < HEADER_SIZE {
+                return Err(Error::DnsLogParseFailed(format!(
+                    "invalid message length {}",
+                    size
+                )));
+            }
+            if remain.len() < 2 + size {
+                break;
+            }
+            match parse_message(&remain[2..2 + size]) {
+                Ok(info) => infos.push(info),
+                Err(e) => {
+                    debug!("skip dns message: {}", e);
+                    last_error = Some(e);
+                }
+            }
+            remain = &remain[2 + size..];
+        }
+        if !remain.is_empty() {
+            self.streams[param.direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn handle(&mut self, mut info: DnsInfo, time: u64, output: &mut Vec<L7ProtocolInfo>) {
+        info.time = time;
+        match info.msg_type {
+            LogMessageType::Request => {
+                let key = info.transaction_id;
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::DnsInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    // a retransmission is answered once, keep the last try
+                    output.push(L7ProtocolInfo::DnsInfo(DnsInfo {
+                        status: L7ResponseStatus::Timeout,
+                        ..prev
+                    }));
+                }
+            }
+            _ => match self.pending.remove(&info.transaction_id) {
+                Some(mut request) => {
+                    request.merge(info);
+                    output.push(L7ProtocolInfo::DnsInfo(request));
+                }
+                None => output.push(L7ProtocolInfo::DnsInfo(info)),
+            },
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for DnsLog {
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer {
+            return false;
+        }
+        let message = match self.l4_protocol {
+            L4Protocol::Udp => payload,
+            L4Protocol::Tcp if payload.len() >= 2 => &payload[2..],
+            L4Protocol::Tcp => return false,
+        };
+        if message.len() < HEADER_SIZE {
+            return false;
+        }
+        let flags = read_u16_be(&message[2..]);
+        // a query, notify or update of at least one question
+        flags & FLAG_RESPONSE == 0
+            && matches!((flags >> 11) & 0xf, 0 | 4 | 5)
+            && read_u16_be(&message[4..]) > 0
+            && parse_message(message).is_ok()
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        match self.parse_messages(payload, param) {
+            Ok(infos) => {
+                for info in infos {
+                    self.handle(info, param.time, &mut output);
+                }
+            }
+            Err(e) => {
+                self.streams[param.direction].buffer.clear();
+                if output.is_empty() {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::DNS
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, SESSION_TIMEOUT};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        for label in name.split('.') {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    fn message(id: u16, flags: u16, counts: [u16; 4], body: &[u8]) -> Vec<u8> {
+        let mut m = [id, flags]
+            .iter()
+            .chain(counts.iter())
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>();
+        m.extend_from_slice(body);
+        m
+    }
+
+    fn record(owner: &[u8], rr_type: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        [
+            owner,
+            &rr_type.to_be_bytes(),
+            &1u16.to_be_bytes(),
+            &ttl.to_be_bytes(),
+            &(rdata.len() as u16).to_be_bytes(),
+            rdata,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn udp_lookup_with_cname_chain() {
+        let mut parser = DnsLog::new(L4Protocol::Udp);
+        let question = [&name("api.shop.svc.cluster.local")[..], &[0, 1, 0, 1]].concat();
+        let query = message(0x1234, 0x0100, [1, 0, 0, 1], &question);
+        let query = [query, record(&[0], TYPE_OPT, 0, &[])].concat();
+        assert!(parser.check_payload(&query, &at(C2S, 0)));
+        assert!(parse(&mut parser, &query, &at(C2S, 100)).is_empty());
+
+        // the owner names point back at the question, and at the CNAME target
+        let target = (HEADER_SIZE + question.len() + 12) as u8;
+        let answers = [
+            record(
+                &[0xc0, 12],
+                TYPE_CNAME,
+                30,
+                &name("lb.shop.svc.cluster.local"),
+            ),
+            record(&[0xc0, target], TYPE_A, 30, &[10, 0, 0, 7]),
+            record(&[0xc0, target], TYPE_A, 30, &[10, 0, 0, 8]),
+            record(&[0xc0, target], TYPE_TXT, 30, b"\x05hello\x02ok"),
+        ]
+        .concat();
+        let additional = [
+            record(
+                &name("_grpc._tcp.shop"),
+                TYPE_SRV,
+                5,
+                &[&[0, 1, 0, 5, 0x1f, 0x90], &name("lb")[..]].concat(),
+            ),
+            record(
+                &name("shop"),
+                TYPE_MX,
+                5,
+                &[&[0, 10][..], &[0xc0, 12]].concat(),
+            ),
+            record(&[0], TYPE_OPT, 0, &[]),
+        ]
+        .concat();
+        let response = message(
+            0x1234,
+            0x8180,
+            [1, 4, 0, 3],
+            &[&question[..], &answers, &additional].concat(),
+        );
+        let info = parse(&mut parser, &response, &at(S2C, 350)).pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(
+            info.query_name.as_deref(),
+            Some("api.shop.svc.cluster.local")
+        );
+        assert_eq!(info.query_type.as_deref(), Some("A"));
+        assert_eq!(
+            (info.rcode, info.status, info.rrt),
+            (Some("NOERROR"), L7ResponseStatus::Ok, 250)
+        );
+        assert_eq!(
+            info.answers[0].data.as_deref(),
+            Some("lb.shop.svc.cluster.local")
+        );
+        assert_eq!(info.answers[1].name, "lb.shop.svc.cluster.local");
+        assert_eq!(info.answers[3].data.as_deref(), Some("\"hello\" \"ok\""));
+        assert_eq!(info.resolved_addresses, vec!["10.0.0.7", "10.0.0.8"]);
+        assert_eq!(info.additionals[0].data.as_deref(), Some("1 5 8080 lb"));
+        assert_eq!(
+            info.additionals[1].data.as_deref(),
+            Some("10 api.shop.svc.cluster.local")
+        );
+        assert_eq!(info.additionals.len(), 2);
+
+        // unanswered, then a pointer loop
+        let query = message(0x1235, 0x0100, [1, 0, 0, 0], &question);
+        parse(&mut parser, &query, &at(C2S, 1000));
+        let info = parse(&mut parser, &query, &at(C2S, 1000 + SESSION_TIMEOUT))
+            .pop()
+            .unwrap();
+        assert_eq!(
+            (info.transaction_id, info.status),
+            (0x1235, L7ResponseStatus::Timeout)
+        );
+        let looped = message(0x1236, 0x8183, [1, 0, 0, 0], &[0xc0, 12, 0, 1, 0, 1]);
+        assert!(parser.parse_payload(&looped, &at(S2C, 2000)).is_err());
+    }
+
+    #[test]
+    fn tcp_messages_and_errors() {
+        let mut parser = DnsLog::new(L4Protocol::Tcp);
+        let framed = |m: Vec<u8>| [(m.len() as u16).to_be_bytes().to_vec(), m].concat();
+        let question = |n: &str, t: u16| [&name(n)[..], &t.to_be_bytes(), &[0, 1]].concat();
+        let queries = [
+            framed(message(
+                1,
+                0x0100,
+                [1, 0, 0, 0],
+                &question("missing.shop.local", TYPE_AAAA),
+            )),
+            framed(message(
+                2,
+                0x0100,
+                [1, 0, 0, 0],
+                &question("7.0.0.10.in-addr.arpa", TYPE_PTR),
+            )),
+        ]
+        .concat();
+        assert!(parser.check_payload(&queries, &at(C2S, 0)));
+        for segment in queries.chunks(7) {
+            assert!(parse(&mut parser, segment, &at(C2S, 0)).is_empty());
+        }
+
+        let soa = [
+            &name("ns.shop.local")[..],
+            &name("admin.shop.local")[..],
+            &[0; 20],
+        ]
+        .concat();
+        let ptr_question = question("7.0.0.10.in-addr.arpa", TYPE_PTR);
+        let responses = [
+            framed(message(
+                2,
+                0x8580,
+                [1, 1, 0, 0],
+                &[
+                    &ptr_question[..],
+                    &record(&[0xc0, 12], TYPE_PTR, 30, &name("api.shop.local")),
+                ]
+                .concat(),
+            )),
+            framed(message(
+                1,
+                0x8183,
+                [1, 0, 1, 0],
+                &[
+                    question("missing.shop.local", TYPE_AAAA),
+                    record(&name("shop.local"), TYPE_SOA, 60, &soa),
+                ]
+                .concat(),
+            )),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &responses, &at(S2C, 80));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].query_type.as_deref(), Some("PTR"));
+        assert_eq!(infos[0].answers[0].data.as_deref(), Some("api.shop.local"));
+        assert!(infos[0].resolved_addresses.is_empty());
+        assert_eq!(infos[1].query_type.as_deref(), Some("AAAA"));
+        assert_eq!(
+            (infos[1].rcode, infos[1].status),
+            (Some("NXDOMAIN"), L7ResponseStatus::ClientError)
+        );
+        assert_eq!(
+            infos[1].authorities[0].data.as_deref(),
+            Some("ns.shop.local")
+        );
+
+        // SERVFAIL to a response without its query
+        let response = framed(message(
+            3,
+            0x8182,
+            [1, 0, 0, 0],
+            &question("shop.local", TYPE_A),
+        ));
+        let info = parse(&mut parser, &response, &at(S2C, 90)).pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Response);
+        assert_eq!(
+            (info.rcode, info.status),
+            (Some("SERVFAIL"), L7ResponseStatus::ServerError)
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use public::l7_protocol::L7Protocol;
+
+use super::{status_code_status, HttpInfo};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, LogMessageType, ParseParam,
+    PendingRequest, PerDirection, MAX_PENDING_SESSIONS, SESSION_TIMEOUT,
+};
+use crate::flow_generator::{Error, Result};
+
+// request line or status line with headers
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// chunk size lines and trailers
+const MAX_LINE_SIZE: usize = 4096;
+const METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+// where a stream is in the message it is reading, bodies are skipped without buffering
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Head,
+    // bytes left of a content-length body
+    Body(u64),
+    ChunkSize,
+    // bytes left of a chunk, followed by its CRLF
+    ChunkData(u64),
+    ChunkDataEnd,
+    Trailer,
+    // a response delimited by the connection close
+    UntilClose,
+    // after 101 Switching Protocols or a successful CONNECT
+    Tunnel,
+}
+
+#[derive(Default)]
+struct Stream {
+    state: State,
+    // bytes of an incomplete head or line
+    buffer: Vec<u8>,
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+fn version(v: &str) -> Result<&'static str> {
+    match v {
+        "HTTP/1.1" => Ok("1.1"),
+        "HTTP/1.0" => Ok("1.0"),
+        _ => Err(Error::HttpLogParseFailed(format!(
+            "unknown version {:?}",
+            v
+        ))),
+    }
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<(&'a str, &'a str)> {
+    lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect()
+}
+
+fn header<'a>(headers: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| *v)
+}
+
+// the last transfer coding being chunked, otherwise content-length
+fn body_state(headers: &[(&str, &str)]) -> Result<Option<State>> {
+    if let Some(te) = header(headers, "transfer-encoding") {
+        let chunked = te
+            .rsplit(',')
+            .next()
+            .is_some_and(|c| c.trim().eq_ignore_ascii_case("chunked"));
+        return Ok(Some(if chunked {
+            State::ChunkSize
+        } else {
+            State::UntilClose
+        }));
+    }
+    match header(headers, "content-length") {
+        Some(len) => {
+            let len = len.parse::<u64>().map_err(|_| {
+                Error::HttpLogParseFailed(format!("invalid content-length {:?}", len))
+            })?;
+            Ok(Some(if len > 0 {
+                State::Body(len)
+            } else {
+                State::Head
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(Default)]
+pub struct Http1Log {
+    streams: PerDirection<Stream>,
+    // pipelined requests are answered in order
+    pending: VecDeque<HttpInfo>,
+    // leading requests of pending already reported as timeout, a late response still
+    // consumes one so that the following responses are paired right
+    timed_out: usize,
+}
+
+impl Http1Log {
+    fn parse_stream(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let joined;
+        let data = if self.streams[direction].buffer.is_empty() {
+            payload
+        } else {
+            joined = [&mem::take(&mut self.streams[direction].buffer)[..], payload].concat();
+            &joined[..]
+        };
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            let state = self.streams[direction].state;
+            let next = match state {
+                State::Head => {
+                    let Some(end) = find(rest, b"\r\n\r\n") else {
+                        if rest.len() > MAX_HEAD_SIZE {
+                            return Err(Error::HttpLogParseFailed("head too long".to_owned()));
+                        }
+                        break;
+                    };
+                    pos += end + 4;
+                    let head = String::from_utf8_lossy(&rest[..end]);
+                    match param.direction {
+                        PacketDirection::ClientToServer => {
+                            self.parse_request(&head, param, output)?
+                        }
+                        PacketDirection::ServerToClient => {
+                            self.parse_response(&head, param, output)?
+                        }
+                    }
+                }
+                State::Body(n) | State::ChunkData(n) => {
+                    let len = n.min(rest.len() as u64);
+                    pos += len as usize;
+                    match (state, n - len) {
+                        (State::Body(_), 0) => State::Head,
+                        (State::Body(_), left) => State::Body(left),
+                        (_, 0) => State::ChunkDataEnd,
+                        (_, left) => State::ChunkData(left),
+                    }
+                }
+                State::ChunkDataEnd => {
+                    if rest.len() < 2 {
+                        break;
+                    }
+                    pos += 2;
+                    State::ChunkSize
+                }
+                State::ChunkSize | State::Trailer => {
+                    let Some(end) = find(rest, b"\r\n") else {
+                        if rest.len() > MAX_LINE_SIZE {
+                            return Err(Error::HttpLogParseFailed("line too long".to_owned()));
+                        }
+                        break;
+                    };
+                    pos += end + 2;
+                    let line = &rest[..end];
+                    match state {
+                        // an empty line ends the trailer section
+                        State::Trailer if line.is_empty() => State::Head,
+                        State::Trailer => State::Trailer,
+                        _ => {
+                            let size = String::from_utf8_lossy(line);
+                            let size = size.split(';').next().unwrap_or_default().trim();
+                            match u64::from_str_radix(size, 16) {
+                                Ok(0) => State::Trailer,
+                                Ok(size) => State::ChunkData(size),
+                                Err(_) => {
+                                    return Err(Error::HttpLogParseFailed(format!(
+                                        "invalid chunk size {:?}",
+                                        size
+                                    )))
+                                }
+                            }
+                        }
+                    }
+                }
+                State::UntilClose | State::Tunnel => {
+                    pos = data.len();
+                    state
+                }
+            };
+            self.streams[direction].state = next;
+        }
+        if pos < data.len() {
+            self.streams[direction].buffer = data[pos..].to_vec();
+        }
+        Ok(())
+    }
+
+    // METHOD SP request-target SP HTTP-version, then headers
+    fn parse_request(
+        &mut self,
+        head: &str,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<State> {
+        let mut lines = head.split("\r\n");
+        let line = lines.next().unwrap_or_default();
+        let mut fields = line.splitn(3, ' ');
+        let (Some(method), Some(path), Some(v)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::HttpLogParseFailed(format!(
+                "invalid request line {:?}",
+                line
+            )));
+        };
+        if !METHODS.contains(&method) {
+            return Err(Error::HttpLogParseFailed(format!(
+                "unknown method {:?}",
+                method
+            )));
+        }
+        let mut info = HttpInfo {
+            msg_type: LogMessageType::Request,
+            protocol: L7Protocol::Http1,
+            version: version(v)?,
+            method: Some(method.to_owned()),
+            path: Some(path.to_owned()),
+            time: param.time,
+            ..Default::default()
+        };
+        let headers = parse_headers(lines);
+        info.fill_headers(&headers, param.parse_config);
+        // requests without a length have no body
+        let state = body_state(&headers)?.unwrap_or(State::Head);
+
+        if self.pending.len() >= MAX_PENDING_SESSIONS {
+            output.push(L7ProtocolInfo::HttpInfo(info));
+        } else {
+            self.pending.push_back(info);
+        }
+        Ok(state)
+    }
+
+    // HTTP-version SP status-code SP reason-phrase, then headers
+    fn parse_response(
+        &mut self,
+        head: &str,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<State> {
+        let mut lines = head.split("\r\n");
+        let line = lines.next().unwrap_or_default();
+        let mut fields = line.splitn(3, ' ');
+        let v = version(fields.next().unwrap_or_default())?;
+        let code = fields
+            .next()
+            .and_then(|c| c.parse::<u16>().ok())
+            .filter(|c| (100..600).contains(c))
+            .ok_or_else(|| Error::HttpLogParseFailed(format!("invalid status line {:?}", line)))?;
+        // interim responses such as 100 Continue are followed by the final one
+        if (100..200).contains(&code) && code != 101 {
+            return Ok(State::Head);
+        }
+
+        let mut info = HttpInfo {
+            msg_type: LogMessageType::Response,
+            protocol: L7Protocol::Http1,
+            version: v,
+            status_code: Some(code),
+            status: status_code_status(code),
+            time: param.time,
+            ..Default::default()
+        };
+        let headers = parse_headers(lines);
+        info.fill_headers(&headers, param.parse_config);
+        let request = self.pending.pop_front();
+        let timed_out = request.is_some() && self.timed_out > 0;
+        if timed_out {
+            self.timed_out -= 1;
+        }
+        let method = request.as_ref().and_then(|r| r.method.as_deref());
+        let tunnel = code == 101 || method == Some("CONNECT") && (200..300).contains(&code);
+        let state = if tunnel {
+            self.streams[PacketDirection::ClientToServer] = Stream {
+                state: State::Tunnel,
+                buffer: vec![],
+            };
+            State::Tunnel
+        } else if method == Some("HEAD") || code == 204 || code == 304 {
+            State::Head
+        } else {
+            body_state(&headers)?.unwrap_or(State::UntilClose)
+        };
+
+        match request {
+            Some(mut request) if !timed_out => {
+                request.merge(info);
+                output.push(L7ProtocolInfo::HttpInfo(request));
+            }
+            _ => output.push(L7ProtocolInfo::HttpInfo(info)),
+        }
+        Ok(state)
+    }
+
+    // responses to later requests may still be in flight, so a timed out request leaves
+    // behind what its response needs to be delimited
+    fn expire_pending(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        for request in self.pending.iter_mut().skip(self.timed_out) {
+            if now.saturating_sub(request.time) < SESSION_TIMEOUT {
+                break;
+            }
+            let placeholder = HttpInfo {
+                method: request.method.clone(),
+                ..Default::default()
+            };
+            output.push(mem::replace(request, placeholder).into_timeout());
+            self.timed_out += 1;
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for Http1Log {
+    // a request line from the client
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer {
+            return false;
+        }
+        let line = &payload[..find(payload, b"\r\n").unwrap_or_default()];
+        let Ok(line) = std::str::from_utf8(line) else {
+            return false;
+        };
+        let mut fields = line.split(' ');
+        matches!(
+            (fields.next(), fields.next(), fields.next(), fields.next()),
+            (Some(m), Some(_), Some(v), None) if METHODS.contains(&m) && version(v).is_ok()
+        )
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        self.expire_pending(param.time, &mut output);
+        if let Err(e) = self.parse_stream(payload, param, &mut output) {
+            // resynchronize on the next message head
+            self.streams[param.direction] = Stream::default();
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Http1
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.pending.clear();
+        self.timed_out = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{
+        test_util::parse, L7ResponseStatus, LogParserConfig,
+    };
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    #[test]
+    fn pipelined_requests() {
+        let config = LogParserConfig {
+            http_extra_headers: vec!["X-Request-Id".to_owned()],
+            ..Default::default()
+        };
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = Http1Log::default();
+        let requests = concat!(
+            "GET /orders/1 HTTP/1.1\r\nHost: shop\r\nUser-Agent: curl/8.4.0\r\n",
+            "traceparent: 00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01\r\n",
+            "x-request-id: r-1\r\n\r\n",
+            "HEAD /orders HTTP/1.1\r\nHost: shop\r\n\r\n",
+            "POST /orders HTTP/1.1\r\nHost: shop\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "5\r\n{\"a\":\r\n3;ext=1\r\n1}\n\r\n0\r\n\r\n",
+        )
+        .as_bytes();
+        assert!(parser.check_payload(requests, &at(C2S, 0)));
+        assert!(!parser.check_payload(b"HTTP/1.1 200 OK\r\n\r\n", &at(S2C, 0)));
+        for segment in requests.chunks(7) {
+            assert!(parse(&mut parser, segment, &at(C2S, 100)).is_empty());
+        }
+        assert_eq!(parser.pending.len(), 3);
+        assert_eq!(parser.streams[C2S].state, State::Head);
+
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 10\r\n\r\n{\"id\":1}\r\n";
+        let (head, body) = response.split_at(response.len() - 4);
+        let info = parse(&mut parser, head, &at(S2C, 300)).pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.method.as_deref(), Some("GET"));
+        assert_eq!(info.path.as_deref(), Some("/orders/1"));
+        assert_eq!(info.host.as_deref(), Some("shop"));
+        assert_eq!(info.user_agent.as_deref(), Some("curl/8.4.0"));
+        assert_eq!(info.status_code, Some(200));
+        assert_eq!(info.response_content_length, Some(10));
+        assert_eq!(info.content_type.as_deref(), Some("application/json"));
+        assert_eq!(
+            info.extra_headers.get("X-Request-Id").map(|v| v.as_str()),
+            Some("r-1")
+        );
+        assert_eq!(
+            info.trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(info.rrt, 200);
+
+        // the body of the first response, then a HEAD response without a body and a
+        // chunked error response with a trailer
+        let responses = [
+            body,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 512\r\n\r\n",
+            b"HTTP/1.1 503 Service Unavailable\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"4\r\nbusy\r\n0\r\nX-Retry: 1\r\n\r\n",
+        ]
+        .concat();
+        let infos = parse(&mut parser, &responses, &at(S2C, 400));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].method.as_deref(), Some("HEAD"));
+        assert_eq!(infos[0].response_content_length, Some(512));
+        assert_eq!(infos[1].method.as_deref(), Some("POST"));
+        assert_eq!(infos[1].status, L7ResponseStatus::ServerError);
+        assert_eq!(parser.streams[S2C].state, State::Head);
+        assert!(parser.streams[S2C].buffer.is_empty());
+    }
+
+    #[test]
+    fn continue_and_upgrade() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = Http1Log::default();
+        let request = b"PUT /files/a HTTP/1.1\r\nContent-Length: 4\r\nExpect: 100-continue\r\n\r\n";
+        parse(&mut parser, request, &at(C2S, 0));
+        assert!(parse(&mut parser, b"HTTP/1.1 100 Continue\r\n\r\n", &at(S2C, 10)).is_empty());
+        parse(&mut parser, b"data", &at(C2S, 20));
+        let info = parse(
+            &mut parser,
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            &at(S2C, 30),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!((info.status_code, info.rrt), (Some(404), 30));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // a response without length is delimited by the connection close
+        parse(&mut parser, b"GET / HTTP/1.0\r\n\r\n", &at(C2S, 40));
+        let info = parse(
+            &mut parser,
+            b"HTTP/1.0 200 OK\r\n\r\nHTTP/1.1 200 OK\r\n\r\n",
+            &at(S2C, 50),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.version, "1.0");
+        assert_eq!(parser.streams[S2C].state, State::UntilClose);
+
+        let mut parser = Http1Log::default();
+        let request = b"GET /ws HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+        parse(&mut parser, request, &at(C2S, 0));
+        let response =
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n\x81\x05hello";
+        let info = parse(&mut parser, response, &at(S2C, 10)).pop().unwrap();
+        assert_eq!(info.status_code, Some(101));
+        assert!(parse(&mut parser, b"\x81\x85GET / HTTP/1.1\r\n\r\n", &at(C2S, 20)).is_empty());
+        assert!(parser.pending.is_empty());
+
+        let mut parser = Http1Log::default();
+        assert!(parser
+            .parse_payload(b"BREW /pot HTTP/1.1\r\n\r\n", &at(C2S, 0))
+            .is_err());
+    }
+
+    #[test]
+    fn timeout_keeps_pipelined_order() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = Http1Log::default();
+        parse(&mut parser, b"HEAD /a HTTP/1.1\r\n\r\n", &at(C2S, 0));
+        parse(&mut parser, b"GET /b HTTP/1.1\r\n\r\n", &at(C2S, 10));
+        let infos = parse(
+            &mut parser,
+            b"GET /c HTTP/1.1\r\n\r\n",
+            &at(C2S, 5 + SESSION_TIMEOUT),
+        );
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].path.as_deref(), Some("/a"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+        assert_eq!(parser.pending.len(), 3);
+
+        // the late response to HEAD has no body even with a length
+        let responses = concat!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        );
+        let infos = parse(
+            &mut parser,
+            responses.as_bytes(),
+            &at(S2C, 8 + SESSION_TIMEOUT),
+        );
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].msg_type, LogMessageType::Response);
+        assert_eq!(infos[0].path, None);
+        assert_eq!(infos[1].msg_type, LogMessageType::Session);
+        assert_eq!(infos[1].path.as_deref(), Some("/b"));
+        assert_eq!(parser.pending.len(), 1);
+        assert_eq!(parser.timed_out, 0);
+    }
+}
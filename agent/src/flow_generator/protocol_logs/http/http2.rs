@@ -0,0 +1,683 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use loona_hpack::Decoder;
+use public::bytes::read_u32_be;
+use public::l7_protocol::L7Protocol;
+
+use super::{status_code_status, HttpInfo};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus, LogMessageType,
+    LogParserConfig, ParseParam, PendingRequest, PerDirection, Stream as FrameStream,
+    MAX_PENDING_SESSIONS, SESSION_TIMEOUT,
+};
+use crate::flow_generator::{Error, Result};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_SIZE: usize = 9;
+// DATA frames are skipped without buffering, the others are needed whole, and
+// a header block must be decoded to keep the HPACK dynamic table in sync
+const MAX_HEADER_BLOCK_SIZE: usize = 256 * 1024;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_PUSH_PROMISE: u8 = 0x5;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+fn error_code_name(code: u32) -> &'static str {
+    match code {
+        0x0 => "NO_ERROR",
+        0x1 => "PROTOCOL_ERROR",
+        0x2 => "INTERNAL_ERROR",
+        0x3 => "FLOW_CONTROL_ERROR",
+        0x4 => "SETTINGS_TIMEOUT",
+        0x5 => "STREAM_CLOSED",
+        0x6 => "FRAME_SIZE_ERROR",
+        0x7 => "REFUSED_STREAM",
+        0x8 => "CANCEL",
+        0x9 => "COMPRESSION_ERROR",
+        0xa => "CONNECT_ERROR",
+        0xb => "ENHANCE_YOUR_CALM",
+        0xc => "INADEQUATE_SECURITY",
+        0xd => "HTTP_1_1_REQUIRED",
+        _ => "UNKNOWN",
+    }
+}
+
+fn grpc_status(code: u32) -> L7ResponseStatus {
+    match code {
+        0 => L7ResponseStatus::Ok,
+        // CANCELLED, INVALID_ARGUMENT, NOT_FOUND, ALREADY_EXISTS, PERMISSION_DENIED,
+        // FAILED_PRECONDITION, OUT_OF_RANGE, UNAUTHENTICATED
+        1 | 3 | 5 | 6 | 7 | 9 | 11 | 16 => L7ResponseStatus::ClientError,
+        // DEADLINE_EXCEEDED
+        4 => L7ResponseStatus::Timeout,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+// [pad length]?[promised stream id]?[stream dependency, weight]?[fragment][padding]
+fn header_fragment(frame_type: u8, flags: u8, frame: &[u8]) -> Result<(Option<u32>, &[u8])> {
+    let invalid = || Error::HttpLogParseFailed("invalid header frame padding".to_owned());
+    let mut fragment = frame;
+    if flags & FLAG_PADDED != 0 {
+        let (&pad, rest) = fragment.split_first().ok_or_else(invalid)?;
+        fragment = rest
+            .get(..rest.len().wrapping_sub(pad as usize))
+            .ok_or_else(invalid)?;
+    }
+    let mut promised = None;
+    if frame_type == FRAME_PUSH_PROMISE {
+        promised = Some(read_u32_be(fragment.get(..4).ok_or_else(invalid)?) & 0x7fff_ffff);
+        fragment = &fragment[4..];
+    } else if flags & FLAG_PRIORITY != 0 {
+        fragment = fragment.get(5..).ok_or_else(invalid)?;
+    }
+    Ok((promised, fragment))
+}
+
+fn header<'a>(headers: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| *v)
+}
+
+// a header block, possibly continued in CONTINUATION frames
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    // set for PUSH_PROMISE
+    promised: Option<u32>,
+    fragment: Vec<u8>,
+}
+
+struct Direction {
+    // a DATA frame is skipped, the others are buffered whole
+    frames: FrameStream,
+    decoder: Decoder<'static>,
+    // the dynamic table is lost once a header block fails to decode
+    desynchronized: bool,
+    block: Option<HeaderBlock>,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Self {
+            frames: FrameStream::default(),
+            decoder: Decoder::new(),
+            desynchronized: false,
+            block: None,
+        }
+    }
+}
+
+impl Direction {
+    fn decode(&mut self, block: &[u8]) -> Vec<(String, String)> {
+        if self.desynchronized {
+            return vec![];
+        }
+        match self.decoder.decode(block) {
+            Ok(headers) => headers
+                .into_iter()
+                .map(|(k, v)| {
+                    (
+                        String::from_utf8_lossy(&k).into_owned(),
+                        String::from_utf8_lossy(&v).into_owned(),
+                    )
+                })
+                .collect(),
+            Err(e) => {
+                debug!("http2 header block decode failed: {}", e);
+                self.desynchronized = true;
+                vec![]
+            }
+        }
+    }
+}
+
+struct Stream {
+    request: HttpInfo,
+    // response headers, then trailers
+    response: Option<HttpInfo>,
+}
+
+#[derive(Default)]
+pub struct Http2Log {
+    directions: PerDirection<Direction>,
+    streams: HashMap<u32, Stream>,
+}
+
+impl Http2Log {
+    fn parse_frames(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let frames = &mut self.directions[param.direction].frames;
+        let data = frames.data(payload);
+
+        let mut remain = &data[..];
+        if param.direction == PacketDirection::ClientToServer {
+            if remain.starts_with(PREFACE) {
+                remain = &remain[PREFACE.len()..];
+            } else if remain.len() < PREFACE.len() && PREFACE.starts_with(remain) {
+                frames.buffer = remain.to_vec();
+                return Ok(());
+            }
+        }
+        let mut result = Ok(());
+        while remain.len() >= FRAME_HEADER_SIZE {
+            let size = (read_u32_be(remain) >> 8) as usize;
+            let frame_type = remain[3];
+            let flags = remain[4];
+            let stream_id = read_u32_be(&remain[5..]) & 0x7fff_ffff;
+            let total_size = FRAME_HEADER_SIZE + size;
+            let frame = if remain.len() >= total_size {
+                let frame = &remain[FRAME_HEADER_SIZE..total_size];
+                remain = &remain[total_size..];
+                frame
+            } else if frame_type == FRAME_DATA {
+                // only the flags of a DATA frame matter
+                self.directions[param.direction].frames.skip = total_size - remain.len();
+                &mem::take(&mut remain)[FRAME_HEADER_SIZE..]
+            } else if size > MAX_HEADER_BLOCK_SIZE {
+                return Err(Error::HttpLogParseFailed(format!(
+                    "frame type {} too large: {}",
+                    frame_type, size
+                )));
+            } else {
+                break;
+            };
+            if let Err(e) = self.handle_frame(frame_type, flags, stream_id, frame, param, output) {
+                debug!("skip http2 frame: {}", e);
+                result = Err(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.directions[param.direction].frames.buffer = remain.to_vec();
+        }
+        result
+    }
+
+    fn handle_frame(
+        &mut self,
+        frame_type: u8,
+        flags: u8,
+        stream_id: u32,
+        frame: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let d = &mut self.directions[param.direction];
+        let block = match frame_type {
+            FRAME_DATA if flags & FLAG_END_STREAM != 0 => {
+                self.end_stream(stream_id, param, output);
+                return Ok(());
+            }
+            FRAME_HEADERS | FRAME_PUSH_PROMISE => {
+                let (promised, fragment) = header_fragment(frame_type, flags, frame)?;
+                HeaderBlock {
+                    stream_id,
+                    end_stream: flags & FLAG_END_STREAM != 0,
+                    promised,
+                    fragment: fragment.to_vec(),
+                }
+            }
+            FRAME_CONTINUATION => {
+                let Some(mut block) = d.block.take().filter(|b| b.stream_id == stream_id) else {
+                    return Err(Error::HttpLogParseFailed(format!(
+                        "unexpected continuation on stream {}",
+                        stream_id
+                    )));
+                };
+                if block.fragment.len() + frame.len() > MAX_HEADER_BLOCK_SIZE {
+                    d.desynchronized = true;
+                    return Err(Error::HttpLogParseFailed(
+                        "header block too large".to_owned(),
+                    ));
+                }
+                block.fragment.extend_from_slice(frame);
+                block
+            }
+            FRAME_RST_STREAM if frame.len() >= 4 => {
+                self.reset_stream(stream_id, read_u32_be(frame), param, output);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        if flags & FLAG_END_HEADERS == 0 {
+            d.block = Some(block);
+            return Ok(());
+        }
+        let headers = d.decode(&block.fragment);
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        match (block.promised, param.direction) {
+            // a pushed response is requested by the promise
+            (Some(promised), _) => self.start_request(promised, &headers, param, output),
+            (None, PacketDirection::ClientToServer) => {
+                // the others are request trailers
+                if !self.streams.contains_key(&block.stream_id) {
+                    self.start_request(block.stream_id, &headers, param, output);
+                }
+            }
+            (None, PacketDirection::ServerToClient) => {
+                self.response_headers(block.stream_id, &headers, param.parse_config);
+            }
+        }
+        if block.end_stream {
+            self.end_stream(block.stream_id, param, output);
+        }
+        Ok(())
+    }
+
+    fn start_request(
+        &mut self,
+        stream_id: u32,
+        headers: &[(&str, &str)],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let is_grpc =
+            header(headers, "content-type").is_some_and(|t| t.starts_with("application/grpc"));
+        let mut info = HttpInfo {
+            msg_type: LogMessageType::Request,
+            protocol: if is_grpc {
+                L7Protocol::Grpc
+            } else {
+                L7Protocol::Http2
+            },
+            version: "2",
+            stream_id: Some(stream_id),
+            method: header(headers, ":method").map(str::to_owned),
+            path: header(headers, ":path").map(str::to_owned),
+            time: param.time,
+            ..Default::default()
+        };
+        info.fill_headers(headers, param.parse_config);
+        if let Some((service, method)) = info
+            .path
+            .as_deref()
+            .filter(|_| is_grpc)
+            .and_then(|p| p.strip_prefix('/')?.split_once('/'))
+        {
+            info.grpc_service = Some(service.to_owned());
+            info.grpc_method = Some(method.to_owned());
+        }
+
+        if self.streams.len() >= MAX_PENDING_SESSIONS {
+            output.push(L7ProtocolInfo::HttpInfo(info));
+        } else {
+            self.streams.insert(
+                stream_id,
+                Stream {
+                    request: info,
+                    response: None,
+                },
+            );
+        }
+    }
+
+    fn response_headers(
+        &mut self,
+        stream_id: u32,
+        headers: &[(&str, &str)],
+        config: Option<&LogParserConfig>,
+    ) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        let code = header(headers, ":status").and_then(|c| c.parse::<u16>().ok());
+        let response = match stream.response.as_mut() {
+            Some(response) => response,
+            // informational responses are followed by the final one
+            None if code.is_some_and(|c| (100..200).contains(&c)) => return,
+            None => {
+                let mut response = HttpInfo {
+                    msg_type: LogMessageType::Response,
+                    status_code: code,
+                    ..Default::default()
+                };
+                response.fill_headers(headers, config);
+                stream.response.insert(response)
+            }
+        };
+        // in the headers of a trailers-only response, otherwise in the trailers
+        if let Some(status) = header(headers, "grpc-status").and_then(|s| s.parse().ok()) {
+            response.grpc_status = Some(status);
+            response.grpc_message = header(headers, "grpc-message").map(str::to_owned);
+        }
+        if let Some(config) = config {
+            for key in config.http_extra_headers.iter() {
+                if let Some(value) = header(headers, key) {
+                    response.extra_headers.insert(key.clone(), value.to_owned());
+                }
+            }
+        }
+        response.status = match (response.grpc_status, response.status_code) {
+            (Some(status), _) => grpc_status(status),
+            (None, Some(code)) => status_code_status(code),
+            _ => L7ResponseStatus::Unknown,
+        };
+    }
+
+    // a stream is logged once the server ends it, the client ending it only ends the request
+    fn end_stream(&mut self, stream_id: u32, param: &ParseParam, output: &mut Vec<L7ProtocolInfo>) {
+        if param.direction == PacketDirection::ClientToServer {
+            return;
+        }
+        let Some(Stream {
+            mut request,
+            response,
+        }) = self.streams.remove(&stream_id)
+        else {
+            return;
+        };
+        let mut response = response.unwrap_or_else(|| HttpInfo {
+            status: L7ResponseStatus::Unknown,
+            ..Default::default()
+        });
+        response.time = param.time;
+        request.merge(response);
+        output.push(L7ProtocolInfo::HttpInfo(request));
+    }
+
+    fn reset_stream(
+        &mut self,
+        stream_id: u32,
+        code: u32,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let Some(Stream {
+            mut request,
+            response,
+        }) = self.streams.remove(&stream_id)
+        else {
+            return;
+        };
+        let mut response = response.unwrap_or_default();
+        response.time = param.time;
+        response.reset_error = Some(error_code_name(code));
+        response.status = match (code, param.direction) {
+            (0, _) => response.status,
+            (_, PacketDirection::ClientToServer) => L7ResponseStatus::ClientError,
+            (_, PacketDirection::ServerToClient) => L7ResponseStatus::ServerError,
+        };
+        request.merge(response);
+        output.push(L7ProtocolInfo::HttpInfo(request));
+    }
+
+    fn expire_pending(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        self.streams.retain(|_, stream| {
+            if now.saturating_sub(stream.request.time) < SESSION_TIMEOUT {
+                return true;
+            }
+            output.push(mem::take(&mut stream.request).into_timeout());
+            false
+        });
+    }
+}
+
+impl L7ProtocolParserInterface for Http2Log {
+    // prior knowledge or h2 over TLS, both start with the client connection preface
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        param.direction == PacketDirection::ClientToServer && payload.starts_with(PREFACE)
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        self.expire_pending(param.time, &mut output);
+        if let Err(e) = self.parse_frames(payload, param, &mut output) {
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Http2
+    }
+
+    fn reset(&mut self) {
+        self.directions = Default::default();
+        self.streams.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use loona_hpack::Encoder;
+
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn frame(frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut f = ((payload.len() as u32) << 8 | frame_type as u32)
+            .to_be_bytes()
+            .to_vec();
+        f.push(flags);
+        f.extend_from_slice(&stream_id.to_be_bytes());
+        f.extend_from_slice(payload);
+        f
+    }
+
+    fn encode(encoder: &mut Encoder, headers: &[(&str, &str)]) -> Vec<u8> {
+        encoder.encode(headers.iter().map(|(k, v)| (k.as_bytes(), v.as_bytes())))
+    }
+
+    fn grpc_request(encoder: &mut Encoder, stream_id: u32) -> Vec<u8> {
+        let block = encode(
+            encoder,
+            &[
+                (":method", "POST"),
+                (":scheme", "http"),
+                (":path", "/helloworld.Greeter/SayHello"),
+                (":authority", "greeter:50051"),
+                ("content-type", "application/grpc"),
+                ("user-agent", "grpc-go/1.59.0"),
+                ("te", "trailers"),
+            ],
+        );
+        // padded, with the block continued in a CONTINUATION frame
+        let mut headers = vec![2];
+        headers.extend_from_slice(&block[..10]);
+        headers.extend_from_slice(&[0, 0]);
+        [
+            frame(FRAME_HEADERS, FLAG_PADDED, stream_id, &headers),
+            frame(
+                FRAME_CONTINUATION,
+                FLAG_END_HEADERS,
+                stream_id,
+                &block[10..],
+            ),
+            frame(
+                FRAME_DATA,
+                FLAG_END_STREAM,
+                stream_id,
+                b"\0\0\0\0\x07\n\x05world",
+            ),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn grpc_unary_calls() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = Http2Log::default();
+        let (mut client, mut server) = (Encoder::new(), Encoder::new());
+        let settings = frame(0x4, 0, 0, &[0, 0x3, 0, 0, 0, 100]);
+        let first = [PREFACE, &settings, &grpc_request(&mut client, 1)].concat();
+        assert!(parser.check_payload(&first, &at(C2S, 0)));
+        for segment in first.chunks(5) {
+            assert!(parse(&mut parser, segment, &at(C2S, 100)).is_empty());
+        }
+
+        let headers = encode(
+            &mut server,
+            &[(":status", "200"), ("content-type", "application/grpc")],
+        );
+        let trailers = encode(&mut server, &[("grpc-status", "0")]);
+        let response = [
+            frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, &headers),
+            frame(FRAME_DATA, 0, 1, b"\0\0\0\0\x0d\n\x0bHello world"),
+            frame(
+                FRAME_HEADERS,
+                FLAG_END_HEADERS | FLAG_END_STREAM,
+                1,
+                &trailers,
+            ),
+        ]
+        .concat();
+        let info = parse(&mut parser, &response, &at(S2C, 400)).pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.protocol, L7Protocol::Grpc);
+        assert_eq!((info.version, info.stream_id), ("2", Some(1)));
+        assert_eq!(info.method.as_deref(), Some("POST"));
+        assert_eq!(info.host.as_deref(), Some("greeter:50051"));
+        assert_eq!(info.user_agent.as_deref(), Some("grpc-go/1.59.0"));
+        assert_eq!(info.grpc_service.as_deref(), Some("helloworld.Greeter"));
+        assert_eq!(info.grpc_method.as_deref(), Some("SayHello"));
+        assert_eq!((info.status_code, info.grpc_status), (Some(200), Some(0)));
+        assert_eq!((info.status, info.rrt), (L7ResponseStatus::Ok, 300));
+
+        // the second call is encoded with the dynamic tables of the first
+        let request = grpc_request(&mut client, 3);
+        assert!(request.len() < first.len() - PREFACE.len() - settings.len());
+        parse(&mut parser, &request, &at(C2S, 500));
+        // trailers-only
+        let headers = encode(
+            &mut server,
+            &[
+                (":status", "200"),
+                ("grpc-status", "5"),
+                ("grpc-message", "no such user"),
+            ],
+        );
+        let response = frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            3,
+            &headers,
+        );
+        let info = parse(&mut parser, &response, &at(S2C, 550)).pop().unwrap();
+        assert_eq!(info.path.as_deref(), Some("/helloworld.Greeter/SayHello"));
+        assert_eq!(info.grpc_status, Some(5));
+        assert_eq!(info.grpc_message.as_deref(), Some("no such user"));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+    }
+
+    #[test]
+    fn reset_and_desync() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = Http2Log::default();
+        let (mut client, mut server) = (Encoder::new(), Encoder::new());
+        let get = |encoder: &mut Encoder, path| {
+            encode(
+                encoder,
+                &[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", path),
+                    (":authority", "shop"),
+                ],
+            )
+        };
+        let request = [
+            PREFACE,
+            &frame(
+                FRAME_HEADERS,
+                FLAG_END_HEADERS | FLAG_END_STREAM,
+                1,
+                &get(&mut client, "/a"),
+            ),
+            &frame(
+                FRAME_HEADERS,
+                FLAG_END_HEADERS | FLAG_END_STREAM,
+                3,
+                &get(&mut client, "/b"),
+            ),
+        ]
+        .concat();
+        parse(&mut parser, &request, &at(C2S, 0));
+
+        // a large body, then the client cancels the other stream
+        let headers = encode(
+            &mut server,
+            &[(":status", "200"), ("content-length", "65536")],
+        );
+        let response = [
+            frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, &headers),
+            frame(FRAME_DATA, FLAG_END_STREAM, 1, &vec![0; 65536]),
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in response.chunks(1400) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 100)));
+        }
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].protocol, L7Protocol::Http2);
+        assert_eq!(infos[0].response_content_length, Some(65536));
+        let info = parse(
+            &mut parser,
+            &frame(FRAME_RST_STREAM, 0, 3, &[0, 0, 0, 8]),
+            &at(C2S, 200),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.path.as_deref(), Some("/b"));
+        assert_eq!(info.reset_error, Some("CANCEL"));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // a truncated dynamic table size update loses the table of the client
+        parse(
+            &mut parser,
+            &frame(FRAME_HEADERS, FLAG_END_HEADERS, 5, &[0x3f]),
+            &at(C2S, 300),
+        );
+        assert!(parser.directions[C2S].desynchronized);
+        let request = frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            7,
+            &get(&mut client, "/c"),
+        );
+        parse(&mut parser, &request, &at(C2S, 400));
+        let headers = encode(&mut server, &[(":status", "503")]);
+        let response = frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            7,
+            &headers,
+        );
+        let info = parse(&mut parser, &response, &at(S2C, 450)).pop().unwrap();
+        assert_eq!((info.stream_id, info.path.as_deref()), (Some(7), None));
+        assert_eq!(info.status, L7ResponseStatus::ServerError);
+    }
+}
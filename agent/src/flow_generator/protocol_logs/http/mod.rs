@@ -0,0 +1,121 @@
+mod http1;
+mod http2;
+
+pub use http1::Http1Log;
+pub use http2::Http2Log;
+
+use std::collections::BTreeMap;
+
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{trace::TraceContext, L7ResponseStatus, LogMessageType, LogParserConfig};
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct HttpInfo {
+    pub msg_type: LogMessageType,
+    // Http1, Http2, or Grpc for HTTP/2 with a gRPC content type
+    pub protocol: L7Protocol,
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_content_length: Option<u64>,
+    // split from a gRPC path of /{service}/{method}
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_method: Option<String>,
+    // headers configured in http_extra_headers, from the request or the response
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_headers: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_content_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // from the trailers of a gRPC response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_status: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_message: Option<String>,
+    // error code of a RST_STREAM ending the stream
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_error: Option<&'static str>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl HttpInfo {
+    // well-known and configured headers, names are matched case insensitively
+    fn fill_headers(&mut self, headers: &[(&str, &str)], config: Option<&LogParserConfig>) {
+        let header = |key: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| *v)
+        };
+        let is_request = self.msg_type == LogMessageType::Request;
+        if is_request {
+            self.host = header("host")
+                .or_else(|| header(":authority"))
+                .map(str::to_owned);
+            self.user_agent = header("user-agent").map(str::to_owned);
+            self.request_content_length = header("content-length").and_then(|v| v.parse().ok());
+        } else {
+            self.response_content_length = header("content-length").and_then(|v| v.parse().ok());
+            self.content_type = header("content-type").map(str::to_owned);
+        }
+        let Some(config) = config else {
+            return;
+        };
+        for key in config.http_extra_headers.iter() {
+            if let Some(value) = header(key) {
+                self.extra_headers.insert(key.clone(), value.to_owned());
+            }
+        }
+        if is_request {
+            self.trace = TraceContext::extract(config, header);
+        }
+    }
+
+    fn merge(&mut self, response: HttpInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.status_code = response.status_code;
+        self.response_content_length = response.response_content_length;
+        self.content_type = response.content_type;
+        self.grpc_status = response.grpc_status;
+        self.grpc_message = response.grpc_message;
+        self.reset_error = response.reset_error;
+        self.status = response.status;
+        for (key, value) in response.extra_headers {
+            self.extra_headers.entry(key).or_insert(value);
+        }
+    }
+}
+
+fn status_code_status(code: u16) -> L7ResponseStatus {
+    match code {
+        400..=499 => L7ResponseStatus::ClientError,
+        500..=599 => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::Ok,
+    }
+}
@@ -0,0 +1,183 @@
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::dns::DnsInfo;
+use super::http::HttpInfo;
+use super::mq::{
+    AmqpInfo, KafkaInfo, MqttInfo, NatsInfo, PulsarConsumerMetrics, PulsarInfo,
+    PulsarTopologyEvent, PulsarTransaction, RocketmqInfo,
+};
+use super::rpc::{DubboInfo, ThriftInfo};
+use super::sql::{MemcachedInfo, MongodbInfo, MysqlInfo, PostgresqlInfo, RedisInfo};
+use super::tls::TlsInfo;
+use super::trace::TraceType;
+use super::zookeeper::ZookeeperInfo;
+use super::{L7ResponseStatus, PendingRequest};
+use crate::common::flow::PacketDirection;
+use crate::config::config::L7LogConfig;
+use crate::flow_generator::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogParserConfig {
+    pub trace_types: Vec<TraceType>,
+    pub span_types: Vec<TraceType>,
+    pub expand_batch: bool,
+    pub max_decompressed_size: usize,
+    pub http_extra_headers: Vec<String>,
+    pub sql_max_length: usize,
+    pub redis_key_obfuscation: bool,
+    pub mongodb_command_summary: bool,
+}
+
+impl From<&L7LogConfig> for LogParserConfig {
+    fn from(config: &L7LogConfig) -> Self {
+        Self {
+            trace_types: config
+                .trace_id_keys
+                .iter()
+                .map(|k| k.as_str().into())
+                .collect(),
+            span_types: config
+                .span_id_keys
+                .iter()
+                .map(|k| k.as_str().into())
+                .collect(),
+            expand_batch: config.message_batch_expansion,
+            max_decompressed_size: config.max_decompressed_size,
+            http_extra_headers: config.http_extra_headers.clone(),
+            sql_max_length: config.sql_max_length,
+            redis_key_obfuscation: config.redis_key_obfuscation,
+            mongodb_command_summary: config.mongodb_command_summary,
+        }
+    }
+}
+
+impl Default for LogParserConfig {
+    fn default() -> Self {
+        (&L7LogConfig::default()).into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseParam<'a> {
+    pub direction: PacketDirection,
+    // unit: microseconds
+    pub time: u64,
+    pub parse_config: Option<&'a LogParserConfig>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum L7ProtocolInfo {
+    HttpInfo(HttpInfo),
+    AmqpInfo(AmqpInfo),
+    KafkaInfo(KafkaInfo),
+    MqttInfo(MqttInfo),
+    NatsInfo(NatsInfo),
+    PulsarInfo(PulsarInfo),
+    PulsarTransaction(PulsarTransaction),
+    PulsarConsumerMetrics(PulsarConsumerMetrics),
+    PulsarTopologyEvent(PulsarTopologyEvent),
+    RocketmqInfo(RocketmqInfo),
+    DnsInfo(DnsInfo),
+    MysqlInfo(MysqlInfo),
+    PostgresqlInfo(PostgresqlInfo),
+    RedisInfo(RedisInfo),
+    MongodbInfo(MongodbInfo),
+    DubboInfo(DubboInfo),
+    ThriftInfo(ThriftInfo),
+    TlsInfo(TlsInfo),
+    MemcachedInfo(MemcachedInfo),
+    ZookeeperInfo(ZookeeperInfo),
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum L7ParseResult {
+    Single(L7ProtocolInfo),
+    Multi(Vec<L7ProtocolInfo>),
+    None,
+}
+
+impl L7ParseResult {
+    pub fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
+
+    pub fn unwrap_single(self) -> L7ProtocolInfo {
+        match self {
+            Self::Single(info) => info,
+            Self::Multi(_) => panic!("parse result is multi but unwrap single"),
+            Self::None => panic!("parse result is none but unwrap single"),
+        }
+    }
+
+    pub fn unwrap_multi(self) -> Vec<L7ProtocolInfo> {
+        match self {
+            Self::Multi(infos) => infos,
+            Self::Single(_) => panic!("parse result is single but unwrap multi"),
+            Self::None => panic!("parse result is none but unwrap multi"),
+        }
+    }
+}
+
+impl From<Vec<L7ProtocolInfo>> for L7ParseResult {
+    fn from(mut infos: Vec<L7ProtocolInfo>) -> Self {
+        match infos.len() {
+            0 => Self::None,
+            1 => Self::Single(infos.pop().unwrap()),
+            _ => Self::Multi(infos),
+        }
+    }
+}
+
+macro_rules! impl_pending_request {
+    ($($info:ident),+) => {
+        $(
+            impl PendingRequest for $info {
+                fn time(&self) -> u64 {
+                    self.time
+                }
+
+                fn into_timeout(mut self) -> L7ProtocolInfo {
+                    self.status = L7ResponseStatus::Timeout;
+                    L7ProtocolInfo::$info(self)
+                }
+            }
+        )+
+    };
+}
+
+impl_pending_request!(
+    HttpInfo,
+    AmqpInfo,
+    KafkaInfo,
+    MqttInfo,
+    NatsInfo,
+    PulsarInfo,
+    RocketmqInfo,
+    DnsInfo,
+    MysqlInfo,
+    PostgresqlInfo,
+    RedisInfo,
+    MongodbInfo,
+    DubboInfo,
+    ThriftInfo,
+    MemcachedInfo,
+    ZookeeperInfo
+);
+
+pub trait L7ProtocolParserInterface {
+    // cheap protocol identification on the first payload of a flow
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool;
+    // parsers keep per-flow state, so every payload of the flow must be fed in order
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult>;
+    fn protocol(&self) -> L7Protocol;
+    // drop per-flow state such as buffered partial frames
+    fn reset(&mut self) {}
+    // called once the flow ends, returns records held back waiting for more traffic
+    fn on_flow_end(&mut self) -> L7ParseResult {
+        L7ParseResult::None
+    }
+}
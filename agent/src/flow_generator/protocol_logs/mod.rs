@@ -0,0 +1,304 @@
+pub mod dns;
+pub mod http;
+mod l7_protocol_log;
+pub mod mq;
+pub mod rpc;
+pub mod sql;
+pub mod tls;
+pub mod trace;
+pub mod zookeeper;
+
+pub use l7_protocol_log::{
+    L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, LogParserConfig, ParseParam,
+};
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::mem;
+use std::ops::{Index, IndexMut};
+
+use public::bytes::{read_u16_be, read_u16_le, read_u32_be, read_u32_le, read_u64_be, read_u64_le};
+use serde::Serialize;
+
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::{Error, Result};
+
+// requests without a response after this are reported as timeout, unit: microseconds
+const SESSION_TIMEOUT: u64 = 120_000_000;
+const MAX_PENDING_SESSIONS: usize = 1024;
+
+// a cursor over a message, integers are in network byte order unless suffixed with _le,
+// reading past the end fails with InsufficientPayloadLength
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(Error::InsufficientPayloadLength);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        mem::take(&mut self.data)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.data
+            .first()
+            .copied()
+            .ok_or(Error::InsufficientPayloadLength)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(read_u16_be(self.take(2)?))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(read_u32_be(self.take(4)?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(read_u64_be(self.take(8)?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(self.u64()? as i64)
+    }
+
+    fn u16_le(&mut self) -> Result<u16> {
+        Ok(read_u16_le(self.take(2)?))
+    }
+
+    fn u32_le(&mut self) -> Result<u32> {
+        Ok(read_u32_le(self.take(4)?))
+    }
+
+    fn i32_le(&mut self) -> Result<i32> {
+        Ok(self.u32_le()? as i32)
+    }
+
+    fn i64_le(&mut self) -> Result<i64> {
+        Ok(read_u64_le(self.take(8)?) as i64)
+    }
+
+    // a string ended by a nul byte, which is skipped
+    fn cstring(&mut self) -> Result<&'a [u8]> {
+        let end = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::InsufficientPayloadLength)?;
+        let s = &self.data[..end];
+        self.data = &self.data[end + 1..];
+        Ok(s)
+    }
+}
+
+// state kept for each direction of a flow
+#[derive(Default)]
+struct PerDirection<T>([T; 2]);
+
+impl<T> Index<PacketDirection> for PerDirection<T> {
+    type Output = T;
+
+    fn index(&self, direction: PacketDirection) -> &T {
+        &self.0[direction as usize]
+    }
+}
+
+impl<T> IndexMut<PacketDirection> for PerDirection<T> {
+    fn index_mut(&mut self, direction: PacketDirection) -> &mut T {
+        &mut self.0[direction as usize]
+    }
+}
+
+// one direction of a protocol over tcp, where messages are cut at segment ends
+#[derive(Default)]
+struct Stream {
+    // bytes of an incomplete message head
+    buffer: Vec<u8>,
+    // bytes left of a message cut after its head
+    skip: usize,
+}
+
+impl Stream {
+    // the buffered bytes followed by the payload, less the bytes to skip
+    fn data(&mut self, payload: &[u8]) -> Vec<u8> {
+        let skipped = self.skip.min(payload.len());
+        self.skip -= skipped;
+        let payload = &payload[skipped..];
+        if self.buffer.is_empty() {
+            payload.to_vec()
+        } else {
+            let mut data = mem::take(&mut self.buffer);
+            data.extend_from_slice(payload);
+            data
+        }
+    }
+}
+
+// a request kept by a parser until its response arrives
+trait PendingRequest: Default {
+    fn time(&self) -> u64;
+    fn into_timeout(self) -> L7ProtocolInfo;
+}
+
+// reports the requests left without a response for SESSION_TIMEOUT
+fn expire_pending<K: Eq + Hash, T: PendingRequest>(
+    pending: &mut HashMap<K, T>,
+    now: u64,
+    output: &mut Vec<L7ProtocolInfo>,
+) {
+    pending.retain(|_, request| {
+        if now.saturating_sub(request.time()) < SESSION_TIMEOUT {
+            return true;
+        }
+        output.push(mem::take(request).into_timeout());
+        false
+    });
+}
+
+// as expire_pending, for requests answered in order
+fn expire_pending_in_order<T: PendingRequest>(
+    pending: &mut VecDeque<T>,
+    now: u64,
+    output: &mut Vec<L7ProtocolInfo>,
+) {
+    while pending
+        .front()
+        .is_some_and(|r| now.saturating_sub(r.time()) >= SESSION_TIMEOUT)
+    {
+        output.push(pending.pop_front().unwrap().into_timeout());
+    }
+}
+
+// queues a request answered in order, once full the oldest one is reported as a timeout
+fn push_pending<T: PendingRequest>(
+    pending: &mut VecDeque<T>,
+    request: T,
+    output: &mut Vec<L7ProtocolInfo>,
+) {
+    if pending.len() >= MAX_PENDING_SESSIONS {
+        output.push(pending.pop_front().unwrap().into_timeout());
+    }
+    pending.push_back(request);
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogMessageType {
+    Request,
+    Response,
+    Session,
+    #[default]
+    Other,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum L7ResponseStatus {
+    #[default]
+    Ok,
+    Timeout,
+    ServerError,
+    ClientError,
+    Unknown,
+}
+
+#[cfg(test)]
+mod test_util {
+    use super::dns::{DnsInfo, DnsLog};
+    use super::http::{Http1Log, Http2Log, HttpInfo};
+    use super::mq::{
+        AmqpInfo, AmqpLog, KafkaInfo, KafkaLog, MqttInfo, MqttLog, NatsInfo, NatsLog,
+        RocketmqInfo, RocketmqLog,
+    };
+    use super::rpc::{DubboInfo, DubboLog, ThriftInfo, ThriftLog};
+    use super::sql::{
+        MemcachedInfo, MemcachedLog, MongodbInfo, MongodbLog, MysqlInfo, MysqlLog,
+        PostgresqlInfo, PostgresqlLog, RedisInfo, RedisLog,
+    };
+    use super::tls::{TlsInfo, TlsLog};
+    use super::zookeeper::{ZookeeperInfo, ZookeeperLog};
+    use super::{L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, ParseParam};
+
+    // a parser and the info it logs
+    pub(super) trait LogParser: L7ProtocolParserInterface {
+        type Info;
+
+        fn info(info: L7ProtocolInfo) -> Option<Self::Info>;
+    }
+
+    macro_rules! impl_log_parser {
+        ($($parser:ident => $info:ident),+) => {
+            $(
+                impl LogParser for $parser {
+                    type Info = $info;
+
+                    fn info(info: L7ProtocolInfo) -> Option<$info> {
+                        match info {
+                            L7ProtocolInfo::$info(info) => Some(info),
+                            _ => None,
+                        }
+                    }
+                }
+            )+
+        };
+    }
+
+    impl_log_parser!(
+        Http1Log => HttpInfo,
+        Http2Log => HttpInfo,
+        AmqpLog => AmqpInfo,
+        KafkaLog => KafkaInfo,
+        MqttLog => MqttInfo,
+        NatsLog => NatsInfo,
+        RocketmqLog => RocketmqInfo,
+        DnsLog => DnsInfo,
+        MysqlLog => MysqlInfo,
+        PostgresqlLog => PostgresqlInfo,
+        RedisLog => RedisInfo,
+        MongodbLog => MongodbInfo,
+        DubboLog => DubboInfo,
+        ThriftLog => ThriftInfo,
+        TlsLog => TlsInfo,
+        MemcachedLog => MemcachedInfo,
+        ZookeeperLog => ZookeeperInfo
+    );
+
+    // the infos logged for a payload, which must parse
+    pub(super) fn parse<P: LogParser>(
+        parser: &mut P,
+        payload: &[u8],
+        param: &ParseParam,
+    ) -> Vec<P::Info> {
+        let infos = match parser.parse_payload(payload, param).unwrap() {
+            L7ParseResult::Single(info) => vec![info],
+            L7ParseResult::Multi(infos) => infos,
+            L7ParseResult::None => vec![],
+        };
+        infos
+            .into_iter()
+            .map(|i| P::info(i).expect("an info of another protocol"))
+            .collect()
+    }
+}
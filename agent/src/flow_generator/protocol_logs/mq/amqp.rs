@@ -0,0 +1,1320 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::{read_u16_be, read_u32_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+const FRAME_HEADER_SIZE: usize = 7;
+const FRAME_END: u8 = 0xce;
+// well above the frame_max brokers agree on, 128KiB by default in RabbitMQ
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+// method arguments and content properties are small, so the rest of a larger
+// frame such as a message body is skipped instead of buffered
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const REPLY_SUCCESS: u16 = 200;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Method = 1,
+    Header = 2,
+    Body = 3,
+    Heartbeat = 8,
+    #[default]
+    Unknown = 0,
+}
+
+impl From<u8> for FrameType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => Self::Method,
+            2 => Self::Header,
+            3 => Self::Body,
+            8 => Self::Heartbeat,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    ConnectionStart,
+    ConnectionStartOk,
+    ConnectionSecure,
+    ConnectionSecureOk,
+    ConnectionTune,
+    ConnectionTuneOk,
+    ConnectionOpen,
+    ConnectionOpenOk,
+    ConnectionClose,
+    ConnectionCloseOk,
+    ConnectionBlocked,
+    ConnectionUnblocked,
+    ConnectionUpdateSecret,
+    ConnectionUpdateSecretOk,
+    ChannelOpen,
+    ChannelOpenOk,
+    ChannelFlow,
+    ChannelFlowOk,
+    ChannelClose,
+    ChannelCloseOk,
+    ExchangeDeclare,
+    ExchangeDeclareOk,
+    ExchangeDelete,
+    ExchangeDeleteOk,
+    ExchangeBind,
+    ExchangeBindOk,
+    ExchangeUnbind,
+    ExchangeUnbindOk,
+    QueueDeclare,
+    QueueDeclareOk,
+    QueueBind,
+    QueueBindOk,
+    QueuePurge,
+    QueuePurgeOk,
+    QueueDelete,
+    QueueDeleteOk,
+    QueueUnbind,
+    QueueUnbindOk,
+    BasicQos,
+    BasicQosOk,
+    BasicConsume,
+    BasicConsumeOk,
+    BasicCancel,
+    BasicCancelOk,
+    BasicPublish,
+    BasicReturn,
+    BasicDeliver,
+    BasicGet,
+    BasicGetOk,
+    BasicGetEmpty,
+    BasicAck,
+    BasicReject,
+    BasicRecoverAsync,
+    BasicRecover,
+    BasicRecoverOk,
+    BasicNack,
+    ConfirmSelect,
+    ConfirmSelectOk,
+    TxSelect,
+    TxSelectOk,
+    TxCommit,
+    TxCommitOk,
+    TxRollback,
+    TxRollbackOk,
+    #[default]
+    Unknown,
+}
+
+// (class id, method id, method)
+const METHODS: &[(u16, u16, Method)] = &[
+    (10, 10, Method::ConnectionStart),
+    (10, 11, Method::ConnectionStartOk),
+    (10, 20, Method::ConnectionSecure),
+    (10, 21, Method::ConnectionSecureOk),
+    (10, 30, Method::ConnectionTune),
+    (10, 31, Method::ConnectionTuneOk),
+    (10, 40, Method::ConnectionOpen),
+    (10, 41, Method::ConnectionOpenOk),
+    (10, 50, Method::ConnectionClose),
+    (10, 51, Method::ConnectionCloseOk),
+    (10, 60, Method::ConnectionBlocked),
+    (10, 61, Method::ConnectionUnblocked),
+    (10, 70, Method::ConnectionUpdateSecret),
+    (10, 71, Method::ConnectionUpdateSecretOk),
+    (20, 10, Method::ChannelOpen),
+    (20, 11, Method::ChannelOpenOk),
+    (20, 20, Method::ChannelFlow),
+    (20, 21, Method::ChannelFlowOk),
+    (20, 40, Method::ChannelClose),
+    (20, 41, Method::ChannelCloseOk),
+    (40, 10, Method::ExchangeDeclare),
+    (40, 11, Method::ExchangeDeclareOk),
+    (40, 20, Method::ExchangeDelete),
+    (40, 21, Method::ExchangeDeleteOk),
+    (40, 30, Method::ExchangeBind),
+    (40, 31, Method::ExchangeBindOk),
+    (40, 40, Method::ExchangeUnbind),
+    (40, 51, Method::ExchangeUnbindOk),
+    (50, 10, Method::QueueDeclare),
+    (50, 11, Method::QueueDeclareOk),
+    (50, 20, Method::QueueBind),
+    (50, 21, Method::QueueBindOk),
+    (50, 30, Method::QueuePurge),
+    (50, 31, Method::QueuePurgeOk),
+    (50, 40, Method::QueueDelete),
+    (50, 41, Method::QueueDeleteOk),
+    (50, 50, Method::QueueUnbind),
+    (50, 51, Method::QueueUnbindOk),
+    (60, 10, Method::BasicQos),
+    (60, 11, Method::BasicQosOk),
+    (60, 20, Method::BasicConsume),
+    (60, 21, Method::BasicConsumeOk),
+    (60, 30, Method::BasicCancel),
+    (60, 31, Method::BasicCancelOk),
+    (60, 40, Method::BasicPublish),
+    (60, 50, Method::BasicReturn),
+    (60, 60, Method::BasicDeliver),
+    (60, 70, Method::BasicGet),
+    (60, 71, Method::BasicGetOk),
+    (60, 72, Method::BasicGetEmpty),
+    (60, 80, Method::BasicAck),
+    (60, 90, Method::BasicReject),
+    (60, 100, Method::BasicRecoverAsync),
+    (60, 110, Method::BasicRecover),
+    (60, 111, Method::BasicRecoverOk),
+    (60, 120, Method::BasicNack),
+    (85, 10, Method::ConfirmSelect),
+    (85, 11, Method::ConfirmSelectOk),
+    (90, 10, Method::TxSelect),
+    (90, 11, Method::TxSelectOk),
+    (90, 20, Method::TxCommit),
+    (90, 21, Method::TxCommitOk),
+    (90, 30, Method::TxRollback),
+    (90, 31, Method::TxRollbackOk),
+];
+
+impl Method {
+    fn from_ids(class_id: u16, method_id: u16) -> Self {
+        METHODS
+            .iter()
+            .find(|(c, m, _)| *c == class_id && *m == method_id)
+            .map(|(_, _, method)| *method)
+            .unwrap_or_default()
+    }
+
+    // synchronous methods answered by a reply on the same channel, unless sent with no-wait
+    fn expects_reply(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectionStart
+                | Self::ConnectionSecure
+                | Self::ConnectionTune
+                | Self::ConnectionOpen
+                | Self::ConnectionClose
+                | Self::ConnectionUpdateSecret
+                | Self::ChannelOpen
+                | Self::ChannelFlow
+                | Self::ChannelClose
+                | Self::ExchangeDeclare
+                | Self::ExchangeDelete
+                | Self::ExchangeBind
+                | Self::ExchangeUnbind
+                | Self::QueueDeclare
+                | Self::QueueBind
+                | Self::QueuePurge
+                | Self::QueueDelete
+                | Self::QueueUnbind
+                | Self::BasicQos
+                | Self::BasicConsume
+                | Self::BasicCancel
+                | Self::BasicGet
+                | Self::BasicRecover
+                | Self::ConfirmSelect
+                | Self::TxSelect
+                | Self::TxCommit
+                | Self::TxRollback
+        )
+    }
+
+    // method id of the request a reply answers, which is the one before it except for
+    // Exchange.UnbindOk and the two replies of Basic.Get
+    fn request_id(&self, method_id: u16) -> Option<u16> {
+        match self {
+            Self::ExchangeUnbindOk => Some(40),
+            Self::BasicGetOk | Self::BasicGetEmpty => Some(70),
+            Self::ConnectionStartOk
+            | Self::ConnectionSecureOk
+            | Self::ConnectionTuneOk
+            | Self::ConnectionOpenOk
+            | Self::ConnectionCloseOk
+            | Self::ConnectionUpdateSecretOk
+            | Self::ChannelOpenOk
+            | Self::ChannelFlowOk
+            | Self::ChannelCloseOk
+            | Self::ExchangeDeclareOk
+            | Self::ExchangeDeleteOk
+            | Self::ExchangeBindOk
+            | Self::QueueDeclareOk
+            | Self::QueueBindOk
+            | Self::QueuePurgeOk
+            | Self::QueueDeleteOk
+            | Self::QueueUnbindOk
+            | Self::BasicQosOk
+            | Self::BasicConsumeOk
+            | Self::BasicCancelOk
+            | Self::BasicRecoverOk
+            | Self::ConfirmSelectOk
+            | Self::TxSelectOk
+            | Self::TxCommitOk
+            | Self::TxRollbackOk => Some(method_id - 1),
+            _ => None,
+        }
+    }
+
+    // followed by a content header and body frames
+    fn has_content(&self) -> bool {
+        matches!(
+            self,
+            Self::BasicPublish | Self::BasicReturn | Self::BasicDeliver | Self::BasicGetOk
+        )
+    }
+}
+
+// reply codes of Connection.Close, Channel.Close and Basic.Return
+fn reply_status(code: u16) -> L7ResponseStatus {
+    match code {
+        REPLY_SUCCESS => L7ResponseStatus::Ok,
+        // connection-forced, resource-error, not-implemented, internal-error
+        320 | 506 | 540 | 541 => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+trait AmqpRead<'a> {
+    fn short_string(&mut self) -> Result<String>;
+    fn long_bytes(&mut self) -> Result<&'a [u8]>;
+    fn table(&mut self) -> Result<Vec<Header>>;
+    fn field_value(&mut self) -> Result<Option<String>>;
+}
+
+impl<'a> AmqpRead<'a> for Reader<'a> {
+    fn short_string(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn long_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    // nested tables and arrays are skipped, they do not carry trace ids
+    fn table(&mut self) -> Result<Vec<Header>> {
+        let mut t = Reader {
+            data: self.long_bytes()?,
+        };
+        let mut headers = vec![];
+        while !t.data.is_empty() {
+            let key = t.short_string()?;
+            if let Some(value) = t.field_value()? {
+                headers.push(Header { key, value });
+            }
+        }
+        Ok(headers)
+    }
+
+    // field types as implemented by RabbitMQ and most clients
+    fn field_value(&mut self) -> Result<Option<String>> {
+        let value = match self.u8()? {
+            b't' => (self.u8()? != 0).to_string(),
+            b'b' => (self.u8()? as i8).to_string(),
+            b'B' => self.u8()?.to_string(),
+            b's' => (self.u16()? as i16).to_string(),
+            b'u' => self.u16()?.to_string(),
+            b'I' => (self.u32()? as i32).to_string(),
+            b'i' => self.u32()?.to_string(),
+            b'l' => (self.u64()? as i64).to_string(),
+            b'f' => f32::from_bits(self.u32()?).to_string(),
+            b'd' => f64::from_bits(self.u64()?).to_string(),
+            b'D' => {
+                let scale = self.u8()?;
+                let value = self.u32()? as i32;
+                format!("{}e-{}", value, scale)
+            }
+            b'S' => String::from_utf8_lossy(self.long_bytes()?).into_owned(),
+            b'T' => self.u64()?.to_string(),
+            b'V' => String::new(),
+            b'x' | b'F' | b'A' => {
+                self.long_bytes()?;
+                return Ok(None);
+            }
+            t => {
+                return Err(Error::AmqpLogParseFailed(format!(
+                    "unknown field type {:?}",
+                    t as char
+                )))
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub key: String,
+    pub value: String,
+}
+
+// basic class properties of a content header
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContentProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_mode: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+}
+
+impl ContentProperties {
+    // [class id][weight][body size][property flags][property list]
+    fn parse(r: &mut Reader) -> Result<(u64, Self)> {
+        r.u16()?; // class id
+        r.u16()?; // weight
+        let body_size = r.u64()?;
+        let flags = r.u16()?;
+        let has = |bit: u16| flags & (1 << bit) != 0;
+        let mut p = ContentProperties::default();
+        if has(15) {
+            p.content_type = Some(r.short_string()?);
+        }
+        if has(14) {
+            p.content_encoding = Some(r.short_string()?);
+        }
+        if has(13) {
+            p.headers = r.table()?;
+        }
+        if has(12) {
+            p.delivery_mode = Some(r.u8()?);
+        }
+        if has(11) {
+            p.priority = Some(r.u8()?);
+        }
+        if has(10) {
+            p.correlation_id = Some(r.short_string()?);
+        }
+        if has(9) {
+            p.reply_to = Some(r.short_string()?);
+        }
+        if has(8) {
+            p.expiration = Some(r.short_string()?);
+        }
+        if has(7) {
+            p.message_id = Some(r.short_string()?);
+        }
+        if has(6) {
+            p.timestamp = Some(r.u64()?);
+        }
+        if has(5) {
+            p.message_type = Some(r.short_string()?);
+        }
+        if has(4) {
+            p.user_id = Some(r.short_string()?);
+        }
+        if has(3) {
+            p.app_id = Some(r.short_string()?);
+        }
+        Ok((body_size, p))
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct AmqpInfo {
+    pub msg_type: LogMessageType,
+    pub channel: u16,
+    pub method: Method,
+    // size of the method frame payload
+    pub frame_size: u32,
+    // from Connection.Open, filled in on every method of the connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exchange_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_tag: Option<String>,
+    // the publish sequence number for publisher confirms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_tag: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiple: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redelivered: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefetch_count: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_count: Option<u32>,
+
+    // Basic.Publish, Return, Deliver and GetOk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<ContentProperties>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a reply is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_method: Option<Method>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_text: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    #[serde(skip)]
+    class_id: u16,
+    #[serde(skip)]
+    method_id: u16,
+    #[serde(skip)]
+    no_wait: bool,
+    // the method that caused a Connection.Close or Channel.Close
+    #[serde(skip)]
+    failed_method: Option<(u16, u16)>,
+}
+
+impl AmqpInfo {
+    // [class id][method id][arguments]
+    fn parse_method(channel: u16, frame: &[u8], frame_size: u32) -> Result<Self> {
+        let mut r = Reader { data: frame };
+        let class_id = r.u16()?;
+        let method_id = r.u16()?;
+        let method = Method::from_ids(class_id, method_id);
+        if method == Method::Unknown {
+            return Err(Error::AmqpLogParseFailed(format!(
+                "unknown method {}.{}",
+                class_id, method_id
+            )));
+        }
+        let mut info = AmqpInfo {
+            msg_type: if method.expects_reply() || method == Method::BasicPublish {
+                LogMessageType::Request
+            } else if method.request_id(method_id).is_some() {
+                LogMessageType::Response
+            } else {
+                LogMessageType::Other
+            },
+            channel,
+            method,
+            frame_size,
+            class_id,
+            method_id,
+            ..Default::default()
+        };
+        if let Err(e) = info.parse_arguments(&mut r) {
+            debug!("skip amqp {:?} arguments: {}", method, e);
+        }
+        Ok(info)
+    }
+
+    fn parse_arguments(&mut self, r: &mut Reader) -> Result<()> {
+        match self.method {
+            Method::ConnectionOpen => self.vhost = Some(r.short_string()?),
+            Method::ConnectionClose | Method::ChannelClose | Method::BasicReturn => {
+                let code = r.u16()?;
+                self.reply_code = Some(code);
+                self.reply_text = Some(r.short_string()?);
+                self.status = reply_status(code);
+                if self.method == Method::BasicReturn {
+                    self.exchange = Some(r.short_string()?);
+                    self.routing_key = Some(r.short_string()?);
+                } else if code != REPLY_SUCCESS {
+                    self.failed_method = Some((r.u16()?, r.u16()?));
+                }
+            }
+            Method::ExchangeDeclare => {
+                r.u16()?; // reserved
+                self.exchange = Some(r.short_string()?);
+                self.exchange_type = Some(r.short_string()?);
+                // passive, durable, auto-delete, internal, no-wait
+                self.no_wait = r.u8()? & 0x10 != 0;
+            }
+            Method::ExchangeDelete => {
+                r.u16()?;
+                self.exchange = Some(r.short_string()?);
+                // if-unused, no-wait
+                self.no_wait = r.u8()? & 0x02 != 0;
+            }
+            Method::ExchangeBind | Method::ExchangeUnbind => {
+                r.u16()?;
+                r.short_string()?; // destination
+                self.exchange = Some(r.short_string()?);
+                self.routing_key = Some(r.short_string()?);
+                self.no_wait = r.u8()? & 0x01 != 0;
+            }
+            Method::QueueDeclare => {
+                r.u16()?;
+                self.queue = Some(r.short_string()?).filter(|q| !q.is_empty());
+                // passive, durable, exclusive, auto-delete, no-wait
+                self.no_wait = r.u8()? & 0x10 != 0;
+            }
+            Method::QueueDeclareOk => {
+                self.queue = Some(r.short_string()?);
+                self.message_count = Some(r.u32()?);
+                self.consumer_count = Some(r.u32()?);
+            }
+            Method::QueueBind | Method::QueueUnbind => {
+                r.u16()?;
+                self.queue = Some(r.short_string()?);
+                self.exchange = Some(r.short_string()?);
+                self.routing_key = Some(r.short_string()?);
+                if self.method == Method::QueueBind {
+                    self.no_wait = r.u8()? & 0x01 != 0;
+                }
+            }
+            Method::QueuePurge | Method::QueueDelete => {
+                r.u16()?;
+                self.queue = Some(r.short_string()?);
+                // no-wait for purge, if-unused, if-empty, no-wait for delete
+                let mask = if self.method == Method::QueuePurge {
+                    0x01
+                } else {
+                    0x04
+                };
+                self.no_wait = r.u8()? & mask != 0;
+            }
+            Method::QueuePurgeOk | Method::QueueDeleteOk => {
+                self.message_count = Some(r.u32()?);
+            }
+            Method::BasicQos => {
+                r.u32()?; // prefetch size
+                self.prefetch_count = Some(r.u16()?);
+            }
+            Method::BasicConsume => {
+                r.u16()?;
+                self.queue = Some(r.short_string()?);
+                self.consumer_tag = Some(r.short_string()?).filter(|t| !t.is_empty());
+                // no-local, no-ack, exclusive, no-wait
+                self.no_wait = r.u8()? & 0x08 != 0;
+            }
+            Method::BasicConsumeOk | Method::BasicCancelOk => {
+                self.consumer_tag = Some(r.short_string()?);
+            }
+            Method::BasicCancel => {
+                self.consumer_tag = Some(r.short_string()?);
+                self.no_wait = r.u8()? & 0x01 != 0;
+            }
+            Method::BasicPublish => {
+                r.u16()?;
+                self.exchange = Some(r.short_string()?);
+                self.routing_key = Some(r.short_string()?);
+            }
+            Method::BasicDeliver => {
+                self.consumer_tag = Some(r.short_string()?);
+                self.delivery_tag = Some(r.u64()?);
+                self.redelivered = Some(r.u8()? & 0x01 != 0);
+                self.exchange = Some(r.short_string()?);
+                self.routing_key = Some(r.short_string()?);
+            }
+            Method::BasicGet => {
+                r.u16()?;
+                self.queue = Some(r.short_string()?);
+            }
+            Method::BasicGetOk => {
+                self.delivery_tag = Some(r.u64()?);
+                self.redelivered = Some(r.u8()? & 0x01 != 0);
+                self.exchange = Some(r.short_string()?);
+                self.routing_key = Some(r.short_string()?);
+                self.message_count = Some(r.u32()?);
+            }
+            Method::BasicAck | Method::BasicNack | Method::BasicReject => {
+                self.delivery_tag = Some(r.u64()?);
+                // Basic.Reject has requeue only
+                if self.method != Method::BasicReject {
+                    self.multiple = Some(r.u8()? & 0x01 != 0);
+                }
+            }
+            Method::ConfirmSelect => self.no_wait = r.u8()? & 0x01 != 0,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn extract_trace(&mut self, config: &LogParserConfig) {
+        let Some(properties) = self.properties.as_ref() else {
+            return;
+        };
+        self.trace = TraceContext::extract(config, |key| {
+            properties
+                .headers
+                .iter()
+                .find(|h| h.key.eq_ignore_ascii_case(key))
+                .map(|h| h.value.as_str())
+        });
+    }
+
+    fn merge(&mut self, response: AmqpInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_method = Some(response.method);
+        self.reply_code = response.reply_code;
+        self.reply_text = response.reply_text;
+        self.status = response.status;
+        // server-named queues and consumer tags
+        if response.queue.is_some() {
+            self.queue = response.queue;
+        }
+        if self.consumer_tag.is_none() {
+            self.consumer_tag = response.consumer_tag;
+        }
+        if response.message_count.is_some() {
+            self.message_count = response.message_count;
+            self.consumer_count = response.consumer_count;
+        }
+        if self.method == Method::BasicGet {
+            self.exchange = response.exchange;
+            self.routing_key = response.routing_key;
+            self.delivery_tag = response.delivery_tag;
+            self.redelivered = response.redelivered;
+            self.body_size = response.body_size;
+            self.properties = response.properties;
+            self.trace = response.trace;
+        }
+    }
+}
+
+// how a reply finds its request on the same connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SessionKey {
+    // only one synchronous method may be in flight on a channel
+    Method {
+        channel: u16,
+        class_id: u16,
+        method_id: u16,
+    },
+    // publishes acknowledged by the broker in confirm mode
+    Confirm {
+        channel: u16,
+        delivery_tag: u64,
+    },
+}
+
+#[derive(Default)]
+struct Channel {
+    confirm: bool,
+    // publish sequence number in confirm mode, starting at 1
+    publish_seq: u64,
+}
+
+#[derive(Default)]
+pub struct AmqpLog {
+    streams: PerDirection<Stream>,
+    vhost: Option<String>,
+    channels: HashMap<u16, Channel>,
+    // methods waiting for their content header, by direction and channel
+    contents: HashMap<(PacketDirection, u16), AmqpInfo>,
+    pending: HashMap<SessionKey, AmqpInfo>,
+}
+
+impl AmqpLog {
+    fn parse_frames(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<AmqpInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= FRAME_HEADER_SIZE {
+            // sent by the client first, and by the server to reject the version
+            if remain.starts_with(&PROTOCOL_HEADER[..4]) {
+                if remain.len() < PROTOCOL_HEADER.len() {
+                    break;
+                }
+                remain = &remain[PROTOCOL_HEADER.len()..];
+                continue;
+            }
+            let frame_type = FrameType::from(remain[0]);
+            let channel = read_u16_be(&remain[1..]);
+            let size = read_u32_be(&remain[3..]) as usize;
+            let total_size = FRAME_HEADER_SIZE + size + 1;
+            let invalid = if frame_type == FrameType::Unknown || size > MAX_FRAME_SIZE {
+                Some(format!("invalid frame type {} or size {}", remain[0], size))
+            } else if remain.len() >= total_size && remain[total_size - 1] != FRAME_END {
+                Some("missing frame end".to_owned())
+            } else {
+                None
+            };
+            if let Some(reason) = invalid {
+                // the rest of the segment can not be framed, keep what is parsed
+                debug!("drop amqp segment: {}", reason);
+                last_error = Some(Error::AmqpLogParseFailed(reason));
+                remain = &[];
+                break;
+            }
+            let frame = if remain.len() >= total_size {
+                let frame = &remain[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + size];
+                remain = &remain[total_size..];
+                frame
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[FRAME_HEADER_SIZE..]
+            } else {
+                break;
+            };
+            if let Err(e) = self.parse_frame(frame_type, channel, frame, size, param, &mut infos) {
+                debug!("skip amqp frame: {}", e);
+                last_error = Some(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn parse_frame(
+        &mut self,
+        frame_type: FrameType,
+        channel: u16,
+        frame: &[u8],
+        size: usize,
+        param: &ParseParam,
+        infos: &mut Vec<AmqpInfo>,
+    ) -> Result<()> {
+        let key = (param.direction, channel);
+        match frame_type {
+            FrameType::Method => {
+                let mut info = AmqpInfo::parse_method(channel, frame, size as u32)?;
+                info.time = param.time;
+                if info.method == Method::ConnectionOpen {
+                    self.vhost = info.vhost.clone();
+                }
+                if info.vhost.is_none() {
+                    info.vhost = self.vhost.clone();
+                }
+                // the previous content method on the channel never got its header
+                if let Some(prev) = self.contents.remove(&key) {
+                    infos.push(prev);
+                }
+                if info.method.has_content() {
+                    self.contents.insert(key, info);
+                } else {
+                    infos.push(info);
+                }
+            }
+            FrameType::Header => {
+                let Some(mut info) = self.contents.remove(&key) else {
+                    return Ok(());
+                };
+                let (body_size, properties) =
+                    ContentProperties::parse(&mut Reader { data: frame })?;
+                info.body_size = Some(body_size);
+                info.properties = Some(properties);
+                if let Some(config) = param.parse_config {
+                    info.extract_trace(config);
+                }
+                infos.push(info);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        mut info: AmqpInfo,
+        direction: PacketDirection,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let channel = info.channel;
+        match info.method {
+            Method::ChannelOpen => {
+                self.channels.insert(channel, Channel::default());
+            }
+            Method::ConfirmSelect => {
+                self.channels.entry(channel).or_default().confirm = true;
+            }
+            Method::BasicPublish => {
+                if let Some(c) = self.channels.get_mut(&channel).filter(|c| c.confirm) {
+                    c.publish_seq += 1;
+                    info.delivery_tag = Some(c.publish_seq);
+                }
+            }
+            Method::BasicAck | Method::BasicNack
+                if direction == PacketDirection::ServerToClient
+                    && self.channels.get(&channel).is_some_and(|c| c.confirm) =>
+            {
+                self.settle_confirms(info, output);
+                return;
+            }
+            Method::ConnectionClose | Method::ChannelClose => self.fail_method(&info, output),
+            Method::ChannelCloseOk => {
+                self.channels.remove(&channel);
+            }
+            _ => (),
+        }
+
+        let key = if info.method == Method::BasicPublish {
+            info.delivery_tag.map(|delivery_tag| SessionKey::Confirm {
+                channel,
+                delivery_tag,
+            })
+        } else if info.method.expects_reply() && !info.no_wait {
+            Some(SessionKey::Method {
+                channel,
+                class_id: info.class_id,
+                method_id: info.method_id,
+            })
+        } else {
+            info.method
+                .request_id(info.method_id)
+                .map(|method_id| SessionKey::Method {
+                    channel,
+                    class_id: info.class_id,
+                    method_id,
+                })
+        };
+        let Some(key) = key else {
+            output.push(L7ProtocolInfo::AmqpInfo(info));
+            return;
+        };
+        match info.msg_type {
+            LogMessageType::Request => {
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::AmqpInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    output.push(L7ProtocolInfo::AmqpInfo(prev));
+                }
+            }
+            _ => match self.pending.remove(&key) {
+                Some(mut request) => {
+                    request.merge(info);
+                    output.push(L7ProtocolInfo::AmqpInfo(request));
+                }
+                None => output.push(L7ProtocolInfo::AmqpInfo(info)),
+            },
+        }
+    }
+
+    // Basic.Ack or Basic.Nack from the broker settles one publish, or with `multiple`
+    // every publish up to the delivery tag, where tag 0 means all outstanding
+    fn settle_confirms(&mut self, mut ack: AmqpInfo, output: &mut Vec<L7ProtocolInfo>) {
+        let tag = ack.delivery_tag.unwrap_or_default();
+        let multiple = ack.multiple.unwrap_or_default();
+        if ack.method == Method::BasicNack {
+            ack.status = L7ResponseStatus::ServerError;
+        }
+        let mut settled = self
+            .pending
+            .keys()
+            .filter(|k| match k {
+                SessionKey::Confirm {
+                    channel,
+                    delivery_tag,
+                } => {
+                    *channel == ack.channel
+                        && (*delivery_tag == tag || multiple && (tag == 0 || *delivery_tag < tag))
+                }
+                _ => false,
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        if settled.is_empty() {
+            output.push(L7ProtocolInfo::AmqpInfo(ack));
+            return;
+        }
+        settled.sort_by_key(|k| match k {
+            SessionKey::Confirm { delivery_tag, .. } => *delivery_tag,
+            _ => 0,
+        });
+        for key in settled {
+            let mut request = self.pending.remove(&key).unwrap();
+            request.merge(ack.clone());
+            output.push(L7ProtocolInfo::AmqpInfo(request));
+        }
+    }
+
+    // a close with an error code is the reply to the method that caused it,
+    // which for a connection error may be on any channel
+    fn fail_method(&mut self, close: &AmqpInfo, output: &mut Vec<L7ProtocolInfo>) {
+        let Some((class_id, method_id)) = close.failed_method else {
+            return;
+        };
+        let key = self.pending.keys().copied().find(|k| match k {
+            SessionKey::Method {
+                channel,
+                class_id: c,
+                method_id: m,
+            } => {
+                *c == class_id
+                    && *m == method_id
+                    && (close.method == Method::ConnectionClose || *channel == close.channel)
+            }
+            _ => false,
+        });
+        if let Some(mut request) = key.and_then(|k| self.pending.remove(&k)) {
+            request.merge(close.clone());
+            output.push(L7ProtocolInfo::AmqpInfo(request));
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for AmqpLog {
+    // a connection starts with the protocol header from the client
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        param.direction == PacketDirection::ClientToServer && payload.starts_with(PROTOCOL_HEADER)
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_frames(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, param.direction, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::AMQP
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.vhost = None;
+        self.channels.clear();
+        self.contents.clear();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    fn short(s: &str) -> Vec<u8> {
+        [vec![s.len() as u8], s.as_bytes().to_vec()].concat()
+    }
+
+    fn frame(frame_type: u8, channel: u16, payload: &[u8]) -> Vec<u8> {
+        let mut f = vec![frame_type];
+        f.extend_from_slice(&channel.to_be_bytes());
+        f.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        f.extend_from_slice(payload);
+        f.push(FRAME_END);
+        f
+    }
+
+    fn method(channel: u16, class_id: u16, method_id: u16, args: &[&[u8]]) -> Vec<u8> {
+        let mut payload = class_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&method_id.to_be_bytes());
+        payload.extend_from_slice(&args.concat());
+        frame(1, channel, &payload)
+    }
+
+    // content type, headers and delivery mode
+    fn content_header(channel: u16, body_size: u64, headers: &[(&str, &str)]) -> Vec<u8> {
+        let mut table = vec![];
+        for (k, v) in headers {
+            table.extend_from_slice(&short(k));
+            table.push(b'S');
+            table.extend_from_slice(&(v.len() as u32).to_be_bytes());
+            table.extend_from_slice(v.as_bytes());
+        }
+        let mut payload = vec![0, 60, 0, 0];
+        payload.extend_from_slice(&body_size.to_be_bytes());
+        payload.extend_from_slice(&0xb000u16.to_be_bytes());
+        payload.extend_from_slice(&short("application/json"));
+        payload.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&table);
+        payload.push(2);
+        frame(2, channel, &payload)
+    }
+
+    fn publish(channel: u16, routing_key: &str, headers: &[(&str, &str)]) -> Vec<u8> {
+        [
+            method(
+                channel,
+                60,
+                40,
+                &[&[0, 0], &short("orders"), &short(routing_key), &[0]],
+            ),
+            content_header(channel, 2, headers),
+            frame(3, channel, b"{}"),
+        ]
+        .concat()
+    }
+
+    fn open_channel(parser: &mut AmqpLog, channel: u16) {
+        parse(parser, &method(channel, 20, 10, &[&[0]]), &at(C2S, 0));
+        let infos = parse(
+            parser,
+            &method(channel, 20, 11, &[&[0, 0, 0, 0]]),
+            &at(S2C, 10),
+        );
+        assert_eq!(infos[0].method, Method::ChannelOpen);
+    }
+
+    #[test]
+    fn connection_and_failed_declare() {
+        let mut parser = AmqpLog::default();
+        assert!(parser.check_payload(PROTOCOL_HEADER, &at(C2S, 0)));
+        assert!(!parser.check_payload(PROTOCOL_HEADER, &at(S2C, 0)));
+        assert!(parse(&mut parser, PROTOCOL_HEADER, &at(C2S, 0)).is_empty());
+
+        // Connection.Start from the server is answered by the client
+        let start = method(
+            0,
+            10,
+            10,
+            &[
+                &[0, 9],
+                &[0, 0, 0, 0],
+                &[0, 0, 0, 5],
+                b"PLAIN",
+                &[0, 0, 0, 5],
+                b"en_US",
+            ],
+        );
+        assert!(parse(&mut parser, &start, &at(S2C, 100)).is_empty());
+        let start_ok = method(
+            0,
+            10,
+            11,
+            &[
+                &[0, 0, 0, 0],
+                &short("PLAIN"),
+                &[0, 0, 0, 0],
+                &short("en_US"),
+            ],
+        );
+        let infos = parse(&mut parser, &start_ok, &at(C2S, 150));
+        assert_eq!(infos[0].method, Method::ConnectionStart);
+        assert_eq!(infos[0].rrt, 50);
+
+        let open = method(0, 10, 40, &[&short("/prod"), &short(""), &[0]]);
+        parse(&mut parser, &open, &at(C2S, 200));
+        let infos = parse(
+            &mut parser,
+            &method(0, 10, 41, &[&short("")]),
+            &at(S2C, 300),
+        );
+        assert_eq!(infos[0].response_method, Some(Method::ConnectionOpenOk));
+        assert_eq!(infos[0].vhost.as_deref(), Some("/prod"));
+
+        open_channel(&mut parser, 1);
+        let declare = method(1, 50, 10, &[&[0, 0], &short(""), &[0x02], &[0, 0, 0, 0]]);
+        parse(&mut parser, &declare, &at(C2S, 400));
+        let declare_ok = method(
+            1,
+            50,
+            11,
+            &[&short("amq.gen-1"), &[0, 0, 0, 3], &[0, 0, 0, 1]],
+        );
+        let info = parse(&mut parser, &declare_ok, &at(S2C, 500))
+            .pop()
+            .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.queue.as_deref(), Some("amq.gen-1"));
+        assert_eq!(
+            (info.message_count, info.consumer_count),
+            (Some(3), Some(1))
+        );
+        assert_eq!(info.vhost.as_deref(), Some("/prod"));
+
+        // a passive declare of a missing exchange closes the channel
+        let declare = method(
+            1,
+            40,
+            10,
+            &[
+                &[0, 0],
+                &short("missing"),
+                &short("topic"),
+                &[0x01],
+                &[0, 0, 0, 0],
+            ],
+        );
+        parse(&mut parser, &declare, &at(C2S, 600));
+        let text = "NOT_FOUND - no exchange 'missing' in vhost '/prod'";
+        let close = method(1, 20, 40, &[&[1, 148], &short(text), &[0, 40], &[0, 10]]);
+        let info = parse(&mut parser, &close, &at(S2C, 700)).pop().unwrap();
+        assert_eq!(info.method, Method::ExchangeDeclare);
+        assert_eq!(info.response_method, Some(Method::ChannelClose));
+        assert_eq!(info.exchange.as_deref(), Some("missing"));
+        assert_eq!(info.reply_code, Some(404));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+        let info = parse(&mut parser, &method(1, 20, 41, &[]), &at(C2S, 800))
+            .pop()
+            .unwrap();
+        assert_eq!(info.method, Method::ChannelClose);
+        assert_eq!(info.rrt, 100);
+
+        // no reply to a no-wait bind
+        let bind = method(
+            2,
+            50,
+            20,
+            &[
+                &[0, 0],
+                &short("q"),
+                &short("orders"),
+                &short("eu.#"),
+                &[1],
+                &[0, 0, 0, 0],
+            ],
+        );
+        let info = parse(&mut parser, &bind, &at(C2S, 900)).pop().unwrap();
+        assert_eq!(info.method, Method::QueueBind);
+        assert_eq!(info.routing_key.as_deref(), Some("eu.#"));
+    }
+
+    #[test]
+    fn publisher_confirms() {
+        let mut parser = AmqpLog::default();
+        let config = LogParserConfig::default();
+        let param = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        open_channel(&mut parser, 1);
+        parse(&mut parser, &method(1, 85, 10, &[&[0]]), &param(C2S, 100));
+        let infos = parse(&mut parser, &method(1, 85, 11, &[]), &param(S2C, 200));
+        assert_eq!(infos[0].method, Method::ConfirmSelect);
+
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let segment = [
+            publish(1, "eu.created", &[("traceparent", traceparent)]),
+            publish(1, "eu.updated", &[]),
+            publish(1, "us.created", &[]),
+        ]
+        .concat();
+        // the content header arrives in the next segment
+        assert!(parse(&mut parser, &segment[..20], &param(C2S, 300)).is_empty());
+        assert!(parse(&mut parser, &segment[20..], &param(C2S, 300)).is_empty());
+
+        let ack = method(1, 60, 80, &[&2u64.to_be_bytes(), &[1]]);
+        let infos = parse(&mut parser, &ack, &param(S2C, 500));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].delivery_tag, Some(1));
+        assert_eq!(infos[0].routing_key.as_deref(), Some("eu.created"));
+        assert_eq!(infos[0].exchange.as_deref(), Some("orders"));
+        assert_eq!(infos[0].body_size, Some(2));
+        assert_eq!(
+            infos[0].trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        let properties = infos[0].properties.as_ref().unwrap();
+        assert_eq!(properties.content_type.as_deref(), Some("application/json"));
+        assert_eq!(properties.delivery_mode, Some(2));
+        assert_eq!(infos[1].routing_key.as_deref(), Some("eu.updated"));
+        assert_eq!((infos[1].rrt, infos[1].status), (200, L7ResponseStatus::Ok));
+
+        let nack = method(1, 60, 120, &[&3u64.to_be_bytes(), &[0]]);
+        let info = parse(&mut parser, &nack, &param(S2C, 600)).pop().unwrap();
+        assert_eq!(info.response_method, Some(Method::BasicNack));
+        assert_eq!(info.routing_key.as_deref(), Some("us.created"));
+        assert_eq!(info.status, L7ResponseStatus::ServerError);
+
+        // without confirm mode, publishes are logged as they are sent
+        open_channel(&mut parser, 2);
+        let info = parse(
+            &mut parser,
+            &publish(2, "eu.created", &[]),
+            &param(C2S, 700),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Request);
+        assert_eq!(info.delivery_tag, None);
+
+        // frames before one without its end octet are kept
+        let mut broken = frame(3, 2, b"{}");
+        *broken.last_mut().unwrap() = 0;
+        let segment = [publish(2, "eu.deleted", &[]), broken].concat();
+        let infos = parse(&mut parser, &segment, &param(C2S, 750));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].routing_key.as_deref(), Some("eu.deleted"));
+
+        // a delivery with a body larger than the buffered head
+        let deliver = method(
+            2,
+            60,
+            60,
+            &[
+                &short("ctag-1"),
+                &7u64.to_be_bytes(),
+                &[1],
+                &short("orders"),
+                &short("eu.created"),
+            ],
+        );
+        let body = frame(3, 2, &vec![b'x'; MAX_HEAD_SIZE * 2]);
+        let payload = [
+            deliver,
+            content_header(2, (MAX_HEAD_SIZE * 2) as u64, &[]),
+            body,
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in payload.chunks(1400) {
+            infos.extend(parse(&mut parser, segment, &param(S2C, 800)));
+        }
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].method, Method::BasicDeliver);
+        assert_eq!(infos[0].consumer_tag.as_deref(), Some("ctag-1"));
+        assert_eq!(
+            (infos[0].delivery_tag, infos[0].redelivered),
+            (Some(7), Some(true))
+        );
+        assert_eq!(infos[0].body_size, Some((MAX_HEAD_SIZE * 2) as u64));
+        let ack = method(2, 60, 80, &[&7u64.to_be_bytes(), &[0]]);
+        let info = parse(&mut parser, &ack, &param(C2S, 900)).pop().unwrap();
+        assert_eq!(info.method, Method::BasicAck);
+        assert_eq!(info.msg_type, LogMessageType::Other);
+    }
+}
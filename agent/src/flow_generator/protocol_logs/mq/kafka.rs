@@ -0,0 +1,1170 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use log::debug;
+use public::bytes::{read_u16_be, read_u32_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus,
+    LogMessageType, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// default socket.request.max.bytes of the broker
+const MAX_MESSAGE_SIZE: usize = 100 * 1024 * 1024;
+// topics and partitions are at the head of a message, so the rest of a larger
+// message such as a fetch response is skipped instead of buffered
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// api_key, api_version, correlation_id and the client_id length
+const MIN_REQUEST_HEADER_SIZE: usize = 10;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i16)]
+pub enum ApiKey {
+    Produce = 0,
+    Fetch = 1,
+    ListOffsets = 2,
+    Metadata = 3,
+    OffsetCommit = 8,
+    OffsetFetch = 9,
+    FindCoordinator = 10,
+    JoinGroup = 11,
+    Heartbeat = 12,
+    LeaveGroup = 13,
+    SyncGroup = 14,
+    DescribeGroups = 15,
+    ListGroups = 16,
+    SaslHandshake = 17,
+    ApiVersions = 18,
+    CreateTopics = 19,
+    DeleteTopics = 20,
+    InitProducerId = 22,
+    AddPartitionsToTxn = 24,
+    AddOffsetsToTxn = 25,
+    EndTxn = 26,
+    TxnOffsetCommit = 28,
+    SaslAuthenticate = 36,
+    #[default]
+    Unknown = -1,
+}
+
+impl From<i16> for ApiKey {
+    fn from(v: i16) -> Self {
+        match v {
+            0 => Self::Produce,
+            1 => Self::Fetch,
+            2 => Self::ListOffsets,
+            3 => Self::Metadata,
+            8 => Self::OffsetCommit,
+            9 => Self::OffsetFetch,
+            10 => Self::FindCoordinator,
+            11 => Self::JoinGroup,
+            12 => Self::Heartbeat,
+            13 => Self::LeaveGroup,
+            14 => Self::SyncGroup,
+            15 => Self::DescribeGroups,
+            16 => Self::ListGroups,
+            17 => Self::SaslHandshake,
+            18 => Self::ApiVersions,
+            19 => Self::CreateTopics,
+            20 => Self::DeleteTopics,
+            22 => Self::InitProducerId,
+            24 => Self::AddPartitionsToTxn,
+            25 => Self::AddOffsetsToTxn,
+            26 => Self::EndTxn,
+            28 => Self::TxnOffsetCommit,
+            36 => Self::SaslAuthenticate,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl ApiKey {
+    // highest known version and the first flexible version, from the message json schemas
+    fn versions(&self) -> (i16, Option<i16>) {
+        match self {
+            Self::Produce => (11, Some(9)),
+            Self::Fetch => (16, Some(12)),
+            Self::ListOffsets => (9, Some(6)),
+            Self::Metadata => (12, Some(9)),
+            Self::OffsetCommit => (9, Some(8)),
+            Self::OffsetFetch => (9, Some(6)),
+            Self::FindCoordinator => (5, Some(3)),
+            Self::JoinGroup => (9, Some(6)),
+            Self::Heartbeat => (4, Some(4)),
+            Self::LeaveGroup => (5, Some(4)),
+            Self::SyncGroup => (5, Some(4)),
+            Self::DescribeGroups => (5, Some(5)),
+            Self::ListGroups => (5, Some(3)),
+            Self::SaslHandshake => (1, None),
+            Self::ApiVersions => (4, Some(3)),
+            Self::CreateTopics => (7, Some(5)),
+            Self::DeleteTopics => (6, Some(4)),
+            Self::InitProducerId => (5, Some(2)),
+            Self::AddPartitionsToTxn => (5, Some(3)),
+            Self::AddOffsetsToTxn => (4, Some(3)),
+            Self::EndTxn => (4, Some(3)),
+            Self::TxnOffsetCommit => (4, Some(3)),
+            Self::SaslAuthenticate => (2, Some(2)),
+            Self::Unknown => (-1, None),
+        }
+    }
+
+    // flexible versions use compact strings and arrays, and tagged fields in headers and structs
+    fn is_flexible(&self, version: i16) -> bool {
+        matches!(self.versions().1, Some(v) if version >= v)
+    }
+}
+
+fn error_name(code: i16) -> Option<&'static str> {
+    let name = match code {
+        -1 => "UNKNOWN_SERVER_ERROR",
+        1 => "OFFSET_OUT_OF_RANGE",
+        2 => "CORRUPT_MESSAGE",
+        3 => "UNKNOWN_TOPIC_OR_PARTITION",
+        4 => "INVALID_FETCH_SIZE",
+        5 => "LEADER_NOT_AVAILABLE",
+        6 => "NOT_LEADER_OR_FOLLOWER",
+        7 => "REQUEST_TIMED_OUT",
+        8 => "BROKER_NOT_AVAILABLE",
+        9 => "REPLICA_NOT_AVAILABLE",
+        10 => "MESSAGE_TOO_LARGE",
+        11 => "STALE_CONTROLLER_EPOCH",
+        12 => "OFFSET_METADATA_TOO_LARGE",
+        13 => "NETWORK_EXCEPTION",
+        14 => "COORDINATOR_LOAD_IN_PROGRESS",
+        15 => "COORDINATOR_NOT_AVAILABLE",
+        16 => "NOT_COORDINATOR",
+        17 => "INVALID_TOPIC_EXCEPTION",
+        18 => "RECORD_LIST_TOO_LARGE",
+        19 => "NOT_ENOUGH_REPLICAS",
+        20 => "NOT_ENOUGH_REPLICAS_AFTER_APPEND",
+        21 => "INVALID_REQUIRED_ACKS",
+        22 => "ILLEGAL_GENERATION",
+        23 => "INCONSISTENT_GROUP_PROTOCOL",
+        24 => "INVALID_GROUP_ID",
+        25 => "UNKNOWN_MEMBER_ID",
+        26 => "INVALID_SESSION_TIMEOUT",
+        27 => "REBALANCE_IN_PROGRESS",
+        28 => "INVALID_COMMIT_OFFSET_SIZE",
+        29 => "TOPIC_AUTHORIZATION_FAILED",
+        30 => "GROUP_AUTHORIZATION_FAILED",
+        31 => "CLUSTER_AUTHORIZATION_FAILED",
+        32 => "INVALID_TIMESTAMP",
+        33 => "UNSUPPORTED_SASL_MECHANISM",
+        34 => "ILLEGAL_SASL_STATE",
+        35 => "UNSUPPORTED_VERSION",
+        36 => "TOPIC_ALREADY_EXISTS",
+        37 => "INVALID_PARTITIONS",
+        38 => "INVALID_REPLICATION_FACTOR",
+        39 => "INVALID_REPLICA_ASSIGNMENT",
+        40 => "INVALID_CONFIG",
+        41 => "NOT_CONTROLLER",
+        42 => "INVALID_REQUEST",
+        43 => "UNSUPPORTED_FOR_MESSAGE_FORMAT",
+        44 => "POLICY_VIOLATION",
+        45 => "OUT_OF_ORDER_SEQUENCE_NUMBER",
+        46 => "DUPLICATE_SEQUENCE_NUMBER",
+        47 => "INVALID_PRODUCER_EPOCH",
+        48 => "INVALID_TXN_STATE",
+        49 => "INVALID_PRODUCER_ID_MAPPING",
+        50 => "INVALID_TRANSACTION_TIMEOUT",
+        51 => "CONCURRENT_TRANSACTIONS",
+        52 => "TRANSACTION_COORDINATOR_FENCED",
+        53 => "TRANSACTIONAL_ID_AUTHORIZATION_FAILED",
+        54 => "SECURITY_DISABLED",
+        55 => "OPERATION_NOT_ATTEMPTED",
+        56 => "KAFKA_STORAGE_ERROR",
+        57 => "LOG_DIR_NOT_FOUND",
+        58 => "SASL_AUTHENTICATION_FAILED",
+        59 => "UNKNOWN_PRODUCER_ID",
+        60 => "REASSIGNMENT_IN_PROGRESS",
+        61 => "DELEGATION_TOKEN_AUTH_DISABLED",
+        62 => "DELEGATION_TOKEN_NOT_FOUND",
+        63 => "DELEGATION_TOKEN_OWNER_MISMATCH",
+        64 => "DELEGATION_TOKEN_REQUEST_NOT_ALLOWED",
+        65 => "DELEGATION_TOKEN_AUTHORIZATION_FAILED",
+        66 => "DELEGATION_TOKEN_EXPIRED",
+        67 => "INVALID_PRINCIPAL_TYPE",
+        68 => "NON_EMPTY_GROUP",
+        69 => "GROUP_ID_NOT_FOUND",
+        70 => "FETCH_SESSION_ID_NOT_FOUND",
+        71 => "INVALID_FETCH_SESSION_EPOCH",
+        72 => "LISTENER_NOT_FOUND",
+        73 => "TOPIC_DELETION_DISABLED",
+        74 => "FENCED_LEADER_EPOCH",
+        75 => "UNKNOWN_LEADER_EPOCH",
+        76 => "UNSUPPORTED_COMPRESSION_TYPE",
+        77 => "STALE_BROKER_EPOCH",
+        78 => "OFFSET_NOT_AVAILABLE",
+        79 => "MEMBER_ID_REQUIRED",
+        80 => "PREFERRED_LEADER_NOT_AVAILABLE",
+        81 => "GROUP_MAX_SIZE_REACHED",
+        82 => "FENCED_INSTANCE_ID",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// errors caused by the state of the cluster rather than the request
+fn error_status(code: i16) -> L7ResponseStatus {
+    match code {
+        0 => L7ResponseStatus::Ok,
+        -1 | 5 | 6 | 7 | 8 | 9 | 13 | 14 | 15 | 16 | 19 | 20 | 41 | 51 | 56 | 78 | 80 => {
+            L7ResponseStatus::ServerError
+        }
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+// reads kafka primitive types, strings and arrays are compact in flexible versions
+struct KafkaReader<'a> {
+    r: Reader<'a>,
+    flexible: bool,
+}
+
+impl<'a> KafkaReader<'a> {
+    fn new(data: &'a [u8], flexible: bool) -> Self {
+        Self {
+            r: Reader { data },
+            flexible,
+        }
+    }
+
+    fn uvarint(&mut self) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..5 {
+            let b = self.u8()?;
+            value |= ((b & 0x7f) as u32) << (i * 7);
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::KafkaLogParseFailed("varint too long".to_owned()))
+    }
+
+    // a nullable string, kept lossy as client ids and topics are only logged
+    fn string(&mut self) -> Result<Option<String>> {
+        let len = if self.flexible {
+            match self.uvarint()? {
+                0 => return Ok(None),
+                n => n as usize - 1,
+            }
+        } else {
+            match self.i16()? {
+                -1 => return Ok(None),
+                n if n < 0 => {
+                    return Err(Error::KafkaLogParseFailed(format!(
+                        "invalid string length {}",
+                        n
+                    )))
+                }
+                n => n as usize,
+            }
+        };
+        Ok(Some(String::from_utf8_lossy(self.take(len)?).into_owned()))
+    }
+
+    // the number of elements, 0 for a null array
+    fn array(&mut self) -> Result<usize> {
+        if self.flexible {
+            return Ok(self.uvarint()?.saturating_sub(1) as usize);
+        }
+        match self.i32()? {
+            -1 => Ok(0),
+            n if n < 0 => Err(Error::KafkaLogParseFailed(format!(
+                "invalid array length {}",
+                n
+            ))),
+            n => Ok(n as usize),
+        }
+    }
+
+    fn tagged_fields(&mut self) -> Result<()> {
+        if !self.flexible {
+            return Ok(());
+        }
+        for _ in 0..self.uvarint()? {
+            self.uvarint()?;
+            let size = self.uvarint()? as usize;
+            self.take(size)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for KafkaReader<'a> {
+    type Target = Reader<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.r
+    }
+}
+
+impl DerefMut for KafkaReader<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.r
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct KafkaInfo {
+    pub msg_type: LogMessageType,
+    pub api_key: ApiKey,
+    pub api_version: i16,
+    pub correlation_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    // size field of the message, not including the field itself
+    pub message_size: u32,
+
+    // of the first partition in the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acks: Option<i16>,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_size: Option<u32>,
+    pub status: L7ResponseStatus,
+    // the first non-zero error code in the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_name: Option<&'static str>,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl KafkaInfo {
+    // [api_key][api_version][correlation_id][client_id][tagged fields][body]
+    // where the tagged fields are present in flexible versions only
+    fn parse_request(message: &[u8]) -> Result<Self> {
+        if message.len() < MIN_REQUEST_HEADER_SIZE {
+            return Err(Error::InsufficientPayloadLength);
+        }
+        let mut info = KafkaInfo {
+            msg_type: LogMessageType::Request,
+            api_key: ApiKey::from(read_u16_be(message) as i16),
+            api_version: read_u16_be(&message[2..]) as i16,
+            correlation_id: read_u32_be(&message[4..]) as i32,
+            ..Default::default()
+        };
+        // client_id stays a legacy string in flexible headers
+        let mut r = KafkaReader::new(&message[8..], false);
+        info.client_id = r.string()?;
+        if let Err(e) = info.parse_request_body(r) {
+            debug!("skip kafka {:?} request body: {}", info.api_key, e);
+        }
+        Ok(info)
+    }
+
+    fn parse_request_body(&mut self, mut r: KafkaReader) -> Result<()> {
+        let v = self.api_version;
+        if !(0..=self.api_key.versions().0).contains(&v) {
+            return Ok(());
+        }
+        r.flexible = self.api_key.is_flexible(v);
+        r.tagged_fields()?;
+        match self.api_key {
+            ApiKey::Produce => {
+                if v >= 3 {
+                    r.string()?; // transactional_id
+                }
+                self.acks = Some(r.i16()?);
+                r.i32()?; // timeout_ms
+                if self.first_partition(&mut r, false)? {
+                    self.partition = Some(r.i32()?);
+                }
+            }
+            ApiKey::Fetch => {
+                if v < 15 {
+                    r.i32()?; // replica_id
+                }
+                r.i32()?; // max_wait_ms
+                r.i32()?; // min_bytes
+                if v >= 3 {
+                    r.i32()?; // max_bytes
+                }
+                if v >= 4 {
+                    r.i8()?; // isolation_level
+                }
+                if v >= 7 {
+                    r.i32()?; // session_id
+                    r.i32()?; // session_epoch
+                }
+                if self.first_partition(&mut r, v >= 13)? {
+                    self.partition = Some(r.i32()?);
+                    if v >= 9 {
+                        r.i32()?; // current_leader_epoch
+                    }
+                    self.offset = Some(r.i64()?);
+                }
+            }
+            ApiKey::ListOffsets => {
+                r.i32()?; // replica_id
+                if v >= 2 {
+                    r.i8()?; // isolation_level
+                }
+                if self.first_partition(&mut r, false)? {
+                    self.partition = Some(r.i32()?);
+                }
+            }
+            ApiKey::Metadata => {
+                let topics = r.array()?;
+                if topics > 0 {
+                    if v >= 10 {
+                        r.take(16)?; // topic_id
+                    }
+                    self.topic = r.string()?;
+                }
+            }
+            ApiKey::OffsetCommit => {
+                self.group_id = r.string()?;
+                if v >= 1 {
+                    r.i32()?; // generation_id
+                    self.member_id = r.string()?;
+                }
+                if v >= 7 {
+                    r.string()?; // group_instance_id
+                }
+                if (2..=4).contains(&v) {
+                    r.i64()?; // retention_time_ms
+                }
+                if self.first_partition(&mut r, false)? {
+                    self.partition = Some(r.i32()?);
+                    self.offset = Some(r.i64()?);
+                }
+            }
+            ApiKey::JoinGroup => {
+                self.group_id = r.string()?;
+                r.i32()?; // session_timeout_ms
+                if v >= 1 {
+                    r.i32()?; // rebalance_timeout_ms
+                }
+                self.member_id = r.string()?;
+            }
+            ApiKey::Heartbeat | ApiKey::SyncGroup => {
+                self.group_id = r.string()?;
+                r.i32()?; // generation_id
+                self.member_id = r.string()?;
+            }
+            ApiKey::LeaveGroup => {
+                self.group_id = r.string()?;
+                if v < 3 {
+                    self.member_id = r.string()?;
+                }
+            }
+            ApiKey::OffsetFetch if v < 8 => self.group_id = r.string()?,
+            _ => (),
+        }
+        // a new member joins with an empty id
+        self.member_id = self.member_id.take().filter(|m| !m.is_empty());
+        Ok(())
+    }
+
+    // [correlation_id][tagged fields][body], where ApiVersions responses keep the
+    // non-flexible header so that clients can read the error of an unsupported version
+    fn parse_response_body(&mut self, body: &[u8]) -> Result<()> {
+        let v = self.api_version;
+        if !(0..=self.api_key.versions().0).contains(&v) {
+            return Ok(());
+        }
+        let mut r = KafkaReader::new(body, self.api_key.is_flexible(v));
+        if self.api_key != ApiKey::ApiVersions {
+            r.tagged_fields()?;
+        }
+        match self.api_key {
+            ApiKey::Produce => {
+                let found = self.first_partition(&mut r, false)?;
+                if found {
+                    self.partition = Some(r.i32()?);
+                    self.error(r.i16()?);
+                    self.offset = Some(r.i64()?); // base_offset
+                }
+            }
+            ApiKey::Fetch => {
+                if v >= 1 {
+                    r.i32()?; // throttle_time_ms
+                }
+                if v >= 7 {
+                    self.error(r.i16()?);
+                    r.i32()?; // session_id
+                }
+                if self.first_partition(&mut r, v >= 13)? {
+                    self.partition = Some(r.i32()?);
+                    self.error(r.i16()?);
+                }
+            }
+            ApiKey::ListOffsets => {
+                if v >= 2 {
+                    r.i32()?; // throttle_time_ms
+                }
+                if self.first_partition(&mut r, false)? {
+                    self.partition = Some(r.i32()?);
+                    self.error(r.i16()?);
+                    if v == 0 {
+                        if r.array()? > 0 {
+                            self.offset = Some(r.i64()?);
+                        }
+                    } else {
+                        r.i64()?; // timestamp
+                        self.offset = Some(r.i64()?);
+                    }
+                }
+            }
+            ApiKey::Metadata => {
+                if v >= 3 {
+                    r.i32()?; // throttle_time_ms
+                }
+                for _ in 0..r.array()? {
+                    r.i32()?; // node_id
+                    r.string()?; // host
+                    r.i32()?; // port
+                    if v >= 1 {
+                        r.string()?; // rack
+                    }
+                    r.tagged_fields()?;
+                }
+                if v >= 2 {
+                    r.string()?; // cluster_id
+                }
+                if v >= 1 {
+                    r.i32()?; // controller_id
+                }
+                if r.array()? > 0 {
+                    self.error(r.i16()?);
+                    self.topic = r.string()?;
+                }
+            }
+            ApiKey::OffsetCommit => {
+                if v >= 3 {
+                    r.i32()?; // throttle_time_ms
+                }
+                if self.first_partition(&mut r, false)? {
+                    self.partition = Some(r.i32()?);
+                    self.error(r.i16()?);
+                }
+            }
+            ApiKey::JoinGroup => {
+                if v >= 2 {
+                    r.i32()?; // throttle_time_ms
+                }
+                self.error(r.i16()?);
+                r.i32()?; // generation_id
+                if v >= 7 {
+                    r.string()?; // protocol_type
+                }
+                r.string()?; // protocol_name
+                r.string()?; // leader
+                if v >= 9 {
+                    r.i8()?; // skip_assignment
+                }
+                self.member_id = r.string()?.filter(|m| !m.is_empty());
+            }
+            ApiKey::Heartbeat | ApiKey::LeaveGroup | ApiKey::SyncGroup => {
+                if v >= 1 {
+                    r.i32()?; // throttle_time_ms
+                }
+                self.error(r.i16()?);
+            }
+            ApiKey::FindCoordinator if v < 4 => {
+                if v >= 1 {
+                    r.i32()?; // throttle_time_ms
+                }
+                self.error(r.i16()?);
+            }
+            ApiKey::ApiVersions => self.error(r.i16()?),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // reads the topic of the first topic entry and leaves the reader at its first
+    // partition, newer fetch versions identify topics by id instead of name
+    fn first_partition(&mut self, r: &mut KafkaReader, topic_id: bool) -> Result<bool> {
+        if r.array()? == 0 {
+            return Ok(false);
+        }
+        if topic_id {
+            r.take(16)?;
+        } else {
+            self.topic = r.string()?;
+        }
+        Ok(r.array()? > 0)
+    }
+
+    fn error(&mut self, code: i16) {
+        if code == 0 || self.error_code.is_some() {
+            return;
+        }
+        self.error_code = Some(code);
+        self.error_name = error_name(code);
+        self.status = error_status(code);
+    }
+
+    fn merge(&mut self, response: KafkaInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_size = Some(response.message_size);
+        self.status = response.status;
+        self.error_code = response.error_code;
+        self.error_name = response.error_name;
+        if self.topic.is_none() {
+            self.topic = response.topic;
+        }
+        if self.partition.is_none() {
+            self.partition = response.partition;
+        }
+        // produce and list offsets responses carry the offset the request asked for
+        if response.offset.is_some() {
+            self.offset = response.offset;
+        }
+        // join group assigns the member id
+        if response.member_id.is_some() {
+            self.member_id = response.member_id;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct KafkaLog {
+    streams: PerDirection<Stream>,
+    pending: HashMap<i32, KafkaInfo>,
+}
+
+impl KafkaLog {
+    fn parse_messages(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<KafkaInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= 4 {
+            let size = read_u32_be(remain) as usize;
+            if !(4..=MAX_MESSAGE_SIZE).contains(&size) {
+                // the rest of the segment can not be framed, keep what is parsed
+                debug!("drop kafka segment at invalid message size {}", size);
+                last_error = Some(Error::KafkaLogParseFailed(format!(
+                    "invalid message size {}",
+                    size
+                )));
+                remain = &[];
+                break;
+            }
+            let message = if remain.len() >= 4 + size {
+                let message = &remain[4..4 + size];
+                remain = &remain[4 + size..];
+                message
+            } else if remain.len() >= 4 + MAX_HEAD_SIZE {
+                self.streams[direction].skip = 4 + size - remain.len();
+                mem::take(&mut remain).get(4..).unwrap_or_default()
+            } else {
+                break;
+            };
+            let result = match param.direction {
+                PacketDirection::ClientToServer => KafkaInfo::parse_request(message),
+                PacketDirection::ServerToClient => self.parse_response(message),
+            };
+            match result {
+                Ok(mut info) => {
+                    info.message_size = size as u32;
+                    info.time = param.time;
+                    infos.push(info);
+                }
+                Err(e) => {
+                    debug!("skip kafka message: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    // the body layout depends on the api of the request with the same correlation id
+    fn parse_response(&self, message: &[u8]) -> Result<KafkaInfo> {
+        if message.len() < 4 {
+            return Err(Error::InsufficientPayloadLength);
+        }
+        let mut info = KafkaInfo {
+            msg_type: LogMessageType::Response,
+            correlation_id: read_u32_be(message) as i32,
+            ..Default::default()
+        };
+        if let Some(request) = self.pending.get(&info.correlation_id) {
+            info.api_key = request.api_key;
+            info.api_version = request.api_version;
+            if let Err(e) = info.parse_response_body(&message[4..]) {
+                debug!("skip kafka {:?} response body: {}", info.api_key, e);
+            }
+        }
+        Ok(info)
+    }
+
+    fn handle(&mut self, info: KafkaInfo, output: &mut Vec<L7ProtocolInfo>) {
+        match info.msg_type {
+            // produce requests with acks=0 get no response
+            LogMessageType::Request if info.api_key == ApiKey::Produce && info.acks == Some(0) => {
+                output.push(L7ProtocolInfo::KafkaInfo(info));
+            }
+            LogMessageType::Request => {
+                let key = info.correlation_id;
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::KafkaInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    // correlation id reused before a response, the previous one is lost
+                    output.push(L7ProtocolInfo::KafkaInfo(prev));
+                }
+            }
+            LogMessageType::Response => match self.pending.remove(&info.correlation_id) {
+                Some(mut request) => {
+                    request.merge(info);
+                    output.push(L7ProtocolInfo::KafkaInfo(request));
+                }
+                None => output.push(L7ProtocolInfo::KafkaInfo(info)),
+            },
+            _ => output.push(L7ProtocolInfo::KafkaInfo(info)),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for KafkaLog {
+    // responses carry nothing but the correlation id, so only requests are checked
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer
+            || payload.len() < 4 + MIN_REQUEST_HEADER_SIZE
+        {
+            return false;
+        }
+        let size = read_u32_be(payload) as usize;
+        if !(MIN_REQUEST_HEADER_SIZE..=MAX_MESSAGE_SIZE).contains(&size) {
+            return false;
+        }
+        let api_key = ApiKey::from(read_u16_be(&payload[4..]) as i16);
+        let api_version = read_u16_be(&payload[6..]) as i16;
+        if api_key == ApiKey::Unknown || !(0..=api_key.versions().0).contains(&api_version) {
+            return false;
+        }
+        let client_id_len = read_u16_be(&payload[12..]) as i16;
+        client_id_len == -1
+            || (client_id_len >= 0
+                && client_id_len as usize <= size - MIN_REQUEST_HEADER_SIZE
+                && payload
+                    .get(14..14 + client_id_len as usize)
+                    .map(|id| id.iter().all(|c| c.is_ascii_graphic()))
+                    .unwrap_or(true))
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_messages(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Kafka
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, SESSION_TIMEOUT};
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    // builds message bodies in the encoding of a given version
+    #[derive(Default)]
+    struct Writer {
+        buf: Vec<u8>,
+        flexible: bool,
+    }
+
+    impl Writer {
+        fn request(api_key: ApiKey, api_version: i16, correlation_id: i32) -> Self {
+            let mut w = Writer::default();
+            w.i16(api_key as i16).i16(api_version).i32(correlation_id);
+            w.string(Some("client-1"));
+            w.flexible = api_key.is_flexible(api_version);
+            w.tagged();
+            w
+        }
+
+        fn response(correlation_id: i32, flexible: bool) -> Self {
+            let mut w = Writer {
+                flexible,
+                ..Default::default()
+            };
+            w.i32(correlation_id).tagged();
+            w
+        }
+
+        fn i8(&mut self, v: i8) -> &mut Self {
+            self.buf.push(v as u8);
+            self
+        }
+
+        fn i16(&mut self, v: i16) -> &mut Self {
+            self.buf.extend_from_slice(&v.to_be_bytes());
+            self
+        }
+
+        fn i32(&mut self, v: i32) -> &mut Self {
+            self.buf.extend_from_slice(&v.to_be_bytes());
+            self
+        }
+
+        fn i64(&mut self, v: i64) -> &mut Self {
+            self.buf.extend_from_slice(&v.to_be_bytes());
+            self
+        }
+
+        fn uvarint(&mut self, mut v: u32) -> &mut Self {
+            while v >= 0x80 {
+                self.buf.push(v as u8 | 0x80);
+                v >>= 7;
+            }
+            self.buf.push(v as u8);
+            self
+        }
+
+        fn string(&mut self, s: Option<&str>) -> &mut Self {
+            match (s, self.flexible) {
+                (None, true) => self.uvarint(0),
+                (None, false) => self.i16(-1),
+                (Some(s), true) => self.uvarint(s.len() as u32 + 1),
+                (Some(s), false) => self.i16(s.len() as i16),
+            };
+            self.buf.extend_from_slice(s.unwrap_or_default().as_bytes());
+            self
+        }
+
+        fn array(&mut self, n: u32) -> &mut Self {
+            if self.flexible {
+                self.uvarint(n + 1)
+            } else {
+                self.i32(n as i32)
+            }
+        }
+
+        fn tagged(&mut self) -> &mut Self {
+            if self.flexible {
+                self.uvarint(0);
+            }
+            self
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            let mut message = (self.buf.len() as u32).to_be_bytes().to_vec();
+            message.extend_from_slice(&self.buf);
+            message
+        }
+    }
+
+    fn session(parser: &mut KafkaLog, request: &[u8], response: &[u8]) -> KafkaInfo {
+        assert!(parse(parser, request, &at(PacketDirection::ClientToServer, 1000)).is_empty());
+        let mut infos = parse(parser, response, &at(PacketDirection::ServerToClient, 1500));
+        assert_eq!(infos.len(), 1);
+        let info = infos.pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.rrt, 500);
+        info
+    }
+
+    fn produce_request(version: i16, correlation_id: i32, acks: i16) -> Vec<u8> {
+        let mut w = Writer::request(ApiKey::Produce, version, correlation_id);
+        w.string(None).i16(acks).i32(30000);
+        w.array(1).string(Some("orders")).array(1).i32(2).finish()
+    }
+
+    #[test]
+    fn produce_session() {
+        let mut parser = KafkaLog::default();
+        let request = produce_request(7, 5, 1);
+        assert!(parser.check_payload(&request, &at(PacketDirection::ClientToServer, 0)));
+        assert!(!parser.check_payload(&request, &at(PacketDirection::ServerToClient, 0)));
+
+        let mut w = Writer::response(5, false);
+        w.array(1).string(Some("orders")).array(1);
+        w.i32(2).i16(0).i64(42).i64(-1).i64(0);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.api_key, ApiKey::Produce);
+        assert_eq!(info.api_version, 7);
+        assert_eq!(info.client_id.as_deref(), Some("client-1"));
+        assert_eq!(info.topic.as_deref(), Some("orders"));
+        assert_eq!(info.partition, Some(2));
+        assert_eq!(info.offset, Some(42));
+        assert_eq!(info.status, L7ResponseStatus::Ok);
+        assert_eq!(info.message_size as usize, request.len() - 4);
+
+        // flexible version with an unknown tagged field in the request header
+        let mut w = Writer::default();
+        w.i16(ApiKey::Produce as i16)
+            .i16(9)
+            .i32(6)
+            .string(Some("client-1"));
+        w.buf.extend_from_slice(&[1, 0, 2, 0xab, 0xcd]);
+        w.flexible = true;
+        w.string(None).i16(-1).i32(30000);
+        w.array(1).string(Some("orders")).array(1).i32(3);
+        let request = w.finish();
+        let mut w = Writer::response(6, true);
+        w.array(1)
+            .string(Some("orders"))
+            .array(1)
+            .i32(3)
+            .i16(6)
+            .i64(-1);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.partition, Some(3));
+        assert_eq!(info.error_code, Some(6));
+        assert_eq!(info.error_name, Some("NOT_LEADER_OR_FOLLOWER"));
+        assert_eq!(info.status, L7ResponseStatus::ServerError);
+
+        // no response for acks=0
+        let infos = parse(
+            &mut parser,
+            &produce_request(3, 7, 0),
+            &at(PacketDirection::ClientToServer, 2000),
+        );
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Request);
+        assert_eq!(infos[0].acks, Some(0));
+    }
+
+    #[test]
+    fn fetch_and_list_offsets() {
+        let mut parser = KafkaLog::default();
+        let mut w = Writer::request(ApiKey::Fetch, 12, 1);
+        w.i32(-1).i32(500).i32(1).i32(1 << 20).i8(0).i32(0).i32(-1);
+        w.array(1).string(Some("orders")).array(1);
+        w.i32(0).i32(-1).i64(100);
+        let request = w.finish();
+        let mut w = Writer::response(1, true);
+        w.i32(0).i16(0).i32(0);
+        w.array(1).string(Some("orders")).array(1).i32(0).i16(1);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.topic.as_deref(), Some("orders"));
+        assert_eq!((info.partition, info.offset), (Some(0), Some(100)));
+        assert_eq!(info.error_name, Some("OFFSET_OUT_OF_RANGE"));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // topics are identified by id from version 13
+        let mut w = Writer::request(ApiKey::Fetch, 13, 2);
+        w.i32(-1).i32(500).i32(1).i32(1 << 20).i8(0).i32(0).i32(-1);
+        w.array(1);
+        w.buf.extend_from_slice(&[0x11; 16]);
+        w.array(1).i32(4).i32(-1).i64(7);
+        let request = w.finish();
+        let info = session(&mut parser, &request, &Writer::response(2, true).finish());
+        assert_eq!(info.topic, None);
+        assert_eq!((info.partition, info.offset), (Some(4), Some(7)));
+
+        let mut w = Writer::request(ApiKey::ListOffsets, 1, 3);
+        w.i32(-1)
+            .array(1)
+            .string(Some("orders"))
+            .array(1)
+            .i32(1)
+            .i64(-1);
+        let request = w.finish();
+        let mut w = Writer::response(3, false);
+        w.array(1).string(Some("orders")).array(1);
+        w.i32(1).i16(0).i64(-1).i64(1234);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!((info.partition, info.offset), (Some(1), Some(1234)));
+    }
+
+    #[test]
+    fn metadata_and_groups() {
+        let mut parser = KafkaLog::default();
+        let mut w = Writer::request(ApiKey::Metadata, 9, 1);
+        w.array(1).string(Some("orders")).tagged();
+        let request = w.i8(1).i8(0).i8(0).tagged().finish();
+        let mut w = Writer::response(1, true);
+        w.i32(0).array(2);
+        for host in ["broker-1", "broker-2"] {
+            w.i32(1)
+                .string(Some(host))
+                .i32(9092)
+                .string(None)
+                .uvarint(0);
+        }
+        w.string(Some("cluster")).i32(1);
+        w.array(1).i16(3).string(Some("orders"));
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.topic.as_deref(), Some("orders"));
+        assert_eq!(info.error_name, Some("UNKNOWN_TOPIC_OR_PARTITION"));
+
+        let mut w = Writer::request(ApiKey::JoinGroup, 5, 2);
+        w.string(Some("billing"))
+            .i32(10000)
+            .i32(300000)
+            .string(Some(""));
+        let request = w.string(None).string(Some("consumer")).finish();
+        let mut w = Writer::response(2, false);
+        w.i32(0)
+            .i16(0)
+            .i32(1)
+            .string(Some("range"))
+            .string(Some("m-1"));
+        w.string(Some("m-1"));
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.group_id.as_deref(), Some("billing"));
+        assert_eq!(info.member_id.as_deref(), Some("m-1"));
+        assert_eq!(info.status, L7ResponseStatus::Ok);
+
+        let mut w = Writer::request(ApiKey::OffsetCommit, 8, 3);
+        w.string(Some("billing"))
+            .i32(1)
+            .string(Some("m-1"))
+            .string(None);
+        w.array(1).string(Some("orders")).array(1).i32(2).i64(99);
+        let request = w.finish();
+        let mut w = Writer::response(3, true);
+        w.i32(0)
+            .array(1)
+            .string(Some("orders"))
+            .array(1)
+            .i32(2)
+            .i16(27);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.group_id.as_deref(), Some("billing"));
+        assert_eq!((info.partition, info.offset), (Some(2), Some(99)));
+        assert_eq!(info.error_name, Some("REBALANCE_IN_PROGRESS"));
+
+        // ApiVersions responses keep the non-flexible header
+        let request = Writer::request(ApiKey::ApiVersions, 3, 4).finish();
+        let mut w = Writer::response(4, false);
+        w.i16(35);
+        let info = session(&mut parser, &request, &w.finish());
+        assert_eq!(info.error_name, Some("UNSUPPORTED_VERSION"));
+    }
+
+    #[test]
+    fn large_message_and_timeout() {
+        let mut parser = KafkaLog::default();
+        let mut w = Writer::request(ApiKey::Fetch, 4, 1);
+        w.i32(-1).i32(500).i32(1).i32(1 << 20).i8(0);
+        w.array(1).string(Some("orders")).array(1).i32(0).i64(0);
+        let request = w.finish();
+        assert!(parse(
+            &mut parser,
+            &request,
+            &at(PacketDirection::ClientToServer, 0)
+        )
+        .is_empty());
+
+        // a large fetch response arrives in segments, only its head is parsed
+        let mut w = Writer::response(1, false);
+        w.i32(0)
+            .array(1)
+            .string(Some("orders"))
+            .array(1)
+            .i32(0)
+            .i16(0);
+        w.buf.resize(MAX_HEAD_SIZE * 4, 0);
+        let response = w.finish();
+        let s2c = at(PacketDirection::ServerToClient, 100);
+        let segments = response.chunks(MAX_HEAD_SIZE / 2).collect::<Vec<_>>();
+        let mut infos = vec![];
+        for segment in segments.iter() {
+            infos.extend(parse(&mut parser, segment, &s2c));
+        }
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert_eq!(infos[0].response_size, Some(response.len() as u32 - 4));
+
+        // the next message after the skipped bytes is parsed again
+        let mut w = Writer::response(9, false);
+        let infos = parse(&mut parser, &w.i32(0).finish(), &s2c);
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Response);
+        assert_eq!(infos[0].api_key, ApiKey::Unknown);
+
+        let request = produce_request(7, 2, 1);
+        parse(
+            &mut parser,
+            &request,
+            &at(PacketDirection::ClientToServer, 200),
+        );
+        let mut w = Writer::request(ApiKey::Heartbeat, 4, 3);
+        let request = w
+            .string(Some("billing"))
+            .i32(1)
+            .string(Some("m-1"))
+            .finish();
+        let infos = parse(
+            &mut parser,
+            &request,
+            &at(PacketDirection::ClientToServer, 200 + SESSION_TIMEOUT),
+        );
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].correlation_id, 2);
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+    }
+
+    #[test]
+    fn invalid_message_size() {
+        let mut parser = KafkaLog::default();
+        let c2s = at(PacketDirection::ClientToServer, 0);
+        let s2c = at(PacketDirection::ServerToClient, 100);
+        let requests = [produce_request(7, 1, 0), produce_request(7, 2, 1)].concat();
+        let infos = parse(&mut parser, &[&requests[..], &[0xff; 8]].concat(), &c2s);
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].acks, Some(0));
+
+        // the bytes after the invalid size are dropped, not buffered
+        let mut w = Writer::response(2, false);
+        let infos = parse(&mut parser, &w.array(0).finish(), &s2c);
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert!(parser
+            .parse_payload(&[0xff; 8], &c2s)
+            .is_err_and(|e| matches!(e, Error::KafkaLogParseFailed(_))));
+    }
+}
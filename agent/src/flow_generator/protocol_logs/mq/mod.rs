@@ -0,0 +1,26 @@
+mod amqp;
+mod kafka;
+mod mqtt;
+mod nats;
+mod pulsar;
+mod pulsar_consumer;
+mod pulsar_lookup;
+mod pulsar_txn;
+mod rocketmq;
+
+use super::{MAX_PENDING_SESSIONS, SESSION_TIMEOUT};
+
+#[allow(clippy::all)]
+pub mod pulsar_proto {
+    include!("pulsar.proto.rs");
+}
+
+pub use amqp::{AmqpInfo, AmqpLog, ContentProperties, FrameType, Header, Method};
+pub use kafka::{ApiKey, KafkaInfo, KafkaLog};
+pub use mqtt::{MqttInfo, MqttLog, PacketType, Subscription, UserProperty};
+pub use nats::{NatsInfo, NatsLog, Op};
+pub use pulsar::{PulsarInfo, PulsarLog};
+pub use pulsar_consumer::{AckLatency, PulsarConsumerMetrics, ACK_LATENCY_BOUNDS};
+pub use pulsar_lookup::{LookupHop, PulsarTopologyEvent, TopologyEventType};
+pub use pulsar_txn::{PulsarTransaction, TransactionOutcome};
+pub use rocketmq::{RocketmqInfo, RocketmqLog};
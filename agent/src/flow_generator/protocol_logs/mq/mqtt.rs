@@ -0,0 +1,923 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// topics and properties are at the head of a packet, so the rest of a larger
+// packet such as a big PUBLISH payload is skipped instead of buffered
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+const PROTOCOL_LEVEL_V311: u8 = 4;
+const PROTOCOL_LEVEL_V5: u8 = 5;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    #[default]
+    Reserved = 0,
+    Connect = 1,
+    Connack = 2,
+    Publish = 3,
+    Puback = 4,
+    Pubrec = 5,
+    Pubrel = 6,
+    Pubcomp = 7,
+    Subscribe = 8,
+    Suback = 9,
+    Unsubscribe = 10,
+    Unsuback = 11,
+    Pingreq = 12,
+    Pingresp = 13,
+    Disconnect = 14,
+    Auth = 15,
+}
+
+impl From<u8> for PacketType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => Self::Connect,
+            2 => Self::Connack,
+            3 => Self::Publish,
+            4 => Self::Puback,
+            5 => Self::Pubrec,
+            6 => Self::Pubrel,
+            7 => Self::Pubcomp,
+            8 => Self::Subscribe,
+            9 => Self::Suback,
+            10 => Self::Unsubscribe,
+            11 => Self::Unsuback,
+            12 => Self::Pingreq,
+            13 => Self::Pingresp,
+            14 => Self::Disconnect,
+            15 => Self::Auth,
+            _ => Self::Reserved,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct UserProperty {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub topic_filter: String,
+    // requested maximum qos, not present in UNSUBSCRIBE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qos: Option<u8>,
+}
+
+// reason codes from 0x80 are failures in MQTT 5
+fn reason_status(code: u8) -> L7ResponseStatus {
+    match code {
+        0x00..=0x7f => L7ResponseStatus::Ok,
+        // unspecified error, implementation specific error, server unavailable,
+        // server busy, server shutting down, quota exceeded, connection rate exceeded
+        0x80 | 0x83 | 0x88 | 0x89 | 0x8b | 0x97 | 0x9f => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+// CONNACK return codes before MQTT 5, of which 3 is server unavailable
+fn connack_status(code: u8) -> L7ResponseStatus {
+    match code {
+        0 => L7ResponseStatus::Ok,
+        3 => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+trait MqttRead<'a> {
+    fn varint(&mut self) -> Result<u32>;
+    fn binary(&mut self) -> Result<&'a [u8]>;
+    fn string(&mut self) -> Result<String>;
+}
+
+impl<'a> MqttRead<'a> for Reader<'a> {
+    fn varint(&mut self) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..4 {
+            let b = self.u8()?;
+            value |= ((b & 0x7f) as u32) << (i * 7);
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::MqttLogParseFailed(
+            "variable byte integer too long".to_owned(),
+        ))
+    }
+
+    fn binary(&mut self) -> Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.binary()?).into_owned())
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MqttInfo {
+    pub msg_type: LogMessageType,
+    pub packet_type: PacketType,
+    // protocol level, 4 for 3.1.1 and 5 for 5.0
+    pub version: u8,
+    pub remaining_length: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_id: Option<u16>,
+    // from CONNECT or assigned by the broker, filled in on every packet of the connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+
+    // CONNECT only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clean_start: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub will_topic: Option<String>,
+
+    // PUBLISH only, the topic is resolved from the alias when sent empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic_alias: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qos: Option<u8>,
+    pub retain: bool,
+    pub dup: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_size: Option<u32>,
+
+    // SUBSCRIBE and UNSUBSCRIBE
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<Subscription>,
+
+    // MQTT 5 properties
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub user_properties: Vec<UserProperty>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_type: Option<PacketType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_present: Option<bool>,
+    // CONNACK return code or MQTT 5 reason code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<u8>,
+    // of each topic filter in SUBACK and UNSUBACK
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reason_codes: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_string: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl MqttInfo {
+    // `body` is the packet after the fixed header, possibly cut short for large packets,
+    // and `version` the protocol level agreed on by CONNECT
+    fn parse(header: u8, body: &[u8], remaining_length: u32, version: u8) -> Result<Self> {
+        let packet_type = PacketType::from(header >> 4);
+        let flags = header & 0x0f;
+        let mut info = MqttInfo {
+            packet_type,
+            msg_type: message_type(packet_type),
+            version,
+            remaining_length,
+            ..Default::default()
+        };
+        let reserved_flags = match packet_type {
+            PacketType::Reserved => {
+                return Err(Error::MqttLogParseFailed("reserved packet type".to_owned()))
+            }
+            PacketType::Publish => flags,
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => 0b0010,
+            _ => 0,
+        };
+        if flags != reserved_flags {
+            return Err(Error::MqttLogParseFailed(format!(
+                "invalid flags {:#x} of {:?}",
+                flags, packet_type
+            )));
+        }
+
+        let mut r = Reader { data: body };
+        let v5 = version == PROTOCOL_LEVEL_V5;
+        match packet_type {
+            PacketType::Connect => info.parse_connect(&mut r)?,
+            PacketType::Connack => {
+                info.session_present = Some(r.u8()? & 0x01 != 0);
+                let code = r.u8()?;
+                info.reason_code = Some(code);
+                info.status = if v5 {
+                    info.parse_properties(&mut r)?;
+                    reason_status(code)
+                } else {
+                    connack_status(code)
+                };
+            }
+            PacketType::Publish => {
+                let qos = (flags >> 1) & 0x03;
+                if qos == 3 {
+                    return Err(Error::MqttLogParseFailed("invalid qos 3".to_owned()));
+                }
+                info.qos = Some(qos);
+                info.retain = flags & 0x01 != 0;
+                info.dup = flags & 0x08 != 0;
+                info.msg_type = if qos == 0 {
+                    LogMessageType::Other
+                } else {
+                    LogMessageType::Request
+                };
+                info.topic = Some(r.string()?).filter(|t| !t.is_empty());
+                if qos > 0 {
+                    info.packet_id = Some(r.u16()?);
+                }
+                if v5 {
+                    info.parse_properties(&mut r)?;
+                }
+                let header_size = body.len() - r.data.len();
+                info.payload_size = Some(remaining_length - header_size as u32);
+            }
+            PacketType::Puback | PacketType::Pubrec | PacketType::Pubrel | PacketType::Pubcomp => {
+                info.packet_id = Some(r.u16()?);
+                // reason code and properties may be omitted when they are success and empty
+                if v5 && !r.data.is_empty() {
+                    info.set_reason(r.u8()?);
+                    if !r.data.is_empty() {
+                        info.parse_properties(&mut r)?;
+                    }
+                }
+            }
+            PacketType::Subscribe | PacketType::Unsubscribe => {
+                info.packet_id = Some(r.u16()?);
+                if v5 {
+                    info.parse_properties(&mut r)?;
+                }
+                while !r.data.is_empty() {
+                    let topic_filter = r.string()?;
+                    let qos = match packet_type {
+                        PacketType::Subscribe => Some(r.u8()? & 0x03),
+                        _ => None,
+                    };
+                    info.subscriptions.push(Subscription { topic_filter, qos });
+                }
+            }
+            PacketType::Suback | PacketType::Unsuback => {
+                info.packet_id = Some(r.u16()?);
+                // UNSUBACK has no payload before MQTT 5
+                if v5 {
+                    info.parse_properties(&mut r)?;
+                }
+                info.reason_codes = r.data.to_vec();
+                // 0x80 is the only failure code before MQTT 5, mostly refused by authorization
+                if let Some(code) = info.reason_codes.iter().find(|c| **c >= 0x80) {
+                    info.status = if v5 {
+                        reason_status(*code)
+                    } else {
+                        L7ResponseStatus::ClientError
+                    };
+                }
+            }
+            PacketType::Disconnect | PacketType::Auth if v5 && !r.data.is_empty() => {
+                info.set_reason(r.u8()?);
+                if !r.data.is_empty() {
+                    info.parse_properties(&mut r)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(info)
+    }
+
+    // variable header: [protocol name][level][flags][keep alive][properties]
+    // payload: [client id][will properties][will topic][will payload][username][password]
+    fn parse_connect(&mut self, r: &mut Reader) -> Result<()> {
+        let name = r.string()?;
+        if name != "MQTT" && name != "MQIsdp" {
+            return Err(Error::MqttLogParseFailed(format!(
+                "invalid protocol name {}",
+                name
+            )));
+        }
+        self.version = r.u8()?;
+        let flags = r.u8()?;
+        self.keep_alive = Some(r.u16()?);
+        self.clean_start = Some(flags & 0x02 != 0);
+        let v5 = self.version == PROTOCOL_LEVEL_V5;
+        if v5 {
+            self.parse_properties(r)?;
+        }
+        // an empty client id asks the broker to assign one
+        self.client_id = Some(r.string()?).filter(|c| !c.is_empty());
+        if flags & 0x04 != 0 {
+            if v5 {
+                let len = r.varint()? as usize;
+                r.take(len)?;
+            }
+            self.will_topic = Some(r.string()?);
+            r.binary()?;
+        }
+        if flags & 0x80 != 0 {
+            self.username = Some(r.string()?);
+        }
+        Ok(())
+    }
+
+    fn parse_properties(&mut self, r: &mut Reader) -> Result<()> {
+        let len = r.varint()? as usize;
+        let mut p = Reader { data: r.take(len)? };
+        while !p.data.is_empty() {
+            match p.varint()? {
+                0x01 | 0x17 | 0x19 | 0x24 | 0x25 | 0x28 | 0x29 | 0x2a => {
+                    p.u8()?;
+                }
+                0x13 | 0x21 | 0x22 => {
+                    p.u16()?;
+                }
+                0x23 => self.topic_alias = Some(p.u16()?),
+                0x02 | 0x11 | 0x18 | 0x27 => {
+                    p.u32()?;
+                }
+                0x0b => {
+                    p.varint()?;
+                }
+                0x03 | 0x08 | 0x15 | 0x1a | 0x1c => {
+                    p.string()?;
+                }
+                0x09 | 0x16 => {
+                    p.binary()?;
+                }
+                // assigned client identifier
+                0x12 => self.client_id = Some(p.string()?),
+                0x1f => self.reason_string = Some(p.string()?),
+                0x26 => {
+                    let key = p.string()?;
+                    let value = p.string()?;
+                    self.user_properties.push(UserProperty { key, value });
+                }
+                id => {
+                    return Err(Error::MqttLogParseFailed(format!(
+                        "unknown property {:#x}",
+                        id
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_reason(&mut self, code: u8) {
+        self.reason_code = Some(code);
+        self.status = reason_status(code);
+    }
+
+    fn extract_trace(&mut self, config: &LogParserConfig) {
+        self.trace = TraceContext::extract(config, |key| {
+            self.user_properties
+                .iter()
+                .find(|p| p.key.eq_ignore_ascii_case(key))
+                .map(|p| p.value.as_str())
+        });
+    }
+
+    fn merge(&mut self, response: MqttInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_type = Some(response.packet_type);
+        self.session_present = response.session_present;
+        self.reason_code = response.reason_code;
+        self.reason_codes = response.reason_codes;
+        self.reason_string = response.reason_string;
+        self.status = response.status;
+        if self.client_id.is_none() {
+            self.client_id = response.client_id;
+        }
+    }
+}
+
+fn message_type(packet_type: PacketType) -> LogMessageType {
+    match packet_type {
+        PacketType::Connect
+        | PacketType::Publish
+        | PacketType::Pubrel
+        | PacketType::Subscribe
+        | PacketType::Unsubscribe
+        | PacketType::Pingreq => LogMessageType::Request,
+        PacketType::Connack
+        | PacketType::Puback
+        | PacketType::Pubrec
+        | PacketType::Pubcomp
+        | PacketType::Suback
+        | PacketType::Unsuback
+        | PacketType::Pingresp => LogMessageType::Response,
+        _ => LogMessageType::Other,
+    }
+}
+
+// returns the size of the fixed header and the remaining length, or None if incomplete
+fn fixed_header(data: &[u8]) -> Result<Option<(usize, usize)>> {
+    let mut len = 0;
+    for i in 0..4 {
+        let Some(b) = data.get(1 + i) else {
+            return Ok(None);
+        };
+        len |= ((b & 0x7f) as usize) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok(Some((2 + i, len)));
+        }
+    }
+    Err(Error::MqttLogParseFailed(
+        "invalid remaining length".to_owned(),
+    ))
+}
+
+// how an acknowledgement finds its packet on the same connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SessionKey {
+    Connect,
+    Ping,
+    Subscribe(u16),
+    Unsubscribe(u16),
+    // both sides publish, each with its own packet identifiers
+    Publish {
+        direction: PacketDirection,
+        packet_id: u16,
+    },
+}
+
+#[derive(Default)]
+pub struct MqttLog {
+    streams: PerDirection<Stream>,
+    // protocol level from CONNECT, 3.1.1 is assumed until one is seen
+    version: Option<u8>,
+    client_id: Option<String>,
+    // topic aliases are set up by each sender separately
+    topic_aliases: PerDirection<HashMap<u16, String>>,
+    pending: HashMap<SessionKey, MqttInfo>,
+}
+
+impl MqttLog {
+    fn parse_packets(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<MqttInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        loop {
+            let (header_size, remaining_length) = match fixed_header(remain) {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(e) => {
+                    // the rest of the segment can not be framed, keep what is parsed
+                    debug!("drop mqtt segment: {}", e);
+                    last_error = Some(e);
+                    remain = &[];
+                    break;
+                }
+            };
+            let header = remain[0];
+            let size = header_size + remaining_length;
+            let body = if remain.len() >= size {
+                let body = &remain[header_size..size];
+                remain = &remain[size..];
+                body
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = size - remain.len();
+                &mem::take(&mut remain)[header_size..]
+            } else {
+                break;
+            };
+            match self.parse_packet(header, body, remaining_length as u32, param) {
+                Ok(info) => infos.push(info),
+                Err(e) => {
+                    debug!("skip mqtt packet: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn parse_packet(
+        &mut self,
+        header: u8,
+        body: &[u8],
+        remaining_length: u32,
+        param: &ParseParam,
+    ) -> Result<MqttInfo> {
+        let version = self.version.unwrap_or(PROTOCOL_LEVEL_V311);
+        let mut info = MqttInfo::parse(header, body, remaining_length, version)?;
+        info.time = param.time;
+        match info.packet_type {
+            PacketType::Connect => {
+                self.version = Some(info.version);
+                self.client_id = info.client_id.clone();
+                self.topic_aliases = Default::default();
+            }
+            PacketType::Connack if info.client_id.is_some() => {
+                self.client_id = info.client_id.clone();
+            }
+            PacketType::Publish => {
+                if let Some(alias) = info.topic_alias {
+                    let aliases = &mut self.topic_aliases[param.direction];
+                    match info.topic.as_ref() {
+                        Some(topic) => {
+                            aliases.insert(alias, topic.clone());
+                        }
+                        None => info.topic = aliases.get(&alias).cloned(),
+                    }
+                }
+            }
+            _ => (),
+        }
+        if info.client_id.is_none() {
+            info.client_id = self.client_id.clone();
+        }
+        if let Some(config) = param.parse_config {
+            info.extract_trace(config);
+        }
+        Ok(info)
+    }
+
+    fn handle(
+        &mut self,
+        info: MqttInfo,
+        direction: PacketDirection,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let key = match info.packet_type {
+            PacketType::Connect | PacketType::Connack => Some(SessionKey::Connect),
+            PacketType::Pingreq | PacketType::Pingresp => Some(SessionKey::Ping),
+            PacketType::Subscribe | PacketType::Suback => info.packet_id.map(SessionKey::Subscribe),
+            PacketType::Unsubscribe | PacketType::Unsuback => {
+                info.packet_id.map(SessionKey::Unsubscribe)
+            }
+            PacketType::Publish | PacketType::Pubrel => {
+                info.packet_id.map(|packet_id| SessionKey::Publish {
+                    direction,
+                    packet_id,
+                })
+            }
+            // acknowledgements flow opposite to the publish
+            PacketType::Puback | PacketType::Pubrec | PacketType::Pubcomp => {
+                info.packet_id.map(|packet_id| SessionKey::Publish {
+                    direction: direction.reversed(),
+                    packet_id,
+                })
+            }
+            _ => None,
+        };
+        let Some(key) = key else {
+            output.push(L7ProtocolInfo::MqttInfo(info));
+            return;
+        };
+
+        match info.packet_type {
+            // a QoS 2 publish ends with PUBCOMP, PUBREC and PUBREL in between are folded into it
+            PacketType::Pubrel if self.pending.contains_key(&key) => (),
+            PacketType::Pubrec if info.status == L7ResponseStatus::Ok => {
+                if !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::MqttInfo(info));
+                }
+            }
+            _ if info.msg_type == LogMessageType::Request => {
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::MqttInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    // packet identifier reused before an acknowledgement, the previous one is lost
+                    output.push(L7ProtocolInfo::MqttInfo(prev));
+                }
+            }
+            _ => match self.pending.remove(&key) {
+                Some(mut request) => {
+                    request.merge(info);
+                    output.push(L7ProtocolInfo::MqttInfo(request));
+                }
+                None => output.push(L7ProtocolInfo::MqttInfo(info)),
+            },
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for MqttLog {
+    // a connection starts with CONNECT: [0x10][remaining length][0x00 0x04 "MQTT"][level]
+    // or [0x00 0x06 "MQIsdp"][level] for 3.1
+    fn check_payload(&mut self, payload: &[u8], _: &ParseParam) -> bool {
+        if payload.first() != Some(&0x10) {
+            return false;
+        }
+        let Ok(Some((header_size, _))) = fixed_header(payload) else {
+            return false;
+        };
+        let mut r = Reader {
+            data: &payload[header_size..],
+        };
+        match (r.string(), r.u8()) {
+            (Ok(name), Ok(level)) => {
+                (name == "MQTT" && (PROTOCOL_LEVEL_V311..=PROTOCOL_LEVEL_V5).contains(&level))
+                    || (name == "MQIsdp" && level == 3)
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_packets(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, param.direction, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::MQTT
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.version = None;
+        self.client_id = None;
+        self.topic_aliases = Default::default();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, SESSION_TIMEOUT};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut v = (s.len() as u16).to_be_bytes().to_vec();
+        v.extend_from_slice(s.as_bytes());
+        v
+    }
+
+    fn varint(mut n: usize) -> Vec<u8> {
+        let mut v = vec![];
+        while n >= 0x80 {
+            v.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        v.push(n as u8);
+        v
+    }
+
+    fn properties(props: &[u8]) -> Vec<u8> {
+        [varint(props.len()), props.to_vec()].concat()
+    }
+
+    fn user_property(key: &str, value: &str) -> Vec<u8> {
+        [vec![0x26], string(key), string(value)].concat()
+    }
+
+    fn packet(header: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let body = parts.concat();
+        [vec![header], varint(body.len()), body].concat()
+    }
+
+    fn connect(level: u8, client_id: &str, props: Option<&[u8]>) -> Vec<u8> {
+        let props = props.map(properties).unwrap_or_default();
+        packet(
+            0x10,
+            &[
+                &string("MQTT"),
+                &[level, 0x82, 0, 60],
+                &props,
+                &string(client_id),
+                &string("user"),
+            ],
+        )
+    }
+
+    fn parse_one(parser: &mut MqttLog, packet: &[u8], param: &ParseParam) -> MqttInfo {
+        let mut infos = parse(parser, packet, param);
+        assert_eq!(infos.len(), 1);
+        infos.pop().unwrap()
+    }
+
+    #[test]
+    fn mqtt_v311() {
+        let mut parser = MqttLog::default();
+        let request = connect(4, "sensor-1", None);
+        assert!(parser.check_payload(&request, &at(C2S, 0)));
+        assert!(!parser.check_payload(&request[..4], &at(C2S, 0)));
+        assert!(parse(&mut parser, &request, &at(C2S, 0)).is_empty());
+        let info = parse_one(&mut parser, &packet(0x20, &[&[1, 0]]), &at(S2C, 100));
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.client_id.as_deref(), Some("sensor-1"));
+        assert_eq!(info.username.as_deref(), Some("user"));
+        assert_eq!((info.keep_alive, info.clean_start), (Some(60), Some(true)));
+        assert_eq!(info.session_present, Some(true));
+        assert_eq!((info.rrt, info.status), (100, L7ResponseStatus::Ok));
+
+        // qos 1 publish and its PUBACK arrive in one segment with the next SUBSCRIBE
+        let publish = packet(0x32, &[&string("fleet/1/temp"), &[0, 10], b"21.5C"]);
+        assert!(parse(&mut parser, &publish, &at(C2S, 200)).is_empty());
+        let subscribe = packet(
+            0x82,
+            &[
+                &[0, 11],
+                &string("fleet/+/cmd"),
+                &[1],
+                &string("fleet/all"),
+                &[0],
+            ],
+        );
+        let segment = [packet(0x40, &[&[0, 10]]), subscribe].concat();
+        let info = parse_one(&mut parser, &segment[..], &at(S2C, 300));
+        assert_eq!(info.response_type, Some(PacketType::Puback));
+        assert_eq!(info.topic.as_deref(), Some("fleet/1/temp"));
+        assert_eq!((info.qos, info.packet_id), (Some(1), Some(10)));
+        assert_eq!(info.payload_size, Some(5));
+        assert_eq!(info.client_id.as_deref(), Some("sensor-1"));
+
+        let info = parse_one(
+            &mut parser,
+            &packet(0x90, &[&[0, 11, 1, 0x80]]),
+            &at(S2C, 400),
+        );
+        assert_eq!(info.packet_type, PacketType::Subscribe);
+        assert_eq!(
+            info.subscriptions,
+            vec![
+                Subscription {
+                    topic_filter: "fleet/+/cmd".to_owned(),
+                    qos: Some(1),
+                },
+                Subscription {
+                    topic_filter: "fleet/all".to_owned(),
+                    qos: Some(0),
+                },
+            ]
+        );
+        assert_eq!(info.reason_codes, vec![1, 0x80]);
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // qos 0 publish from the broker, split across segments
+        let publish = packet(0x31, &[&string("fleet/1/cmd"), b"reboot"]);
+        assert!(parse(&mut parser, &publish[..5], &at(S2C, 500)).is_empty());
+        let info = parse_one(&mut parser, &publish[5..], &at(S2C, 500));
+        assert_eq!(info.msg_type, LogMessageType::Other);
+        assert_eq!((info.qos, info.retain), (Some(0), true));
+        assert_eq!(info.topic.as_deref(), Some("fleet/1/cmd"));
+
+        // packets before an invalid remaining length are kept
+        let segment = [&publish[..], &[0x30, 0xff, 0xff, 0xff, 0xff, 0x01]].concat();
+        let info = parse_one(&mut parser, &segment, &at(S2C, 550));
+        assert_eq!(info.topic.as_deref(), Some("fleet/1/cmd"));
+        assert!(parser
+            .parse_payload(&[0x30, 0xff, 0xff, 0xff, 0xff, 0x01], &at(S2C, 560))
+            .is_err());
+
+        assert!(parse(&mut parser, &[0xc0, 0], &at(C2S, 600)).is_empty());
+        let info = parse_one(&mut parser, &[0xd0, 0], &at(S2C, 700));
+        assert_eq!(info.packet_type, PacketType::Pingreq);
+        assert_eq!(info.rrt, 100);
+
+        let mut parser = MqttLog::default();
+        parse(&mut parser, &connect(4, "sensor-2", None), &at(C2S, 0));
+        let info = parse_one(&mut parser, &packet(0x20, &[&[0, 5]]), &at(S2C, 100));
+        assert_eq!(info.reason_code, Some(5));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+    }
+
+    #[test]
+    fn mqtt_v5() {
+        let mut parser = MqttLog::default();
+        let config = LogParserConfig::default();
+        let param = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let props = user_property("region", "eu");
+        parse(&mut parser, &connect(5, "", Some(&props)), &param(C2S, 0));
+        let props = properties(&[&[0x12][..], &string("auto-7f3a"), &[0x22, 0, 10]].concat());
+        let info = parse_one(
+            &mut parser,
+            &packet(0x20, &[&[0, 0], &props]),
+            &param(S2C, 100),
+        );
+        assert_eq!(info.version, 5);
+        assert_eq!(info.client_id.as_deref(), Some("auto-7f3a"));
+        assert_eq!(
+            info.user_properties,
+            vec![UserProperty {
+                key: "region".to_owned(),
+                value: "eu".to_owned(),
+            }]
+        );
+
+        // the first publish sets up topic alias 1, the second one uses it
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let props = properties(
+            &[
+                &[0x23, 0, 1][..],
+                &user_property("traceparent", traceparent),
+            ]
+            .concat(),
+        );
+        let publish = packet(0x32, &[&string("fleet/1/temp"), &[0, 1], &props, b"21.5"]);
+        parse(&mut parser, &publish, &param(C2S, 200));
+        let info = parse_one(&mut parser, &packet(0x40, &[&[0, 1]]), &param(S2C, 300));
+        assert_eq!(info.topic_alias, Some(1));
+        assert_eq!(
+            info.trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+
+        let props = properties(&[0x23, 0, 1]);
+        let publish = packet(0x32, &[&string(""), &[0, 2], &props, b"21.7"]);
+        assert!(parse(&mut parser, &publish, &param(C2S, 400)).is_empty());
+        let info = parse_one(
+            &mut parser,
+            &packet(0x40, &[&[0, 2, 0x87, 0]]),
+            &param(S2C, 500),
+        );
+        assert_eq!(info.topic.as_deref(), Some("fleet/1/temp"));
+        assert_eq!(info.reason_code, Some(0x87));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // a qos 2 publish from the broker is one session ending with PUBCOMP
+        let publish = packet(0x35, &[&string("fleet/1/cmd"), &[0, 1], &[0], b"on"]);
+        assert!(parse(&mut parser, &publish, &param(S2C, 600)).is_empty());
+        assert!(parse(&mut parser, &packet(0x50, &[&[0, 1]]), &param(C2S, 700)).is_empty());
+        assert!(parse(&mut parser, &packet(0x62, &[&[0, 1]]), &param(S2C, 800)).is_empty());
+        let info = parse_one(&mut parser, &packet(0x70, &[&[0, 1]]), &param(C2S, 900));
+        assert_eq!(info.packet_type, PacketType::Publish);
+        assert_eq!(info.response_type, Some(PacketType::Pubcomp));
+        assert_eq!((info.qos, info.rrt), (Some(2), 300));
+        assert_eq!(info.topic.as_deref(), Some("fleet/1/cmd"));
+
+        let unsubscribe = packet(0xa2, &[&[0, 3, 0], &string("fleet/+/cmd")]);
+        parse(&mut parser, &unsubscribe, &param(C2S, 1000));
+        let info = parse_one(
+            &mut parser,
+            &packet(0xb0, &[&[0, 3, 0, 0x11]]),
+            &param(S2C, 1100),
+        );
+        assert_eq!(info.subscriptions[0].qos, None);
+        assert_eq!(info.reason_codes, vec![0x11]);
+        assert_eq!(info.status, L7ResponseStatus::Ok);
+
+        let info = parse_one(&mut parser, &packet(0xe0, &[&[0x8b, 0]]), &param(S2C, 1200));
+        assert_eq!(info.packet_type, PacketType::Disconnect);
+        assert_eq!(info.status, L7ResponseStatus::ServerError);
+
+        // unacknowledged publishes time out
+        let publish = packet(0x32, &[&string("fleet/1/temp"), &[0, 4], &[0]]);
+        parse(&mut parser, &publish, &param(C2S, 2000));
+        let info = parse_one(&mut parser, &[0xc0, 0], &param(C2S, 2000 + SESSION_TIMEOUT));
+        assert_eq!(info.packet_id, Some(4));
+        assert_eq!(info.status, L7ResponseStatus::Timeout);
+    }
+}
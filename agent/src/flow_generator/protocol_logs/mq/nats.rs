@@ -0,0 +1,804 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::l7_protocol::L7Protocol;
+use serde::{Deserialize, Serialize};
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PerDirection, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// default max_control_line of nats-server
+const MAX_CONTROL_LINE: usize = 4096;
+// headers and JetStream API replies fit in the head of a message, the rest of
+// a larger payload is skipped instead of buffered
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const HEADER_VERSION: &str = "NATS/1.0";
+const JS_API_PREFIX: &str = "$JS.API.";
+// longest first, so that e.g. CONSUMER.MSG.NEXT is not taken for a consumer named MSG
+const JS_APIS: &[&str] = &[
+    "CONSUMER.DURABLE.CREATE",
+    "CONSUMER.LEADER.STEPDOWN",
+    "STREAM.LEADER.STEPDOWN",
+    "META.LEADER.STEPDOWN",
+    "STREAM.PEER.REMOVE",
+    "CONSUMER.MSG.NEXT",
+    "STREAM.MSG.DELETE",
+    "STREAM.MSG.GET",
+    "CONSUMER.CREATE",
+    "CONSUMER.DELETE",
+    "CONSUMER.NAMES",
+    "CONSUMER.PAUSE",
+    "CONSUMER.INFO",
+    "CONSUMER.LIST",
+    "STREAM.SNAPSHOT",
+    "STREAM.RESTORE",
+    "STREAM.CREATE",
+    "STREAM.UPDATE",
+    "STREAM.DELETE",
+    "STREAM.PURGE",
+    "STREAM.NAMES",
+    "STREAM.INFO",
+    "STREAM.LIST",
+    "ACCOUNT.PURGE",
+    "DIRECT.GET",
+    "INFO",
+];
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Op {
+    Info,
+    Connect,
+    Pub,
+    Hpub,
+    Sub,
+    Unsub,
+    Msg,
+    Hmsg,
+    Ping,
+    Pong,
+    #[serde(rename = "+OK")]
+    Ok,
+    #[serde(rename = "-ERR")]
+    Err,
+    #[default]
+    #[serde(rename = "UNKNOWN")]
+    Unknown,
+}
+
+impl Op {
+    // operation names are case insensitive
+    fn parse(name: &[u8]) -> Self {
+        match name.to_ascii_uppercase().as_slice() {
+            b"INFO" => Self::Info,
+            b"CONNECT" => Self::Connect,
+            b"PUB" => Self::Pub,
+            b"HPUB" => Self::Hpub,
+            b"SUB" => Self::Sub,
+            b"UNSUB" => Self::Unsub,
+            b"MSG" => Self::Msg,
+            b"HMSG" => Self::Hmsg,
+            b"PING" => Self::Ping,
+            b"PONG" => Self::Pong,
+            b"+OK" => Self::Ok,
+            b"-ERR" => Self::Err,
+            _ => Self::Unknown,
+        }
+    }
+
+    fn is_message(&self) -> bool {
+        matches!(self, Self::Pub | Self::Hpub | Self::Msg | Self::Hmsg)
+    }
+}
+
+// -ERR messages caused by the server side, the others are protocol or permission errors
+fn err_status(message: &str) -> L7ResponseStatus {
+    let message = message.to_ascii_lowercase();
+    if [
+        "stale connection",
+        "maximum connections exceeded",
+        "slow consumer",
+    ]
+    .iter()
+    .any(|m| message.contains(m))
+    {
+        L7ResponseStatus::ServerError
+    } else {
+        L7ResponseStatus::ClientError
+    }
+}
+
+// status of a header-only message such as 503 no responders or 408 request timeout,
+// and the code of a JetStream API error
+fn code_status(code: u16) -> L7ResponseStatus {
+    match code {
+        408 => L7ResponseStatus::Timeout,
+        400..=499 => L7ResponseStatus::ClientError,
+        500.. => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::Ok,
+    }
+}
+
+// `$JS.API.STREAM.INFO.ORDERS` is api STREAM.INFO on stream ORDERS
+fn parse_js_api(subject: &str) -> Option<(&'static str, Option<String>)> {
+    let rest = subject.strip_prefix(JS_API_PREFIX)?;
+    let api = JS_APIS.iter().find(|api| {
+        rest.strip_prefix(**api)
+            .is_some_and(|s| s.is_empty() || s.starts_with('.'))
+    })?;
+    let stream = rest[api.len()..]
+        .strip_prefix('.')
+        .and_then(|s| s.split('.').next())
+        .filter(|_| *api != "INFO" && *api != "ACCOUNT.PURGE" && !api.starts_with("META."))
+        .map(str::to_owned);
+    Some((api, stream))
+}
+
+#[derive(Deserialize)]
+struct ConnectOptions {
+    name: Option<String>,
+    lang: Option<String>,
+    version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServerInfo {
+    server_name: Option<String>,
+    version: Option<String>,
+}
+
+// JetStream API responses and publish acks
+#[derive(Deserialize)]
+struct JsReply {
+    #[serde(rename = "type")]
+    reply_type: Option<String>,
+    error: Option<JsError>,
+    stream: Option<String>,
+    seq: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct JsError {
+    code: u16,
+    description: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct NatsInfo {
+    pub msg_type: LogMessageType,
+    pub op: Op,
+    // from CONNECT, filled in on every operation of the connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_lang: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    // from INFO, on CONNECT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub js_api: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a reply is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_op: Option<Op>,
+    // header status of the reply, or the code of a JetStream API error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // sequence in the stream of a JetStream publish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    #[serde(skip)]
+    js_reply: Option<Vec<u8>>,
+}
+
+impl NatsInfo {
+    fn parse_args(&mut self, args: &[&str]) -> Result<()> {
+        let invalid = || Error::NatsLogParseFailed(format!("invalid {:?} arguments", args));
+        let size = |s: &str| s.parse::<u32>().map_err(|_| invalid());
+        // the size arguments come last, with headers first when present
+        let sizes = match self.op {
+            Op::Hpub | Op::Hmsg => 2,
+            _ => 1,
+        };
+        match (self.op, args.len()) {
+            (Op::Pub | Op::Hpub, n) if n == 1 + sizes || n == 2 + sizes => {
+                self.subject = Some(args[0].to_owned());
+                if n == 2 + sizes {
+                    self.reply_to = Some(args[1].to_owned());
+                }
+            }
+            (Op::Msg | Op::Hmsg, n) if n == 2 + sizes || n == 3 + sizes => {
+                self.subject = Some(args[0].to_owned());
+                self.sid = Some(args[1].to_owned());
+                if n == 3 + sizes {
+                    self.reply_to = Some(args[2].to_owned());
+                }
+            }
+            (Op::Sub, 2 | 3) => {
+                self.subject = Some(args[0].to_owned());
+                if args.len() == 3 {
+                    self.queue_group = Some(args[1].to_owned());
+                }
+                self.sid = Some(args[args.len() - 1].to_owned());
+            }
+            (Op::Unsub, 1 | 2) => self.sid = Some(args[0].to_owned()),
+            _ => return Err(invalid()),
+        }
+        if self.op.is_message() {
+            let total = size(args[args.len() - 1])?;
+            if sizes == 2 {
+                let header = size(args[args.len() - 2])?;
+                if header > total {
+                    return Err(invalid());
+                }
+                self.header_size = Some(header);
+                self.payload_size = Some(total - header);
+            } else {
+                self.payload_size = Some(total);
+            }
+        }
+        Ok(())
+    }
+
+    // NATS/1.0[ status[ description]]\r\n followed by `key: value` lines
+    fn parse_headers(&mut self, headers: &[u8], config: Option<&LogParserConfig>) -> Result<()> {
+        let headers = String::from_utf8_lossy(headers);
+        let mut lines = headers.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let Some(status) = status_line.strip_prefix(HEADER_VERSION) else {
+            return Err(Error::NatsLogParseFailed(format!(
+                "invalid header version {:?}",
+                status_line
+            )));
+        };
+        let mut status = status.trim().splitn(2, ' ');
+        if let Some(code) = status.next().and_then(|c| c.parse::<u16>().ok()) {
+            self.status_code = Some(code);
+            self.status = code_status(code);
+            self.error_message = status.next().map(|d| d.trim().to_owned());
+        }
+        let Some(config) = config else {
+            return Ok(());
+        };
+        let fields = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .collect::<Vec<_>>();
+        self.trace = TraceContext::extract(config, |key| {
+            fields
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| *v)
+        });
+        Ok(())
+    }
+
+    fn merge(&mut self, response: NatsInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_op = Some(response.op);
+        self.status_code = response.status_code;
+        self.error_message = response.error_message;
+        self.status = response.status;
+        if let Some(reply) = response.js_reply {
+            self.merge_js_reply(&reply);
+        }
+    }
+
+    fn merge_js_reply(&mut self, reply: &[u8]) {
+        let Ok(reply) = serde_json::from_slice::<JsReply>(reply) else {
+            return;
+        };
+        let is_api = self.js_api.is_some()
+            || reply
+                .reply_type
+                .as_deref()
+                .is_some_and(|t| t.starts_with("io.nats.jetstream."));
+        // a publish to a stream subject is acked with its stream and sequence
+        if is_api || reply.stream.is_some() && reply.seq.is_some() {
+            if let Some(error) = reply.error {
+                self.status_code = Some(error.code);
+                self.status = code_status(error.code);
+                self.error_message = error.description;
+            }
+            if self.stream.is_none() {
+                self.stream = reply.stream;
+            }
+            self.seq = reply.seq;
+        }
+    }
+}
+
+// how a reply finds its request on the same connection
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SessionKey {
+    // answered by +OK, -ERR or the PONG of the PING sent along with it
+    Connect,
+    Ping(PacketDirection),
+    // a request published with a reply inbox, by the direction it was sent in
+    Reply {
+        direction: PacketDirection,
+        inbox: String,
+    },
+}
+
+#[derive(Default)]
+pub struct NatsLog {
+    streams: PerDirection<Stream>,
+    client: Option<ConnectOptions>,
+    server: Option<ServerInfo>,
+    pending: HashMap<SessionKey, NatsInfo>,
+}
+
+impl NatsLog {
+    // an operation from its control line, None until the line is complete
+    fn parse_control_line(
+        &mut self,
+        data: &[u8],
+        param: &ParseParam,
+    ) -> Result<Option<(usize, NatsInfo)>> {
+        let Some(end) = data.windows(2).position(|w| w == b"\r\n") else {
+            if data.len() > MAX_CONTROL_LINE {
+                return Err(Error::NatsLogParseFailed(
+                    "control line too long".to_owned(),
+                ));
+            }
+            return Ok(None);
+        };
+        let line = &data[..end];
+        let (name, args) = match line.iter().position(|b| *b == b' ' || *b == b'\t') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, &[][..]),
+        };
+        let op = Op::parse(name);
+        if op == Op::Unknown {
+            return Err(Error::NatsLogParseFailed(format!(
+                "unknown operation {:?}",
+                String::from_utf8_lossy(name)
+            )));
+        }
+        let args = String::from_utf8_lossy(args);
+        let mut info = NatsInfo {
+            op,
+            time: param.time,
+            ..Default::default()
+        };
+        match op {
+            Op::Info | Op::Connect => self.parse_json(&mut info, args.trim()),
+            Op::Err => {
+                let message = args.trim().trim_matches('\'');
+                info.status = err_status(message);
+                info.error_message = Some(message.to_owned());
+            }
+            Op::Ping | Op::Pong | Op::Ok => (),
+            _ => {
+                let args = args.split_ascii_whitespace().collect::<Vec<_>>();
+                info.parse_args(&args)?;
+            }
+        }
+        Ok(Some((end + 2, info)))
+    }
+
+    fn parse_ops(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<NatsInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while !remain.is_empty() {
+            let (line_size, mut info) = match self.parse_control_line(remain, param) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break,
+                Err(e) => {
+                    // the rest of the segment can not be split into operations, keep what is parsed
+                    debug!("drop nats segment: {}", e);
+                    last_error = Some(e);
+                    remain = &[];
+                    break;
+                }
+            };
+            if !info.op.is_message() {
+                remain = &remain[line_size..];
+                infos.push(info);
+                continue;
+            }
+
+            // [headers][payload]\r\n
+            let message_size = info.header_size.unwrap_or_default() as usize
+                + info.payload_size.unwrap_or_default() as usize;
+            let total_size = line_size + message_size + 2;
+            let message = if remain.len() >= total_size {
+                let message = &remain[line_size..line_size + message_size];
+                remain = &remain[total_size..];
+                message
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[line_size..]
+            } else {
+                break;
+            };
+            let header_size = info.header_size.unwrap_or_default() as usize;
+            if header_size > 0 {
+                let headers = &message[..header_size.min(message.len())];
+                if let Err(e) = info.parse_headers(headers, param.parse_config) {
+                    debug!("skip nats headers: {}", e);
+                    last_error = Some(e);
+                }
+            }
+            if message.len() > header_size && message[header_size] == b'{' {
+                info.js_reply = Some(message[header_size..].to_vec());
+            }
+            infos.push(info);
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn parse_json(&mut self, info: &mut NatsInfo, json: &str) {
+        if info.op == Op::Connect {
+            match serde_json::from_str::<ConnectOptions>(json) {
+                Ok(options) => self.client = Some(options),
+                Err(e) => debug!("skip nats connect options: {}", e),
+            }
+        } else {
+            match serde_json::from_str::<ServerInfo>(json) {
+                Ok(server) => self.server = Some(server),
+                Err(e) => debug!("skip nats server info: {}", e),
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        mut info: NatsInfo,
+        direction: PacketDirection,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        if let Some(client) = self.client.as_ref() {
+            info.client_name = client.name.clone();
+            if info.op == Op::Connect {
+                info.client_lang = client.lang.clone();
+                info.client_version = client.version.clone();
+            }
+        }
+        let connecting = self.pending.contains_key(&SessionKey::Connect);
+        let (key, is_request) = match info.op {
+            // the server sends it again on cluster changes
+            Op::Info => return,
+            Op::Connect => {
+                if let Some(server) = self.server.as_ref() {
+                    info.server_name = server.server_name.clone();
+                    info.server_version = server.version.clone();
+                }
+                (Some(SessionKey::Connect), true)
+            }
+            // sent right after CONNECT to wait for the server to accept it
+            Op::Ping if connecting && direction == PacketDirection::ClientToServer => return,
+            Op::Ping => (Some(SessionKey::Ping(direction)), true),
+            Op::Pong if connecting && direction == PacketDirection::ServerToClient => {
+                (Some(SessionKey::Connect), false)
+            }
+            Op::Pong => (Some(SessionKey::Ping(direction.reversed())), false),
+            Op::Ok | Op::Err if connecting => (Some(SessionKey::Connect), false),
+            // acks every operation in verbose mode
+            Op::Ok => return,
+            _ if info.op.is_message() => {
+                let reply = SessionKey::Reply {
+                    direction: direction.reversed(),
+                    inbox: info.subject.clone().unwrap_or_default(),
+                };
+                if self.pending.contains_key(&reply) {
+                    (Some(reply), false)
+                } else if let Some(inbox) = info.reply_to.clone() {
+                    if let Some((api, stream)) = info.subject.as_deref().and_then(parse_js_api) {
+                        info.js_api = Some(api.to_owned());
+                        info.stream = stream;
+                    }
+                    (Some(SessionKey::Reply { direction, inbox }), true)
+                } else {
+                    (None, false)
+                }
+            }
+            _ => (None, false),
+        };
+        info.msg_type = match (&key, is_request) {
+            (_, true) => LogMessageType::Request,
+            (Some(_), false) => LogMessageType::Response,
+            // publishes are one-way without a reply inbox
+            (None, _) if matches!(info.op, Op::Pub | Op::Hpub) => LogMessageType::Request,
+            (None, _) => LogMessageType::Other,
+        };
+
+        let Some(key) = key else {
+            output.push(L7ProtocolInfo::NatsInfo(info));
+            return;
+        };
+        if is_request {
+            if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                output.push(L7ProtocolInfo::NatsInfo(info));
+            } else if let Some(prev) = self.pending.insert(key, info) {
+                output.push(L7ProtocolInfo::NatsInfo(prev));
+            }
+            return;
+        }
+        match self.pending.remove(&key) {
+            Some(mut request) => {
+                request.merge(info);
+                output.push(L7ProtocolInfo::NatsInfo(request));
+            }
+            None => output.push(L7ProtocolInfo::NatsInfo(info)),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for NatsLog {
+    // a connection starts with INFO from the server, followed by CONNECT from the client
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        let prefix = match param.direction {
+            PacketDirection::ServerToClient => &b"INFO {"[..],
+            PacketDirection::ClientToServer => &b"CONNECT {"[..],
+        };
+        payload.len() > prefix.len()
+            && payload[..prefix.len()].eq_ignore_ascii_case(prefix)
+            && payload.windows(2).any(|w| w == b"\r\n")
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_ops(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, param.direction, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::NATS
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.client = None;
+        self.server = None;
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    #[test]
+    fn request_reply() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = NatsLog::default();
+        let info = br#"INFO {"server_id":"NDX","server_name":"nats-0","version":"2.10.7","headers":true,"max_payload":1048576}"#;
+        let info = [&info[..], b"\r\n"].concat();
+        assert!(parser.check_payload(&info, &at(S2C, 0)));
+        assert!(parse(&mut parser, &info, &at(S2C, 0)).is_empty());
+        let connect = b"CONNECT {\"verbose\":false,\"name\":\"billing\",\"lang\":\"go\",\"version\":\"1.31.0\",\"headers\":true}\r\nPING\r\n";
+        assert!(parser.check_payload(connect, &at(C2S, 10)));
+        assert!(parse(&mut parser, connect, &at(C2S, 10)).is_empty());
+        let info = parse(&mut parser, b"PONG\r\n", &at(S2C, 30)).pop().unwrap();
+        assert_eq!((info.op, info.response_op), (Op::Connect, Some(Op::Pong)));
+        assert_eq!(info.server_version.as_deref(), Some("2.10.7"));
+        assert_eq!(info.client_lang.as_deref(), Some("go"));
+        assert_eq!(info.rrt, 20);
+
+        let sub = b"SUB _INBOX.abc.* 1\r\nsub orders.* workers 2\r\n";
+        let infos = parse(&mut parser, sub, &at(C2S, 40));
+        assert_eq!(infos[1].queue_group.as_deref(), Some("workers"));
+        assert_eq!(infos[1].sid.as_deref(), Some("2"));
+        assert_eq!(infos[1].client_name.as_deref(), Some("billing"));
+
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let headers = format!("NATS/1.0\r\ntraceparent: {}\r\n\r\n", traceparent);
+        let request = format!(
+            "HPUB rates.eur _INBOX.abc.1 {} {}\r\n{}{{}}\r\n",
+            headers.len(),
+            headers.len() + 2,
+            headers
+        );
+        // split in the middle of the headers
+        let (head, tail) = request.as_bytes().split_at(40);
+        assert!(parse(&mut parser, head, &at(C2S, 100)).is_empty());
+        assert!(parse(&mut parser, tail, &at(C2S, 100)).is_empty());
+        let info = parse(
+            &mut parser,
+            b"MSG _INBOX.abc.1 1 4\r\n1.08\r\n",
+            &at(S2C, 350),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.subject.as_deref(), Some("rates.eur"));
+        assert_eq!(info.payload_size, Some(2));
+        assert_eq!(info.rrt, 250);
+        assert_eq!(
+            info.trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+
+        // no responders
+        parse(
+            &mut parser,
+            b"PUB rates.jpy _INBOX.abc.2 0\r\n\r\n",
+            &at(C2S, 400),
+        );
+        let reply = b"HMSG _INBOX.abc.2 1 16 16\r\nNATS/1.0 503\r\n\r\n\r\n";
+        let info = parse(&mut parser, reply, &at(S2C, 410)).pop().unwrap();
+        assert_eq!(info.status_code, Some(503));
+        assert_eq!(info.status, L7ResponseStatus::ServerError);
+
+        // served on this connection
+        let request = b"MSG orders.new 2 workers-reply.9 3\r\nabc\r\n";
+        assert!(parse(&mut parser, request, &at(S2C, 500)).is_empty());
+        let reply = b"PUB workers-reply.9 2\r\nok\r\n";
+        let info = parse(&mut parser, reply, &at(C2S, 520)).pop().unwrap();
+        assert_eq!(info.op, Op::Msg);
+        assert_eq!(info.response_op, Some(Op::Pub));
+        assert_eq!((info.rrt, info.status), (20, L7ResponseStatus::Ok));
+
+        let info = parse(
+            &mut parser,
+            b"-ERR 'Permissions Violation for Publish to \"admin\"'\r\n",
+            &at(S2C, 600),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.op, Op::Err);
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+    }
+
+    #[test]
+    fn invalid_operation() {
+        let param = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = NatsLog::default();
+        let request = b"PUB rates.eur _INBOX.abc.1 0\r\n\r\nBOGUS rates\r\n";
+        assert!(parse(&mut parser, request, &param(C2S, 0)).is_empty());
+        let info = parse(
+            &mut parser,
+            b"MSG _INBOX.abc.1 1 4\r\n1.08\r\n",
+            &param(S2C, 10),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.subject.as_deref(), Some("rates.eur"));
+
+        let result = parser.parse_payload(b"BOGUS rates\r\n", &param(C2S, 20));
+        assert!(matches!(result, Err(Error::NatsLogParseFailed(_))));
+    }
+
+    #[test]
+    fn jetstream_api() {
+        assert_eq!(
+            parse_js_api("$JS.API.CONSUMER.MSG.NEXT.ORDERS.worker"),
+            Some(("CONSUMER.MSG.NEXT", Some("ORDERS".to_owned())))
+        );
+        assert_eq!(parse_js_api("$JS.API.INFO"), Some(("INFO", None)));
+        assert_eq!(parse_js_api("orders.new"), None);
+
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = NatsLog::default();
+        let request = b"PUB $JS.API.STREAM.INFO.MISSING _INBOX.x.1 0\r\n\r\n";
+        parse(&mut parser, request, &at(C2S, 0));
+        let body = br#"{"type":"io.nats.jetstream.api.v1.stream_info_response","error":{"code":404,"err_code":10059,"description":"stream not found"}}"#;
+        let reply = [
+            format!("MSG _INBOX.x.1 1 {}\r\n", body.len()).as_bytes(),
+            body,
+            b"\r\n",
+        ]
+        .concat();
+        let info = parse(&mut parser, &reply, &at(S2C, 80)).pop().unwrap();
+        assert_eq!(info.js_api.as_deref(), Some("STREAM.INFO"));
+        assert_eq!(info.stream.as_deref(), Some("MISSING"));
+        assert_eq!(info.status_code, Some(404));
+        assert_eq!(info.error_message.as_deref(), Some("stream not found"));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+
+        // publish acked by the stream
+        parse(
+            &mut parser,
+            b"PUB orders.new _INBOX.x.2 2\r\n{}\r\n",
+            &at(C2S, 100),
+        );
+        let ack = br#"{"stream":"ORDERS","seq":42}"#;
+        let reply = [
+            format!("MSG _INBOX.x.2 1 {}\r\n", ack.len()).as_bytes(),
+            ack,
+            b"\r\n",
+        ]
+        .concat();
+        let info = parse(&mut parser, &reply, &at(S2C, 130)).pop().unwrap();
+        assert_eq!(info.js_api, None);
+        assert_eq!(
+            (info.stream.as_deref(), info.seq),
+            (Some("ORDERS"), Some(42))
+        );
+        assert_eq!(info.rrt, 30);
+
+        // a large message is cut at its head
+        let payload = vec![b'x'; MAX_HEAD_SIZE * 2];
+        let message = [
+            format!("PUB orders.big {}\r\n", payload.len()).as_bytes(),
+            &payload,
+            b"\r\nPING\r\n",
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in message.chunks(1400) {
+            infos.extend(parse(&mut parser, segment, &at(C2S, 200)));
+        }
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].payload_size, Some((MAX_HEAD_SIZE * 2) as u32));
+        let info = parse(&mut parser, b"PONG\r\n", &at(S2C, 210))
+            .pop()
+            .unwrap();
+        assert_eq!((info.op, info.rrt), (Op::Ping, 10));
+    }
+}
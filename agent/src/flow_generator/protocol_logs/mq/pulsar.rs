@@ -0,0 +1,1696 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::mem;
+
+use flate2::read::ZlibDecoder;
+use log::debug;
+use prost::Message;
+use public::bytes::{read_u16_be, read_u32_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::pulsar_consumer::ConsumerTracker;
+use super::pulsar_lookup::LookupTracker;
+use super::pulsar_proto::{
+    base_command::Type as CommandType, command_lookup_topic_response,
+    command_partitioned_topic_metadata_response, command_topic_migrated::ResourceType, BaseCommand,
+    BrokerEntryMetadata, CompressionType, KeyValue, MessageMetadata, ServerError,
+    SingleMessageMetadata,
+};
+use super::pulsar_txn::TransactionTracker;
+use super::MAX_PENDING_SESSIONS;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PendingRequest, PerDirection,
+    Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+const MAGIC_CRC32C: u16 = 0x0e01;
+const MAGIC_BROKER_ENTRY_METADATA: u16 = 0x0e02;
+// Commands.DEFAULT_MAX_MESSAGE_SIZE + Commands.MESSAGE_SIZE_FRAME_PADDING
+const MAX_FRAME_SIZE: usize = 5 * 1024 * 1024 + 10 * 1024;
+
+// a single message in a batched SEND or MESSAGE payload
+#[derive(Debug, Default, Clone, PartialEq)]
+struct BatchEntry {
+    metadata: SingleMessageMetadata,
+    // producers only set it when it differs from the batch's base sequence id
+    sequence_id: u64,
+    trace: TraceContext,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PulsarInfo {
+    pub msg_type: LogMessageType,
+    pub command_type: CommandType,
+    // totalSize field of the frame, not including the field itself
+    pub frame_size: u32,
+    pub command: Box<BaseCommand>,
+
+    // only SEND and MESSAGE carry the payload section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_entry_metadata: Option<BrokerEntryMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+    pub payload_size: u32,
+    // with batch expansion enabled, a record is logged per message of a batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_metadata: Option<SingleMessageMetadata>,
+    #[serde(skip)]
+    entries: Vec<BatchEntry>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub producer_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub producer_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<String>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_type: Option<CommandType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_frame_size: Option<u32>,
+    pub status: L7ResponseStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ServerError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl PulsarInfo {
+    // `frame` is everything after the totalSize field:
+    //   [commandSize][command][magic 0x0e02][brokerEntryMetadataSize][brokerEntryMetadata]
+    //   [magic 0x0e01][checksum][metadataSize][metadata][payload]
+    // and the sections after the command are present for SEND and MESSAGE only
+    fn parse(frame: &[u8], config: Option<&LogParserConfig>) -> Result<Self> {
+        if frame.len() < 4 {
+            return Err(Error::InsufficientPayloadLength);
+        }
+        let command_size = read_u32_be(frame) as usize;
+        let Some(command) = frame.get(4..4 + command_size) else {
+            return Err(Error::PulsarLogParseFailed(format!(
+                "command size {} exceeds frame size {}",
+                command_size,
+                frame.len()
+            )));
+        };
+        let command =
+            BaseCommand::decode(command).map_err(|e| Error::PulsarLogParseFailed(e.to_string()))?;
+        let command_type = CommandType::try_from(command.r#type).map_err(|_| {
+            Error::PulsarLogParseFailed(format!("unknown command type {}", command.r#type))
+        })?;
+
+        let mut info = PulsarInfo {
+            msg_type: message_type(command_type),
+            command_type,
+            frame_size: frame.len() as u32,
+            command: Box::new(command),
+            ..Default::default()
+        };
+        let rest = &frame[4 + command_size..];
+        if !rest.is_empty() {
+            if !matches!(command_type, CommandType::Send | CommandType::Message) {
+                return Err(Error::PulsarLogParseFailed(format!(
+                    "unexpected payload after command {}",
+                    command_type.as_str_name()
+                )));
+            }
+            let payload = info.parse_payload_section(rest)?;
+            if let Some(config) = config {
+                if config.expand_batch {
+                    if let Err(e) = info.expand_batch(payload, config) {
+                        debug!("skip pulsar batch: {}", e);
+                    }
+                }
+                info.extract_trace(config);
+            }
+        }
+        info.fill_fields();
+        Ok(info)
+    }
+
+    fn parse_payload_section<'a>(&mut self, mut payload: &'a [u8]) -> Result<&'a [u8]> {
+        if payload.len() >= 6 && read_u16_be(payload) == MAGIC_BROKER_ENTRY_METADATA {
+            let (metadata, rest) = split_sized(&payload[2..])?;
+            self.broker_entry_metadata = Some(
+                BrokerEntryMetadata::decode(metadata)
+                    .map_err(|e| Error::PulsarLogParseFailed(e.to_string()))?,
+            );
+            payload = rest;
+        }
+        if payload.len() >= 6 && read_u16_be(payload) == MAGIC_CRC32C {
+            self.checksum = Some(read_u32_be(&payload[2..]));
+            payload = &payload[6..];
+        }
+        let (metadata, rest) = split_sized(payload)?;
+        self.metadata = Some(
+            MessageMetadata::decode(metadata)
+                .map_err(|e| Error::PulsarLogParseFailed(e.to_string()))?,
+        );
+        self.payload_size = rest.len() as u32;
+        Ok(rest)
+    }
+
+    // batch payload, possibly compressed as a whole:
+    //   [singleMetadataSize][singleMetadata][singlePayload] * numMessagesInBatch
+    fn expand_batch(&mut self, payload: &[u8], config: &LogParserConfig) -> Result<()> {
+        let Some(metadata) = self.metadata.as_ref() else {
+            return Ok(());
+        };
+        let Some(count) = metadata.num_messages_in_batch else {
+            return Ok(());
+        };
+        let compression = metadata.compression.unwrap_or_default();
+        let compression = CompressionType::try_from(compression).map_err(|_| {
+            Error::PulsarLogParseFailed(format!("unknown compression type {}", compression))
+        })?;
+        let uncompressed_size = metadata.uncompressed_size.unwrap_or_default() as usize;
+        let base_sequence_id = metadata.sequence_id;
+
+        let decompressed;
+        let mut entries = match compression {
+            CompressionType::None => payload,
+            _ if config.max_decompressed_size == 0 => {
+                return Err(Error::PulsarLogParseFailed(
+                    "decompression disabled".to_owned(),
+                ));
+            }
+            _ if metadata.uncompressed_size.is_none() => {
+                return Err(Error::PulsarLogParseFailed(
+                    "compressed batch without uncompressed size".to_owned(),
+                ));
+            }
+            _ => {
+                if uncompressed_size > config.max_decompressed_size {
+                    return Err(Error::PulsarLogParseFailed(format!(
+                        "uncompressed size {} exceeds limit {}",
+                        uncompressed_size, config.max_decompressed_size
+                    )));
+                }
+                decompressed = decompress(compression, payload, uncompressed_size)?;
+                &decompressed[..]
+            }
+        };
+
+        // a batch failing halfway is dropped as a whole
+        let mut batch = Vec::with_capacity((count.max(0) as usize).min(64));
+        for i in 0..count.max(0) as u64 {
+            let (single, rest) = split_sized(entries)?;
+            let mut single = SingleMessageMetadata::decode(single)
+                .map_err(|e| Error::PulsarLogParseFailed(e.to_string()))?;
+            single.event_time = single.event_time.filter(|t| *t > 0);
+            let size = single.payload_size.max(0) as usize;
+            if size > rest.len() {
+                return Err(Error::PulsarLogParseFailed(format!(
+                    "batch message size {} exceeds remaining {}",
+                    size,
+                    rest.len()
+                )));
+            }
+            entries = &rest[size..];
+            batch.push(BatchEntry {
+                sequence_id: single
+                    .sequence_id
+                    .unwrap_or(base_sequence_id.wrapping_add(i)),
+                trace: trace_from_properties(&single.properties, config),
+                metadata: single,
+            });
+        }
+        self.entries = batch;
+        Ok(())
+    }
+
+    // one record per message of the batch, each with its own metadata
+    fn split_batch(mut self) -> Vec<Self> {
+        let entries = mem::take(&mut self.entries);
+        if entries.is_empty() {
+            return vec![self];
+        }
+        entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let mut info = self.clone();
+                info.batch_index = Some(i as u32);
+                info.sequence_id = Some(entry.sequence_id);
+                info.payload_size = entry.metadata.payload_size.max(0) as u32;
+                if !entry.trace.is_empty() {
+                    info.trace = entry.trace;
+                }
+                info.single_metadata = Some(entry.metadata);
+                info
+            })
+            .collect()
+    }
+
+    // copies the identifiers and names carried by the command itself
+    fn fill_fields(&mut self) {
+        let c = &self.command;
+        let mut error = None;
+        let mut error_message = None;
+        if let Some(m) = &c.subscribe {
+            self.topic = Some(m.topic.clone());
+            self.subscription = Some(m.subscription.clone());
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+            self.consumer_name = m.consumer_name.clone();
+        } else if let Some(m) = &c.producer {
+            self.topic = Some(m.topic.clone());
+            self.producer_id = Some(m.producer_id);
+            self.request_id = Some(m.request_id);
+            self.producer_name = m.producer_name.clone();
+        } else if let Some(m) = &c.send {
+            self.producer_id = Some(m.producer_id);
+            self.sequence_id = Some(m.sequence_id);
+        } else if let Some(m) = &c.send_receipt {
+            self.producer_id = Some(m.producer_id);
+            self.sequence_id = Some(m.sequence_id);
+        } else if let Some(m) = &c.send_error {
+            self.producer_id = Some(m.producer_id);
+            self.sequence_id = Some(m.sequence_id);
+            error = Some(m.error);
+            error_message = Some(&m.message);
+        } else if let Some(m) = &c.message {
+            self.consumer_id = Some(m.consumer_id);
+        } else if let Some(m) = &c.ack {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = m.request_id;
+        } else if let Some(m) = &c.ack_response {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = m.request_id;
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.flow {
+            self.consumer_id = Some(m.consumer_id);
+        } else if let Some(m) = &c.unsubscribe {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.success {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.error {
+            self.request_id = Some(m.request_id);
+            error = Some(m.error);
+            error_message = Some(&m.message);
+        } else if let Some(m) = &c.close_producer {
+            self.producer_id = Some(m.producer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.close_consumer {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.producer_success {
+            self.request_id = Some(m.request_id);
+            self.producer_name = Some(m.producer_name.clone());
+        } else if let Some(m) = &c.redeliver_unacknowledged_messages {
+            self.consumer_id = Some(m.consumer_id);
+        } else if let Some(m) = &c.partition_metadata {
+            self.topic = Some(m.topic.clone());
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.partition_metadata_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+            if error.is_none()
+                && m.response
+                    == Some(command_partitioned_topic_metadata_response::LookupType::Failed as i32)
+            {
+                error = Some(ServerError::UnknownError as i32);
+            }
+        } else if let Some(m) = &c.lookup_topic {
+            self.topic = Some(m.topic.clone());
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.lookup_topic_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+            if error.is_none()
+                && m.response == Some(command_lookup_topic_response::LookupType::Failed as i32)
+            {
+                error = Some(ServerError::UnknownError as i32);
+            }
+        } else if let Some(m) = &c.consumer_stats {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.consumer_stats_response {
+            self.request_id = Some(m.request_id);
+            error = m.error_code;
+            error_message = m.error_message.as_ref();
+        } else if let Some(m) = &c.reached_end_of_topic {
+            self.consumer_id = Some(m.consumer_id);
+        } else if let Some(m) = &c.seek {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_last_message_id {
+            self.consumer_id = Some(m.consumer_id);
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_last_message_id_response {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.active_consumer_change {
+            self.consumer_id = Some(m.consumer_id);
+        } else if let Some(m) = &c.get_topics_of_namespace {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_topics_of_namespace_response {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_schema {
+            self.topic = Some(m.topic.clone());
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_schema_response {
+            self.request_id = Some(m.request_id);
+            error = m.error_code;
+            error_message = m.error_message.as_ref();
+        } else if let Some(m) = &c.get_or_create_schema {
+            self.topic = Some(m.topic.clone());
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.get_or_create_schema_response {
+            self.request_id = Some(m.request_id);
+            error = m.error_code;
+            error_message = m.error_message.as_ref();
+        } else if let Some(m) = &c.new_txn {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.new_txn_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.add_partition_to_txn {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.add_partition_to_txn_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.add_subscription_to_txn {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.add_subscription_to_txn_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.end_txn {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.end_txn_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.end_txn_on_partition {
+            self.topic = m.topic.clone();
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.end_txn_on_partition_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.end_txn_on_subscription {
+            if let Some(s) = &m.subscription {
+                self.topic = Some(s.topic.clone());
+                self.subscription = Some(s.subscription.clone());
+            }
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.end_txn_on_subscription_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.tc_client_connect_request {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.tc_client_connect_response {
+            self.request_id = Some(m.request_id);
+            error = m.error;
+            error_message = m.message.as_ref();
+        } else if let Some(m) = &c.watch_topic_list {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.watch_topic_list_success {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.watch_topic_list_close {
+            self.request_id = Some(m.request_id);
+        } else if let Some(m) = &c.topic_migrated {
+            match ResourceType::try_from(m.resource_type) {
+                Ok(ResourceType::Producer) => self.producer_id = Some(m.resource_id),
+                Ok(ResourceType::Consumer) => self.consumer_id = Some(m.resource_id),
+                Err(_) => (),
+            }
+        }
+
+        if let Some(e) = error {
+            let e = ServerError::try_from(e).unwrap_or(ServerError::UnknownError);
+            self.status = error_status(e);
+            self.error = Some(e);
+            self.error_message = error_message.cloned();
+        }
+        if self.producer_name.is_none() {
+            self.producer_name = self.metadata.as_ref().map(|m| m.producer_name.clone());
+        }
+    }
+
+    fn extract_trace(&mut self, config: &LogParserConfig) {
+        if let Some(m) = self.metadata.as_ref() {
+            self.trace = trace_from_properties(&m.properties, config);
+        }
+    }
+
+    fn session_key(&self) -> Option<SessionKey> {
+        match self.command_type {
+            CommandType::Connect | CommandType::Connected => Some(SessionKey::Connect),
+            CommandType::Ping | CommandType::Pong => Some(SessionKey::Ping),
+            CommandType::AuthChallenge | CommandType::AuthResponse => Some(SessionKey::Auth),
+            CommandType::Send | CommandType::SendReceipt | CommandType::SendError => {
+                Some(SessionKey::Send {
+                    producer_id: self.producer_id?,
+                    sequence_id: self.sequence_id?,
+                })
+            }
+            _ => self.request_id.map(SessionKey::RequestId),
+        }
+    }
+
+    fn merge(&mut self, response: PulsarInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_type = Some(response.command_type);
+        self.response_frame_size = Some(response.frame_size);
+        self.status = response.status;
+        self.error = response.error;
+        self.error_message = response.error_message;
+        if self.producer_name.is_none() {
+            self.producer_name = response.producer_name;
+        }
+        if self.trace.is_empty() {
+            self.trace = response.trace;
+        }
+    }
+
+    fn fill_client(&mut self, client: &Client) {
+        if self.topic.is_none() {
+            self.topic = Some(client.topic.clone());
+        }
+        if self.producer_id.is_some() && self.producer_name.is_none() {
+            self.producer_name = client.name.clone();
+        }
+        if self.consumer_id.is_some() && self.consumer_name.is_none() {
+            self.consumer_name = client.name.clone();
+        }
+        if self.subscription.is_none() {
+            self.subscription = client.subscription.clone();
+        }
+    }
+}
+
+fn trace_from_properties(properties: &[KeyValue], config: &LogParserConfig) -> TraceContext {
+    TraceContext::extract(config, |key| {
+        properties
+            .iter()
+            .find(|p| p.key.eq_ignore_ascii_case(key))
+            .map(|p| p.value.as_str())
+    })
+}
+
+// batched messages carry their key, event time and properties in the single message metadata
+fn split_batches(output: Vec<L7ProtocolInfo>) -> Vec<L7ProtocolInfo> {
+    let mut split = Vec::with_capacity(output.len());
+    for info in output {
+        match info {
+            L7ProtocolInfo::PulsarInfo(info) if !info.entries.is_empty() => split.extend(
+                info.split_batch()
+                    .into_iter()
+                    .map(L7ProtocolInfo::PulsarInfo),
+            ),
+            info => split.push(info),
+        }
+    }
+    split
+}
+
+fn error_status(error: ServerError) -> L7ResponseStatus {
+    match error {
+        ServerError::UnknownError
+        | ServerError::MetadataError
+        | ServerError::PersistenceError
+        | ServerError::ServiceNotReady
+        | ServerError::ConsumerAssignError
+        | ServerError::TransactionCoordinatorNotFound => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+// pulsar compresses the whole batch payload without any framing,
+// the codec is given the uncompressed size recorded in the metadata
+fn decompress(compression: CompressionType, data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let failed = |e: String| {
+        Error::PulsarLogParseFailed(format!(
+            "{} decompression failed: {}",
+            compression.as_str_name(),
+            e
+        ))
+    };
+    let output = match compression {
+        CompressionType::None => data.to_vec(),
+        CompressionType::Lz4 => {
+            lz4_flex::block::decompress(data, size).map_err(|e| failed(e.to_string()))?
+        }
+        CompressionType::Zlib => {
+            let mut output = Vec::with_capacity(size);
+            ZlibDecoder::new(data)
+                .take(size as u64 + 1)
+                .read_to_end(&mut output)
+                .map_err(|e| failed(e.to_string()))?;
+            output
+        }
+        CompressionType::Zstd => {
+            zstd::bulk::decompress(data, size).map_err(|e| failed(e.to_string()))?
+        }
+        CompressionType::Snappy => {
+            let len = snap::raw::decompress_len(data).map_err(|e| failed(e.to_string()))?;
+            if len > size {
+                return Err(failed(format!("size {} exceeds {}", len, size)));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| failed(e.to_string()))?
+        }
+    };
+    if output.len() != size {
+        return Err(failed(format!(
+            "got {} bytes, expected {}",
+            output.len(),
+            size
+        )));
+    }
+    Ok(output)
+}
+
+// splits a [size: u32][data] section off the head of `payload`
+fn split_sized(payload: &[u8]) -> Result<(&[u8], &[u8])> {
+    if payload.len() < 4 {
+        return Err(Error::InsufficientPayloadLength);
+    }
+    let size = read_u32_be(payload) as usize;
+    if payload.len() - 4 < size {
+        return Err(Error::PulsarLogParseFailed(format!(
+            "section size {} exceeds remaining {}",
+            size,
+            payload.len() - 4
+        )));
+    }
+    Ok((&payload[4..4 + size], &payload[4 + size..]))
+}
+
+fn message_type(command_type: CommandType) -> LogMessageType {
+    match command_type {
+        CommandType::Connect
+        | CommandType::Subscribe
+        | CommandType::Producer
+        | CommandType::Send
+        | CommandType::Ack
+        | CommandType::Flow
+        | CommandType::Unsubscribe
+        | CommandType::CloseProducer
+        | CommandType::CloseConsumer
+        | CommandType::Ping
+        | CommandType::RedeliverUnacknowledgedMessages
+        | CommandType::PartitionedMetadata
+        | CommandType::Lookup
+        | CommandType::ConsumerStats
+        | CommandType::Seek
+        | CommandType::GetLastMessageId
+        | CommandType::GetTopicsOfNamespace
+        | CommandType::GetSchema
+        | CommandType::AuthChallenge
+        | CommandType::GetOrCreateSchema
+        | CommandType::NewTxn
+        | CommandType::AddPartitionToTxn
+        | CommandType::AddSubscriptionToTxn
+        | CommandType::EndTxn
+        | CommandType::EndTxnOnPartition
+        | CommandType::EndTxnOnSubscription
+        | CommandType::TcClientConnectRequest
+        | CommandType::WatchTopicList
+        | CommandType::WatchTopicListClose => LogMessageType::Request,
+        CommandType::Connected
+        | CommandType::SendReceipt
+        | CommandType::SendError
+        | CommandType::Success
+        | CommandType::Error
+        | CommandType::ProducerSuccess
+        | CommandType::Pong
+        | CommandType::PartitionedMetadataResponse
+        | CommandType::LookupResponse
+        | CommandType::ConsumerStatsResponse
+        | CommandType::GetLastMessageIdResponse
+        | CommandType::GetTopicsOfNamespaceResponse
+        | CommandType::GetSchemaResponse
+        | CommandType::AuthResponse
+        | CommandType::AckResponse
+        | CommandType::GetOrCreateSchemaResponse
+        | CommandType::NewTxnResponse
+        | CommandType::AddPartitionToTxnResponse
+        | CommandType::AddSubscriptionToTxnResponse
+        | CommandType::EndTxnResponse
+        | CommandType::EndTxnOnPartitionResponse
+        | CommandType::EndTxnOnSubscriptionResponse
+        | CommandType::TcClientConnectResponse
+        | CommandType::WatchTopicListSuccess => LogMessageType::Response,
+        // pushed by the broker without a request
+        CommandType::Message
+        | CommandType::ReachedEndOfTopic
+        | CommandType::ActiveConsumerChange
+        | CommandType::WatchTopicUpdate
+        | CommandType::TopicMigrated => LogMessageType::Other,
+    }
+}
+
+// how a response finds its request on the same connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SessionKey {
+    Connect,
+    Ping,
+    Auth,
+    Send { producer_id: u64, sequence_id: u64 },
+    RequestId(u64),
+}
+
+// a producer or consumer registered on the connection
+#[derive(Debug, Default, Clone)]
+struct Client {
+    topic: String,
+    name: Option<String>,
+    subscription: Option<String>,
+}
+
+#[derive(Default)]
+pub struct PulsarLog {
+    // frames are buffered whole
+    streams: PerDirection<Stream>,
+
+    producers: HashMap<u64, Client>,
+    consumers: HashMap<u64, Client>,
+    pending: HashMap<SessionKey, PulsarInfo>,
+    transactions: TransactionTracker,
+    consumer_metrics: ConsumerTracker,
+    lookups: LookupTracker,
+}
+
+impl PulsarLog {
+    fn parse_frames(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<PulsarInfo>> {
+        let data = self.streams[param.direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= 4 {
+            let total_size = read_u32_be(remain) as usize;
+            if !(4..=MAX_FRAME_SIZE).contains(&total_size) {
+                // the rest of the segment can not be framed, keep what is parsed
+                debug!("drop pulsar segment at invalid frame size {}", total_size);
+                last_error = Some(Error::PulsarLogParseFailed(format!(
+                    "invalid frame size {}",
+                    total_size
+                )));
+                remain = &[];
+                break;
+            }
+            if remain.len() < 4 + total_size {
+                break;
+            }
+            match PulsarInfo::parse(&remain[4..4 + total_size], param.parse_config) {
+                Ok(info) => infos.push(info),
+                Err(e) => {
+                    debug!("skip pulsar frame: {}", e);
+                    last_error = Some(e);
+                }
+            }
+            remain = &remain[4 + total_size..];
+        }
+        if !remain.is_empty() {
+            self.streams[param.direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn handle(&mut self, mut info: PulsarInfo, output: &mut Vec<L7ProtocolInfo>) {
+        if let Some(c) = info.producer_id.and_then(|id| self.producers.get(&id)) {
+            info.fill_client(c);
+        } else if let Some(c) = info.consumer_id.and_then(|id| self.consumers.get(&id)) {
+            info.fill_client(c);
+        }
+        self.consumer_metrics.observe(&info);
+
+        let Some(key) = info.session_key() else {
+            let event = self.lookups.on_push(&info);
+            output.push(L7ProtocolInfo::PulsarInfo(info));
+            if let Some(e) = event {
+                output.push(L7ProtocolInfo::PulsarTopologyEvent(e));
+            }
+            return;
+        };
+        match info.msg_type {
+            LogMessageType::Request => {
+                self.register_client(&info);
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::PulsarInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    // request id reused before a response, the previous one is lost
+                    output.push(L7ProtocolInfo::PulsarInfo(prev));
+                }
+            }
+            LogMessageType::Response => match self.pending.remove(&key) {
+                Some(mut request) => {
+                    let transaction = self.transactions.on_response(&request, &info);
+                    let lookup = self.lookups.on_response(&request, &info);
+                    request.merge(info);
+                    self.update_client(&request);
+                    output.push(L7ProtocolInfo::PulsarInfo(request));
+                    if let Some(t) = transaction {
+                        output.push(L7ProtocolInfo::PulsarTransaction(t));
+                    }
+                    if let Some(e) = lookup {
+                        output.push(L7ProtocolInfo::PulsarTopologyEvent(e));
+                    }
+                }
+                None => output.push(L7ProtocolInfo::PulsarInfo(info)),
+            },
+            _ => output.push(L7ProtocolInfo::PulsarInfo(info)),
+        }
+    }
+
+    fn register_client(&mut self, request: &PulsarInfo) {
+        let client = || Client {
+            topic: request.topic.clone().unwrap_or_default(),
+            name: request
+                .producer_name
+                .clone()
+                .or_else(|| request.consumer_name.clone()),
+            subscription: request.subscription.clone(),
+        };
+        match (
+            request.command_type,
+            request.producer_id,
+            request.consumer_id,
+        ) {
+            (CommandType::Producer, Some(id), _) => {
+                self.producers.insert(id, client());
+            }
+            (CommandType::Subscribe, _, Some(id)) => {
+                self.consumers.insert(id, client());
+            }
+            _ => (),
+        }
+    }
+
+    fn update_client(&mut self, session: &PulsarInfo) {
+        let failed = session.error.is_some();
+        match (
+            session.command_type,
+            session.producer_id,
+            session.consumer_id,
+        ) {
+            (CommandType::Producer, Some(id), _) if failed => {
+                self.producers.remove(&id);
+            }
+            (CommandType::Producer, Some(id), _) => {
+                if let Some(p) = self.producers.get_mut(&id) {
+                    p.name = session.producer_name.clone();
+                }
+            }
+            (CommandType::Subscribe, _, Some(id)) if failed => {
+                self.consumers.remove(&id);
+            }
+            (CommandType::CloseProducer, Some(id), _) if !failed => {
+                self.producers.remove(&id);
+            }
+            (CommandType::CloseConsumer | CommandType::Unsubscribe, _, Some(id)) if !failed => {
+                self.consumers.remove(&id);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for PulsarLog {
+    fn check_payload(&mut self, payload: &[u8], _: &ParseParam) -> bool {
+        if payload.len() < 8 {
+            return false;
+        }
+        let total_size = read_u32_be(payload) as usize;
+        if !(4..=MAX_FRAME_SIZE).contains(&total_size) {
+            return false;
+        }
+        let command_size = read_u32_be(&payload[4..]) as usize;
+        if command_size == 0 || command_size > total_size - 4 {
+            return false;
+        }
+        match payload.get(8..8 + command_size) {
+            Some(command) => BaseCommand::decode(command)
+                .ok()
+                .and_then(|c| CommandType::try_from(c.r#type).ok())
+                .is_some(),
+            None => false,
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        self.transactions.expire(param.time, &mut output);
+        self.consumer_metrics.flush(param.time, &mut output);
+        self.lookups.expire(param.time, &mut output);
+        let infos = match self.parse_frames(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for mut info in infos {
+            info.time = param.time;
+            self.handle(info, &mut output);
+        }
+        Ok(split_batches(output).into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Pulsar
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.producers.clear();
+        self.consumers.clear();
+        self.pending.clear();
+        self.transactions.clear();
+        self.consumer_metrics.clear();
+        self.lookups.clear();
+    }
+
+    fn on_flow_end(&mut self) -> L7ParseResult {
+        let mut output = vec![];
+        output.extend(self.pending.drain().map(|(_, r)| r.into_timeout()));
+        self.transactions.drain(&mut output);
+        self.lookups.drain(&mut output);
+        split_batches(output).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::common::flow::PacketDirection;
+    use crate::flow_generator::protocol_logs::mq::pulsar_proto::{
+        CommandError, CommandLookupTopic, CommandLookupTopicResponse, CommandMessage,
+        CommandProducer, CommandProducerSuccess, CommandSend, CommandSendError, CommandSendReceipt,
+        CommandSubscribe, CommandSuccess, MessageIdData,
+    };
+    use crate::flow_generator::protocol_logs::SESSION_TIMEOUT;
+
+    const C2S: ParseParam = ParseParam {
+        direction: PacketDirection::ClientToServer,
+        time: 0,
+        parse_config: None,
+    };
+    const S2C: ParseParam = ParseParam {
+        direction: PacketDirection::ServerToClient,
+        time: 0,
+        parse_config: None,
+    };
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    fn simple_frame(command: &BaseCommand) -> Vec<u8> {
+        let command = command.encode_to_vec();
+        let mut frame = vec![];
+        frame.extend_from_slice(&(4 + command.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&command);
+        frame
+    }
+
+    fn payload_frame(
+        command: &BaseCommand,
+        broker_entry_metadata: Option<&BrokerEntryMetadata>,
+        checksum: Option<u32>,
+        metadata: &MessageMetadata,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut body = vec![];
+        let command = command.encode_to_vec();
+        body.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        body.extend_from_slice(&command);
+        if let Some(m) = broker_entry_metadata {
+            let m = m.encode_to_vec();
+            body.extend_from_slice(&MAGIC_BROKER_ENTRY_METADATA.to_be_bytes());
+            body.extend_from_slice(&(m.len() as u32).to_be_bytes());
+            body.extend_from_slice(&m);
+        }
+        if let Some(c) = checksum {
+            body.extend_from_slice(&MAGIC_CRC32C.to_be_bytes());
+            body.extend_from_slice(&c.to_be_bytes());
+        }
+        let metadata = metadata.encode_to_vec();
+        body.extend_from_slice(&(metadata.len() as u32).to_be_bytes());
+        body.extend_from_slice(&metadata);
+        body.extend_from_slice(payload);
+
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        frame
+    }
+
+    fn command(command_type: CommandType) -> BaseCommand {
+        BaseCommand {
+            r#type: command_type as i32,
+            ..Default::default()
+        }
+    }
+
+    fn parse_frame(frame: &[u8]) -> PulsarInfo {
+        PulsarInfo::parse(&frame[4..], None).unwrap()
+    }
+
+    fn unwrap_pulsar(info: L7ProtocolInfo) -> PulsarInfo {
+        match info {
+            L7ProtocolInfo::PulsarInfo(info) => info,
+            _ => panic!("not a pulsar info: {:?}", info),
+        }
+    }
+
+    fn parse_single(parser: &mut PulsarLog, frame: &[u8], param: &ParseParam) -> PulsarInfo {
+        unwrap_pulsar(parser.parse_payload(frame, param).unwrap().unwrap_single())
+    }
+
+    fn parse_batch(frame: &[u8], param: &ParseParam) -> Vec<PulsarInfo> {
+        match PulsarLog::default().parse_payload(frame, param).unwrap() {
+            L7ParseResult::Multi(infos) => infos.into_iter().map(unwrap_pulsar).collect(),
+            result => vec![unwrap_pulsar(result.unwrap_single())],
+        }
+    }
+
+    fn metadata() -> MessageMetadata {
+        MessageMetadata {
+            producer_name: "standalone-0-1".to_owned(),
+            sequence_id: 7,
+            publish_time: 1700000000000,
+            properties: vec![KeyValue {
+                key: "k".to_owned(),
+                value: "v".to_owned(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn send(producer_id: u64, sequence_id: u64) -> Vec<u8> {
+        let send = BaseCommand {
+            r#type: CommandType::Send as i32,
+            send: Some(CommandSend {
+                producer_id,
+                sequence_id,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        payload_frame(&send, None, Some(0xdeadbeef), &metadata(), b"hello")
+    }
+
+    fn send_receipt(producer_id: u64, sequence_id: u64) -> Vec<u8> {
+        simple_frame(&BaseCommand {
+            r#type: CommandType::SendReceipt as i32,
+            send_receipt: Some(CommandSendReceipt {
+                producer_id,
+                sequence_id,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn producer(producer_id: u64, request_id: u64, topic: &str) -> Vec<u8> {
+        simple_frame(&BaseCommand {
+            r#type: CommandType::Producer as i32,
+            producer: Some(CommandProducer {
+                topic: topic.to_owned(),
+                producer_id,
+                request_id,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn producer_success(request_id: u64, producer_name: &str) -> Vec<u8> {
+        simple_frame(&BaseCommand {
+            r#type: CommandType::ProducerSuccess as i32,
+            producer_success: Some(CommandProducerSuccess {
+                request_id,
+                producer_name: producer_name.to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn every_command_type() {
+        let all = (0..=i32::from(u8::MAX))
+            .filter_map(|t| CommandType::try_from(t).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(all.len(), 58);
+
+        let mut parser = PulsarLog::default();
+        for t in all {
+            let frame = simple_frame(&command(t));
+            assert!(parser.check_payload(&frame, &C2S), "check {:?}", t);
+            let info = parse_frame(&frame);
+            assert_eq!(info.command_type, t);
+            assert_eq!(info.frame_size as usize, frame.len() - 4);
+            assert_eq!(info.msg_type, message_type(t));
+            assert!(info.metadata.is_none());
+        }
+    }
+
+    #[test]
+    fn send_with_checksum() {
+        let frame = send(1, 7);
+        assert!(PulsarLog::default().check_payload(&frame, &C2S));
+        let info = parse_frame(&frame);
+        assert_eq!(info.command_type, CommandType::Send);
+        assert_eq!(info.msg_type, LogMessageType::Request);
+        assert_eq!(info.producer_id, Some(1));
+        assert_eq!(info.sequence_id, Some(7));
+        assert_eq!(info.producer_name.as_deref(), Some("standalone-0-1"));
+        assert_eq!(info.checksum, Some(0xdeadbeef));
+        assert_eq!(info.metadata, Some(metadata()));
+        assert_eq!(info.payload_size, 5);
+    }
+
+    #[test]
+    fn send_without_checksum() {
+        let info = parse_frame(&payload_frame(
+            &command(CommandType::Send),
+            None,
+            None,
+            &metadata(),
+            b"",
+        ));
+        assert_eq!(info.checksum, None);
+        assert_eq!(info.metadata, Some(metadata()));
+        assert_eq!(info.payload_size, 0);
+    }
+
+    #[test]
+    fn message_with_broker_entry_metadata() {
+        let message = BaseCommand {
+            r#type: CommandType::Message as i32,
+            message: Some(CommandMessage {
+                consumer_id: 3,
+                message_id: MessageIdData {
+                    ledger_id: 10,
+                    entry_id: 2,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let broker_entry_metadata = BrokerEntryMetadata {
+            broker_timestamp: Some(1700000000001),
+            index: Some(42),
+        };
+        let frame = payload_frame(
+            &message,
+            Some(&broker_entry_metadata),
+            Some(1),
+            &metadata(),
+            b"world!",
+        );
+
+        let info = parse_single(&mut PulsarLog::default(), &frame, &S2C);
+        assert_eq!(info.msg_type, LogMessageType::Other);
+        assert_eq!(info.consumer_id, Some(3));
+        assert_eq!(info.broker_entry_metadata, Some(broker_entry_metadata));
+        assert_eq!(info.checksum, Some(1));
+        assert_eq!(info.metadata, Some(metadata()));
+        assert_eq!(info.payload_size, 6);
+    }
+
+    #[test]
+    fn multiple_frames_in_one_segment() {
+        let mut segment = simple_frame(&command(CommandType::Pong));
+        segment.extend(payload_frame(
+            &command(CommandType::Message),
+            None,
+            Some(2),
+            &metadata(),
+            b"x",
+        ));
+        segment.extend(simple_frame(&command(CommandType::ReachedEndOfTopic)));
+
+        let infos = PulsarLog::default()
+            .parse_payload(&segment, &S2C)
+            .unwrap()
+            .unwrap_multi()
+            .into_iter()
+            .map(|i| unwrap_pulsar(i).command_type)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            infos,
+            vec![
+                CommandType::Pong,
+                CommandType::Message,
+                CommandType::ReachedEndOfTopic
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_spanning_segments() {
+        let frame = payload_frame(
+            &command(CommandType::Message),
+            None,
+            Some(3),
+            &metadata(),
+            b"abc",
+        );
+        let mut parser = PulsarLog::default();
+
+        // split inside the totalSize field, then inside the metadata
+        assert!(parser.parse_payload(&frame[..2], &S2C).unwrap().is_none());
+        assert!(parser.parse_payload(&frame[2..20], &S2C).unwrap().is_none());
+        // the other direction is buffered separately
+        let flow = simple_frame(&command(CommandType::Flow));
+        let info = parse_single(&mut parser, &flow, &C2S);
+        assert_eq!(info.command_type, CommandType::Flow);
+
+        let mut tail = frame[20..].to_vec();
+        tail.extend(simple_frame(&command(CommandType::ActiveConsumerChange)));
+        let infos = parser.parse_payload(&tail, &S2C).unwrap().unwrap_multi();
+        let message = unwrap_pulsar(infos[0].clone());
+        assert_eq!(message.command_type, CommandType::Message);
+        assert_eq!(message.payload_size, 3);
+        assert_eq!(message.metadata, Some(metadata()));
+        assert_eq!(
+            unwrap_pulsar(infos[1].clone()).command_type,
+            CommandType::ActiveConsumerChange
+        );
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let mut parser = PulsarLog::default();
+        assert!(!parser.check_payload(b"GET / HTTP/1.1\r\n\r\n", &C2S));
+        assert!(parser
+            .parse_payload(b"GET / HTTP/1.1\r\n\r\n", &C2S)
+            .is_err());
+
+        // payload section is only allowed after SEND and MESSAGE
+        let frame = payload_frame(&command(CommandType::Flow), None, None, &metadata(), b"");
+        assert!(parser.parse_payload(&frame, &C2S).is_err());
+
+        // an invalid frame does not poison the stream
+        let info = parse_single(
+            &mut parser,
+            &simple_frame(&command(CommandType::Flow)),
+            &C2S,
+        );
+        assert_eq!(info.command_type, CommandType::Flow);
+
+        // frames before an invalid size are still reported
+        let mut segment = simple_frame(&command(CommandType::Flow));
+        segment.extend([0xff; 8]);
+        let info = parse_single(&mut parser, &segment, &C2S);
+        assert_eq!(info.command_type, CommandType::Flow);
+        let info = parse_single(
+            &mut parser,
+            &simple_frame(&command(CommandType::Flow)),
+            &C2S,
+        );
+        assert_eq!(info.command_type, CommandType::Flow);
+    }
+
+    #[test]
+    fn producer_and_send_sessions() {
+        let mut parser = PulsarLog::default();
+        let topic = "persistent://public/default/orders";
+
+        assert!(parser
+            .parse_payload(
+                &producer(1, 10, topic),
+                &at(PacketDirection::ClientToServer, 100)
+            )
+            .unwrap()
+            .is_none());
+        let session = parse_single(
+            &mut parser,
+            &producer_success(10, "standalone-0-7"),
+            &at(PacketDirection::ServerToClient, 350),
+        );
+        assert_eq!(session.msg_type, LogMessageType::Session);
+        assert_eq!(session.command_type, CommandType::Producer);
+        assert_eq!(session.response_type, Some(CommandType::ProducerSuccess));
+        assert_eq!(session.topic.as_deref(), Some(topic));
+        assert_eq!(session.producer_name.as_deref(), Some("standalone-0-7"));
+        assert_eq!(session.status, L7ResponseStatus::Ok);
+        assert_eq!(session.rrt, 250);
+
+        // pipelined sends acknowledged out of order
+        let mut sends = send(1, 1);
+        sends.extend(send(1, 2));
+        assert!(parser
+            .parse_payload(&sends, &at(PacketDirection::ClientToServer, 1000))
+            .unwrap()
+            .is_none());
+        let mut receipts = send_receipt(1, 2);
+        receipts.extend(simple_frame(&BaseCommand {
+            r#type: CommandType::SendError as i32,
+            send_error: Some(CommandSendError {
+                producer_id: 1,
+                sequence_id: 1,
+                error: ServerError::PersistenceError as i32,
+                message: "bookie down".to_owned(),
+            }),
+            ..Default::default()
+        }));
+        let sessions = parser
+            .parse_payload(&receipts, &at(PacketDirection::ServerToClient, 1500))
+            .unwrap()
+            .unwrap_multi()
+            .into_iter()
+            .map(unwrap_pulsar)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sessions[0].sequence_id, Some(2));
+        assert_eq!(sessions[0].response_type, Some(CommandType::SendReceipt));
+        assert_eq!(sessions[0].status, L7ResponseStatus::Ok);
+        assert_eq!(sessions[0].topic.as_deref(), Some(topic));
+        assert_eq!(sessions[0].producer_name.as_deref(), Some("standalone-0-1"));
+        assert_eq!(sessions[0].rrt, 500);
+
+        assert_eq!(sessions[1].sequence_id, Some(1));
+        assert_eq!(sessions[1].response_type, Some(CommandType::SendError));
+        assert_eq!(sessions[1].status, L7ResponseStatus::ServerError);
+        assert_eq!(sessions[1].error, Some(ServerError::PersistenceError));
+        assert_eq!(sessions[1].error_message.as_deref(), Some("bookie down"));
+        assert_eq!(sessions[1].topic.as_deref(), Some(topic));
+    }
+
+    #[test]
+    fn subscribe_error_session() {
+        let mut parser = PulsarLog::default();
+        let subscribe = simple_frame(&BaseCommand {
+            r#type: CommandType::Subscribe as i32,
+            subscribe: Some(CommandSubscribe {
+                topic: "persistent://public/default/orders".to_owned(),
+                subscription: "billing".to_owned(),
+                consumer_id: 5,
+                request_id: 11,
+                consumer_name: Some("billing-0".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert!(parser.parse_payload(&subscribe, &C2S).unwrap().is_none());
+
+        let error = simple_frame(&BaseCommand {
+            r#type: CommandType::Error as i32,
+            error: Some(CommandError {
+                request_id: 11,
+                error: ServerError::AuthorizationError as i32,
+                message: "not authorized".to_owned(),
+            }),
+            ..Default::default()
+        });
+        let session = parse_single(&mut parser, &error, &S2C);
+        assert_eq!(session.msg_type, LogMessageType::Session);
+        assert_eq!(session.subscription.as_deref(), Some("billing"));
+        assert_eq!(session.consumer_name.as_deref(), Some("billing-0"));
+        assert_eq!(session.status, L7ResponseStatus::ClientError);
+        assert_eq!(session.error, Some(ServerError::AuthorizationError));
+        // the failed consumer is forgotten
+        assert!(parser.consumers.is_empty());
+    }
+
+    #[test]
+    fn lookup_session() {
+        let mut parser = PulsarLog::default();
+        let lookup = simple_frame(&BaseCommand {
+            r#type: CommandType::Lookup as i32,
+            lookup_topic: Some(CommandLookupTopic {
+                topic: "persistent://public/default/orders".to_owned(),
+                request_id: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let response = simple_frame(&BaseCommand {
+            r#type: CommandType::LookupResponse as i32,
+            lookup_topic_response: Some(CommandLookupTopicResponse {
+                request_id: 3,
+                response: Some(command_lookup_topic_response::LookupType::Failed as i32),
+                error: Some(ServerError::TopicNotFound as i32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert!(parser.parse_payload(&lookup, &C2S).unwrap().is_none());
+        let mut infos = parser
+            .parse_payload(&response, &S2C)
+            .unwrap()
+            .unwrap_multi();
+        assert!(matches!(
+            infos.pop(),
+            Some(L7ProtocolInfo::PulsarTopologyEvent(_))
+        ));
+        let session = unwrap_pulsar(infos.pop().unwrap());
+        assert_eq!(session.command_type, CommandType::Lookup);
+        assert_eq!(
+            session.topic.as_deref(),
+            Some("persistent://public/default/orders")
+        );
+        assert_eq!(session.status, L7ResponseStatus::ClientError);
+        assert_eq!(session.error, Some(ServerError::TopicNotFound));
+    }
+
+    #[test]
+    fn unmatched_and_timeout() {
+        let mut parser = PulsarLog::default();
+
+        // response without a captured request
+        let success = simple_frame(&BaseCommand {
+            r#type: CommandType::Success as i32,
+            success: Some(CommandSuccess {
+                request_id: 99,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let info = parse_single(&mut parser, &success, &S2C);
+        assert_eq!(info.msg_type, LogMessageType::Response);
+        assert_eq!(info.request_id, Some(99));
+
+        assert!(parser
+            .parse_payload(
+                &simple_frame(&command(CommandType::Ping)),
+                &at(PacketDirection::ClientToServer, 1)
+            )
+            .unwrap()
+            .is_none());
+        let info = parse_single(
+            &mut parser,
+            &simple_frame(&command(CommandType::Flow)),
+            &at(PacketDirection::ClientToServer, SESSION_TIMEOUT),
+        );
+        assert_eq!(info.command_type, CommandType::Flow);
+        // the ping expires on the first payload after the timeout
+        let infos = parser
+            .parse_payload(
+                &simple_frame(&command(CommandType::Flow)),
+                &at(PacketDirection::ClientToServer, 1 + SESSION_TIMEOUT),
+            )
+            .unwrap()
+            .unwrap_multi();
+        let ping = unwrap_pulsar(infos[0].clone());
+        assert_eq!(ping.command_type, CommandType::Ping);
+        assert_eq!(ping.status, L7ResponseStatus::Timeout);
+
+        // requests left when the flow ends are timeouts too
+        let producer = producer(1, 2, "persistent://public/default/orders");
+        let param = at(PacketDirection::ClientToServer, 2 + SESSION_TIMEOUT);
+        assert!(parser.parse_payload(&producer, &param).unwrap().is_none());
+        let info = unwrap_pulsar(parser.on_flow_end().unwrap_single());
+        assert_eq!(info.command_type, CommandType::Producer);
+        assert_eq!(info.status, L7ResponseStatus::Timeout);
+        assert!(parser.on_flow_end().is_none());
+    }
+
+    #[test]
+    fn trace_context_from_properties() {
+        let config = LogParserConfig::default();
+        let mut metadata = metadata();
+        metadata.properties = vec![
+            KeyValue {
+                key: "uber-trace-id".to_owned(),
+                value: "5e2f:a1:b2:1".to_owned(),
+            },
+            KeyValue {
+                key: "traceparent".to_owned(),
+                value: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+            },
+        ];
+        let send = BaseCommand {
+            r#type: CommandType::Send as i32,
+            send: Some(CommandSend::default()),
+            ..Default::default()
+        };
+        let send = payload_frame(&send, None, None, &metadata, b"");
+        let message = payload_frame(&command(CommandType::Message), None, None, &metadata, b"");
+
+        // traceparent comes before uber-trace-id in the default key list
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..C2S
+        };
+        let mut parser = PulsarLog::default();
+        assert!(parser.parse_payload(&send, &param).unwrap().is_none());
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let session = parse_single(&mut parser, &send_receipt(0, 0), &param);
+        assert_eq!(session.msg_type, LogMessageType::Session);
+        assert_eq!(
+            session.trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(session.trace.span_id.as_deref(), Some("b7ad6b7169203331"));
+
+        // the consumer side sees the same properties
+        let info = parse_single(&mut parser, &message, &param);
+        assert_eq!(info.trace, session.trace);
+
+        let config = LogParserConfig {
+            trace_types: vec!["uber-trace-id".into()],
+            span_types: vec!["uber-trace-id".into()],
+            ..Default::default()
+        };
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let info = parse_single(&mut parser, &message, &param);
+        assert_eq!(info.trace.trace_id.as_deref(), Some("5e2f"));
+        assert_eq!(info.trace.span_id.as_deref(), Some("a1"));
+        assert_eq!(info.trace.parent_span_id.as_deref(), Some("b2"));
+
+        // nothing is extracted without a parse config
+        let info = parse_single(&mut parser, &message, &S2C);
+        assert!(info.trace.is_empty());
+    }
+
+    fn batch_entries() -> Vec<u8> {
+        let mut entries = vec![];
+        for i in 0..3u64 {
+            let single = SingleMessageMetadata {
+                partition_key: Some(format!("key-{}", i)),
+                payload_size: 5,
+                event_time: Some(if i == 0 { 0 } else { 1700000000000 + i }),
+                sequence_id: (i == 2).then_some(100),
+                properties: if i == 1 {
+                    vec![KeyValue {
+                        key: "traceparent".to_owned(),
+                        value: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+                    }]
+                } else {
+                    vec![]
+                },
+                ..Default::default()
+            }
+            .encode_to_vec();
+            entries.extend_from_slice(&(single.len() as u32).to_be_bytes());
+            entries.extend_from_slice(&single);
+            entries.extend_from_slice(b"hello");
+        }
+        entries
+    }
+
+    fn batch_frame(compression: CompressionType, payload: &[u8]) -> Vec<u8> {
+        let metadata = MessageMetadata {
+            num_messages_in_batch: Some(3),
+            compression: Some(compression as i32),
+            uncompressed_size: Some(batch_entries().len() as u32),
+            ..metadata()
+        };
+        payload_frame(
+            &command(CommandType::Message),
+            None,
+            None,
+            &metadata,
+            payload,
+        )
+    }
+
+    #[test]
+    fn batch_expansion() {
+        let config = LogParserConfig::default();
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let entries = batch_entries();
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&entries).unwrap();
+        let frames = [
+            batch_frame(CompressionType::None, &entries),
+            batch_frame(CompressionType::Lz4, &lz4_flex::block::compress(&entries)),
+            batch_frame(CompressionType::Zlib, &zlib.finish().unwrap()),
+            batch_frame(
+                CompressionType::Zstd,
+                &zstd::bulk::compress(&entries, 3).unwrap(),
+            ),
+            batch_frame(
+                CompressionType::Snappy,
+                &snap::raw::Encoder::new().compress_vec(&entries).unwrap(),
+            ),
+        ];
+        for frame in frames.iter() {
+            let infos = parse_batch(frame, &param);
+            assert_eq!(infos.len(), 3);
+            let single = |i: usize| infos[i].single_metadata.as_ref().unwrap();
+            assert!(infos
+                .iter()
+                .all(|i| i.command_type == CommandType::Message && i.payload_size == 5));
+            assert_eq!(infos[2].batch_index, Some(2));
+            assert_eq!(single(0).partition_key.as_deref(), Some("key-0"));
+            assert_eq!(single(0).event_time, None);
+            assert_eq!(infos[0].sequence_id, Some(7));
+            assert_eq!(single(1).event_time, Some(1700000000001));
+            assert_eq!(infos[1].sequence_id, Some(8));
+            assert_eq!(infos[2].sequence_id, Some(100));
+            assert_eq!(single(1).properties.len(), 1);
+            assert_eq!(infos[1].trace.span_id.as_deref(), Some("b7ad6b7169203331"));
+            assert!(infos[0].trace.is_empty());
+        }
+
+        // a batched send is answered once, each message is logged with the receipt
+        let mut parser = PulsarLog::default();
+        let send = BaseCommand {
+            r#type: CommandType::Send as i32,
+            send: Some(CommandSend {
+                producer_id: 1,
+                sequence_id: 7,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let metadata = MessageMetadata {
+            num_messages_in_batch: Some(3),
+            ..metadata()
+        };
+        let frame = payload_frame(&send, None, None, &metadata, &entries);
+        let send_param = ParseParam {
+            direction: PacketDirection::ClientToServer,
+            ..param
+        };
+        assert!(parser.parse_payload(&frame, &send_param).unwrap().is_none());
+        let receipt_param = ParseParam { time: 300, ..param };
+        let infos = parser
+            .parse_payload(&send_receipt(1, 7), &receipt_param)
+            .unwrap()
+            .unwrap_multi()
+            .into_iter()
+            .map(unwrap_pulsar)
+            .collect::<Vec<_>>();
+        assert_eq!(infos.len(), 3);
+        assert!(infos
+            .iter()
+            .all(|i| i.msg_type == LogMessageType::Session && i.rrt == 300));
+        assert_eq!(infos[2].sequence_id, Some(100));
+
+        let config = LogParserConfig {
+            max_decompressed_size: entries.len() - 1,
+            ..Default::default()
+        };
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let info = parse_single(&mut PulsarLog::default(), &frames[1], &param);
+        assert_eq!(info.batch_index, None);
+        assert_eq!(info.command_type, CommandType::Message);
+        // budget only applies to compressed payloads
+        assert_eq!(parse_batch(&frames[0], &param).len(), 3);
+
+        let config = LogParserConfig {
+            expand_batch: false,
+            ..Default::default()
+        };
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let info = parse_single(&mut PulsarLog::default(), &frames[0], &param);
+        assert_eq!(info.batch_index, None);
+        let info = parse_single(&mut PulsarLog::default(), &frames[0], &S2C);
+        assert_eq!(info.batch_index, None);
+
+        // corrupted data keeps the frame but drops the batch
+        let info = parse_single(
+            &mut PulsarLog::default(),
+            &batch_frame(CompressionType::Zstd, b"not zstd"),
+            &ParseParam {
+                parse_config: Some(&LogParserConfig::default()),
+                ..S2C
+            },
+        );
+        assert_eq!(info.batch_index, None);
+        assert_eq!(info.payload_size, 8);
+
+        // nor are the messages before a truncated one kept
+        let info = parse_single(
+            &mut PulsarLog::default(),
+            &batch_frame(CompressionType::None, &entries[..entries.len() - 3]),
+            &ParseParam {
+                parse_config: Some(&LogParserConfig::default()),
+                ..S2C
+            },
+        );
+        assert_eq!(info.batch_index, None);
+
+        let config = LogParserConfig {
+            max_decompressed_size: 0,
+            ..Default::default()
+        };
+        let param = ParseParam {
+            parse_config: Some(&config),
+            ..S2C
+        };
+        let info = parse_single(&mut PulsarLog::default(), &frames[1], &param);
+        assert_eq!(info.batch_index, None);
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use super::pulsar::PulsarInfo;
+use super::pulsar_proto::{base_command::Type as CommandType, command_ack::AckType, MessageIdData};
+use crate::flow_generator::protocol_logs::L7ProtocolInfo;
+
+// unit: microseconds
+const METRICS_INTERVAL: u64 = 60_000_000;
+// deliveries tracked per consumer for ack latency, later ones only count as unacked
+const MAX_UNACKED_ENTRIES: usize = 10000;
+// upper bounds of ack latency buckets, the last bucket holds everything above, unit: microseconds
+pub const ACK_LATENCY_BOUNDS: [u64; 6] =
+    [1_000, 10_000, 100_000, 1_000_000, 10_000_000, 60_000_000];
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct AckLatency {
+    pub buckets: [u64; ACK_LATENCY_BOUNDS.len() + 1],
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl AckLatency {
+    fn observe(&mut self, latency: u64) {
+        let i = ACK_LATENCY_BOUNDS
+            .iter()
+            .position(|b| latency <= *b)
+            .unwrap_or(ACK_LATENCY_BOUNDS.len());
+        self.buckets[i] += 1;
+        self.count += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+}
+
+// per subscription view of the consumers on one connection, counters cover the
+// interval between start_time and end_time while gauges are taken at end_time
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PulsarConsumerMetrics {
+    pub topic: String,
+    pub subscription: String,
+    pub consumers: u32,
+
+    pub outstanding_permits: u64,
+    pub unacked_messages: u64,
+    // age of the earliest delivery still waiting for an ack, unit: microseconds
+    pub oldest_unacked_age: u64,
+
+    pub permits_granted: u64,
+    pub delivered_messages: u64,
+    pub acked_messages: u64,
+    pub ack_validation_errors: u64,
+    pub redelivery_requests: u64,
+    pub redelivered_messages: u64,
+    pub ack_latency: AckLatency,
+
+    // unit: microseconds
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Default)]
+struct Consumer {
+    topic: String,
+    subscription: String,
+    permits: u64,
+    // (ledger_id, entry_id) -> (delivery time, messages in the entry not yet acked)
+    unacked: BTreeMap<(u64, u64), (u64, u64)>,
+    // messages delivered after the tracking limit was reached
+    untracked: u64,
+}
+
+impl Consumer {
+    fn unacked_messages(&self) -> u64 {
+        self.unacked.values().map(|(_, n)| n).sum::<u64>() + self.untracked
+    }
+}
+
+#[derive(Default)]
+pub struct ConsumerTracker {
+    consumers: HashMap<u64, Consumer>,
+    // counters keyed by (topic, subscription)
+    counters: HashMap<(String, String), PulsarConsumerMetrics>,
+    start_time: Option<u64>,
+}
+
+impl ConsumerTracker {
+    // called with every parsed command after the consumer's topic and subscription are filled in
+    pub fn observe(&mut self, info: &PulsarInfo) {
+        let c = &info.command;
+        let Some(consumer_id) = info.consumer_id else {
+            return;
+        };
+        if info.command_type == CommandType::CloseConsumer {
+            self.consumers.remove(&consumer_id);
+            return;
+        }
+        if !matches!(
+            info.command_type,
+            CommandType::Flow
+                | CommandType::Message
+                | CommandType::Ack
+                | CommandType::RedeliverUnacknowledgedMessages
+        ) {
+            return;
+        }
+
+        let consumer = self.consumers.entry(consumer_id).or_default();
+        consumer.topic = info.topic.clone().unwrap_or_default();
+        consumer.subscription = info.subscription.clone().unwrap_or_default();
+        let counters = self
+            .counters
+            .entry((consumer.topic.clone(), consumer.subscription.clone()))
+            .or_default();
+
+        if let Some(m) = &c.flow {
+            consumer.permits += m.message_permits as u64;
+            counters.permits_granted += m.message_permits as u64;
+        } else if let Some(m) = &c.message {
+            // every message of a batch takes a permit
+            let count = info
+                .metadata
+                .as_ref()
+                .and_then(|m| m.num_messages_in_batch)
+                .unwrap_or(1)
+                .max(1) as u64;
+            consumer.permits = consumer.permits.saturating_sub(count);
+            counters.delivered_messages += count;
+            if m.redelivery_count.unwrap_or_default() > 0 {
+                counters.redelivered_messages += count;
+            }
+            let key = (m.message_id.ledger_id, m.message_id.entry_id);
+            if consumer.unacked.len() < MAX_UNACKED_ENTRIES || consumer.unacked.contains_key(&key) {
+                consumer.unacked.insert(key, (info.time, count));
+            } else {
+                consumer.untracked += count;
+            }
+        } else if let Some(m) = &c.ack {
+            if m.validation_error.is_some() {
+                counters.ack_validation_errors += 1;
+            }
+            let acked: u64 = match AckType::try_from(m.ack_type) {
+                Ok(AckType::Cumulative) => m
+                    .message_id
+                    .iter()
+                    .map(|id| ack_cumulative(consumer, id, info.time, counters))
+                    .sum(),
+                _ => m
+                    .message_id
+                    .iter()
+                    .map(|id| ack_individual(consumer, id, info.time, counters))
+                    .sum(),
+            };
+            counters.acked_messages += acked;
+        } else if let Some(m) = &c.redeliver_unacknowledged_messages {
+            counters.redelivery_requests += 1;
+            // an empty list asks for everything unacked, all of it comes back as new deliveries
+            if m.message_ids.is_empty() {
+                consumer.unacked.clear();
+                consumer.untracked = 0;
+            } else {
+                for id in m.message_ids.iter() {
+                    consumer.unacked.remove(&(id.ledger_id, id.entry_id));
+                }
+            }
+        }
+    }
+
+    // emits one record per subscription every METRICS_INTERVAL
+    pub fn flush(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        let start_time = *self.start_time.get_or_insert(now);
+        if now.saturating_sub(start_time) < METRICS_INTERVAL {
+            return;
+        }
+        self.start_time = Some(now);
+
+        for consumer in self.consumers.values() {
+            let m = self
+                .counters
+                .entry((consumer.topic.clone(), consumer.subscription.clone()))
+                .or_default();
+            m.consumers += 1;
+            m.outstanding_permits += consumer.permits;
+            m.unacked_messages += consumer.unacked_messages();
+            if let Some(oldest) = consumer.unacked.values().map(|(t, _)| *t).min() {
+                m.oldest_unacked_age = m.oldest_unacked_age.max(now.saturating_sub(oldest));
+            }
+        }
+        let mut metrics = self.counters.drain().collect::<Vec<_>>();
+        metrics.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        for ((topic, subscription), mut m) in metrics {
+            m.topic = topic;
+            m.subscription = subscription;
+            m.start_time = start_time;
+            m.end_time = now;
+            output.push(L7ProtocolInfo::PulsarConsumerMetrics(m));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.consumers.clear();
+        self.counters.clear();
+        self.start_time = None;
+    }
+}
+
+// a batch index acks a single message of the entry
+fn ack_individual(
+    consumer: &mut Consumer,
+    id: &MessageIdData,
+    now: u64,
+    counters: &mut PulsarConsumerMetrics,
+) -> u64 {
+    let key = (id.ledger_id, id.entry_id);
+    let Some((time, count)) = consumer.unacked.get_mut(&key) else {
+        return 1;
+    };
+    counters.ack_latency.observe(now.saturating_sub(*time));
+    if id.batch_index.unwrap_or(-1) >= 0 && *count > 1 {
+        *count -= 1;
+        return 1;
+    }
+    let count = *count;
+    consumer.unacked.remove(&key);
+    count
+}
+
+fn ack_cumulative(
+    consumer: &mut Consumer,
+    id: &MessageIdData,
+    now: u64,
+    counters: &mut PulsarConsumerMetrics,
+) -> u64 {
+    let acked = consumer
+        .unacked
+        .range(..=(id.ledger_id, id.entry_id))
+        .map(|(k, _)| *k)
+        .collect::<Vec<_>>();
+    let mut count = 0;
+    for key in acked {
+        let (time, n) = consumer.unacked.remove(&key).unwrap();
+        counters.ack_latency.observe(now.saturating_sub(time));
+        count += n;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    use crate::common::flow::PacketDirection;
+    use crate::flow_generator::protocol_logs::mq::pulsar_proto::{
+        BaseCommand, CommandAck, CommandCloseConsumer, CommandFlow, CommandMessage,
+        CommandRedeliverUnacknowledgedMessages, CommandSubscribe, CommandSuccess, MessageMetadata,
+    };
+    use crate::flow_generator::protocol_logs::mq::PulsarLog;
+    use crate::flow_generator::protocol_logs::{
+        L7ParseResult, L7ProtocolParserInterface, ParseParam,
+    };
+
+    const TOPIC: &str = "persistent://public/default/billing";
+
+    fn frame(command: BaseCommand, metadata: Option<MessageMetadata>) -> Vec<u8> {
+        let command = command.encode_to_vec();
+        let mut body = vec![];
+        body.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        body.extend_from_slice(&command);
+        if let Some(m) = metadata {
+            let m = m.encode_to_vec();
+            body.extend_from_slice(&(m.len() as u32).to_be_bytes());
+            body.extend_from_slice(&m);
+        }
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        frame
+    }
+
+    fn parse(
+        parser: &mut PulsarLog,
+        frame: &[u8],
+        direction: PacketDirection,
+        time: u64,
+    ) -> L7ParseResult {
+        let param = ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        parser.parse_payload(frame, &param).unwrap()
+    }
+
+    fn client(parser: &mut PulsarLog, command: BaseCommand, time: u64) -> L7ParseResult {
+        parse(
+            parser,
+            &frame(command, None),
+            PacketDirection::ClientToServer,
+            time,
+        )
+    }
+
+    fn message_id(ledger_id: u64, entry_id: u64, batch_index: Option<i32>) -> MessageIdData {
+        MessageIdData {
+            ledger_id,
+            entry_id,
+            batch_index,
+            ..Default::default()
+        }
+    }
+
+    fn deliver(parser: &mut PulsarLog, entry_id: u64, batch: i32, redelivery: u32, time: u64) {
+        let command = BaseCommand {
+            r#type: CommandType::Message as i32,
+            message: Some(CommandMessage {
+                consumer_id: 1,
+                message_id: message_id(1, entry_id, None),
+                redelivery_count: Some(redelivery),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let metadata = MessageMetadata {
+            num_messages_in_batch: (batch > 1).then_some(batch),
+            ..Default::default()
+        };
+        parse(
+            parser,
+            &frame(command, Some(metadata)),
+            PacketDirection::ServerToClient,
+            time,
+        );
+    }
+
+    fn ack(ack_type: AckType, id: MessageIdData, validation_error: Option<i32>) -> BaseCommand {
+        BaseCommand {
+            r#type: CommandType::Ack as i32,
+            ack: Some(CommandAck {
+                consumer_id: 1,
+                ack_type: ack_type as i32,
+                message_id: vec![id],
+                validation_error,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn metrics(result: L7ParseResult) -> Vec<PulsarConsumerMetrics> {
+        let infos = match result {
+            L7ParseResult::Single(info) => vec![info],
+            L7ParseResult::Multi(infos) => infos,
+            L7ParseResult::None => vec![],
+        };
+        infos
+            .into_iter()
+            .filter_map(|i| match i {
+                L7ProtocolInfo::PulsarConsumerMetrics(m) => Some(m),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn consumer_metrics() {
+        let mut parser = PulsarLog::default();
+        client(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::Subscribe as i32,
+                subscribe: Some(CommandSubscribe {
+                    topic: TOPIC.to_owned(),
+                    subscription: "billing".to_owned(),
+                    consumer_id: 1,
+                    request_id: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            0,
+        );
+        parse(
+            &mut parser,
+            &frame(
+                BaseCommand {
+                    r#type: CommandType::Success as i32,
+                    success: Some(CommandSuccess {
+                        request_id: 1,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                None,
+            ),
+            PacketDirection::ServerToClient,
+            100,
+        );
+        client(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::Flow as i32,
+                flow: Some(CommandFlow {
+                    consumer_id: 1,
+                    message_permits: 100,
+                }),
+                ..Default::default()
+            },
+            200,
+        );
+
+        deliver(&mut parser, 1, 3, 0, 1000);
+        deliver(&mut parser, 2, 1, 0, 2000);
+        deliver(&mut parser, 3, 1, 1, 3000);
+        deliver(&mut parser, 4, 1, 0, 30_000_000);
+        // one message of the batch, then everything up to entry 2
+        client(
+            &mut parser,
+            ack(AckType::Individual, message_id(1, 1, Some(0)), None),
+            5000,
+        );
+        client(
+            &mut parser,
+            ack(AckType::Cumulative, message_id(1, 2, None), None),
+            12000,
+        );
+        client(
+            &mut parser,
+            ack(AckType::Individual, message_id(1, 3, None), Some(2)),
+            20000,
+        );
+        client(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::RedeliverUnacknowledgedMessages as i32,
+                redeliver_unacknowledged_messages: Some(CommandRedeliverUnacknowledgedMessages {
+                    consumer_id: 1,
+                    message_ids: vec![message_id(1, 9, None)],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            25000,
+        );
+
+        let ping = BaseCommand {
+            r#type: CommandType::Ping as i32,
+            ..Default::default()
+        };
+        let mut m = metrics(client(&mut parser, ping.clone(), 60_000_000));
+        assert_eq!(m.len(), 1);
+        let m = m.pop().unwrap();
+        assert_eq!(m.topic, TOPIC);
+        assert_eq!(m.subscription, "billing");
+        assert_eq!(m.consumers, 1);
+        assert_eq!(m.permits_granted, 100);
+        assert_eq!(m.outstanding_permits, 94);
+        assert_eq!(m.delivered_messages, 6);
+        assert_eq!(m.acked_messages, 5);
+        assert_eq!(m.unacked_messages, 1);
+        assert_eq!(m.oldest_unacked_age, 30_000_000);
+        assert_eq!(m.ack_validation_errors, 1);
+        assert_eq!(m.redelivery_requests, 1);
+        assert_eq!(m.redelivered_messages, 1);
+        // 4000, 10000 | 11000, 17000
+        assert_eq!(m.ack_latency.buckets, [0, 2, 2, 0, 0, 0, 0]);
+        assert_eq!(m.ack_latency.count, 4);
+        assert_eq!(m.ack_latency.sum, 42000);
+        assert_eq!(m.ack_latency.max, 17000);
+        assert_eq!((m.start_time, m.end_time), (0, 60_000_000));
+
+        // gauges are reported while the consumer is idle, counters start over
+        let mut m = metrics(client(&mut parser, ping.clone(), 120_000_000));
+        assert_eq!(m.len(), 1);
+        let m = m.pop().unwrap();
+        assert_eq!(m.unacked_messages, 1);
+        assert_eq!(m.oldest_unacked_age, 90_000_000);
+        assert_eq!(m.delivered_messages, 0);
+        assert_eq!(m.ack_latency, AckLatency::default());
+
+        client(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::CloseConsumer as i32,
+                close_consumer: Some(CommandCloseConsumer {
+                    consumer_id: 1,
+                    request_id: 2,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            130_000_000,
+        );
+        assert!(metrics(client(&mut parser, ping, 180_000_001)).is_empty());
+    }
+}
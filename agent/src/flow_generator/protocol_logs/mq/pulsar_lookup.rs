@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::pulsar::PulsarInfo;
+use super::pulsar_proto::{
+    command_lookup_topic_response::LookupType, command_topic_migrated::ResourceType, ServerError,
+};
+use super::{MAX_PENDING_SESSIONS, SESSION_TIMEOUT};
+use crate::flow_generator::protocol_logs::{L7ProtocolInfo, L7ResponseStatus};
+
+// clients give up after this many redirects of one lookup
+const MAX_LOOKUP_HOPS: usize = 32;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TopologyEventType {
+    // a lookup chain ended in CONNECT or FAILED
+    #[default]
+    Lookup,
+    // the broker moved a producer or consumer to another cluster
+    Migration,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct LookupHop {
+    // the request asked for an authoritative answer, set on lookups following a redirect
+    pub authoritative: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<LookupType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_service_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_service_url_tls: Option<String>,
+    pub proxy_through_service_url: bool,
+    // unit: microseconds
+    pub time: u64,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PulsarTopologyEvent {
+    pub event_type: TopologyEventType,
+    pub topic: String,
+    // lookup responses in order, empty for migrations
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hops: Vec<LookupHop>,
+    pub redirects: u32,
+    // the broker the client ends up on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_service_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_service_url_tls: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<ResourceType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_id: Option<u64>,
+    pub status: L7ResponseStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ServerError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // unit: microseconds
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+// records the lookup chain of each topic on one connection, which is complete when the
+// client looks up through a proxy or keeps asking the same broker after a redirect.
+// only redirects followed on the same connection are counted, a client connecting to
+// the redirected broker starts another flow and leaves this chain ending at the redirect
+#[derive(Default)]
+pub struct LookupTracker {
+    chains: HashMap<String, PulsarTopologyEvent>,
+}
+
+impl LookupTracker {
+    // called with every request and its response, returns the event once the chain ends
+    pub fn on_response(
+        &mut self,
+        request: &PulsarInfo,
+        response: &PulsarInfo,
+    ) -> Option<PulsarTopologyEvent> {
+        let lookup = request.command.lookup_topic.as_ref()?;
+        let r = response.command.lookup_topic_response.as_ref()?;
+        if self.chains.len() >= MAX_PENDING_SESSIONS && !self.chains.contains_key(&lookup.topic) {
+            return None;
+        }
+        let chain =
+            self.chains
+                .entry(lookup.topic.clone())
+                .or_insert_with(|| PulsarTopologyEvent {
+                    topic: lookup.topic.clone(),
+                    start_time: request.time,
+                    ..Default::default()
+                });
+        let response_type = r.response.and_then(|t| LookupType::try_from(t).ok());
+        chain.hops.push(LookupHop {
+            authoritative: lookup.authoritative.unwrap_or_default(),
+            response: response_type,
+            broker_service_url: r.broker_service_url.clone(),
+            broker_service_url_tls: r.broker_service_url_tls.clone(),
+            proxy_through_service_url: r.proxy_through_service_url.unwrap_or_default(),
+            time: response.time,
+        });
+        chain.broker_service_url = r.broker_service_url.clone();
+        chain.broker_service_url_tls = r.broker_service_url_tls.clone();
+        chain.end_time = response.time;
+        chain.status = response.status;
+        chain.error = response.error;
+        chain.error_message = response.error_message.clone();
+
+        let ended = match response_type {
+            Some(LookupType::Redirect) => {
+                chain.redirects += 1;
+                chain.hops.len() >= MAX_LOOKUP_HOPS
+            }
+            _ => true,
+        };
+        if ended {
+            self.chains.remove(&lookup.topic)
+        } else {
+            None
+        }
+    }
+
+    // TOPIC_MIGRATED is pushed by the broker without a request
+    pub fn on_push(&self, info: &PulsarInfo) -> Option<PulsarTopologyEvent> {
+        let m = info.command.topic_migrated.as_ref()?;
+        Some(PulsarTopologyEvent {
+            event_type: TopologyEventType::Migration,
+            topic: info.topic.clone().unwrap_or_default(),
+            broker_service_url: m.broker_service_url.clone(),
+            broker_service_url_tls: m.broker_service_url_tls.clone(),
+            resource_type: ResourceType::try_from(m.resource_type).ok(),
+            resource_id: Some(m.resource_id),
+            start_time: info.time,
+            end_time: info.time,
+            ..Default::default()
+        })
+    }
+
+    // chains left hanging on a redirect are reported once the client stops following it here
+    pub fn expire(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        let expired = self
+            .chains
+            .iter()
+            .filter(|(_, c)| now.saturating_sub(c.end_time) >= SESSION_TIMEOUT)
+            .map(|(topic, _)| topic.clone())
+            .collect::<Vec<_>>();
+        for topic in expired {
+            let chain = self.chains.remove(&topic).unwrap();
+            output.push(L7ProtocolInfo::PulsarTopologyEvent(chain));
+        }
+    }
+
+    // the connection is gone, so are the chains still waiting on it
+    pub fn drain(&mut self, output: &mut Vec<L7ProtocolInfo>) {
+        output.extend(
+            self.chains
+                .drain()
+                .map(|(_, c)| L7ProtocolInfo::PulsarTopologyEvent(c)),
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.chains.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    use crate::common::flow::PacketDirection;
+    use crate::flow_generator::protocol_logs::mq::pulsar_proto::{
+        base_command::Type as CommandType, BaseCommand, CommandLookupTopic,
+        CommandLookupTopicResponse, CommandProducer, CommandProducerSuccess, CommandTopicMigrated,
+    };
+    use crate::flow_generator::protocol_logs::mq::PulsarLog;
+    use crate::flow_generator::protocol_logs::{
+        L7ParseResult, L7ProtocolParserInterface, ParseParam,
+    };
+
+    const TOPIC: &str = "persistent://public/default/orders";
+
+    fn parse(
+        parser: &mut PulsarLog,
+        command: BaseCommand,
+        direction: PacketDirection,
+        time: u64,
+    ) -> Vec<PulsarTopologyEvent> {
+        let command = command.encode_to_vec();
+        let mut frame = vec![];
+        frame.extend_from_slice(&(command.len() as u32 + 4).to_be_bytes());
+        frame.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&command);
+        let param = ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let infos = match parser.parse_payload(&frame, &param).unwrap() {
+            L7ParseResult::Single(info) => vec![info],
+            L7ParseResult::Multi(infos) => infos,
+            L7ParseResult::None => vec![],
+        };
+        infos
+            .into_iter()
+            .filter_map(|i| match i {
+                L7ProtocolInfo::PulsarTopologyEvent(e) => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn lookup(
+        parser: &mut PulsarLog,
+        request_id: u64,
+        authoritative: bool,
+        response: LookupType,
+        broker: &str,
+        time: u64,
+    ) -> Vec<PulsarTopologyEvent> {
+        let request = BaseCommand {
+            r#type: CommandType::Lookup as i32,
+            lookup_topic: Some(CommandLookupTopic {
+                topic: TOPIC.to_owned(),
+                request_id,
+                authoritative: Some(authoritative),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(parse(parser, request, PacketDirection::ClientToServer, time).is_empty());
+        let response = BaseCommand {
+            r#type: CommandType::LookupResponse as i32,
+            lookup_topic_response: Some(CommandLookupTopicResponse {
+                request_id,
+                response: Some(response as i32),
+                broker_service_url: Some(broker.to_owned()),
+                proxy_through_service_url: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        parse(
+            parser,
+            response,
+            PacketDirection::ServerToClient,
+            time + 100,
+        )
+    }
+
+    #[test]
+    fn lookup_chain() {
+        let mut parser = PulsarLog::default();
+        let b1 = "pulsar://broker-1:6650";
+        let b2 = "pulsar://broker-2:6650";
+        assert!(lookup(&mut parser, 1, false, LookupType::Redirect, b1, 1000).is_empty());
+        assert!(lookup(&mut parser, 2, true, LookupType::Redirect, b2, 2000).is_empty());
+        let mut events = lookup(&mut parser, 3, true, LookupType::Connect, b2, 3000);
+        assert_eq!(events.len(), 1);
+        let e = events.pop().unwrap();
+        assert_eq!(e.event_type, TopologyEventType::Lookup);
+        assert_eq!(e.topic, TOPIC);
+        assert_eq!(e.redirects, 2);
+        assert_eq!(e.broker_service_url.as_deref(), Some(b2));
+        assert_eq!(e.status, L7ResponseStatus::Ok);
+        assert_eq!((e.start_time, e.end_time), (1000, 3100));
+        let hops = e
+            .hops
+            .iter()
+            .map(|h| (h.authoritative, h.response, h.broker_service_url.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hops,
+            vec![
+                (false, Some(LookupType::Redirect), Some(b1)),
+                (true, Some(LookupType::Redirect), Some(b2)),
+                (true, Some(LookupType::Connect), Some(b2)),
+            ]
+        );
+        assert!(e.hops.iter().all(|h| h.proxy_through_service_url));
+
+        // the client followed the redirect on another connection
+        assert!(lookup(&mut parser, 4, false, LookupType::Redirect, b1, 10000).is_empty());
+        let ping = BaseCommand {
+            r#type: CommandType::Ping as i32,
+            ..Default::default()
+        };
+        let events = parse(
+            &mut parser,
+            ping,
+            PacketDirection::ClientToServer,
+            10100 + SESSION_TIMEOUT,
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].redirects, 1);
+        assert_eq!(events[0].hops.len(), 1);
+
+        // or the chain is reported when the old connection closes
+        assert!(lookup(&mut parser, 5, false, LookupType::Redirect, b2, 20000).is_empty());
+        // along with the ping sent to expire the first chain, never answered
+        let infos = parser.on_flow_end().unwrap_multi();
+        assert!(matches!(
+            &infos[0],
+            L7ProtocolInfo::PulsarInfo(p) if p.status == L7ResponseStatus::Timeout
+        ));
+        let event = match &infos[1] {
+            L7ProtocolInfo::PulsarTopologyEvent(e) => e,
+            r => panic!("unexpected {:?}", r),
+        };
+        assert_eq!(event.redirects, 1);
+        assert_eq!(event.broker_service_url.as_deref(), Some(b2));
+        assert!(matches!(parser.on_flow_end(), L7ParseResult::None));
+    }
+
+    #[test]
+    fn topic_migration() {
+        let mut parser = PulsarLog::default();
+        let producer = BaseCommand {
+            r#type: CommandType::Producer as i32,
+            producer: Some(CommandProducer {
+                topic: TOPIC.to_owned(),
+                producer_id: 1,
+                request_id: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let success = BaseCommand {
+            r#type: CommandType::ProducerSuccess as i32,
+            producer_success: Some(CommandProducerSuccess {
+                request_id: 1,
+                producer_name: "orders-0".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        parse(&mut parser, producer, PacketDirection::ClientToServer, 0);
+        parse(&mut parser, success, PacketDirection::ServerToClient, 100);
+
+        let migrated = BaseCommand {
+            r#type: CommandType::TopicMigrated as i32,
+            topic_migrated: Some(CommandTopicMigrated {
+                resource_id: 1,
+                resource_type: ResourceType::Producer as i32,
+                broker_service_url: Some("pulsar://green-cluster:6650".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut events = parse(&mut parser, migrated, PacketDirection::ServerToClient, 5000);
+        assert_eq!(events.len(), 1);
+        let e = events.pop().unwrap();
+        assert_eq!(e.event_type, TopologyEventType::Migration);
+        assert_eq!(e.topic, TOPIC);
+        assert_eq!(e.resource_type, Some(ResourceType::Producer));
+        assert_eq!(e.resource_id, Some(1));
+        assert_eq!(
+            e.broker_service_url.as_deref(),
+            Some("pulsar://green-cluster:6650")
+        );
+        assert_eq!(e.start_time, 5000);
+    }
+}
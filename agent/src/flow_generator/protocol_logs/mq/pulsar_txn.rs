@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::pulsar::PulsarInfo;
+use super::pulsar_proto::{
+    base_command::Type as CommandType, ServerError, Subscription, TxnAction,
+};
+use super::{MAX_PENDING_SESSIONS, SESSION_TIMEOUT};
+use crate::flow_generator::protocol_logs::{L7ProtocolInfo, L7ResponseStatus};
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionOutcome {
+    Commit,
+    Abort,
+    // no END_TXN seen before the transaction ttl
+    #[default]
+    Timeout,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PulsarTransaction {
+    pub txnid_most_bits: u64,
+    pub txnid_least_bits: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tc_id: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<Subscription>,
+    // the requested action, whether it took effect is given by status
+    pub outcome: TransactionOutcome,
+    pub status: L7ResponseStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ServerError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // unit: microseconds
+    pub start_time: u64,
+    pub end_time: u64,
+    pub duration: u64,
+
+    #[serde(skip)]
+    deadline: u64,
+}
+
+impl PulsarTransaction {
+    fn new(txn_id: TxnId, time: u64) -> Self {
+        Self {
+            txnid_most_bits: txn_id.0,
+            txnid_least_bits: txn_id.1,
+            start_time: time,
+            deadline: time.saturating_add(SESSION_TIMEOUT),
+            ..Default::default()
+        }
+    }
+
+    // keeps the first failure, later operations usually fail for the same reason
+    fn record_error(&mut self, response: &PulsarInfo) {
+        if response.error.is_some() && self.error.is_none() {
+            self.status = response.status;
+            self.error = response.error;
+            self.error_message = response.error_message.clone();
+        }
+    }
+
+    fn finish(mut self, outcome: TransactionOutcome, time: u64) -> Self {
+        self.outcome = outcome;
+        self.end_time = time;
+        self.duration = time.saturating_sub(self.start_time);
+        self
+    }
+
+    fn into_timeout(mut self) -> L7ProtocolInfo {
+        self.status = L7ResponseStatus::Timeout;
+        let deadline = self.deadline;
+        L7ProtocolInfo::PulsarTransaction(self.finish(TransactionOutcome::Timeout, deadline))
+    }
+}
+
+// (txnid_most_bits, txnid_least_bits)
+type TxnId = (u64, u64);
+
+fn txn_id(most_bits: Option<u64>, least_bits: Option<u64>) -> TxnId {
+    (
+        most_bits.unwrap_or_default(),
+        least_bits.unwrap_or_default(),
+    )
+}
+
+// follows transactions through the coordinator commands of one connection,
+// from NEW_TXN to the END_TXN that commits or aborts them
+#[derive(Default)]
+pub struct TransactionTracker {
+    transactions: HashMap<TxnId, PulsarTransaction>,
+}
+
+impl TransactionTracker {
+    // called with every request and its response, returns the summary once the transaction ends
+    pub fn on_response(
+        &mut self,
+        request: &PulsarInfo,
+        response: &PulsarInfo,
+    ) -> Option<PulsarTransaction> {
+        let c = &request.command;
+        match request.command_type {
+            CommandType::NewTxn => {
+                let r = response.command.new_txn_response.as_ref()?;
+                if response.error.is_some() {
+                    return None;
+                }
+                let txn_id = txn_id(r.txnid_most_bits, r.txnid_least_bits);
+                let txn = self.get_or_insert(txn_id, request.time)?;
+                txn.tc_id = c.new_txn.as_ref().and_then(|m| m.tc_id);
+                if let Some(ttl) = c.new_txn.as_ref().and_then(|m| m.txn_ttl_seconds) {
+                    txn.deadline = request.time.saturating_add(ttl.saturating_mul(1_000_000));
+                }
+            }
+            CommandType::AddPartitionToTxn => {
+                let m = c.add_partition_to_txn.as_ref()?;
+                let txn = self
+                    .get_or_insert(txn_id(m.txnid_most_bits, m.txnid_least_bits), request.time)?;
+                txn.record_error(response);
+                if response.error.is_none() {
+                    for p in m.partitions.iter() {
+                        if !txn.partitions.contains(p) {
+                            txn.partitions.push(p.clone());
+                        }
+                    }
+                }
+            }
+            CommandType::AddSubscriptionToTxn => {
+                let m = c.add_subscription_to_txn.as_ref()?;
+                let txn = self
+                    .get_or_insert(txn_id(m.txnid_most_bits, m.txnid_least_bits), request.time)?;
+                txn.record_error(response);
+                if response.error.is_none() {
+                    for s in m.subscription.iter() {
+                        if !txn.subscriptions.contains(s) {
+                            txn.subscriptions.push(s.clone());
+                        }
+                    }
+                }
+            }
+            CommandType::EndTxn => {
+                let m = c.end_txn.as_ref()?;
+                let txn_id = txn_id(m.txnid_most_bits, m.txnid_least_bits);
+                let mut txn = self
+                    .transactions
+                    .remove(&txn_id)
+                    .unwrap_or_else(|| PulsarTransaction::new(txn_id, request.time));
+                if response.error.is_some() {
+                    txn.status = response.status;
+                    txn.error = response.error;
+                    txn.error_message = response.error_message.clone();
+                }
+                let outcome = match m.txn_action.map(TxnAction::try_from) {
+                    Some(Ok(TxnAction::Abort)) => TransactionOutcome::Abort,
+                    _ => TransactionOutcome::Commit,
+                };
+                return Some(txn.finish(outcome, response.time));
+            }
+            // the coordinator forwards END_TXN to the owning brokers on its own connections,
+            // only transactions already seen on this connection are updated
+            CommandType::EndTxnOnPartition => {
+                let m = c.end_txn_on_partition.as_ref()?;
+                let txn = self
+                    .transactions
+                    .get_mut(&txn_id(m.txnid_most_bits, m.txnid_least_bits))?;
+                txn.record_error(response);
+                if let Some(p) = m.topic.as_ref().filter(|p| !txn.partitions.contains(p)) {
+                    txn.partitions.push(p.clone());
+                }
+            }
+            CommandType::EndTxnOnSubscription => {
+                let m = c.end_txn_on_subscription.as_ref()?;
+                let txn = self
+                    .transactions
+                    .get_mut(&txn_id(m.txnid_most_bits, m.txnid_least_bits))?;
+                txn.record_error(response);
+                if let Some(s) = m
+                    .subscription
+                    .as_ref()
+                    .filter(|s| !txn.subscriptions.contains(s))
+                {
+                    txn.subscriptions.push(s.clone());
+                }
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn get_or_insert(&mut self, txn_id: TxnId, time: u64) -> Option<&mut PulsarTransaction> {
+        if self.transactions.len() >= MAX_PENDING_SESSIONS
+            && !self.transactions.contains_key(&txn_id)
+        {
+            return None;
+        }
+        Some(
+            self.transactions
+                .entry(txn_id)
+                .or_insert_with(|| PulsarTransaction::new(txn_id, time)),
+        )
+    }
+
+    // transactions not ended before their ttl are aborted by the coordinator
+    pub fn expire(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        let expired = self
+            .transactions
+            .iter()
+            .filter(|(_, t)| now > t.deadline)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            let txn = self.transactions.remove(&id).unwrap();
+            output.push(txn.into_timeout());
+        }
+    }
+
+    // transactions still open when the connection closes
+    pub fn drain(&mut self, output: &mut Vec<L7ProtocolInfo>) {
+        output.extend(self.transactions.drain().map(|(_, t)| t.into_timeout()));
+    }
+
+    pub fn clear(&mut self) {
+        self.transactions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    use crate::common::flow::PacketDirection;
+    use crate::flow_generator::protocol_logs::mq::pulsar_proto::{
+        BaseCommand, CommandAddPartitionToTxn, CommandAddPartitionToTxnResponse,
+        CommandAddSubscriptionToTxn, CommandAddSubscriptionToTxnResponse, CommandEndTxn,
+        CommandEndTxnResponse, CommandNewTxn, CommandNewTxnResponse,
+    };
+    use crate::flow_generator::protocol_logs::mq::PulsarLog;
+    use crate::flow_generator::protocol_logs::{
+        L7ParseResult, L7ProtocolParserInterface, ParseParam,
+    };
+
+    fn frame(command: BaseCommand) -> Vec<u8> {
+        let command = command.encode_to_vec();
+        let mut frame = vec![];
+        frame.extend_from_slice(&(command.len() as u32 + 4).to_be_bytes());
+        frame.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&command);
+        frame
+    }
+
+    fn parse(parser: &mut PulsarLog, command: BaseCommand, time: u64) -> L7ParseResult {
+        let direction = match CommandType::try_from(command.r#type).unwrap() {
+            CommandType::NewTxn
+            | CommandType::AddPartitionToTxn
+            | CommandType::AddSubscriptionToTxn
+            | CommandType::EndTxn => PacketDirection::ClientToServer,
+            _ => PacketDirection::ServerToClient,
+        };
+        let param = ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        parser.parse_payload(&frame(command), &param).unwrap()
+    }
+
+    fn transaction(result: L7ParseResult) -> PulsarTransaction {
+        match result.unwrap_multi().pop() {
+            Some(L7ProtocolInfo::PulsarTransaction(t)) => t,
+            other => panic!("not a transaction: {:?}", other),
+        }
+    }
+
+    fn new_txn(parser: &mut PulsarLog, request_id: u64, ttl: u64, least_bits: u64, time: u64) {
+        parse(
+            parser,
+            BaseCommand {
+                r#type: CommandType::NewTxn as i32,
+                new_txn: Some(CommandNewTxn {
+                    request_id,
+                    txn_ttl_seconds: Some(ttl),
+                    tc_id: Some(0),
+                }),
+                ..Default::default()
+            },
+            time,
+        );
+        parse(
+            parser,
+            BaseCommand {
+                r#type: CommandType::NewTxnResponse as i32,
+                new_txn_response: Some(CommandNewTxnResponse {
+                    request_id,
+                    txnid_most_bits: Some(0),
+                    txnid_least_bits: Some(least_bits),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            time + 100,
+        );
+    }
+
+    fn end_txn(request_id: u64, least_bits: u64, action: TxnAction) -> BaseCommand {
+        BaseCommand {
+            r#type: CommandType::EndTxn as i32,
+            end_txn: Some(CommandEndTxn {
+                request_id,
+                txnid_most_bits: Some(0),
+                txnid_least_bits: Some(least_bits),
+                txn_action: Some(action as i32),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn end_txn_response(request_id: u64, error: Option<ServerError>) -> BaseCommand {
+        BaseCommand {
+            r#type: CommandType::EndTxnResponse as i32,
+            end_txn_response: Some(CommandEndTxnResponse {
+                request_id,
+                error: error.map(|e| e as i32),
+                message: error.map(|_| "conflict".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn committed_transaction() {
+        let mut parser = PulsarLog::default();
+        new_txn(&mut parser, 1, 60, 5, 1000);
+
+        let subscription = Subscription {
+            topic: "persistent://public/default/in".to_owned(),
+            subscription: "pipeline".to_owned(),
+        };
+        for (request_id, partition) in [(2, "out-partition-0"), (3, "out-partition-1")] {
+            parse(
+                &mut parser,
+                BaseCommand {
+                    r#type: CommandType::AddPartitionToTxn as i32,
+                    add_partition_to_txn: Some(CommandAddPartitionToTxn {
+                        request_id,
+                        txnid_most_bits: Some(0),
+                        txnid_least_bits: Some(5),
+                        partitions: vec![partition.to_owned(), "out-partition-0".to_owned()],
+                    }),
+                    ..Default::default()
+                },
+                2000,
+            );
+            parse(
+                &mut parser,
+                BaseCommand {
+                    r#type: CommandType::AddPartitionToTxnResponse as i32,
+                    add_partition_to_txn_response: Some(CommandAddPartitionToTxnResponse {
+                        request_id,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                2100,
+            );
+        }
+        parse(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::AddSubscriptionToTxn as i32,
+                add_subscription_to_txn: Some(CommandAddSubscriptionToTxn {
+                    request_id: 4,
+                    txnid_most_bits: Some(0),
+                    txnid_least_bits: Some(5),
+                    subscription: vec![subscription.clone()],
+                }),
+                ..Default::default()
+            },
+            3000,
+        );
+        parse(
+            &mut parser,
+            BaseCommand {
+                r#type: CommandType::AddSubscriptionToTxnResponse as i32,
+                add_subscription_to_txn_response: Some(CommandAddSubscriptionToTxnResponse {
+                    request_id: 4,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            3100,
+        );
+
+        assert!(parse(&mut parser, end_txn(5, 5, TxnAction::Commit), 4000).is_none());
+        let t = transaction(parse(&mut parser, end_txn_response(5, None), 4500));
+        assert_eq!((t.txnid_most_bits, t.txnid_least_bits), (0, 5));
+        assert_eq!(t.tc_id, Some(0));
+        assert_eq!(t.partitions, vec!["out-partition-0", "out-partition-1"]);
+        assert_eq!(t.subscriptions, vec![subscription]);
+        assert_eq!(t.outcome, TransactionOutcome::Commit);
+        assert_eq!(t.status, L7ResponseStatus::Ok);
+        assert_eq!((t.start_time, t.end_time, t.duration), (1000, 4500, 3500));
+
+        // ended transactions are no longer tracked, nothing expires after the ttl
+        let result = parse(&mut parser, end_txn_response(99, None), 100_000_000);
+        assert!(matches!(
+            result.unwrap_single(),
+            L7ProtocolInfo::PulsarInfo(_)
+        ));
+    }
+
+    #[test]
+    fn failed_and_expired_transactions() {
+        let mut parser = PulsarLog::default();
+        new_txn(&mut parser, 1, 60, 5, 0);
+        new_txn(&mut parser, 2, 1, 6, 0);
+
+        parse(&mut parser, end_txn(3, 5, TxnAction::Abort), 1000);
+        let t = transaction(parse(
+            &mut parser,
+            end_txn_response(3, Some(ServerError::TransactionConflict)),
+            1200,
+        ));
+        assert_eq!(t.outcome, TransactionOutcome::Abort);
+        assert_eq!(t.status, L7ResponseStatus::ClientError);
+        assert_eq!(t.error, Some(ServerError::TransactionConflict));
+        assert_eq!(t.error_message.as_deref(), Some("conflict"));
+
+        // the second transaction outlives its 1s ttl
+        let result = parse(&mut parser, end_txn(4, 7, TxnAction::Commit), 1_000_001);
+        match result.unwrap_single() {
+            L7ProtocolInfo::PulsarTransaction(t) => {
+                assert_eq!(t.txnid_least_bits, 6);
+                assert_eq!(t.outcome, TransactionOutcome::Timeout);
+                assert_eq!(t.status, L7ResponseStatus::Timeout);
+                assert_eq!(t.duration, 1_000_000);
+            }
+            other => panic!("not a transaction: {:?}", other),
+        }
+
+        // END_TXN for a transaction created before capture still gets a summary
+        let t = transaction(parse(&mut parser, end_txn_response(4, None), 1_000_100));
+        assert_eq!(t.txnid_least_bits, 7);
+        assert_eq!(t.duration, 99);
+
+        // a ttl too long to be added to the time never expires
+        new_txn(&mut parser, 5, u64::MAX, 8, 2_000_000);
+        assert!(parse(&mut parser, end_txn(6, 9, TxnAction::Commit), u64::MAX).is_none());
+
+        // and is reported as a timeout when the flow ends, with the END_TXN left unanswered
+        let infos = parser.on_flow_end().unwrap_multi();
+        assert_eq!(infos.len(), 2);
+        assert!(infos.iter().any(|i| matches!(
+            i,
+            L7ProtocolInfo::PulsarTransaction(t)
+                if t.txnid_least_bits == 8 && t.outcome == TransactionOutcome::Timeout
+        )));
+        assert!(infos.iter().any(|i| matches!(
+            i,
+            L7ProtocolInfo::PulsarInfo(r)
+                if r.command_type == CommandType::EndTxn && r.status == L7ResponseStatus::Timeout
+        )));
+    }
+}
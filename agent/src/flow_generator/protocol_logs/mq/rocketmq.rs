@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::read_u32_be;
+use public::l7_protocol::L7Protocol;
+use serde::{Deserialize, Serialize};
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// default frameMaxLength of the remoting server
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+// headers fit in the head of a frame, the rest of a larger message body is
+// skipped instead of buffered
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const SERIALIZE_JSON: u8 = 0;
+const SERIALIZE_ROCKETMQ: u8 = 1;
+const FLAG_RESPONSE: i32 = 0x01;
+const FLAG_ONEWAY: i32 = 0x02;
+// separators of the message properties carried in extFields
+const NAME_VALUE_SEPARATOR: char = '\u{1}';
+const PROPERTY_SEPARATOR: char = '\u{2}';
+
+// RequestCode of rocketmq-remoting
+fn request_name(code: i32) -> Option<&'static str> {
+    let name = match code {
+        10 => "SEND_MESSAGE",
+        11 => "PULL_MESSAGE",
+        12 => "QUERY_MESSAGE",
+        13 => "QUERY_BROKER_OFFSET",
+        14 => "QUERY_CONSUMER_OFFSET",
+        15 => "UPDATE_CONSUMER_OFFSET",
+        17 => "UPDATE_AND_CREATE_TOPIC",
+        21 => "GET_ALL_TOPIC_CONFIG",
+        26 => "GET_BROKER_CONFIG",
+        28 => "GET_BROKER_RUNTIME_INFO",
+        29 => "SEARCH_OFFSET_BY_TIMESTAMP",
+        30 => "GET_MAX_OFFSET",
+        31 => "GET_MIN_OFFSET",
+        32 => "GET_EARLIEST_MSG_STORETIME",
+        33 => "VIEW_MESSAGE_BY_ID",
+        34 => "HEART_BEAT",
+        35 => "UNREGISTER_CLIENT",
+        36 => "CONSUMER_SEND_MSG_BACK",
+        37 => "END_TRANSACTION",
+        38 => "GET_CONSUMER_LIST_BY_GROUP",
+        39 => "CHECK_TRANSACTION_STATE",
+        40 => "NOTIFY_CONSUMER_IDS_CHANGED",
+        41 => "LOCK_BATCH_MQ",
+        42 => "UNLOCK_BATCH_MQ",
+        43 => "GET_ALL_CONSUMER_OFFSET",
+        45 => "GET_ALL_DELAY_OFFSET",
+        46 => "CHECK_CLIENT_CONFIG",
+        103 => "REGISTER_BROKER",
+        104 => "UNREGISTER_BROKER",
+        105 => "GET_ROUTEINFO_BY_TOPIC",
+        106 => "GET_BROKER_CLUSTER_INFO",
+        200 => "UPDATE_AND_CREATE_SUBSCRIPTIONGROUP",
+        201 => "GET_ALL_SUBSCRIPTIONGROUP_CONFIG",
+        206 => "GET_ALL_TOPIC_LIST_FROM_NAMESERVER",
+        207 => "DELETE_SUBSCRIPTIONGROUP",
+        215 => "DELETE_TOPIC_IN_BROKER",
+        216 => "DELETE_TOPIC_IN_NAMESRV",
+        224 => "GET_TOPICS_BY_CLUSTER",
+        300 => "QUERY_TOPIC_CONSUME_BY_WHO",
+        304 => "GET_CONSUMER_CONNECTION_LIST",
+        307 => "GET_CONSUMER_RUNNING_INFO",
+        309 => "CONSUME_MESSAGE_DIRECTLY",
+        310 => "SEND_MESSAGE_V2",
+        320 => "SEND_BATCH_MESSAGE",
+        321 => "QUERY_CONSUME_QUEUE",
+        350 => "SEND_REPLY_MESSAGE",
+        351 => "SEND_REPLY_MESSAGE_V2",
+        352 => "PUSH_REPLY_MESSAGE_TO_CLIENT",
+        200050 => "POP_MESSAGE",
+        200051 => "ACK_MESSAGE",
+        200052 => "PEEK_MESSAGE",
+        200053 => "CHANGE_MESSAGE_INVISIBLETIME",
+        200054 => "NOTIFICATION",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// ResponseCode of rocketmq-remoting
+fn response_name(code: i32) -> Option<&'static str> {
+    let name = match code {
+        0 => "SUCCESS",
+        1 => "SYSTEM_ERROR",
+        2 => "SYSTEM_BUSY",
+        3 => "REQUEST_CODE_NOT_SUPPORTED",
+        4 => "TRANSACTION_FAILED",
+        10 => "FLUSH_DISK_TIMEOUT",
+        11 => "SLAVE_NOT_AVAILABLE",
+        12 => "FLUSH_SLAVE_TIMEOUT",
+        13 => "MESSAGE_ILLEGAL",
+        14 => "SERVICE_NOT_AVAILABLE",
+        15 => "VERSION_NOT_SUPPORTED",
+        16 => "NO_PERMISSION",
+        17 => "TOPIC_NOT_EXIST",
+        18 => "TOPIC_EXIST_ALREADY",
+        19 => "PULL_NOT_FOUND",
+        20 => "PULL_RETRY_IMMEDIATELY",
+        21 => "PULL_OFFSET_MOVED",
+        22 => "QUERY_NOT_FOUND",
+        23 => "SUBSCRIPTION_PARSE_FAILED",
+        24 => "SUBSCRIPTION_NOT_EXIST",
+        25 => "SUBSCRIPTION_NOT_LATEST",
+        26 => "SUBSCRIPTION_GROUP_NOT_EXIST",
+        27 => "FILTER_DATA_NOT_EXIST",
+        28 => "FILTER_DATA_NOT_LATEST",
+        200 => "TRANSACTION_SHOULD_COMMIT",
+        201 => "TRANSACTION_SHOULD_ROLLBACK",
+        202 => "TRANSACTION_STATE_UNKNOW",
+        203 => "TRANSACTION_STATE_GROUP_WRONG",
+        204 => "NO_BUYER_ID",
+        205 => "NOT_IN_CURRENT_UNIT",
+        206 => "CONSUMER_NOT_ONLINE",
+        207 => "CONSUME_MSG_TIMEOUT",
+        208 => "NO_MESSAGE",
+        209 => "POLLING_FULL",
+        210 => "POLLING_TIMEOUT",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn response_status(code: i32) -> L7ResponseStatus {
+    match code {
+        // the usual outcomes of long polling and lookups
+        0 | 19 | 20 | 22 | 208 | 210 => L7ResponseStatus::Ok,
+        1 | 2 | 4 | 10 | 11 | 12 | 14 | 209 => L7ResponseStatus::ServerError,
+        207 => L7ResponseStatus::Timeout,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+// LanguageCode of rocketmq-remoting, by its ordinal in ROCKETMQ serialized headers
+fn language_name(code: u8) -> &'static str {
+    match code {
+        0 => "JAVA",
+        1 => "CPP",
+        2 => "DOTNET",
+        3 => "PYTHON",
+        4 => "DELPHI",
+        5 => "ERLANG",
+        6 => "RUBY",
+        7 => "OTHER",
+        8 => "HTTP",
+        9 => "GO",
+        10 => "PHP",
+        11 => "OMS",
+        12 => "RUST",
+        _ => "UNKNOWN",
+    }
+}
+
+trait RocketmqRead {
+    fn string(&mut self, len: i32) -> Result<String>;
+}
+
+impl RocketmqRead for Reader<'_> {
+    fn string(&mut self, len: i32) -> Result<String> {
+        let len = usize::try_from(len)
+            .map_err(|_| Error::RocketmqLogParseFailed(format!("invalid length {}", len)))?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+// RemotingCommand without its body
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Command {
+    code: i32,
+    language: Option<String>,
+    version: Option<i32>,
+    opaque: i32,
+    #[serde(default)]
+    flag: i32,
+    remark: Option<String>,
+    #[serde(default)]
+    ext_fields: HashMap<String, String>,
+}
+
+impl Command {
+    fn parse(serialize_type: u8, header: &[u8]) -> Result<Self> {
+        match serialize_type {
+            SERIALIZE_JSON => serde_json::from_slice(header)
+                .map_err(|e| Error::RocketmqLogParseFailed(format!("invalid json header: {}", e))),
+            SERIALIZE_ROCKETMQ => Self::parse_rocketmq(header),
+            t => Err(Error::RocketmqLogParseFailed(format!(
+                "unknown serialize type {}",
+                t
+            ))),
+        }
+    }
+
+    // [code i16][language u8][version i16][opaque i32][flag i32]
+    // [remark length i32][remark][ext length i32]([key length i16][key][value length i32][value])*
+    fn parse_rocketmq(header: &[u8]) -> Result<Self> {
+        let mut r = Reader { data: header };
+        let mut command = Command {
+            code: r.i16()? as i32,
+            language: Some(language_name(r.u8()?).to_owned()),
+            version: Some(r.i16()? as i32),
+            opaque: r.i32()?,
+            flag: r.i32()?,
+            ..Default::default()
+        };
+        let len = r.i32()?;
+        if len > 0 {
+            command.remark = Some(r.string(len)?);
+        }
+        let len = r.i32()?;
+        if len > 0 {
+            let mut ext = Reader {
+                data: r.take(len as usize)?,
+            };
+            while !ext.data.is_empty() {
+                let len = ext.i16()? as i32;
+                let key = ext.string(len)?;
+                let len = ext.i32()?;
+                let value = ext.string(len)?;
+                command.ext_fields.insert(key, value);
+            }
+        }
+        Ok(command)
+    }
+
+    fn ext(&self, keys: &[&str]) -> Option<String> {
+        keys.iter()
+            .find_map(|k| self.ext_fields.get(*k))
+            .filter(|v| !v.is_empty())
+            .cloned()
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct RocketmqInfo {
+    pub msg_type: LogMessageType,
+    pub code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_name: Option<&'static str>,
+    pub opaque: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    pub header_size: u32,
+    pub body_size: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    // producer or consumer group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_name: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl RocketmqInfo {
+    fn new(command: Command, config: Option<&LogParserConfig>) -> Self {
+        let mut info = RocketmqInfo {
+            language: command.language.clone(),
+            version: command.version,
+            opaque: command.opaque,
+            // the short names are of SEND_MESSAGE_V2 and SEND_BATCH_MESSAGE
+            topic: command.ext(&["topic", "b"]),
+            group: command.ext(&["producerGroup", "consumerGroup", "group", "a"]),
+            queue_id: command.ext(&["queueId", "e"]).and_then(|v| v.parse().ok()),
+            queue_offset: command
+                .ext(&["queueOffset", "commitOffset"])
+                .and_then(|v| v.parse().ok()),
+            msg_id: command.ext(&["msgId", "offsetMsgId"]),
+            transaction_id: command.ext(&["transactionId"]),
+            ..Default::default()
+        };
+        if command.flag & FLAG_RESPONSE != 0 {
+            info.msg_type = LogMessageType::Response;
+            info.response_code = Some(command.code);
+            info.response_name = response_name(command.code);
+            info.status = response_status(command.code);
+            info.remark = command.remark.clone();
+        } else {
+            // oneway requests such as UNREGISTER_CLIENT are not answered
+            info.msg_type = if command.flag & FLAG_ONEWAY != 0 {
+                LogMessageType::Other
+            } else {
+                LogMessageType::Request
+            };
+            info.code = command.code;
+            info.code_name = request_name(command.code);
+        }
+
+        let properties = command
+            .ext(&["properties", "i"])
+            .map(|p| {
+                p.split(PROPERTY_SEPARATOR)
+                    .filter_map(|kv| kv.split_once(NAME_VALUE_SEPARATOR))
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let property = |key: &str| {
+            properties
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        };
+        info.keys = property("KEYS").map(str::to_owned);
+        info.tags = property("TAGS").map(str::to_owned);
+        if info.msg_id.is_none() {
+            info.msg_id = property("UNIQ_KEY").map(str::to_owned);
+        }
+        if let Some(config) = config {
+            info.trace = TraceContext::extract(config, |key| {
+                property(key).or_else(|| command.ext_fields.get(key).map(String::as_str))
+            });
+        }
+        info
+    }
+
+    fn merge(&mut self, response: RocketmqInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_code = response.response_code;
+        self.response_name = response.response_name;
+        self.remark = response.remark;
+        self.status = response.status;
+        // where the broker stored a sent message
+        if response.msg_id.is_some() {
+            self.msg_id = response.msg_id;
+        }
+        if response.queue_id.is_some() {
+            self.queue_id = response.queue_id;
+        }
+        if response.queue_offset.is_some() {
+            self.queue_offset = response.queue_offset;
+        }
+        if response.transaction_id.is_some() {
+            self.transaction_id = response.transaction_id;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RocketmqLog {
+    streams: PerDirection<Stream>,
+    // opaque is chosen by the side sending the request, brokers send requests
+    // such as CHECK_TRANSACTION_STATE to clients too
+    pending: HashMap<(PacketDirection, i32), RocketmqInfo>,
+}
+
+// [total length u32][serialize type u8][header length u24][header][body]
+fn frame_head(data: &[u8]) -> Result<Option<(usize, u8, usize)>> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let total_size = read_u32_be(data) as usize;
+    let serialize_type = data[4];
+    let header_size = read_u32_be(&data[4..]) as usize & 0xff_ffff;
+    if total_size > MAX_FRAME_SIZE
+        || header_size + 4 > total_size
+        || serialize_type > SERIALIZE_ROCKETMQ
+    {
+        return Err(Error::RocketmqLogParseFailed(format!(
+            "invalid frame length {} header length {} serialize type {}",
+            total_size, header_size, serialize_type
+        )));
+    }
+    Ok(Some((total_size + 4, serialize_type, header_size)))
+}
+
+impl RocketmqLog {
+    fn parse_frames(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<RocketmqInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while let Some((total_size, serialize_type, header_size)) = frame_head(remain)? {
+            let frame = if remain.len() >= total_size {
+                let frame = &remain[8..total_size];
+                remain = &remain[total_size..];
+                frame
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[8..]
+            } else {
+                break;
+            };
+            if frame.len() < header_size {
+                debug!("skip rocketmq frame with a header of {} bytes", header_size);
+                continue;
+            }
+            match Command::parse(serialize_type, &frame[..header_size]) {
+                Ok(command) => {
+                    let mut info = RocketmqInfo::new(command, param.parse_config);
+                    info.header_size = header_size as u32;
+                    info.body_size = (total_size - 8 - header_size) as u32;
+                    info.time = param.time;
+                    infos.push(info);
+                }
+                Err(e) => {
+                    debug!("skip rocketmq frame: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        info: RocketmqInfo,
+        direction: PacketDirection,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        match info.msg_type {
+            LogMessageType::Request => {
+                let key = (direction, info.opaque);
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::RocketmqInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    output.push(L7ProtocolInfo::RocketmqInfo(prev));
+                }
+            }
+            LogMessageType::Response => {
+                match self.pending.remove(&(direction.reversed(), info.opaque)) {
+                    Some(mut request) => {
+                        request.merge(info);
+                        output.push(L7ProtocolInfo::RocketmqInfo(request));
+                    }
+                    None => output.push(L7ProtocolInfo::RocketmqInfo(info)),
+                }
+            }
+            _ => output.push(L7ProtocolInfo::RocketmqInfo(info)),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for RocketmqLog {
+    // a request with a known code from the client
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer {
+            return false;
+        }
+        let Ok(Some((_, serialize_type, header_size))) = frame_head(payload) else {
+            return false;
+        };
+        payload
+            .get(8..8 + header_size)
+            .and_then(|header| Command::parse(serialize_type, header).ok())
+            .is_some_and(|c| c.flag & FLAG_RESPONSE == 0 && request_name(c.code).is_some())
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_frames(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, param.direction, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::RocketMQ
+    }
+
+    fn reset(&mut self) {
+        self.streams = Default::default();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn frame(serialize_type: u8, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut f = ((4 + header.len() + body.len()) as u32)
+            .to_be_bytes()
+            .to_vec();
+        f.extend_from_slice(&((serialize_type as u32) << 24 | header.len() as u32).to_be_bytes());
+        f.extend_from_slice(header);
+        f.extend_from_slice(body);
+        f
+    }
+
+    fn rocketmq_header(
+        code: i16,
+        opaque: i32,
+        flag: i32,
+        remark: &str,
+        ext: &[(&str, &str)],
+    ) -> Vec<u8> {
+        let mut h = code.to_be_bytes().to_vec();
+        h.push(9);
+        h.extend_from_slice(&453i16.to_be_bytes());
+        h.extend_from_slice(&opaque.to_be_bytes());
+        h.extend_from_slice(&flag.to_be_bytes());
+        h.extend_from_slice(&(remark.len() as i32).to_be_bytes());
+        h.extend_from_slice(remark.as_bytes());
+        let mut fields = vec![];
+        for (k, v) in ext {
+            fields.extend_from_slice(&(k.len() as i16).to_be_bytes());
+            fields.extend_from_slice(k.as_bytes());
+            fields.extend_from_slice(&(v.len() as i32).to_be_bytes());
+            fields.extend_from_slice(v.as_bytes());
+        }
+        h.extend_from_slice(&(fields.len() as i32).to_be_bytes());
+        h.extend_from_slice(&fields);
+        h
+    }
+
+    #[test]
+    fn json_send_message() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = RocketmqLog::default();
+        let properties = "TAGS\u{1}created\u{2}KEYS\u{1}order-1\u{2}UNIQ_KEY\u{1}7F000001\u{2}traceparent\u{1}00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01\u{2}";
+        let header = serde_json::json!({
+            "code": 310,
+            "language": "JAVA",
+            "version": 453,
+            "opaque": 7,
+            "flag": 0,
+            "extFields": {"a": "billing", "b": "orders", "e": "3", "i": properties},
+            "serializeTypeCurrentRPC": "JSON",
+        })
+        .to_string();
+        let request = frame(SERIALIZE_JSON, header.as_bytes(), b"{\"id\":1}");
+        assert!(parser.check_payload(&request, &at(C2S, 0)));
+        assert!(!parser.check_payload(&request, &at(S2C, 0)));
+        assert!(parse(&mut parser, &request[..10], &at(C2S, 100)).is_empty());
+        assert!(parse(&mut parser, &request[10..], &at(C2S, 100)).is_empty());
+
+        let header = r#"{"code":0,"flag":1,"opaque":7,"language":"JAVA","version":453,"extFields":{"msgId":"0A0A0A0A00002A9F0000000000000F3C","queueId":"3","queueOffset":"1024"}}"#;
+        let info = parse(
+            &mut parser,
+            &frame(SERIALIZE_JSON, header.as_bytes(), &[]),
+            &at(S2C, 900),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.code_name, Some("SEND_MESSAGE_V2"));
+        assert_eq!(info.topic.as_deref(), Some("orders"));
+        assert_eq!(info.group.as_deref(), Some("billing"));
+        assert_eq!((info.queue_id, info.queue_offset), (Some(3), Some(1024)));
+        assert_eq!(
+            info.msg_id.as_deref(),
+            Some("0A0A0A0A00002A9F0000000000000F3C")
+        );
+        assert_eq!(info.tags.as_deref(), Some("created"));
+        assert_eq!(info.keys.as_deref(), Some("order-1"));
+        assert_eq!(info.body_size, 8);
+        assert_eq!(
+            info.trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!((info.rrt, info.status), (800, L7ResponseStatus::Ok));
+    }
+
+    #[test]
+    fn rocketmq_serialized() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = RocketmqLog::default();
+        let ext = [
+            ("consumerGroup", "billing"),
+            ("topic", "orders"),
+            ("queueId", "1"),
+            ("queueOffset", "12"),
+        ];
+        let pull = frame(
+            SERIALIZE_ROCKETMQ,
+            &rocketmq_header(11, 21, 0, "", &ext),
+            &[],
+        );
+        // a heartbeat with a large body in the same segment
+        let heartbeat = frame(
+            SERIALIZE_ROCKETMQ,
+            &rocketmq_header(34, 22, 0, "", &[]),
+            &vec![0; MAX_HEAD_SIZE * 2],
+        );
+        assert!(parser.check_payload(&pull, &at(C2S, 0)));
+        let mut infos = vec![];
+        for segment in [pull, heartbeat].concat().chunks(1400) {
+            infos.extend(parse(&mut parser, segment, &at(C2S, 0)));
+        }
+        assert!(infos.is_empty());
+
+        let header = rocketmq_header(
+            17,
+            21,
+            FLAG_RESPONSE,
+            "topic[orders] not exist, apply first please!",
+            &[],
+        );
+        let info = parse(
+            &mut parser,
+            &frame(SERIALIZE_ROCKETMQ, &header, &[]),
+            &at(S2C, 40),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.code_name, Some("PULL_MESSAGE"));
+        assert_eq!(info.language.as_deref(), Some("GO"));
+        assert_eq!(info.group.as_deref(), Some("billing"));
+        assert_eq!(info.response_name, Some("TOPIC_NOT_EXIST"));
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+        assert!(info.remark.unwrap().starts_with("topic[orders]"));
+
+        let header = rocketmq_header(0, 22, FLAG_RESPONSE, "", &[]);
+        let info = parse(
+            &mut parser,
+            &frame(SERIALIZE_ROCKETMQ, &header, &[]),
+            &at(S2C, 50),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.code_name, Some("HEART_BEAT"));
+        assert_eq!(info.body_size, (MAX_HEAD_SIZE * 2) as u32);
+
+        // oneway requests are not answered
+        let header = rocketmq_header(35, 23, FLAG_ONEWAY, "", &[("producerGroup", "billing")]);
+        let info = parse(
+            &mut parser,
+            &frame(SERIALIZE_ROCKETMQ, &header, &[]),
+            &at(C2S, 60),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Other);
+
+        // requests from the broker are answered by the client
+        let header = rocketmq_header(39, 1, 0, "", &[("transactionId", "tx-1")]);
+        assert!(parse(
+            &mut parser,
+            &frame(SERIALIZE_ROCKETMQ, &header, &[]),
+            &at(S2C, 70)
+        )
+        .is_empty());
+        let header = rocketmq_header(0, 1, FLAG_RESPONSE, "", &[]);
+        let info = parse(
+            &mut parser,
+            &frame(SERIALIZE_ROCKETMQ, &header, &[]),
+            &at(C2S, 75),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(info.code_name, Some("CHECK_TRANSACTION_STATE"));
+        assert_eq!(info.transaction_id.as_deref(), Some("tx-1"));
+        assert_eq!(info.rrt, 5);
+    }
+}
@@ -0,0 +1,940 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::{read_u16_be, read_u32_be, read_u64_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, trace::TraceContext, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface,
+    L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// [magic u16][flag u8][status u8][request id u64][body length u32]
+const HEADER_SIZE: usize = 16;
+const MAGIC: u16 = 0xdabb;
+// payload of dubbo defaults to 8 MiB, larger ones are taken for garbage
+const MAX_BODY_SIZE: usize = 64 << 20;
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+const FLAG_REQUEST: u8 = 0x80;
+const FLAG_TWO_WAY: u8 = 0x40;
+const FLAG_EVENT: u8 = 0x20;
+const SERIALIZATION_MASK: u8 = 0x1f;
+const SERIALIZATION_HESSIAN2: u8 = 2;
+
+const STATUS_OK: u8 = 20;
+
+// response types written before the value of an OK response
+const RESPONSE_WITH_EXCEPTION: i32 = 0;
+const RESPONSE_WITH_EXCEPTION_WITH_ATTACHMENTS: i32 = 3;
+
+// nesting of hessian lists, maps and objects skipped over
+const MAX_DEPTH: usize = 32;
+// class definitions kept for the objects of a message
+const MAX_CLASSES: usize = 256;
+
+fn serialization_name(id: u8) -> &'static str {
+    match id {
+        2 => "hessian2",
+        3 => "java",
+        4 => "compactedjava",
+        6 => "fastjson",
+        7 => "nativejava",
+        8 => "kryo",
+        9 => "fst",
+        12 => "avro",
+        21 => "protobuf",
+        23 => "fastjson2",
+        _ => "unknown",
+    }
+}
+
+fn status_name(status: u8) -> Option<&'static str> {
+    let name = match status {
+        20 => "OK",
+        30 => "CLIENT_TIMEOUT",
+        31 => "SERVER_TIMEOUT",
+        35 => "CHANNEL_INACTIVE",
+        40 => "BAD_REQUEST",
+        50 => "BAD_RESPONSE",
+        60 => "SERVICE_NOT_FOUND",
+        70 => "SERVICE_ERROR",
+        80 => "SERVER_ERROR",
+        90 => "CLIENT_ERROR",
+        100 => "SERVER_THREADPOOL_EXHAUSTED_ERROR",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn response_status(status: u8) -> L7ResponseStatus {
+    match status {
+        STATUS_OK => L7ResponseStatus::Ok,
+        30 | 31 => L7ResponseStatus::Timeout,
+        // a malformed request, or a service the provider does not export
+        40 | 60 | 90 => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct DubboInfo {
+    pub msg_type: LogMessageType,
+    pub request_id: u64,
+    // bodies are decoded only when serialized with hessian2
+    pub serialization: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dubbo_version: Option<String>,
+    // the interface, as in org.apache.dubbo.demo.DemoService
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method_name: Option<String>,
+    // from the attachments of the request
+    #[serde(flatten)]
+    pub trace: TraceContext,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_name: Option<&'static str>,
+    // class of an exception thrown by the method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception: Option<String>,
+    // message of the exception, or the error of a call failed in dubbo itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl DubboInfo {
+    fn parse(header: &Header, body: &[u8], config: Option<&LogParserConfig>) -> Self {
+        let serialization = header.flag & SERIALIZATION_MASK;
+        let mut info = DubboInfo {
+            request_id: header.request_id,
+            serialization: serialization_name(serialization),
+            ..Default::default()
+        };
+        if header.flag & FLAG_REQUEST != 0 {
+            // oneway calls are not answered
+            info.msg_type = if header.flag & FLAG_TWO_WAY != 0 {
+                LogMessageType::Request
+            } else {
+                LogMessageType::Other
+            };
+        } else {
+            info.msg_type = LogMessageType::Response;
+            info.status_code = Some(header.status);
+            info.status_name = status_name(header.status);
+            info.status = response_status(header.status);
+        }
+        if serialization != SERIALIZATION_HESSIAN2 {
+            return info;
+        }
+        let mut decoder = Decoder {
+            r: Reader { data: body },
+            classes: vec![],
+        };
+        let result = match info.msg_type {
+            LogMessageType::Response => info.parse_response(&mut decoder, header.status),
+            _ => info.parse_request(&mut decoder, config),
+        };
+        // what was decoded before a body cut at its head or an unknown value is kept
+        if let Err(e) = result {
+            debug!("dubbo body of request {}: {}", info.request_id, e);
+        }
+        info
+    }
+
+    // [dubbo version][service name][service version][method name][parameter types]
+    // [arguments][attachments]
+    fn parse_request(
+        &mut self,
+        decoder: &mut Decoder,
+        config: Option<&LogParserConfig>,
+    ) -> Result<()> {
+        self.dubbo_version = decoder.string()?;
+        self.service_name = decoder.string()?;
+        self.service_version = decoder.string()?.filter(|v| !v.is_empty());
+        self.method_name = decoder.string()?;
+        let Some(config) = config else {
+            return Ok(());
+        };
+        let types = decoder.string()?.unwrap_or_default();
+        for _ in 0..argument_count(&types) {
+            decoder.skip(0)?;
+        }
+        let attachments = decoder.string_map()?;
+        self.trace = TraceContext::extract(config, |key| {
+            attachments
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        });
+        Ok(())
+    }
+
+    // [response type][value or exception][attachments], or an error message unless OK
+    fn parse_response(&mut self, decoder: &mut Decoder, status: u8) -> Result<()> {
+        if status != STATUS_OK {
+            self.error_message = decoder.string()?;
+            return Ok(());
+        }
+        if matches!(
+            decoder.int()?,
+            RESPONSE_WITH_EXCEPTION | RESPONSE_WITH_EXCEPTION_WITH_ATTACHMENTS
+        ) {
+            self.status = L7ResponseStatus::ServerError;
+            let (class, message) = decoder.exception()?;
+            self.exception = Some(class);
+            self.error_message = message;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, response: DubboInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.status_code = response.status_code;
+        self.status_name = response.status_name;
+        self.exception = response.exception;
+        self.error_message = response.error_message;
+        self.status = response.status;
+    }
+}
+
+// parameter types are a JVM descriptor, as in Ljava/lang/String;[I for (String, int[])
+fn argument_count(types: &str) -> usize {
+    let mut count = 0;
+    let mut chars = types.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => continue,
+            'L' => {
+                chars.by_ref().find(|&c| c == ';');
+            }
+            _ => (),
+        }
+        count += 1;
+    }
+    count
+}
+
+trait DubboRead<'a> {
+    fn chars(&mut self, n: usize) -> Result<&'a [u8]>;
+}
+
+impl<'a> DubboRead<'a> for Reader<'a> {
+    // hessian counts the characters of a string rather than its bytes, characters out
+    // of the basic plane are two of them in four bytes, or two surrogates of three
+    fn chars(&mut self, n: usize) -> Result<&'a [u8]> {
+        let mut size = 0;
+        let mut count = 0;
+        while count < n {
+            let b = *self
+                .data
+                .get(size)
+                .ok_or(Error::InsufficientPayloadLength)?;
+            let (bytes, chars) = match b {
+                0x00..=0x7f => (1, 1),
+                0xc0..=0xdf => (2, 1),
+                0xe0..=0xef => (3, 1),
+                0xf0..=0xf7 => (4, 2),
+                _ => {
+                    return Err(Error::DubboLogParseFailed(format!(
+                        "invalid utf-8 byte {:#x}",
+                        b
+                    )))
+                }
+            };
+            size += bytes;
+            count += chars;
+        }
+        self.take(size)
+    }
+}
+
+// decodes the few hessian2 values logged and skips over the others
+struct Decoder<'a> {
+    r: Reader<'a>,
+    // field names of the class definitions met, referred to by objects
+    classes: Vec<(String, Vec<String>)>,
+}
+
+impl Decoder<'_> {
+    fn int(&mut self) -> Result<i32> {
+        let tag = self.r.u8()?;
+        let value = match tag {
+            0x80..=0xbf => tag as i32 - 0x90,
+            0xc0..=0xcf => (tag as i32 - 0xc8) << 8 | self.r.u8()? as i32,
+            0xd0..=0xd7 => (tag as i32 - 0xd4) << 16 | self.r.u16()? as i32,
+            b'I' => read_u32_be(self.r.take(4)?) as i32,
+            _ => {
+                return Err(Error::DubboLogParseFailed(format!(
+                    "invalid int tag {:#x}",
+                    tag
+                )))
+            }
+        };
+        Ok(value)
+    }
+
+    // a string, possibly in chunks, or null
+    fn string(&mut self) -> Result<Option<String>> {
+        if self.r.peek()? == b'N' {
+            self.r.u8()?;
+            return Ok(None);
+        }
+        let mut s = vec![];
+        loop {
+            let tag = self.r.u8()?;
+            let len = match tag {
+                0x00..=0x1f => tag as usize,
+                0x30..=0x33 => (tag as usize - 0x30) << 8 | self.r.u8()? as usize,
+                b'R' | b'S' => self.r.u16()? as usize,
+                _ => {
+                    return Err(Error::DubboLogParseFailed(format!(
+                        "invalid string tag {:#x}",
+                        tag
+                    )))
+                }
+            };
+            s.extend_from_slice(self.r.chars(len)?);
+            // R is a chunk followed by another
+            if tag != b'R' {
+                return Ok(Some(String::from_utf8_lossy(&s).into_owned()));
+            }
+        }
+    }
+
+    fn is_string(&self) -> bool {
+        matches!(self.r.peek(), Ok(0x00..=0x1f | 0x30..=0x33 | b'R' | b'S'))
+    }
+
+    // entries of a map whose keys and values are strings, the others are skipped
+    fn string_map(&mut self) -> Result<Vec<(String, String)>> {
+        match self.r.u8()? {
+            b'H' => (),
+            b'M' => self.skip(1)?,
+            b'N' => return Ok(vec![]),
+            tag => {
+                return Err(Error::DubboLogParseFailed(format!(
+                    "invalid map tag {:#x}",
+                    tag
+                )))
+            }
+        }
+        let mut entries = vec![];
+        while self.r.peek()? != b'Z' {
+            let key = self.string_or_skip()?;
+            let value = self.string_or_skip()?;
+            if let (Some(key), Some(value)) = (key, value) {
+                entries.push((key, value));
+            }
+        }
+        self.r.u8()?;
+        Ok(entries)
+    }
+
+    fn string_or_skip(&mut self) -> Result<Option<String>> {
+        if self.is_string() {
+            return self.string();
+        }
+        self.skip(1)?;
+        Ok(None)
+    }
+
+    // ['C' name field count field names] then the object, fields in their order
+    fn class_definition(&mut self) -> Result<()> {
+        let name = self.string()?.unwrap_or_default();
+        let count = self.int()?.max(0) as usize;
+        let mut fields = Vec::with_capacity(count.min(64));
+        for _ in 0..count {
+            fields.push(self.string()?.unwrap_or_default());
+        }
+        if self.classes.len() >= MAX_CLASSES {
+            return Err(Error::DubboLogParseFailed(
+                "too many class definitions".to_owned(),
+            ));
+        }
+        self.classes.push((name, fields));
+        Ok(())
+    }
+
+    fn class_fields(&self, index: i32) -> Result<usize> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.classes.get(i))
+            .map(|(_, fields)| fields.len())
+            .ok_or_else(|| Error::DubboLogParseFailed(format!("undefined class {}", index)))
+    }
+
+    // the class of an exception and its detailMessage, the rest of it is not read
+    fn exception(&mut self) -> Result<(String, Option<String>)> {
+        while self.r.peek()? == b'C' {
+            self.r.u8()?;
+            self.class_definition()?;
+        }
+        let index = match self.r.u8()? {
+            b'O' => self.int()?,
+            tag @ 0x60..=0x6f => (tag - 0x60) as i32,
+            tag => {
+                return Err(Error::DubboLogParseFailed(format!(
+                    "invalid exception tag {:#x}",
+                    tag
+                )))
+            }
+        };
+        self.class_fields(index)?;
+        let (class, fields) = self.classes[index as usize].clone();
+        for field in fields {
+            if field == "detailMessage" {
+                return Ok((class, self.string_or_skip()?));
+            }
+            self.skip(1)?;
+        }
+        Ok((class, None))
+    }
+
+    fn skip_until_end(&mut self, depth: usize) -> Result<()> {
+        while self.r.peek()? != b'Z' {
+            self.skip(depth + 1)?;
+        }
+        self.r.u8()?;
+        Ok(())
+    }
+
+    fn skip_values(&mut self, n: usize, depth: usize) -> Result<()> {
+        for _ in 0..n {
+            self.skip(depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::DubboLogParseFailed(
+                "values nested too deep".to_owned(),
+            ));
+        }
+        // chunks and class definitions are followed by the value they belong to
+        let tag = loop {
+            let tag = self.r.u8()?;
+            match tag {
+                // chunks of binaries and strings, A and R are followed by another
+                b'A' | b'B' => {
+                    let len = self.r.u16()? as usize;
+                    self.r.take(len)?;
+                    if tag == b'B' {
+                        return Ok(());
+                    }
+                }
+                b'R' | b'S' => {
+                    let len = self.r.u16()? as usize;
+                    self.r.chars(len)?;
+                    if tag == b'S' {
+                        return Ok(());
+                    }
+                }
+                // a class definition comes before the first object of the class
+                b'C' => self.class_definition()?,
+                _ => break tag,
+            }
+        };
+        match tag {
+            // compact strings and binaries
+            0x00..=0x1f => {
+                self.r.chars(tag as usize)?;
+            }
+            0x20..=0x2f => {
+                self.r.take(tag as usize - 0x20)?;
+            }
+            0x30..=0x33 => {
+                let len = (tag as usize - 0x30) << 8 | self.r.u8()? as usize;
+                self.r.chars(len)?;
+            }
+            0x34..=0x37 => {
+                let len = (tag as usize - 0x34) << 8 | self.r.u8()? as usize;
+                self.r.take(len)?;
+            }
+            b'F' | b'T' | b'N' | 0x5b | 0x5c | 0x80..=0xbf | 0xd8..=0xef => (),
+            0x5d | 0xc0..=0xcf | 0xf0..=0xff => {
+                self.r.take(1)?;
+            }
+            0x38..=0x3f | 0x5e | 0xd0..=0xd7 => {
+                self.r.take(2)?;
+            }
+            b'I' | b'K' | b'Y' | 0x5f => {
+                self.r.take(4)?;
+            }
+            b'D' | b'J' | b'L' => {
+                self.r.take(8)?;
+            }
+            // references to an earlier value
+            b'Q' => {
+                self.int()?;
+            }
+            // maps and variable length lists end with Z, typed ones start with their type
+            b'H' | b'W' => self.skip_until_end(depth)?,
+            b'M' | b'U' => {
+                self.skip(depth + 1)?;
+                self.skip_until_end(depth)?;
+            }
+            b'V' => {
+                self.skip(depth + 1)?;
+                let len = self.int()?.max(0) as usize;
+                self.skip_values(len, depth)?;
+            }
+            b'X' => {
+                let len = self.int()?.max(0) as usize;
+                self.skip_values(len, depth)?;
+            }
+            0x70..=0x77 => {
+                self.skip(depth + 1)?;
+                self.skip_values(tag as usize - 0x70, depth)?;
+            }
+            0x78..=0x7f => self.skip_values(tag as usize - 0x78, depth)?,
+            b'O' => {
+                let index = self.int()?;
+                let fields = self.class_fields(index)?;
+                self.skip_values(fields, depth)?;
+            }
+            0x60..=0x6f => {
+                let fields = self.class_fields((tag - 0x60) as i32)?;
+                self.skip_values(fields, depth)?;
+            }
+            _ => {
+                return Err(Error::DubboLogParseFailed(format!(
+                    "invalid tag {:#x}",
+                    tag
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Header {
+    flag: u8,
+    status: u8,
+    request_id: u64,
+    body_size: usize,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let magic = read_u16_be(data);
+        let body_size = read_u32_be(&data[12..]) as usize;
+        if magic != MAGIC || body_size > MAX_BODY_SIZE {
+            return Err(Error::DubboLogParseFailed(format!(
+                "invalid header magic {:#x} body length {}",
+                magic, body_size
+            )));
+        }
+        Ok(Self {
+            flag: data[2],
+            status: data[3],
+            request_id: read_u64_be(&data[4..]),
+            body_size,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct DubboLog {
+    streams: PerDirection<Stream>,
+    // request ids are chosen by the side sending the request, providers call
+    // callbacks of consumers too
+    pending: HashMap<(PacketDirection, u64), DubboInfo>,
+}
+
+impl DubboLog {
+    fn parse_messages(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<DubboInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= HEADER_SIZE {
+            let header = match Header::parse(remain) {
+                Ok(header) => header,
+                Err(e) => {
+                    // the rest of the segment can not be framed, keep what is parsed
+                    debug!("drop dubbo segment: {}", e);
+                    last_error = Some(e);
+                    remain = &[];
+                    break;
+                }
+            };
+            let total_size = HEADER_SIZE + header.body_size;
+            let body = if remain.len() >= total_size {
+                let body = &remain[HEADER_SIZE..total_size];
+                remain = &remain[total_size..];
+                body
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[HEADER_SIZE..]
+            } else {
+                break;
+            };
+            // heartbeats and other events
+            if header.flag & FLAG_EVENT != 0 {
+                continue;
+            }
+            let mut info = DubboInfo::parse(&header, body, param.parse_config);
+            info.time = param.time;
+            infos.push(info);
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        info: DubboInfo,
+        direction: PacketDirection,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        match info.msg_type {
+            LogMessageType::Request => {
+                let key = (direction, info.request_id);
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::DubboInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    output.push(L7ProtocolInfo::DubboInfo(prev));
+                }
+            }
+            LogMessageType::Response => {
+                match self
+                    .pending
+                    .remove(&(direction.reversed(), info.request_id))
+                {
+                    Some(mut request) => {
+                        request.merge(info);
+                        output.push(L7ProtocolInfo::DubboInfo(request));
+                    }
+                    None => output.push(L7ProtocolInfo::DubboInfo(info)),
+                }
+            }
+            _ => output.push(L7ProtocolInfo::DubboInfo(info)),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for DubboLog {
+    // a request from the client, with a method name when serialized with hessian2
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.len() < HEADER_SIZE {
+            return false;
+        }
+        let Ok(header) = Header::parse(payload) else {
+            return false;
+        };
+        let serialization = header.flag & SERIALIZATION_MASK;
+        if header.flag & (FLAG_REQUEST | FLAG_EVENT) != FLAG_REQUEST
+            || serialization_name(serialization) == "unknown"
+        {
+            return false;
+        }
+        serialization != SERIALIZATION_HESSIAN2
+            || DubboInfo::parse(&header, &payload[HEADER_SIZE..], None)
+                .method_name
+                .is_some()
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_messages(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, param.direction, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Dubbo
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, SESSION_TIMEOUT};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn message(flag: u8, status: u8, request_id: u64, body: &[u8]) -> Vec<u8> {
+        let mut m = MAGIC.to_be_bytes().to_vec();
+        m.extend([flag, status]);
+        m.extend(request_id.to_be_bytes());
+        m.extend((body.len() as u32).to_be_bytes());
+        m.extend_from_slice(body);
+        m
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let len = s.chars().count();
+        let mut v = if len < 32 {
+            vec![len as u8]
+        } else {
+            let mut v = vec![b'S'];
+            v.extend((len as u16).to_be_bytes());
+            v
+        };
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn invocation(method: &str, types: &str, args: &[u8], attachments: &[u8]) -> Vec<u8> {
+        [
+            string("2.0.2"),
+            string("org.apache.dubbo.demo.DemoService"),
+            string("1.0.0"),
+            string(method),
+            string(types),
+            args.to_vec(),
+            attachments.to_vec(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn hessian2_calls() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = DubboLog::default();
+        let hessian2 = FLAG_REQUEST | FLAG_TWO_WAY | SERIALIZATION_HESSIAN2;
+
+        // (String, User, Object[]) with a class definition, attachments after them
+        let args = [
+            string("wörld 🌍"),
+            b"C".to_vec(),
+            string("com.demo.User"),
+            vec![0x92],
+            string("name"),
+            string("age"),
+            vec![0x60],
+            string("alice"),
+            vec![0xc8, 0x1e],
+            vec![0x79, b'N'],
+        ]
+        .concat();
+        let attachments = [
+            b"H".to_vec(),
+            string("path"),
+            string("org.apache.dubbo.demo.DemoService"),
+            string("timeout"),
+            vec![0xd4, 0x0b, 0xb8],
+            string("traceparent"),
+            string("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+            b"Z".to_vec(),
+        ]
+        .concat();
+        let body = invocation(
+            "sayHello",
+            "Ljava/lang/String;Lcom/demo/User;[Ljava/lang/Object;",
+            &args,
+            &attachments,
+        );
+        let request = message(hessian2, 0, 1, &body);
+        assert!(parser.check_payload(&request, &at(C2S, 0)));
+        let (head, tail) = request.split_at(40);
+        assert!(parse(&mut parser, head, &at(C2S, 0)).is_empty());
+        assert!(parse(&mut parser, tail, &at(C2S, 10)).is_empty());
+
+        // a heartbeat between the request and its response
+        let heartbeat = message(hessian2 | FLAG_EVENT, 0, 2, b"N");
+        assert!(!parser.check_payload(&heartbeat, &at(C2S, 20)));
+        assert!(parse(&mut parser, &heartbeat, &at(C2S, 20)).is_empty());
+        let response = [
+            message(SERIALIZATION_HESSIAN2 | FLAG_EVENT, STATUS_OK, 2, b"N"),
+            message(
+                SERIALIZATION_HESSIAN2,
+                STATUS_OK,
+                1,
+                &[&[0x91][..], &string("hi")].concat(),
+            ),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &response, &at(S2C, 310));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert_eq!(infos[0].serialization, "hessian2");
+        assert_eq!(infos[0].dubbo_version.as_deref(), Some("2.0.2"));
+        assert_eq!(
+            infos[0].service_name.as_deref(),
+            Some("org.apache.dubbo.demo.DemoService")
+        );
+        assert_eq!(infos[0].service_version.as_deref(), Some("1.0.0"));
+        assert_eq!(infos[0].method_name.as_deref(), Some("sayHello"));
+        assert_eq!(
+            infos[0].trace.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(infos[0].status_name, Some("OK"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        assert_eq!(infos[0].rrt, 300);
+
+        // an exception thrown by the method, whose cause refers to itself
+        let request = message(hessian2, 0, 3, &invocation("fail", "", &[], b"H\x5a"));
+        let exception = [
+            vec![0x90, b'C'],
+            string("java.lang.IllegalStateException"),
+            vec![0x93],
+            string("cause"),
+            string("detailMessage"),
+            string("stackTrace"),
+            vec![0x60, b'Q', 0x90],
+            string("boom"),
+            vec![0x78],
+        ]
+        .concat();
+        let response = message(SERIALIZATION_HESSIAN2, STATUS_OK, 3, &exception);
+        // and a oneway call, logged on its own
+        let oneway = message(
+            FLAG_REQUEST | SERIALIZATION_HESSIAN2,
+            0,
+            4,
+            &invocation("notify", "", &[], b"N"),
+        );
+        let infos = parse(&mut parser, &[request, oneway].concat(), &at(C2S, 400));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Other);
+        assert_eq!(infos[0].method_name.as_deref(), Some("notify"));
+        let infos = parse(&mut parser, &response, &at(S2C, 500));
+        assert_eq!(infos[0].method_name.as_deref(), Some("fail"));
+        assert_eq!(
+            infos[0].exception.as_deref(),
+            Some("java.lang.IllegalStateException")
+        );
+        assert_eq!(infos[0].error_message.as_deref(), Some("boom"));
+        assert_eq!(infos[0].status, L7ResponseStatus::ServerError);
+    }
+
+    #[test]
+    fn failures_and_other_serializations() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = DubboLog::default();
+        let hessian2 = FLAG_REQUEST | FLAG_TWO_WAY | SERIALIZATION_HESSIAN2;
+
+        let requests = [
+            message(hessian2, 0, 10, &invocation("slow", "", &[], b"N")),
+            message(hessian2, 0, 11, &invocation("missing", "", &[], b"N")),
+            message(hessian2, 0, 12, &invocation("lost", "", &[], b"N")),
+            // fastjson2 bodies are not decoded
+            message(FLAG_REQUEST | FLAG_TWO_WAY | 23, 0, 13, b"\"2.0.2\""),
+        ];
+        assert!(parser.check_payload(&requests[3], &at(C2S, 0)));
+        assert!(parse(&mut parser, &requests.concat(), &at(C2S, 0)).is_empty());
+        let responses = [
+            message(
+                SERIALIZATION_HESSIAN2,
+                31,
+                10,
+                &string("Waiting server-side response timeout"),
+            ),
+            message(
+                SERIALIZATION_HESSIAN2,
+                60,
+                11,
+                &string("Not found exported service"),
+            ),
+            message(23, STATUS_OK, 13, b"4\"ok\""),
+        ];
+        let mut infos = parse(&mut parser, &responses.concat(), &at(S2C, 100));
+        infos.sort_by_key(|i| i.request_id);
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0].status_name, Some("SERVER_TIMEOUT"));
+        assert_eq!(
+            infos[0].error_message.as_deref(),
+            Some("Waiting server-side response timeout")
+        );
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+        assert_eq!(infos[1].method_name.as_deref(), Some("missing"));
+        assert_eq!(infos[1].status, L7ResponseStatus::ClientError);
+        assert_eq!(infos[2].serialization, "fastjson2");
+        assert_eq!(infos[2].method_name, None);
+        assert_eq!(infos[2].status, L7ResponseStatus::Ok);
+
+        let infos = parse(&mut parser, &[], &at(S2C, SESSION_TIMEOUT + 1));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].method_name.as_deref(), Some("lost"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+
+        assert!(!parser.check_payload(&responses[0], &at(C2S, 0)));
+        assert!(!parser.check_payload(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n", &at(C2S, 0)));
+        assert!(parser
+            .parse_payload(&[0xca; HEADER_SIZE], &at(C2S, 0))
+            .is_err());
+
+        // a request followed by an invalid header
+        let request = message(hessian2, 0, 14, &invocation("retry", "", &[], b"N"));
+        let requests = [request, vec![0xca; HEADER_SIZE]].concat();
+        assert!(parse(&mut parser, &requests, &at(C2S, 200)).is_empty());
+        let response = message(SERIALIZATION_HESSIAN2, STATUS_OK, 14, &[0x91, b'N']);
+        let info = parse(&mut parser, &response, &at(S2C, 250)).pop().unwrap();
+        assert_eq!(info.method_name.as_deref(), Some("retry"));
+        assert_eq!(info.msg_type, LogMessageType::Session);
+    }
+
+    #[test]
+    fn long_chains() {
+        // an argument in 20000 empty string chunks, then too many class definitions
+        let chunks = [&b"R\0\0".repeat(20000)[..], &string("end")].concat();
+        let mut decoder = Decoder {
+            r: Reader { data: &chunks },
+            classes: vec![],
+        };
+        assert!(decoder.skip(1).is_ok());
+        assert!(decoder.r.data.is_empty());
+
+        let class = [b"C".to_vec(), string("com.demo.User"), vec![0x90]].concat();
+        let classes = [&class.repeat(MAX_CLASSES + 1)[..], &[0x60]].concat();
+        let mut decoder = Decoder {
+            r: Reader { data: &classes },
+            classes: vec![],
+        };
+        assert!(matches!(
+            decoder.skip(1),
+            Err(Error::DubboLogParseFailed(_))
+        ));
+    }
+}
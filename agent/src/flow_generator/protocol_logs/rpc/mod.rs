@@ -0,0 +1,7 @@
+mod dubbo;
+mod thrift;
+
+use super::MAX_PENDING_SESSIONS;
+
+pub use dubbo::{DubboInfo, DubboLog};
+pub use thrift::{ThriftInfo, ThriftLog};
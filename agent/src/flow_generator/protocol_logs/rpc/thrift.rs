@@ -0,0 +1,878 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::read_u32_be;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::MAX_PENDING_SESSIONS;
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus,
+    LogMessageType, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// frames default to 16 MiB at most, servers such as hbase raise it
+const MAX_FRAME_SIZE: usize = 64 << 20;
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_NAME_SIZE: usize = 1024;
+// nesting of structs and containers skipped over
+const MAX_DEPTH: usize = 32;
+
+// strict TBinaryProtocol: [0x80 0x01 0x00 message type][name][seqid], the older
+// non-strict one starting with the name can't be told from a frame size and is left out
+const BINARY_VERSION_MASK: u32 = 0xffff_0000;
+const BINARY_VERSION_1: u32 = 0x8001_0000;
+// TCompactProtocol: [0x82][message type << 5 | version][seqid][name]
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+const COMPACT_VERSION: u8 = 1;
+
+const CALL: u8 = 1;
+const REPLY: u8 = 2;
+const EXCEPTION: u8 = 3;
+const ONEWAY: u8 = 4;
+
+// field types of TBinaryProtocol, those of TCompactProtocol are mapped to them
+const T_STOP: u8 = 0;
+const T_BOOL: u8 = 2;
+const T_BYTE: u8 = 3;
+const T_DOUBLE: u8 = 4;
+const T_I16: u8 = 6;
+const T_I32: u8 = 8;
+const T_I64: u8 = 10;
+const T_STRING: u8 = 11;
+const T_STRUCT: u8 = 12;
+const T_MAP: u8 = 13;
+const T_SET: u8 = 14;
+const T_LIST: u8 = 15;
+const T_UUID: u8 = 16;
+
+fn message_type_name(message_type: u8) -> &'static str {
+    match message_type {
+        CALL => "CALL",
+        REPLY => "REPLY",
+        EXCEPTION => "EXCEPTION",
+        ONEWAY => "ONEWAY",
+        _ => "UNKNOWN",
+    }
+}
+
+// TApplicationException types
+fn exception_type_name(kind: i32) -> &'static str {
+    match kind {
+        1 => "UNKNOWN_METHOD",
+        2 => "INVALID_MESSAGE_TYPE",
+        3 => "WRONG_METHOD_NAME",
+        4 => "BAD_SEQUENCE_ID",
+        5 => "MISSING_RESULT",
+        6 => "INTERNAL_ERROR",
+        7 => "PROTOCOL_ERROR",
+        8 => "INVALID_TRANSFORM",
+        9 => "INVALID_PROTOCOL",
+        10 => "UNSUPPORTED_CLIENT_TYPE",
+        _ => "UNKNOWN",
+    }
+}
+
+// calls the server does not know or can't decode are the client's fault
+fn exception_status(kind: Option<i32>) -> L7ResponseStatus {
+    match kind {
+        Some(1 | 2 | 3 | 7 | 8 | 9 | 10) => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ThriftInfo {
+    pub msg_type: LogMessageType,
+    // binary or compact
+    pub protocol: &'static str,
+    // sent in TFramedTransport
+    pub framed: bool,
+    pub message_type: &'static str,
+    // the service of TMultiplexedProtocol, sent as in Service:method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    pub method_name: String,
+    pub seq_id: i32,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_type: Option<&'static str>,
+    // of a TApplicationException
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception_type: Option<&'static str>,
+    // the field of a reply holding one of the exceptions declared by the method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception_field_id: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception_message: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+impl ThriftInfo {
+    fn parse(message: &[u8], framed: bool) -> Result<Self> {
+        let mut decoder = Decoder::new(message)?;
+        let (name, message_type, seq_id) = decoder.message_begin()?;
+        let name = String::from_utf8_lossy(name);
+        let (service_name, method_name) = match name.split_once(':') {
+            Some((service, method)) => (Some(service.to_owned()), method.to_owned()),
+            None => (None, name.into_owned()),
+        };
+        let mut info = ThriftInfo {
+            msg_type: match message_type {
+                CALL => LogMessageType::Request,
+                // oneway calls are not answered
+                ONEWAY => LogMessageType::Other,
+                REPLY | EXCEPTION => LogMessageType::Response,
+                _ => {
+                    return Err(Error::ThriftLogParseFailed(format!(
+                        "invalid message type {}",
+                        message_type
+                    )))
+                }
+            },
+            protocol: decoder.protocol.as_str(),
+            framed,
+            message_type: message_type_name(message_type),
+            service_name,
+            method_name,
+            seq_id,
+            ..Default::default()
+        };
+        if info.msg_type != LogMessageType::Response {
+            return Ok(info);
+        }
+        info.response_type = Some(info.message_type);
+        // what is decoded before a message cut at its head is kept
+        match message_type {
+            EXCEPTION => {
+                let (message, kind) = decoder.application_exception();
+                info.exception_type = kind.map(exception_type_name);
+                info.exception_message = message;
+                info.status = exception_status(kind);
+            }
+            _ => {
+                if let Some((id, message)) = decoder.declared_exception().ok().flatten() {
+                    info.exception_field_id = Some(id);
+                    info.exception_message = message;
+                    info.status = L7ResponseStatus::ServerError;
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    fn merge(&mut self, response: ThriftInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        self.response_type = response.response_type;
+        self.exception_type = response.exception_type;
+        self.exception_field_id = response.exception_field_id;
+        self.exception_message = response.exception_message;
+        self.status = response.status;
+    }
+}
+
+trait ThriftRead {
+    fn varint(&mut self) -> Result<u64>;
+    fn zigzag(&mut self) -> Result<i64>;
+}
+
+impl ThriftRead for Reader<'_> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::ThriftLogParseFailed("varint too long".to_owned()))
+    }
+
+    fn zigzag(&mut self) -> Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Protocol {
+    Binary,
+    Compact,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Compact => "compact",
+        }
+    }
+}
+
+fn compact_type(t: u8) -> Result<u8> {
+    let t = match t {
+        // booleans of fields carry their value in the type
+        1 | 2 => T_BOOL,
+        3 => T_BYTE,
+        4 => T_I16,
+        5 => T_I32,
+        6 => T_I64,
+        7 => T_DOUBLE,
+        8 => T_STRING,
+        9 => T_LIST,
+        10 => T_SET,
+        11 => T_MAP,
+        12 => T_STRUCT,
+        13 => T_UUID,
+        _ => {
+            return Err(Error::ThriftLogParseFailed(format!(
+                "invalid compact type {}",
+                t
+            )))
+        }
+    };
+    Ok(t)
+}
+
+struct Decoder<'a> {
+    r: Reader<'a>,
+    protocol: Protocol,
+    // the value of a compact boolean field was in its header
+    bool_in_header: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(message: &'a [u8]) -> Result<Self> {
+        let protocol = match message.first() {
+            Some(0x80) => Protocol::Binary,
+            Some(&COMPACT_PROTOCOL_ID) => Protocol::Compact,
+            _ => {
+                return Err(Error::ThriftLogParseFailed(
+                    "not a strict binary or compact message".to_owned(),
+                ))
+            }
+        };
+        Ok(Self {
+            r: Reader { data: message },
+            protocol,
+            bool_in_header: false,
+        })
+    }
+
+    fn message_begin(&mut self) -> Result<(&'a [u8], u8, i32)> {
+        match self.protocol {
+            Protocol::Binary => {
+                let version = self.r.u32()?;
+                if version & BINARY_VERSION_MASK != BINARY_VERSION_1 {
+                    return Err(Error::ThriftLogParseFailed(format!(
+                        "invalid version {:#x}",
+                        version
+                    )));
+                }
+                let name = self.name()?;
+                let seq_id = self.r.u32()? as i32;
+                Ok((name, version as u8, seq_id))
+            }
+            Protocol::Compact => {
+                self.r.u8()?;
+                let b = self.r.u8()?;
+                if b & 0x1f != COMPACT_VERSION {
+                    return Err(Error::ThriftLogParseFailed(format!(
+                        "invalid compact version {}",
+                        b & 0x1f
+                    )));
+                }
+                let seq_id = self.r.varint()? as i32;
+                let name = self.name()?;
+                Ok((name, b >> 5, seq_id))
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a [u8]> {
+        let name = self.binary()?;
+        if name.is_empty() || name.len() > MAX_NAME_SIZE || !name.iter().all(u8::is_ascii_graphic) {
+            return Err(Error::ThriftLogParseFailed(format!(
+                "invalid method name {:?}",
+                String::from_utf8_lossy(&name[..name.len().min(MAX_NAME_SIZE)])
+            )));
+        }
+        Ok(name)
+    }
+
+    fn binary(&mut self) -> Result<&'a [u8]> {
+        let size = match self.protocol {
+            Protocol::Binary => self.r.u32()? as i32 as i64,
+            Protocol::Compact => self.r.varint()? as i64,
+        };
+        let size = usize::try_from(size)
+            .map_err(|_| Error::ThriftLogParseFailed(format!("invalid binary size {}", size)))?;
+        self.r.take(size)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        match self.protocol {
+            Protocol::Binary => Ok(self.r.u32()? as i32),
+            Protocol::Compact => Ok(self.r.zigzag()? as i32),
+        }
+    }
+
+    // the type and id of the next field of a struct, none at its end
+    fn field_begin(&mut self, last_id: &mut i16) -> Result<Option<(u8, i16)>> {
+        match self.protocol {
+            Protocol::Binary => {
+                let t = self.r.u8()?;
+                if t == T_STOP {
+                    return Ok(None);
+                }
+                Ok(Some((t, self.r.u16()? as i16)))
+            }
+            // [id delta << 4 | type], or [type] followed by the id when the delta is zero
+            Protocol::Compact => {
+                let b = self.r.u8()?;
+                if b & 0x0f == T_STOP {
+                    return Ok(None);
+                }
+                let id = match b >> 4 {
+                    0 => self.r.zigzag()? as i16,
+                    delta => last_id.wrapping_add(delta as i16),
+                };
+                *last_id = id;
+                let t = compact_type(b & 0x0f)?;
+                self.bool_in_header = t == T_BOOL;
+                Ok(Some((t, id)))
+            }
+        }
+    }
+
+    fn list_begin(&mut self) -> Result<(u8, usize)> {
+        match self.protocol {
+            Protocol::Binary => {
+                let t = self.r.u8()?;
+                Ok((t, self.r.u32()? as i32 as usize))
+            }
+            // [size << 4 | type], sizes from 15 follow as a varint
+            Protocol::Compact => {
+                let b = self.r.u8()?;
+                let size = match b >> 4 {
+                    15 => self.r.varint()? as usize,
+                    size => size as usize,
+                };
+                Ok((compact_type(b & 0x0f)?, size))
+            }
+        }
+    }
+
+    fn map_begin(&mut self) -> Result<(u8, u8, usize)> {
+        match self.protocol {
+            Protocol::Binary => {
+                let (k, v) = (self.r.u8()?, self.r.u8()?);
+                Ok((k, v, self.r.u32()? as i32 as usize))
+            }
+            // [size][key type << 4 | value type], the types only when not empty
+            Protocol::Compact => {
+                let size = self.r.varint()? as usize;
+                if size == 0 {
+                    return Ok((T_STOP, T_STOP, 0));
+                }
+                let b = self.r.u8()?;
+                Ok((compact_type(b >> 4)?, compact_type(b & 0x0f)?, size))
+            }
+        }
+    }
+
+    fn skip(&mut self, t: u8, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::ThriftLogParseFailed(
+                "values nested too deep".to_owned(),
+            ));
+        }
+        let compact = self.protocol == Protocol::Compact;
+        match t {
+            T_BOOL if mem::take(&mut self.bool_in_header) => (),
+            T_BOOL | T_BYTE => {
+                self.r.take(1)?;
+            }
+            T_I16 | T_I32 | T_I64 if compact => {
+                self.r.varint()?;
+            }
+            T_I16 => {
+                self.r.take(2)?;
+            }
+            T_I32 => {
+                self.r.take(4)?;
+            }
+            T_I64 | T_DOUBLE => {
+                self.r.take(8)?;
+            }
+            T_UUID => {
+                self.r.take(16)?;
+            }
+            T_STRING => {
+                self.binary()?;
+            }
+            T_STRUCT => {
+                let mut last_id = 0;
+                while let Some((t, _)) = self.field_begin(&mut last_id)? {
+                    self.skip(t, depth + 1)?;
+                }
+            }
+            T_MAP => {
+                let (k, v, size) = self.map_begin()?;
+                for _ in 0..size {
+                    self.skip(k, depth + 1)?;
+                    self.skip(v, depth + 1)?;
+                }
+            }
+            T_SET | T_LIST => {
+                let (t, size) = self.list_begin()?;
+                for _ in 0..size {
+                    self.skip(t, depth + 1)?;
+                }
+            }
+            _ => {
+                return Err(Error::ThriftLogParseFailed(format!(
+                    "invalid field type {}",
+                    t
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    // TApplicationException {1: string message, 2: i32 type}, what was decoded before an error
+    fn application_exception(&mut self) -> (Option<String>, Option<i32>) {
+        let (mut message, mut kind) = (None, None);
+        let mut last_id = 0;
+        while let Ok(Some((t, id))) = self.field_begin(&mut last_id) {
+            let result = match (t, id) {
+                (T_STRING, 1) => self
+                    .binary()
+                    .map(|m| message = Some(String::from_utf8_lossy(m).into_owned())),
+                (T_I32, 2) => self.i32().map(|k| kind = Some(k)),
+                _ => self.skip(t, 1),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        (message, kind)
+    }
+
+    // the result struct of a reply holds the return value as field 0, or one of the
+    // exceptions declared by the method, whose first string is usually its message
+    fn declared_exception(&mut self) -> Result<Option<(i16, Option<String>)>> {
+        let mut last_id = 0;
+        let Some((t, id)) = self.field_begin(&mut last_id)? else {
+            return Ok(None);
+        };
+        if id == 0 || t != T_STRUCT {
+            return Ok(None);
+        }
+        let mut last_id = 0;
+        while let Some((t, _)) = self.field_begin(&mut last_id)? {
+            if t == T_STRING {
+                let message = String::from_utf8_lossy(self.binary()?).into_owned();
+                return Ok(Some((id, Some(message))));
+            }
+            self.skip(t, 1)?;
+        }
+        Ok(Some((id, None)))
+    }
+}
+
+// the size of an unframed message, found by skipping over its arguments or result
+fn message_size(data: &[u8]) -> Result<usize> {
+    let mut decoder = Decoder::new(data)?;
+    decoder.message_begin()?;
+    decoder.skip(T_STRUCT, 0)?;
+    Ok(data.len() - decoder.r.data.len())
+}
+
+#[derive(Default)]
+pub struct ThriftLog {
+    streams: PerDirection<Stream>,
+    // by seqid, servers reply with the seqid of the call
+    pending: HashMap<i32, ThriftInfo>,
+}
+
+impl ThriftLog {
+    fn parse_messages(&mut self, payload: &[u8], param: &ParseParam) -> Result<Vec<ThriftInfo>> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut infos = vec![];
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while !remain.is_empty() {
+            // messages start with their protocol, frames with their size
+            let framed = !matches!(remain[0], 0x80 | COMPACT_PROTOCOL_ID);
+            let message = if framed {
+                if remain.len() < 4 {
+                    break;
+                }
+                let size = read_u32_be(remain) as usize;
+                if size == 0 || size > MAX_FRAME_SIZE {
+                    // the rest of the segment can not be framed, keep what is parsed
+                    debug!("drop thrift segment: invalid frame size {}", size);
+                    last_error = Some(Error::ThriftLogParseFailed(format!(
+                        "invalid frame size {}",
+                        size
+                    )));
+                    remain = &[];
+                    break;
+                }
+                let total_size = 4 + size;
+                if remain.len() >= total_size {
+                    let message = &remain[4..total_size];
+                    remain = &remain[total_size..];
+                    message
+                } else if remain.len() >= MAX_HEAD_SIZE {
+                    self.streams[direction].skip = total_size - remain.len();
+                    &mem::take(&mut remain)[4..]
+                } else {
+                    break;
+                }
+            } else {
+                match message_size(remain) {
+                    Ok(size) => {
+                        let message = &remain[..size];
+                        remain = &remain[size..];
+                        message
+                    }
+                    Err(Error::InsufficientPayloadLength) if remain.len() < MAX_HEAD_SIZE => break,
+                    // too large to be buffered, parsing resumes from a packet starting a message
+                    Err(Error::InsufficientPayloadLength) => mem::take(&mut remain),
+                    Err(e) => {
+                        debug!("drop thrift segment: {}", e);
+                        last_error = Some(e);
+                        remain = &[];
+                        break;
+                    }
+                }
+            };
+            match ThriftInfo::parse(message, framed) {
+                Ok(mut info) => {
+                    info.time = param.time;
+                    infos.push(info);
+                }
+                Err(e) => {
+                    debug!("skip thrift message: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if infos.is_empty() => Err(e),
+            _ => Ok(infos),
+        }
+    }
+
+    fn handle(&mut self, info: ThriftInfo, output: &mut Vec<L7ProtocolInfo>) {
+        match info.msg_type {
+            LogMessageType::Request => {
+                let key = info.seq_id;
+                if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+                    output.push(L7ProtocolInfo::ThriftInfo(info));
+                } else if let Some(prev) = self.pending.insert(key, info) {
+                    // clients reusing a seqid such as 0 call one at a time
+                    output.push(L7ProtocolInfo::ThriftInfo(prev));
+                }
+            }
+            LogMessageType::Response => match self.pending.remove(&info.seq_id) {
+                Some(mut request) => {
+                    request.merge(info);
+                    output.push(L7ProtocolInfo::ThriftInfo(request));
+                }
+                None => output.push(L7ProtocolInfo::ThriftInfo(info)),
+            },
+            _ => output.push(L7ProtocolInfo::ThriftInfo(info)),
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for ThriftLog {
+    // a call from the client, framed or not
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.len() < 4 {
+            return false;
+        }
+        let message = match payload[0] {
+            0x80 | COMPACT_PROTOCOL_ID => payload,
+            _ => &payload[4..],
+        };
+        Decoder::new(message)
+            .and_then(|mut d| d.message_begin())
+            .is_ok_and(|(_, t, _)| t == CALL || t == ONEWAY)
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        let infos = match self.parse_messages(payload, param) {
+            Ok(infos) => infos,
+            Err(e) if output.is_empty() => return Err(e),
+            Err(_) => return Ok(output.into()),
+        };
+        for info in infos {
+            self.handle(info, &mut output);
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Thrift
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, SESSION_TIMEOUT};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn binary_message(message_type: u8, name: &str, seq_id: i32, body: &[u8]) -> Vec<u8> {
+        let mut m = (BINARY_VERSION_1 | message_type as u32)
+            .to_be_bytes()
+            .to_vec();
+        m.extend(binary_string(name));
+        m.extend(seq_id.to_be_bytes());
+        m.extend_from_slice(body);
+        m
+    }
+
+    fn binary_string(s: &str) -> Vec<u8> {
+        let mut v = (s.len() as u32).to_be_bytes().to_vec();
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn framed(message: Vec<u8>) -> Vec<u8> {
+        let mut f = (message.len() as u32).to_be_bytes().to_vec();
+        f.extend(message);
+        f
+    }
+
+    fn varint(mut n: u64) -> Vec<u8> {
+        let mut v = vec![];
+        while n >= 0x80 {
+            v.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        v.push(n as u8);
+        v
+    }
+
+    fn compact_string(s: &str) -> Vec<u8> {
+        let mut v = varint(s.len() as u64);
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn compact_message(message_type: u8, name: &str, seq_id: i32, body: &[u8]) -> Vec<u8> {
+        let mut m = vec![COMPACT_PROTOCOL_ID, message_type << 5 | COMPACT_VERSION];
+        m.extend(varint(seq_id as u32 as u64));
+        m.extend(compact_string(name));
+        m.extend_from_slice(body);
+        m
+    }
+
+    #[test]
+    fn binary_framed_and_unframed() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = ThriftLog::default();
+
+        // getRow(1: string table, 2: list<string> columns, 3: map<string, string> attributes)
+        let args = [
+            &[T_STRING, 0, 1][..],
+            &binary_string("t1"),
+            &[T_LIST, 0, 2, T_STRING, 0, 0, 0, 2],
+            &binary_string("cf:a"),
+            &binary_string("cf:b"),
+            &[T_MAP, 0, 3, T_STRING, T_STRING, 0, 0, 0, 1],
+            &binary_string("k"),
+            &binary_string("v"),
+            &[T_STOP],
+        ]
+        .concat();
+        let calls = [
+            framed(binary_message(CALL, "Hbase:getRow", 1, &args)),
+            framed(binary_message(CALL, "deleteTable", 2, &[T_STOP])),
+            framed(binary_message(CALL, "missingMethod", 3, &[T_STOP])),
+        ];
+        assert!(parser.check_payload(&calls[0], &at(C2S, 0)));
+        assert!(parse(&mut parser, &calls.concat(), &at(C2S, 0)).is_empty());
+
+        let success = [T_STRUCT, 0, 0, T_I32, 0, 1, 0, 0, 0, 42, T_STOP, T_STOP];
+        // IOError {1: string message} declared as field 1 of the result
+        let io_error = [
+            &[T_STRUCT, 0, 1, T_STRING, 0, 1][..],
+            &binary_string("table t2 not found"),
+            &[T_STOP, T_STOP],
+        ]
+        .concat();
+        let application_exception = [
+            &[T_STRING, 0, 1][..],
+            &binary_string("Invalid method name: 'missingMethod'"),
+            &[T_I32, 0, 2, 0, 0, 0, 1, T_STOP],
+        ]
+        .concat();
+        let replies = [
+            framed(binary_message(REPLY, "Hbase:getRow", 1, &success)),
+            framed(binary_message(REPLY, "deleteTable", 2, &io_error)),
+            framed(binary_message(
+                EXCEPTION,
+                "missingMethod",
+                3,
+                &application_exception,
+            )),
+        ];
+        let infos = parse(&mut parser, &replies.concat(), &at(S2C, 200));
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert_eq!(infos[0].protocol, "binary");
+        assert!(infos[0].framed);
+        assert_eq!(infos[0].message_type, "CALL");
+        assert_eq!(infos[0].service_name.as_deref(), Some("Hbase"));
+        assert_eq!(infos[0].method_name, "getRow");
+        assert_eq!(infos[0].response_type, Some("REPLY"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        assert_eq!(infos[0].rrt, 200);
+        assert_eq!(infos[1].exception_field_id, Some(1));
+        assert_eq!(
+            infos[1].exception_message.as_deref(),
+            Some("table t2 not found")
+        );
+        assert_eq!(infos[1].status, L7ResponseStatus::ServerError);
+        assert_eq!(infos[2].response_type, Some("EXCEPTION"));
+        assert_eq!(infos[2].exception_type, Some("UNKNOWN_METHOD"));
+        assert_eq!(
+            infos[2].exception_message.as_deref(),
+            Some("Invalid method name: 'missingMethod'")
+        );
+        assert_eq!(infos[2].status, L7ResponseStatus::ClientError);
+
+        // unframed messages end where their struct does
+        let call = binary_message(CALL, "ping", 4, &args);
+        assert!(parser.check_payload(&call, &at(C2S, 300)));
+        let (head, tail) = call.split_at(30);
+        assert!(parse(&mut parser, head, &at(C2S, 300)).is_empty());
+        assert!(parse(&mut parser, tail, &at(C2S, 300)).is_empty());
+        let reply = binary_message(REPLY, "ping", 4, &[T_STOP]);
+        let infos = parse(&mut parser, &[reply.clone(), reply].concat(), &at(S2C, 350));
+        assert_eq!(infos.len(), 2);
+        assert!(!infos[0].framed);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        // a reply without a call
+        assert_eq!(infos[1].msg_type, LogMessageType::Response);
+
+        // an invalid frame size after a call
+        let call = framed(binary_message(CALL, "deleteTable", 5, &[T_STOP]));
+        let calls = [call, vec![0xff; 8]].concat();
+        assert!(parse(&mut parser, &calls, &at(C2S, 400)).is_empty());
+        let reply = framed(binary_message(REPLY, "deleteTable", 5, &[T_STOP]));
+        let info = parse(&mut parser, &reply, &at(S2C, 450)).pop().unwrap();
+        assert_eq!(info.msg_type, LogMessageType::Session);
+        assert_eq!(info.rrt, 50);
+
+        assert!(!parser.check_payload(&framed(vec![0, 0, 0, 1, 0]), &at(C2S, 0)));
+        assert!(parser.parse_payload(&[0xff; 8], &at(C2S, 0)).is_err());
+    }
+
+    #[test]
+    fn compact_protocol() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = ThriftLog::default();
+
+        let args = [
+            // 1: binary, 2: bool in the header, 5: list<bool> of 3
+            &[0x18][..],
+            &compact_string("select 1"),
+            &[0x11, 0x39, 0x31, 1, 2, 1],
+            // 300: struct {1: i64}, the id is long form as the delta exceeds 15
+            &[0x0c],
+            &varint(600),
+            &[0x16],
+            &varint(2 << 40),
+            &[T_STOP],
+            // 301: an empty map
+            &[0x1b, 0],
+            &[T_STOP],
+        ]
+        .concat();
+        let calls = [
+            compact_message(CALL, "query", 300, &args),
+            compact_message(ONEWAY, "log", 301, &[T_STOP]),
+            compact_message(CALL, "compute", 302, &[T_STOP]),
+            compact_message(CALL, "lost", 303, &[T_STOP]),
+        ];
+        assert!(parser.check_payload(&calls[0], &at(C2S, 0)));
+        let infos = parse(&mut parser, &calls.concat(), &at(C2S, 0));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Other);
+        assert_eq!(infos[0].message_type, "ONEWAY");
+        assert_eq!(infos[0].method_name, "log");
+
+        // field 0 of the result can only be sent with its id in long form
+        let success = [0x05, 0x00, 0x54, T_STOP];
+        let internal_error = [
+            &[0x18][..],
+            &compact_string("division by zero"),
+            &[0x15, 12, T_STOP],
+        ]
+        .concat();
+        let replies = [
+            compact_message(REPLY, "query", 300, &success),
+            compact_message(EXCEPTION, "compute", 302, &internal_error),
+        ];
+        let infos = parse(&mut parser, &replies.concat(), &at(S2C, 100));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].protocol, "compact");
+        assert!(!infos[0].framed);
+        assert_eq!(infos[0].seq_id, 300);
+        assert_eq!(infos[0].method_name, "query");
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        assert_eq!(infos[0].rrt, 100);
+        assert_eq!(infos[1].exception_type, Some("INTERNAL_ERROR"));
+        assert_eq!(
+            infos[1].exception_message.as_deref(),
+            Some("division by zero")
+        );
+        assert_eq!(infos[1].status, L7ResponseStatus::ServerError);
+
+        let infos = parse(&mut parser, &[], &at(S2C, SESSION_TIMEOUT));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].method_name, "lost");
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+    }
+}
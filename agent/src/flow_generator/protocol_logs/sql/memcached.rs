@@ -0,0 +1,757 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use log::debug;
+use public::bytes::{read_u16_be, read_u32_be};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending_in_order, push_pending, L7ParseResult, L7ProtocolInfo,
+    L7ProtocolParserInterface, L7ResponseStatus, LogMessageType, ParseParam, PerDirection, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// a get of a few hundred keys, data blocks are skipped instead of buffered
+const MAX_LINE_SIZE: usize = 64 * 1024;
+const MAX_KEY_SIZE: usize = 250;
+const MAX_ERROR_SIZE: usize = 256;
+
+const HEADER_SIZE: usize = 24;
+const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+
+const OP_STAT: u8 = 0x10;
+
+const STATUS_NO_ERROR: u16 = 0;
+const STATUS_KEY_NOT_FOUND: u16 = 1;
+
+fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x00 => "get",
+        0x01 => "set",
+        0x02 => "add",
+        0x03 => "replace",
+        0x04 => "delete",
+        0x05 => "increment",
+        0x06 => "decrement",
+        0x07 => "quit",
+        0x08 => "flush",
+        0x09 => "getq",
+        0x0a => "noop",
+        0x0b => "version",
+        0x0c => "getk",
+        0x0d => "getkq",
+        0x0e => "append",
+        0x0f => "prepend",
+        OP_STAT => "stat",
+        0x11 => "setq",
+        0x12 => "addq",
+        0x13 => "replaceq",
+        0x14 => "deleteq",
+        0x15 => "incrementq",
+        0x16 => "decrementq",
+        0x17 => "quitq",
+        0x18 => "flushq",
+        0x19 => "appendq",
+        0x1a => "prependq",
+        0x1b => "verbosity",
+        0x1c => "touch",
+        0x1d => "gat",
+        0x1e => "gatq",
+        0x20 => "sasl_list_mechs",
+        0x21 => "sasl_auth",
+        0x22 => "sasl_step",
+        0x23 => "gatk",
+        0x24 => "gatkq",
+        _ => "unknown",
+    }
+}
+
+// answered only on a miss for gets, on a failure for the others
+fn is_quiet(opcode: u8) -> bool {
+    matches!(opcode, 0x09 | 0x0d | 0x11..=0x1a | 0x1e | 0x24)
+}
+
+fn is_binary_retrieval(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        0x00 | 0x09 | 0x0c | 0x0d | 0x1d | 0x1e | 0x23 | 0x24
+    )
+}
+
+fn is_text_retrieval(command: &str) -> bool {
+    matches!(command, "get" | "gets" | "gat" | "gats")
+}
+
+fn status_name(status: u16) -> &'static str {
+    match status {
+        STATUS_NO_ERROR => "NO_ERROR",
+        STATUS_KEY_NOT_FOUND => "KEY_NOT_FOUND",
+        0x02 => "KEY_EXISTS",
+        0x03 => "VALUE_TOO_LARGE",
+        0x04 => "INVALID_ARGUMENTS",
+        0x05 => "ITEM_NOT_STORED",
+        0x06 => "NON_NUMERIC_VALUE",
+        0x07 => "WRONG_VBUCKET",
+        0x20 => "AUTH_ERROR",
+        0x21 => "AUTH_CONTINUE",
+        0x81 => "UNKNOWN_COMMAND",
+        0x82 => "OUT_OF_MEMORY",
+        0x83 => "NOT_SUPPORTED",
+        0x84 => "INTERNAL_ERROR",
+        0x85 => "BUSY",
+        0x86 => "TEMPORARY_FAILURE",
+        _ => "UNKNOWN",
+    }
+}
+
+// misses and failed conditional stores are part of caching as usual
+fn binary_status(status: u16) -> L7ResponseStatus {
+    match status {
+        0x00..=0x02 | 0x05 | 0x21 => L7ResponseStatus::Ok,
+        0x82 | 0x84..=0x86 => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+fn text_result(word: &str) -> Option<&'static str> {
+    let result = match word {
+        "STORED" => "STORED",
+        "NOT_STORED" => "NOT_STORED",
+        "EXISTS" => "EXISTS",
+        "NOT_FOUND" => "NOT_FOUND",
+        "DELETED" => "DELETED",
+        "TOUCHED" => "TOUCHED",
+        "OK" => "OK",
+        "VERSION" => "VERSION",
+        "ERROR" => "ERROR",
+        "CLIENT_ERROR" => "CLIENT_ERROR",
+        "SERVER_ERROR" => "SERVER_ERROR",
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn retrieval_result(hits: u32, keys: u32) -> &'static str {
+    match hits {
+        0 => "MISS",
+        _ if hits >= keys => "HIT",
+        _ => "PARTIAL_HIT",
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MemcachedInfo {
+    pub msg_type: LogMessageType,
+    // text or binary
+    pub protocol: &'static str,
+    // as sent in the text protocol, the name of the opcode in the binary one
+    pub command: String,
+    // the first one of a multi-key get
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub key_count: u32,
+
+    // filled in when a response is merged into the request
+    // the reply line such as STORED, the status name of binary responses,
+    // HIT, MISS or PARTIAL_HIT for retrievals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<&'static str>,
+    // keys found by a retrieval
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    #[serde(skip)]
+    opcode: u8,
+    #[serde(skip)]
+    opaque: u32,
+}
+
+impl MemcachedInfo {
+    fn session(&mut self, time: u64) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = time.saturating_sub(self.time);
+    }
+}
+
+struct Header {
+    magic: u8,
+    opcode: u8,
+    key_size: usize,
+    extras_size: usize,
+    // vbucket id of requests
+    status: u16,
+    body_size: usize,
+    opaque: u32,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let header = Header {
+            magic: data[0],
+            opcode: data[1],
+            key_size: read_u16_be(&data[2..]) as usize,
+            extras_size: data[4] as usize,
+            status: read_u16_be(&data[6..]),
+            body_size: read_u32_be(&data[8..]) as usize,
+            opaque: read_u32_be(&data[12..]),
+        };
+        if header.key_size + header.extras_size > header.body_size {
+            return Err(Error::MemcachedLogParseFailed(format!(
+                "invalid body size {} of opcode {:#04x}",
+                header.body_size, header.opcode
+            )));
+        }
+        Ok(header)
+    }
+
+    // what is logged of the body, values are left out but for error messages
+    fn head_size(&self) -> usize {
+        let size = self.extras_size + self.key_size;
+        if self.magic == RESPONSE_MAGIC && self.status != STATUS_NO_ERROR {
+            self.body_size.min(size + MAX_ERROR_SIZE)
+        } else {
+            size
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemcachedLog {
+    streams: PerDirection<Stream>,
+    // commands are answered in order
+    pending: VecDeque<MemcachedInfo>,
+}
+
+impl MemcachedLog {
+    fn parse_messages(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut remain = &data[..];
+        while !remain.is_empty() {
+            if matches!(remain[0], REQUEST_MAGIC | RESPONSE_MAGIC) {
+                if remain.len() < HEADER_SIZE {
+                    break;
+                }
+                let header = Header::parse(remain)?;
+                let total_size = HEADER_SIZE + header.body_size;
+                let body = if remain.len() >= total_size {
+                    let body = &remain[HEADER_SIZE..total_size];
+                    remain = &remain[total_size..];
+                    body
+                } else if remain.len() >= HEADER_SIZE + header.head_size() {
+                    self.streams[direction].skip = total_size - remain.len();
+                    &mem::take(&mut remain)[HEADER_SIZE..]
+                } else {
+                    break;
+                };
+                match header.magic {
+                    REQUEST_MAGIC => self.handle_binary_request(&header, body, param, output),
+                    _ => self.handle_binary_response(&header, body, param, output),
+                }
+                continue;
+            }
+
+            let Some(size) = remain.windows(2).position(|w| w == b"\r\n") else {
+                if remain.len() > MAX_LINE_SIZE {
+                    return Err(Error::MemcachedLogParseFailed("line too long".to_owned()));
+                }
+                break;
+            };
+            let line = String::from_utf8_lossy(&remain[..size]);
+            remain = &remain[size + 2..];
+            let data_size = match param.direction {
+                PacketDirection::ClientToServer => {
+                    self.handle_text_request(&line, param, output)?
+                }
+                PacketDirection::ServerToClient => self.handle_text_reply(&line, param, output)?,
+            };
+            // the data block of a storage command or a retrieved value, with its CRLF
+            if let Some(size) = data_size.map(|s| s + 2) {
+                if remain.len() >= size {
+                    remain = &remain[size..];
+                } else {
+                    self.streams[direction].skip = size - remain.len();
+                    remain = &[];
+                }
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+        Ok(())
+    }
+
+    // the size of the data block following the command
+    fn handle_text_request(
+        &mut self,
+        line: &str,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<Option<usize>> {
+        let words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+        let Some(&command) = words.first() else {
+            return Ok(None);
+        };
+        if !command.bytes().all(|b| b.is_ascii_lowercase() || b == b'_') {
+            return Err(Error::MemcachedLogParseFailed(format!(
+                "invalid command {:?}",
+                command
+            )));
+        }
+        let mut data_size = None;
+        let keys = match command {
+            "get" | "gets" => &words[1..],
+            // gat <exptime> <key>*
+            "gat" | "gats" => words.get(2..).unwrap_or_default(),
+            // <command> <key> <flags> <exptime> <bytes> [<cas unique>] [noreply]
+            "set" | "add" | "replace" | "append" | "prepend" | "cas" => {
+                let size = words.get(4).and_then(|s| s.parse().ok()).ok_or_else(|| {
+                    Error::MemcachedLogParseFailed(format!("invalid storage command {:?}", line))
+                })?;
+                data_size = Some(size);
+                &words[1..2]
+            }
+            "delete" | "incr" | "decr" | "touch" => words.get(1..2).unwrap_or_default(),
+            _ => &[],
+        };
+        let info = MemcachedInfo {
+            msg_type: LogMessageType::Request,
+            protocol: "text",
+            command: command.to_owned(),
+            key: keys.first().map(|k| k.chars().take(MAX_KEY_SIZE).collect()),
+            key_count: keys.len() as u32,
+            time: param.time,
+            ..Default::default()
+        };
+        let no_reply = command == "quit"
+            || !is_text_retrieval(command) && words.len() > 1 && words.last() == Some(&"noreply");
+        if no_reply {
+            output.push(L7ProtocolInfo::MemcachedInfo(MemcachedInfo {
+                msg_type: LogMessageType::Other,
+                ..info
+            }));
+        } else {
+            push_pending(&mut self.pending, info, output);
+        }
+        Ok(data_size)
+    }
+
+    fn handle_text_reply(
+        &mut self,
+        line: &str,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<Option<usize>> {
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match word {
+            // VALUE <key> <flags> <bytes> [<cas unique>]
+            "VALUE" => {
+                let size = rest
+                    .split(' ')
+                    .filter(|w| !w.is_empty())
+                    .nth(2)
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| {
+                        Error::MemcachedLogParseFailed(format!("invalid value line {:?}", line))
+                    })?;
+                if let Some(info) = self.pending.front_mut() {
+                    *info.hit_count.get_or_insert(0) += 1;
+                }
+                return Ok(Some(size));
+            }
+            // lines of stats, ended with END
+            "STAT" | "ITEM" => return Ok(None),
+            "" => return Ok(None),
+            _ => (),
+        }
+        let Some(mut info) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        info.session(param.time);
+        match word {
+            "END" if is_text_retrieval(&info.command) => {
+                let hits = *info.hit_count.get_or_insert(0);
+                info.result = Some(retrieval_result(hits, info.key_count));
+            }
+            "ERROR" | "CLIENT_ERROR" | "SERVER_ERROR" => {
+                info.result = text_result(word);
+                info.status = match word {
+                    "SERVER_ERROR" => L7ResponseStatus::ServerError,
+                    _ => L7ResponseStatus::ClientError,
+                };
+                if !rest.is_empty() {
+                    info.error = Some(rest.to_owned());
+                }
+            }
+            // the new value of incr and decr goes without a result
+            _ => info.result = text_result(word),
+        }
+        output.push(L7ProtocolInfo::MemcachedInfo(info));
+        Ok(None)
+    }
+
+    fn handle_binary_request(
+        &mut self,
+        header: &Header,
+        body: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let key = body
+            .get(header.extras_size..header.extras_size + header.key_size)
+            .filter(|k| !k.is_empty())
+            .map(|k| String::from_utf8_lossy(&k[..k.len().min(MAX_KEY_SIZE)]).into_owned());
+        let info = MemcachedInfo {
+            msg_type: LogMessageType::Request,
+            protocol: "binary",
+            command: opcode_name(header.opcode).to_owned(),
+            key_count: key.is_some() as u32,
+            key,
+            time: param.time,
+            opcode: header.opcode,
+            opaque: header.opaque,
+            ..Default::default()
+        };
+        // not answered at all
+        if header.opcode == 0x17 {
+            output.push(L7ProtocolInfo::MemcachedInfo(MemcachedInfo {
+                msg_type: LogMessageType::Other,
+                ..info
+            }));
+            return;
+        }
+        push_pending(&mut self.pending, info, output);
+    }
+
+    fn handle_binary_response(
+        &mut self,
+        header: &Header,
+        body: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let key = body
+            .get(header.extras_size..header.extras_size + header.key_size)
+            .unwrap_or_default();
+        // stats come one per response, the last one without a key
+        if header.opcode == OP_STAT && !key.is_empty() {
+            return;
+        }
+        let matches = |info: &MemcachedInfo| {
+            info.opcode == header.opcode
+                && info.opaque == header.opaque
+                && (key.is_empty() || info.key.as_deref().map(str::as_bytes) == Some(key))
+        };
+        // quiet commands left unanswered before this response succeeded, or missed
+        while let Some(info) = self.pending.front() {
+            if info.protocol != "binary" || matches(info) || !is_quiet(info.opcode) {
+                break;
+            }
+            let mut info = self.pending.pop_front().unwrap();
+            info.session(param.time);
+            if is_binary_retrieval(info.opcode) {
+                info.hit_count = Some(0);
+                info.result = Some(retrieval_result(0, 1));
+            } else {
+                info.result = Some(status_name(STATUS_NO_ERROR));
+            }
+            output.push(L7ProtocolInfo::MemcachedInfo(info));
+        }
+        if !self.pending.front().is_some_and(matches) {
+            debug!(
+                "memcached response of opcode {:#04x} opaque {} without a request",
+                header.opcode, header.opaque
+            );
+            return;
+        }
+        let mut info = self.pending.pop_front().unwrap();
+        info.session(param.time);
+        info.status = binary_status(header.status);
+        match header.status {
+            STATUS_NO_ERROR | STATUS_KEY_NOT_FOUND if is_binary_retrieval(info.opcode) => {
+                let hits = (header.status == STATUS_NO_ERROR) as u32;
+                info.hit_count = Some(hits);
+                info.result = Some(retrieval_result(hits, 1));
+            }
+            STATUS_NO_ERROR => info.result = Some(status_name(header.status)),
+            _ => {
+                info.result = Some(status_name(header.status));
+                let message = body
+                    .get(header.extras_size + header.key_size..)
+                    .unwrap_or_default();
+                if !message.is_empty() {
+                    let message = &message[..message.len().min(MAX_ERROR_SIZE)];
+                    info.error = Some(String::from_utf8_lossy(message).into_owned());
+                }
+            }
+        }
+        output.push(L7ProtocolInfo::MemcachedInfo(info));
+    }
+}
+
+impl L7ProtocolParserInterface for MemcachedLog {
+    // a binary request header, or a known text command
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer {
+            return false;
+        }
+        if payload.first() == Some(&REQUEST_MAGIC) {
+            return payload.len() >= HEADER_SIZE
+                && opcode_name(payload[1]) != "unknown"
+                // data type, always raw bytes
+                && payload[5] == 0
+                && Header::parse(payload).is_ok();
+        }
+        let Some(size) = payload.windows(2).position(|w| w == b"\r\n") else {
+            return false;
+        };
+        let line = String::from_utf8_lossy(&payload[..size]);
+        let mut words = line.split(' ').filter(|w| !w.is_empty());
+        let (Some(command), count) = (words.next(), words.count()) else {
+            return false;
+        };
+        match command {
+            "get" | "gets" | "delete" | "incr" | "decr" | "touch" => count >= 1,
+            "gat" | "gats" => count >= 2,
+            "set" | "add" | "replace" | "append" | "prepend" => (4..=5).contains(&count),
+            "cas" => (5..=6).contains(&count),
+            "stats" | "version" | "flush_all" | "verbosity" => true,
+            _ => false,
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending_in_order(&mut self.pending, param.time, &mut output);
+        if let Err(e) = self.parse_messages(payload, param, &mut output) {
+            debug!("reset memcached stream: {}", e);
+            self.streams[param.direction] = Stream::default();
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Memcached
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{
+        test_util::parse, MAX_PENDING_SESSIONS, SESSION_TIMEOUT,
+    };
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn binary(magic: u8, opcode: u8, status: u16, opaque: u32, key: &str, value: &[u8]) -> Vec<u8> {
+        let extras: &[u8] = match (magic, opcode) {
+            (REQUEST_MAGIC, 0x01 | 0x11) => &[0; 8],
+            (RESPONSE_MAGIC, 0x00 | 0x09 | 0x0c | 0x0d) if status == 0 => &[0; 4],
+            _ => &[],
+        };
+        let mut m = vec![magic, opcode];
+        m.extend((key.len() as u16).to_be_bytes());
+        m.extend([extras.len() as u8, 0]);
+        m.extend(status.to_be_bytes());
+        m.extend(((extras.len() + key.len() + value.len()) as u32).to_be_bytes());
+        m.extend(opaque.to_be_bytes());
+        m.extend([0; 8]);
+        m.extend_from_slice(extras);
+        m.extend(key.as_bytes());
+        m.extend_from_slice(value);
+        m
+    }
+
+    #[test]
+    fn text_commands() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = MemcachedLog::default();
+        let big = "v".repeat(10_000);
+        let requests = [
+            format!("set user:1 0 3600 {}\r\n{}\r\n", big.len(), big),
+            "get user:1 user:2 user:3\r\n".to_owned(),
+            "gets user:9\r\n".to_owned(),
+            "cas user:1 0 0 2 123456\r\nv2\r\n".to_owned(),
+            "delete user:2 noreply\r\n".to_owned(),
+            "incr counter 1\r\n".to_owned(),
+            "incr name 1\r\n".to_owned(),
+            "add user:1 0 0 1\r\nx\r\n".to_owned(),
+            "stats\r\n".to_owned(),
+        ]
+        .concat();
+        assert!(parser.check_payload(requests.as_bytes(), &at(C2S, 0)));
+        assert!(!parser.check_payload(b"GET / HTTP/1.1\r\n", &at(C2S, 0)));
+        let mut infos = vec![];
+        for segment in requests.as_bytes().chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(C2S, 0)));
+        }
+        // sent with noreply
+        assert_eq!(infos.len(), 1);
+        assert_eq!(
+            (infos[0].msg_type, infos[0].key.as_deref()),
+            (LogMessageType::Other, Some("user:2"))
+        );
+        assert_eq!(parser.pending.len(), 8);
+
+        let replies = [
+            "STORED\r\n".to_owned(),
+            format!("VALUE user:1 0 {}\r\n{}\r\n", big.len(), big),
+            "VALUE user:3 0 1\r\nz\r\nEND\r\n".to_owned(),
+            "END\r\n".to_owned(),
+            "EXISTS\r\n".to_owned(),
+            "42\r\n".to_owned(),
+            "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".to_owned(),
+            "NOT_STORED\r\n".to_owned(),
+            "STAT pid 1\r\nSTAT uptime 10\r\nEND\r\n".to_owned(),
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in replies.as_bytes().chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 300)));
+        }
+        let summary = infos
+            .iter()
+            .map(|i| (i.command.as_str(), i.key_count, i.result, i.hit_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("set", 1, Some("STORED"), None),
+                ("get", 3, Some("PARTIAL_HIT"), Some(2)),
+                ("gets", 1, Some("MISS"), Some(0)),
+                ("cas", 1, Some("EXISTS"), None),
+                ("incr", 1, None, None),
+                ("incr", 1, Some("CLIENT_ERROR"), None),
+                ("add", 1, Some("NOT_STORED"), None),
+                ("stats", 0, None, None),
+            ]
+        );
+        assert_eq!(
+            (infos[1].key.as_deref(), infos[1].rrt),
+            (Some("user:1"), 300)
+        );
+        assert_eq!(infos[5].status, L7ResponseStatus::ClientError);
+        assert_eq!(
+            infos[5].error.as_deref(),
+            Some("cannot increment or decrement non-numeric value")
+        );
+        assert!(infos.iter().all(|i| i.msg_type == LogMessageType::Session));
+        assert!(parser.pending.is_empty());
+
+        assert!(parse(&mut parser, b"version\r\n", &at(C2S, 1000)).is_empty());
+        let infos = parse(&mut parser, &[], &at(C2S, 1000 + SESSION_TIMEOUT));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+
+        // the oldest get is reported once too many wait for their reply
+        let gets = (0..=MAX_PENDING_SESSIONS)
+            .map(|i| format!("get user:{}\r\n", i))
+            .collect::<String>();
+        let infos = parse(&mut parser, gets.as_bytes(), &at(C2S, 2000));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].key.as_deref(), Some("user:0"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+    }
+
+    #[test]
+    fn binary_commands() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = MemcachedLog::default();
+        let value = vec![7; 5000];
+        let requests = [
+            binary(REQUEST_MAGIC, 0x01, 0, 1, "k1", &value),
+            // a multi-get as quiet gets ended with a noop
+            binary(REQUEST_MAGIC, 0x0d, 0, 2, "k1", &[]),
+            binary(REQUEST_MAGIC, 0x0d, 0, 3, "k2", &[]),
+            binary(REQUEST_MAGIC, 0x0a, 0, 4, "", &[]),
+            binary(REQUEST_MAGIC, 0x11, 0, 5, "k3", b"v"),
+            binary(REQUEST_MAGIC, 0x05, 0, 6, "k1", &[0; 20]),
+            binary(REQUEST_MAGIC, 0x00, 0, 7, "k4", &[]),
+        ]
+        .concat();
+        assert!(parser.check_payload(&requests, &at(C2S, 0)));
+        for segment in requests.chunks(1460) {
+            assert!(parse(&mut parser, segment, &at(C2S, 0)).is_empty());
+        }
+        assert_eq!(parser.pending.len(), 7);
+
+        let responses = [
+            binary(RESPONSE_MAGIC, 0x01, 0, 1, "", &[]),
+            binary(RESPONSE_MAGIC, 0x0d, 0, 2, "k1", &value),
+            binary(RESPONSE_MAGIC, 0x0a, 0, 4, "", &[]),
+            binary(
+                RESPONSE_MAGIC,
+                0x05,
+                0x06,
+                6,
+                "",
+                b"Non-numeric server-side value for incr or decr",
+            ),
+            binary(RESPONSE_MAGIC, 0x00, 0x01, 7, "", b"Not found"),
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in responses.chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 100)));
+        }
+        let summary = infos
+            .iter()
+            .map(|i| (i.command.as_str(), i.key.as_deref(), i.result, i.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("set", Some("k1"), Some("NO_ERROR"), L7ResponseStatus::Ok),
+                ("getkq", Some("k1"), Some("HIT"), L7ResponseStatus::Ok),
+                ("getkq", Some("k2"), Some("MISS"), L7ResponseStatus::Ok),
+                ("noop", None, Some("NO_ERROR"), L7ResponseStatus::Ok),
+                ("setq", Some("k3"), Some("NO_ERROR"), L7ResponseStatus::Ok),
+                (
+                    "increment",
+                    Some("k1"),
+                    Some("NON_NUMERIC_VALUE"),
+                    L7ResponseStatus::ClientError
+                ),
+                ("get", Some("k4"), Some("MISS"), L7ResponseStatus::Ok),
+            ]
+        );
+        assert_eq!(
+            infos[5].error.as_deref(),
+            Some("Non-numeric server-side value for incr or decr")
+        );
+        assert_eq!(infos[2].hit_count, Some(0));
+        assert!(parser.pending.is_empty());
+    }
+}
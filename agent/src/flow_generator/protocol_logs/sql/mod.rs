@@ -0,0 +1,37 @@
+mod memcached;
+mod mongodb;
+mod mysql;
+mod postgresql;
+mod redis;
+
+use super::{L7ResponseStatus, LogParserConfig, MAX_PENDING_SESSIONS, SESSION_TIMEOUT};
+
+pub use memcached::{MemcachedInfo, MemcachedLog};
+pub use mongodb::{MongodbInfo, MongodbLog};
+pub use mysql::{MysqlInfo, MysqlLog};
+pub use postgresql::{PostgresqlInfo, PostgresqlLog};
+pub use redis::{RedisInfo, RedisLog};
+
+// cut at sql_max_length, without a config statements are kept whole
+fn truncate_sql(sql: &[u8], config: Option<&LogParserConfig>) -> String {
+    let max_length = config.map_or(usize::MAX, |c| c.sql_max_length);
+    let mut sql = String::from_utf8_lossy(&sql[..sql.len().min(max_length)]).into_owned();
+    if sql.len() > max_length {
+        // a multi-byte character cut in half became a replacement character
+        let mut end = max_length;
+        while !sql.is_char_boundary(end) {
+            end -= 1;
+        }
+        sql.truncate(end);
+    }
+    sql
+}
+
+// errors in the classes of malformed statements, violated constraints or
+// rejected credentials are caused by the client
+fn sql_state_status(sql_state: &str) -> L7ResponseStatus {
+    match sql_state.get(..2) {
+        Some("21" | "22" | "23" | "28" | "42" | "44") => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
@@ -0,0 +1,1060 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::mem;
+
+use flate2::read::ZlibDecoder;
+use log::debug;
+use public::bytes::{read_u32_le, read_u64_le};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{truncate_sql, MAX_PENDING_SESSIONS};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus,
+    LogMessageType, LogParserConfig, ParseParam, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// [messageLength][requestID][responseTo][opCode]
+const HEADER_SIZE: usize = 16;
+// maxMessageSizeBytes of the server
+const MAX_MESSAGE_SIZE: usize = 48_000_000;
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// compressed messages are decompressed as a whole and buffered up to this size
+const MAX_COMPRESSED_SIZE: usize = 256 * 1024;
+const MIN_DOCUMENT_SIZE: usize = 5;
+
+const OP_REPLY: i32 = 1;
+const OP_UPDATE: i32 = 2001;
+const OP_INSERT: i32 = 2002;
+const OP_QUERY: i32 = 2004;
+const OP_GET_MORE: i32 = 2005;
+const OP_DELETE: i32 = 2006;
+const OP_KILL_CURSORS: i32 = 2007;
+const OP_COMPRESSED: i32 = 2012;
+const OP_MSG: i32 = 2013;
+
+// OP_MSG flag bits
+const CHECKSUM_PRESENT: u32 = 1;
+const MORE_TO_COME: u32 = 1 << 1;
+// OP_REPLY response flags
+const QUERY_FAILURE: u32 = 1 << 1;
+
+const COMPRESSOR_NOOP: u8 = 0;
+const COMPRESSOR_SNAPPY: u8 = 1;
+const COMPRESSOR_ZLIB: u8 = 2;
+const COMPRESSOR_ZSTD: u8 = 3;
+
+// bson element types
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BINARY: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const OBJECT_ID: u8 = 0x07;
+const BOOLEAN: u8 = 0x08;
+const DATE_TIME: u8 = 0x09;
+const NULL: u8 = 0x0a;
+const REGEX: u8 = 0x0b;
+const DB_POINTER: u8 = 0x0c;
+const JAVASCRIPT: u8 = 0x0d;
+const SYMBOL: u8 = 0x0e;
+const JAVASCRIPT_WITH_SCOPE: u8 = 0x0f;
+const INT32: u8 = 0x10;
+const TIMESTAMP: u8 = 0x11;
+const INT64: u8 = 0x12;
+const DECIMAL128: u8 = 0x13;
+const MIN_KEY: u8 = 0xff;
+const MAX_KEY: u8 = 0x7f;
+
+fn op_code_name(op_code: i32) -> &'static str {
+    match op_code {
+        OP_REPLY => "OP_REPLY",
+        OP_UPDATE => "OP_UPDATE",
+        OP_INSERT => "OP_INSERT",
+        OP_QUERY => "OP_QUERY",
+        OP_GET_MORE => "OP_GET_MORE",
+        OP_DELETE => "OP_DELETE",
+        OP_KILL_CURSORS => "OP_KILL_CURSORS",
+        OP_COMPRESSED => "OP_COMPRESSED",
+        OP_MSG => "OP_MSG",
+        _ => "UNKNOWN",
+    }
+}
+
+// time limits exceeded, or errors of the server and its replica set rather than of the command
+fn error_status(code: Option<i32>) -> L7ResponseStatus {
+    match code {
+        // MaxTimeMSExpired, NetworkTimeout, ExceededTimeLimit
+        Some(50 | 89 | 262) => L7ResponseStatus::Timeout,
+        None | Some(1 | 6 | 7 | 64 | 91 | 189 | 10107 | 11600 | 11602 | 13435 | 13436) => {
+            L7ResponseStatus::ServerError
+        }
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MongodbInfo {
+    pub msg_type: LogMessageType,
+    // of the request, compressed messages are logged with the opcode they wrap
+    pub op_code: &'static str,
+    pub request_id: i32,
+    // the first key of the command document, as in find or insert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    // only with mongodb_command_summary, values of nested documents are never logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_summary: Option<String>,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ok: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<i32>,
+    // documents matched or written, or returned to a legacy query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<i64>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    // an OP_QUERY on a collection rather than on $cmd, answered with the documents found
+    #[serde(skip)]
+    legacy_query: bool,
+}
+
+impl MongodbInfo {
+    fn request(header: &Header, body: &Body, config: Option<&LogParserConfig>, time: u64) -> Self {
+        let mut info = MongodbInfo {
+            msg_type: LogMessageType::Request,
+            op_code: op_code_name(body.op_code),
+            request_id: header.request_id,
+            time,
+            ..Default::default()
+        };
+        let mut document = body.document;
+        if let Some(namespace) = body.namespace {
+            let namespace = String::from_utf8_lossy(namespace);
+            let (database, collection) = namespace.split_once('.').unwrap_or((&namespace, ""));
+            info.database = Some(database.to_owned());
+            if collection != "$cmd" {
+                info.legacy_query = true;
+                info.command = Some("find".to_owned());
+                info.collection = Some(collection.to_owned());
+            } else if let Some(query) = document.and_then(|d| elements(d).next()) {
+                // commands with a read preference are wrapped as in {$query: {...}}
+                if matches!(query.key, b"$query" | b"query") && query.kind == DOCUMENT {
+                    document = Some(query.value);
+                }
+            }
+        }
+        let Some(document) = document else {
+            return info;
+        };
+        if !info.legacy_query {
+            info.fill_command(document);
+        }
+        if config.is_some_and(|c| c.mongodb_command_summary) {
+            let summary = summarize(document, &body.sequences);
+            info.command_summary = Some(truncate_sql(summary.as_bytes(), config));
+        }
+        info
+    }
+
+    fn fill_command(&mut self, document: &[u8]) {
+        let mut elements = elements(document);
+        if let Some(first) = elements.next() {
+            self.command = Some(first.key());
+            self.collection = first.as_str();
+        }
+        for e in elements {
+            match e.key {
+                b"$db" => self.database = e.as_str(),
+                // getMore names the collection after the cursor id
+                b"collection" if self.collection.is_none() => self.collection = e.as_str(),
+                _ => {}
+            }
+        }
+    }
+
+    fn fill_reply(&mut self, document: &[u8]) {
+        for e in elements(document) {
+            match e.key {
+                b"ok" => self.ok = e.as_f64().map(|ok| ok != 0.0),
+                b"errmsg" | b"$err" => self.error_message = e.as_str(),
+                b"code" => self.error_code = e.as_i64().map(|c| c as i32),
+                b"n" => self.n = e.as_i64(),
+                // the first failed write of a batch, the others are alike
+                b"writeErrors" | b"writeConcernError" if self.error_message.is_none() => {
+                    let error = match e.kind {
+                        ARRAY => e.elements().next(),
+                        _ => Some(e),
+                    };
+                    for f in error.iter().flat_map(Element::elements) {
+                        match f.key {
+                            b"errmsg" => self.error_message = f.as_str(),
+                            b"code" => self.error_code = f.as_i64().map(|c| c as i32),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn merge(&mut self, body: &Body, time: u64) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = time.saturating_sub(self.time);
+        let failed = body.op_code == OP_REPLY && body.flags & QUERY_FAILURE != 0;
+        if failed {
+            self.ok = Some(false);
+        }
+        if self.legacy_query && !failed {
+            self.n = Some(body.number_returned as i64);
+        } else if let Some(document) = body.document {
+            self.fill_reply(document);
+        }
+        self.status = if self.ok == Some(false) || self.error_message.is_some() {
+            error_status(self.error_code)
+        } else {
+            L7ResponseStatus::Ok
+        };
+    }
+}
+
+// top level keys of a command with the values of scalars, but only the size of
+// nested documents and arrays, as in {find: "users", filter: {2}, limit: 10}
+fn summarize(document: &[u8], sequences: &[(&[u8], usize)]) -> String {
+    let mut fields = vec![];
+    for e in elements(document) {
+        let value = match e.kind {
+            DOCUMENT => format!("{{{}}}", e.elements().count()),
+            ARRAY => format!("[{}]", e.elements().count()),
+            STRING => format!("{:?}", e.as_str().unwrap_or_default()),
+            DOUBLE => e.as_f64().unwrap_or_default().to_string(),
+            INT32 | INT64 => e.as_i64().unwrap_or_default().to_string(),
+            BOOLEAN => (e.value[0] != 0).to_string(),
+            NULL => "null".to_owned(),
+            OBJECT_ID => "ObjectId".to_owned(),
+            DATE_TIME => "Date".to_owned(),
+            BINARY => "BinData".to_owned(),
+            TIMESTAMP => "Timestamp".to_owned(),
+            DECIMAL128 => "Decimal128".to_owned(),
+            REGEX => "Regex".to_owned(),
+            _ => "?".to_owned(),
+        };
+        fields.push(format!("{}: {}", e.key(), value));
+    }
+    // document sequences of OP_MSG, such as the documents of an insert
+    for (identifier, count) in sequences {
+        fields.push(format!(
+            "{}: [{}]",
+            String::from_utf8_lossy(identifier),
+            count
+        ));
+    }
+    format!("{{{}}}", fields.join(", "))
+}
+
+trait MongodbRead<'a> {
+    fn document(&mut self) -> Result<&'a [u8]>;
+}
+
+impl<'a> MongodbRead<'a> for Reader<'a> {
+    // a document, cut where the message was cut at its head
+    fn document(&mut self) -> Result<&'a [u8]> {
+        let size = read_u32_le(self.data.get(..4).ok_or(Error::InsufficientPayloadLength)?);
+        if (size as usize) < MIN_DOCUMENT_SIZE {
+            return Err(Error::MongodbLogParseFailed(format!(
+                "invalid document size {}",
+                size
+            )));
+        }
+        self.take((size as usize).min(self.data.len()))
+    }
+}
+
+// an element of a document: [type][key cstring][value]
+struct Element<'a> {
+    kind: u8,
+    key: &'a [u8],
+    value: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    fn key(&self) -> String {
+        String::from_utf8_lossy(self.key).into_owned()
+    }
+
+    fn as_str(&self) -> Option<String> {
+        if self.kind != STRING {
+            return None;
+        }
+        // [size][bytes][0]
+        let s = self.value.get(4..self.value.len() - 1)?;
+        Some(String::from_utf8_lossy(s).into_owned())
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self.kind {
+            INT32 => Some(read_u32_le(self.value) as i32 as i64),
+            INT64 => Some(read_u64_le(self.value) as i64),
+            DOUBLE => Some(self.as_f64()? as i64),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self.kind {
+            DOUBLE => Some(f64::from_bits(read_u64_le(self.value))),
+            INT32 | INT64 => Some(self.as_i64()? as f64),
+            BOOLEAN => Some(self.value[0] as f64),
+            _ => None,
+        }
+    }
+
+    // of a nested document or array
+    fn elements(&self) -> Elements<'a> {
+        match self.kind {
+            DOCUMENT | ARRAY => elements(self.value),
+            _ => elements(&[]),
+        }
+    }
+}
+
+// elements are decoded lazily, up to the first one cut or malformed
+struct Elements<'a> {
+    r: Reader<'a>,
+}
+
+fn elements(document: &[u8]) -> Elements<'_> {
+    Elements {
+        r: Reader {
+            data: document.get(4..).unwrap_or_default(),
+        },
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Element<'a>> {
+        let kind = self.r.u8().ok()?;
+        if kind == 0 {
+            return None;
+        }
+        let key = self.r.cstring().ok()?;
+        let data = self.r.data;
+        let size = |offset: usize| -> Option<usize> {
+            let size = data.get(offset..offset + 4).map(read_u32_le)? as i32;
+            usize::try_from(size).ok()
+        };
+        let value_size = match kind {
+            UNDEFINED | NULL | MIN_KEY | MAX_KEY => 0,
+            BOOLEAN => 1,
+            INT32 => 4,
+            DOUBLE | DATE_TIME | TIMESTAMP | INT64 => 8,
+            OBJECT_ID => 12,
+            DECIMAL128 => 16,
+            STRING | JAVASCRIPT | SYMBOL => 4 + size(0)?,
+            DB_POINTER => 4 + size(0)? + 12,
+            DOCUMENT | ARRAY | JAVASCRIPT_WITH_SCOPE => size(0)?,
+            BINARY => 5 + size(0)?,
+            // [pattern cstring][options cstring]
+            REGEX => {
+                let pattern = data.iter().position(|&b| b == 0)? + 1;
+                pattern + data[pattern..].iter().position(|&b| b == 0)? + 1
+            }
+            _ => return None,
+        };
+        // a string is at least its terminating zero, a document its size and terminator
+        let min_size = match kind {
+            STRING | JAVASCRIPT | SYMBOL => 5,
+            DOCUMENT | ARRAY => MIN_DOCUMENT_SIZE,
+            _ => 0,
+        };
+        if value_size < min_size {
+            self.r.data = &[];
+            return None;
+        }
+        let value = self.r.take(value_size).ok()?;
+        Some(Element { kind, key, value })
+    }
+}
+
+struct Header {
+    length: usize,
+    request_id: i32,
+    response_to: i32,
+    op_code: i32,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let length = read_u32_le(data) as usize;
+        if !(HEADER_SIZE..=MAX_MESSAGE_SIZE).contains(&length) {
+            return Err(Error::MongodbLogParseFailed(format!(
+                "invalid message length {}",
+                length
+            )));
+        }
+        Ok(Self {
+            length,
+            request_id: read_u32_le(&data[4..]) as i32,
+            response_to: read_u32_le(&data[8..]) as i32,
+            op_code: read_u32_le(&data[12..]) as i32,
+        })
+    }
+}
+
+// what is logged of a message body, documents are decoded only as needed
+#[derive(Default)]
+struct Body<'a> {
+    // the opcode wrapped in OP_COMPRESSED, if compressed
+    op_code: i32,
+    // OP_MSG flag bits, or OP_REPLY response flags
+    flags: u32,
+    // the command or reply, of OP_REPLY only the first document returned
+    document: Option<&'a [u8]>,
+    // identifiers and document counts of OP_MSG document sequences
+    sequences: Vec<(&'a [u8], usize)>,
+    // fullCollectionName of OP_QUERY
+    namespace: Option<&'a [u8]>,
+    // numberReturned of OP_REPLY
+    number_returned: i32,
+}
+
+impl<'a> Body<'a> {
+    fn parse(op_code: i32, data: &'a [u8], whole: bool) -> Result<Self> {
+        let mut r = Reader { data };
+        let mut body = Body {
+            op_code,
+            ..Default::default()
+        };
+        match op_code {
+            // [flagBits][sections][checksum, if present]
+            OP_MSG => {
+                body.flags = r.u32_le()?;
+                if whole && body.flags & CHECKSUM_PRESENT != 0 {
+                    r.data = &r.data[..r.data.len().saturating_sub(4)];
+                }
+                while !r.data.is_empty() {
+                    match r.u8()? {
+                        0 => body.document = Some(r.document()?),
+                        // [size][identifier cstring][documents]
+                        1 => {
+                            let size = (r.u32_le()? as usize).saturating_sub(4);
+                            let mut section = Reader {
+                                data: r.take(size.min(r.data.len()))?,
+                            };
+                            let identifier = section.cstring()?;
+                            let mut count = 0;
+                            while section.document().is_ok() {
+                                count += 1;
+                            }
+                            body.sequences.push((identifier, count));
+                        }
+                        kind => {
+                            return Err(Error::MongodbLogParseFailed(format!(
+                                "invalid section kind {}",
+                                kind
+                            )))
+                        }
+                    }
+                }
+            }
+            // [flags][fullCollectionName][numberToSkip][numberToReturn][query]...
+            OP_QUERY => {
+                body.flags = r.u32_le()?;
+                body.namespace = Some(r.cstring()?);
+                r.take(8)?;
+                body.document = Some(r.document()?);
+            }
+            // [responseFlags][cursorID][startingFrom][numberReturned][documents]
+            OP_REPLY => {
+                body.flags = r.u32_le()?;
+                r.i64_le()?;
+                r.i32_le()?;
+                body.number_returned = r.i32_le()?;
+                body.document = r.document().ok();
+            }
+            // the other legacy opcodes were removed from servers along with OP_QUERY on collections
+            _ => {
+                return Err(Error::MongodbLogParseFailed(format!(
+                    "unsupported opcode {}",
+                    op_code_name(op_code)
+                )))
+            }
+        }
+        Ok(body)
+    }
+}
+
+// [originalOpcode][uncompressedSize][compressorId][compressedMessage]
+fn decompress(data: &[u8], max_size: usize) -> Result<(i32, Vec<u8>)> {
+    let mut r = Reader { data };
+    let op_code = r.i32_le()?;
+    let size = r.i32_le()?;
+    let compressor = r.u8()?;
+    let size = usize::try_from(size)
+        .ok()
+        .filter(|&s| s <= max_size)
+        .ok_or_else(|| {
+            Error::MongodbLogParseFailed(format!(
+                "uncompressed size {} exceeds limit {}",
+                size, max_size
+            ))
+        })?;
+    let failed = |e: String| {
+        Error::MongodbLogParseFailed(format!(
+            "compressor {} decompression failed: {}",
+            compressor, e
+        ))
+    };
+    let data = r.data;
+    let output = match compressor {
+        COMPRESSOR_NOOP => data.to_vec(),
+        COMPRESSOR_SNAPPY => {
+            let len = snap::raw::decompress_len(data).map_err(|e| failed(e.to_string()))?;
+            if len > size {
+                return Err(failed(format!("size {} exceeds {}", len, size)));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| failed(e.to_string()))?
+        }
+        COMPRESSOR_ZLIB => {
+            let mut output = Vec::with_capacity(size);
+            ZlibDecoder::new(data)
+                .take(size as u64 + 1)
+                .read_to_end(&mut output)
+                .map_err(|e| failed(e.to_string()))?;
+            output
+        }
+        COMPRESSOR_ZSTD => zstd::bulk::decompress(data, size).map_err(|e| failed(e.to_string()))?,
+        _ => return Err(failed("unknown compressor".to_owned())),
+    };
+    if output.len() != size {
+        return Err(failed(format!(
+            "got {} bytes, expected {}",
+            output.len(),
+            size
+        )));
+    }
+    Ok((op_code, output))
+}
+
+#[derive(Default)]
+pub struct MongodbLog {
+    streams: PerDirection<Stream>,
+    // by requestID, answered by responseTo
+    pending: HashMap<i32, MongodbInfo>,
+    // requestIDs of exhaust replies, each answered by the next reply of the cursor
+    exhaust: HashSet<i32>,
+}
+
+impl MongodbLog {
+    fn parse_messages(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= HEADER_SIZE {
+            let header = match Header::parse(remain) {
+                Ok(header) => header,
+                Err(e) => {
+                    self.streams[direction] = Stream::default();
+                    return Err(e);
+                }
+            };
+            let limit = match header.op_code {
+                OP_COMPRESSED => MAX_COMPRESSED_SIZE,
+                _ => MAX_HEAD_SIZE,
+            };
+            let (message, whole) = if remain.len() >= header.length {
+                let message = &remain[HEADER_SIZE..header.length];
+                remain = &remain[header.length..];
+                (message, true)
+            } else if remain.len() >= limit {
+                self.streams[direction].skip = header.length - remain.len();
+                (&mem::take(&mut remain)[HEADER_SIZE..], false)
+            } else {
+                break;
+            };
+            if let Err(e) = self.handle_message(&header, message, whole, param, output) {
+                debug!("skip mongodb message: {}", e);
+                last_error = Some(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+        last_error.map_or(Ok(()), Err)
+    }
+
+    fn handle_message(
+        &mut self,
+        header: &Header,
+        data: &[u8],
+        whole: bool,
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let decompressed;
+        let (op_code, data) = if header.op_code == OP_COMPRESSED {
+            if !whole {
+                return Err(Error::MongodbLogParseFailed(format!(
+                    "compressed message of {} bytes",
+                    header.length
+                )));
+            }
+            let max_size = param.parse_config.map_or_else(
+                || LogParserConfig::default().max_decompressed_size,
+                |c| c.max_decompressed_size,
+            );
+            let (op_code, data) = decompress(data, max_size)?;
+            decompressed = data;
+            (op_code, &decompressed[..])
+        } else {
+            (header.op_code, data)
+        };
+        let body = Body::parse(op_code, data, whole)?;
+        match param.direction {
+            PacketDirection::ClientToServer => {
+                let info = MongodbInfo::request(header, &body, param.parse_config, param.time);
+                // unacknowledged writes get no reply
+                let more_to_come = body.op_code == OP_MSG && body.flags & MORE_TO_COME != 0;
+                self.handle_request(info, more_to_come, output);
+            }
+            PacketDirection::ServerToClient => self.handle_reply(header, &body, param.time, output),
+        }
+        Ok(())
+    }
+
+    fn handle_request(
+        &mut self,
+        info: MongodbInfo,
+        more_to_come: bool,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let key = info.request_id;
+        if more_to_come
+            || self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key)
+        {
+            output.push(L7ProtocolInfo::MongodbInfo(info));
+        } else if let Some(prev) = self.pending.insert(key, info) {
+            // requestID reused before a reply, the previous one is lost
+            output.push(L7ProtocolInfo::MongodbInfo(prev));
+        }
+    }
+
+    fn handle_reply(
+        &mut self,
+        header: &Header,
+        body: &Body,
+        time: u64,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        // replies of an exhaust cursor follow each other with moreToCome set on all but the last
+        let more_to_come = body.op_code == OP_MSG && body.flags & MORE_TO_COME != 0;
+        if more_to_come {
+            if self.exhaust.len() >= MAX_PENDING_SESSIONS {
+                self.exhaust.clear();
+            }
+            self.exhaust.insert(header.request_id);
+        }
+        if self.exhaust.remove(&header.response_to) {
+            return;
+        }
+        let info = match self.pending.remove(&header.response_to) {
+            Some(mut request) => {
+                request.merge(body, time);
+                request
+            }
+            None => {
+                let mut info = MongodbInfo {
+                    op_code: op_code_name(body.op_code),
+                    request_id: header.response_to,
+                    time,
+                    ..Default::default()
+                };
+                info.merge(body, time);
+                info.msg_type = LogMessageType::Response;
+                info
+            }
+        };
+        output.push(L7ProtocolInfo::MongodbInfo(info));
+    }
+}
+
+impl L7ProtocolParserInterface for MongodbLog {
+    // replies carry nothing to tell them from other little-endian protocols, so only commands are checked
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.len() < HEADER_SIZE {
+            return false;
+        }
+        let Ok(header) = Header::parse(payload) else {
+            return false;
+        };
+        if header.response_to != 0
+            || !matches!(header.op_code, OP_MSG | OP_QUERY | OP_COMPRESSED)
+            || payload.len() > header.length
+        {
+            return false;
+        }
+        let mut parser = MongodbLog::default();
+        let mut output = vec![];
+        let whole = payload.len() == header.length;
+        if parser
+            .handle_message(&header, &payload[HEADER_SIZE..], whole, param, &mut output)
+            .is_err()
+        {
+            return false;
+        }
+        let info = match output.pop() {
+            Some(L7ProtocolInfo::MongodbInfo(info)) => Some(info),
+            _ => parser.pending.into_values().next(),
+        };
+        info.is_some_and(|i| i.command.is_some())
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending(&mut self.pending, param.time, &mut output);
+        if let Err(e) = self.parse_messages(payload, param, &mut output) {
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::MongoDB
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bson::{doc, Document};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn message(request_id: i32, response_to: i32, op_code: i32, body: &[u8]) -> Vec<u8> {
+        let mut m = ((HEADER_SIZE + body.len()) as u32).to_le_bytes().to_vec();
+        m.extend_from_slice(&request_id.to_le_bytes());
+        m.extend_from_slice(&response_to.to_le_bytes());
+        m.extend_from_slice(&op_code.to_le_bytes());
+        m.extend_from_slice(body);
+        m
+    }
+
+    fn op_msg(flags: u32, document: Document, sequence: Option<(&str, Vec<Document>)>) -> Vec<u8> {
+        let mut body = flags.to_le_bytes().to_vec();
+        body.push(0);
+        body.extend(bson::to_vec(&document).unwrap());
+        if let Some((identifier, documents)) = sequence {
+            let mut section = identifier.as_bytes().to_vec();
+            section.push(0);
+            for d in documents {
+                section.extend(bson::to_vec(&d).unwrap());
+            }
+            body.push(1);
+            body.extend(((section.len() + 4) as u32).to_le_bytes());
+            body.extend(section);
+        }
+        body
+    }
+
+    #[test]
+    fn op_msg_commands() {
+        let config = LogParserConfig {
+            mongodb_command_summary: true,
+            ..Default::default()
+        };
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = MongodbLog::default();
+
+        let find = message(
+            1,
+            0,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! {
+                    "find": "users",
+                    "filter": { "age": { "$gt": 30 }, "city": "Paris" },
+                    "limit": 10,
+                    "singleBatch": false,
+                    "$db": "app",
+                },
+                None,
+            ),
+        );
+        assert!(parser.check_payload(&find, &at(C2S, 0)));
+        assert!(parse(&mut parser, &find, &at(C2S, 100)).is_empty());
+        // a first batch larger than the head kept, with ok after it
+        let users: Vec<_> = (0..1000)
+            .map(|i| doc! { "name": format!("user-{}", i), "age": 40 })
+            .collect();
+        let reply = message(
+            2,
+            1,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! { "cursor": { "firstBatch": users, "id": 0i64, "ns": "app.users" }, "ok": 1.0 },
+                None,
+            ),
+        );
+        assert!(reply.len() > MAX_HEAD_SIZE);
+        let mut infos = vec![];
+        for segment in reply.chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 600)));
+        }
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Session);
+        assert_eq!(infos[0].op_code, "OP_MSG");
+        assert_eq!(infos[0].command.as_deref(), Some("find"));
+        assert_eq!(infos[0].collection.as_deref(), Some("users"));
+        assert_eq!(infos[0].database.as_deref(), Some("app"));
+        assert_eq!(
+            infos[0].command_summary.as_deref(),
+            Some(r#"{find: "users", filter: {2}, limit: 10, singleBatch: false, $db: "app"}"#)
+        );
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        assert_eq!(infos[0].rrt, 500);
+
+        // inserts carry their documents in a sequence, a duplicate fails alone
+        let insert = message(
+            3,
+            0,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! { "insert": "users", "ordered": false, "$db": "app" },
+                Some(("documents", vec![doc! { "_id": 1 }, doc! { "_id": 2 }])),
+            ),
+        );
+        let reply = message(
+            4,
+            3,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! {
+                    "n": 1,
+                    "writeErrors": [{ "index": 1, "code": 11000, "errmsg": "E11000 duplicate key error" }],
+                    "ok": 1.0,
+                },
+                None,
+            ),
+        );
+        // an unacknowledged write, and a command timing out
+        let update = message(
+            5,
+            0,
+            OP_MSG,
+            &op_msg(
+                MORE_TO_COME,
+                doc! { "update": "users", "updates": [{ "q": {}, "u": {} }], "$db": "app" },
+                None,
+            ),
+        );
+        let aggregate = message(
+            6,
+            0,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! { "aggregate": "orders", "pipeline": [], "maxTimeMS": 10, "$db": "shop" },
+                None,
+            ),
+        );
+        let timeout = message(
+            7,
+            6,
+            OP_MSG,
+            &op_msg(
+                0,
+                doc! { "ok": 0.0, "errmsg": "operation exceeded time limit", "code": 50 },
+                None,
+            ),
+        );
+        let infos = parse(
+            &mut parser,
+            &[insert, update, aggregate].concat(),
+            &at(C2S, 1000),
+        );
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].msg_type, LogMessageType::Request);
+        assert_eq!(infos[0].command.as_deref(), Some("update"));
+        let infos = parse(&mut parser, &[reply, timeout].concat(), &at(S2C, 1200));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(
+            infos[0].command_summary.as_deref(),
+            Some(r#"{insert: "users", ordered: false, $db: "app", documents: [2]}"#)
+        );
+        assert_eq!(infos[0].n, Some(1));
+        assert_eq!(infos[0].ok, Some(true));
+        assert_eq!(infos[0].error_code, Some(11000));
+        assert_eq!(infos[0].status, L7ResponseStatus::ClientError);
+        assert_eq!(infos[1].collection.as_deref(), Some("orders"));
+        assert_eq!(infos[1].database.as_deref(), Some("shop"));
+        assert_eq!(infos[1].ok, Some(false));
+        assert_eq!(
+            infos[1].error_message.as_deref(),
+            Some("operation exceeded time limit")
+        );
+        assert_eq!(infos[1].status, L7ResponseStatus::Timeout);
+    }
+
+    #[test]
+    fn legacy_and_compressed() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let op_query = |namespace: &str, query: Document| {
+            let mut body = 0u32.to_le_bytes().to_vec();
+            body.extend(namespace.as_bytes());
+            body.push(0);
+            body.extend([0; 4]);
+            body.extend((-1i32).to_le_bytes());
+            body.extend(bson::to_vec(&query).unwrap());
+            body
+        };
+        let op_reply = |flags: u32, documents: &[Document]| {
+            let mut body = flags.to_le_bytes().to_vec();
+            body.extend(0i64.to_le_bytes());
+            body.extend(0i32.to_le_bytes());
+            body.extend((documents.len() as i32).to_le_bytes());
+            for d in documents {
+                body.extend(bson::to_vec(d).unwrap());
+            }
+            body
+        };
+        let mut parser = MongodbLog::default();
+
+        // the handshake of drivers is still sent as OP_QUERY
+        let hello = message(
+            1,
+            0,
+            OP_QUERY,
+            &op_query(
+                "admin.$cmd",
+                doc! { "$query": { "isMaster": 1, "client": {} }, "$readPreference": {} },
+            ),
+        );
+        assert!(parser.check_payload(&hello, &at(C2S, 0)));
+        let query = message(2, 0, OP_QUERY, &op_query("app.users", doc! { "age": 40 }));
+        let bad_query = message(3, 0, OP_QUERY, &op_query("app.users", doc! { "$where": 1 }));
+        assert!(parse(
+            &mut parser,
+            &[hello, query, bad_query].concat(),
+            &at(C2S, 0)
+        )
+        .is_empty());
+        let replies = [
+            message(
+                10,
+                1,
+                OP_REPLY,
+                &op_reply(0, &[doc! { "ismaster": true, "ok": 1.0 }]),
+            ),
+            message(
+                11,
+                2,
+                OP_REPLY,
+                &op_reply(0, &[doc! { "ok": 0 }, doc! { "ok": 1 }]),
+            ),
+            message(
+                12,
+                3,
+                OP_REPLY,
+                &op_reply(QUERY_FAILURE, &[doc! { "$err": "bad $where", "code": 2 }]),
+            ),
+        ];
+        let mut infos = parse(&mut parser, &replies.concat(), &at(S2C, 10));
+        infos.sort_by_key(|i| i.request_id);
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0].op_code, "OP_QUERY");
+        assert_eq!(infos[0].command.as_deref(), Some("isMaster"));
+        assert_eq!(infos[0].database.as_deref(), Some("admin"));
+        assert_eq!(infos[0].collection, None);
+        assert_eq!(infos[0].status, L7ResponseStatus::Ok);
+        // documents found are not mistaken for a failed command
+        assert_eq!(infos[1].command.as_deref(), Some("find"));
+        assert_eq!(infos[1].collection.as_deref(), Some("users"));
+        assert_eq!(infos[1].n, Some(2));
+        assert_eq!(infos[1].ok, None);
+        assert_eq!(infos[1].status, L7ResponseStatus::Ok);
+        assert_eq!(infos[2].error_message.as_deref(), Some("bad $where"));
+        assert_eq!(infos[2].status, L7ResponseStatus::ClientError);
+
+        // OP_COMPRESSED wraps the original opcode
+        let compressed = |op_code: i32, original: &[u8]| {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(original).unwrap();
+            let mut body = op_code.to_le_bytes().to_vec();
+            body.extend((original.len() as i32).to_le_bytes());
+            body.push(COMPRESSOR_ZLIB);
+            body.extend(encoder.finish().unwrap());
+            body
+        };
+        let count = op_msg(0, doc! { "count": "users", "$db": "app" }, None);
+        let request = message(20, 0, OP_COMPRESSED, &compressed(OP_MSG, &count));
+        assert!(parser.check_payload(&request, &at(C2S, 20)));
+        assert!(parse(&mut parser, &request, &at(C2S, 20)).is_empty());
+        let count = op_msg(0, doc! { "n": 7, "ok": 1.0 }, None);
+        let reply = message(21, 20, OP_COMPRESSED, &compressed(OP_MSG, &count));
+        let infos = parse(&mut parser, &reply, &at(S2C, 30));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].op_code, "OP_MSG");
+        assert_eq!(infos[0].command.as_deref(), Some("count"));
+        assert_eq!(infos[0].n, Some(7));
+        assert_eq!(infos[0].command_summary, None);
+
+        // the size claimed is checked before anything is decompressed
+        let mut bomb = compressed(OP_MSG, &count);
+        bomb[4..8].copy_from_slice(&(64i32 << 20).to_le_bytes());
+        let request = message(22, 0, OP_COMPRESSED, &bomb);
+        assert!(parser.parse_payload(&request, &at(C2S, 40)).is_err());
+        assert!(parser.pending.is_empty());
+    }
+}
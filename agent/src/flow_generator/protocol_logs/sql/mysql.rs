@@ -0,0 +1,915 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::{read_u32_le, read_u64_le};
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{sql_state_status, truncate_sql, MAX_PENDING_SESSIONS, SESSION_TIMEOUT};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus, LogMessageType,
+    ParseParam, PendingRequest, PerDirection, Reader, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+const HEADER_SIZE: usize = 4;
+// packets of this length are continued in the next one
+const MAX_PACKET_SIZE: usize = 0xff_ffff;
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const PROTOCOL_VERSION: u8 = 10;
+
+const CLIENT_CONNECT_WITH_DB: u32 = 0x8;
+const CLIENT_PROTOCOL_41: u32 = 0x200;
+const CLIENT_SSL: u32 = 0x800;
+const CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x8_0000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x20_0000;
+const CLIENT_DEPRECATE_EOF: u32 = 0x100_0000;
+const CLIENT_QUERY_ATTRIBUTES: u32 = 0x800_0000;
+
+const SERVER_MORE_RESULTS_EXISTS: u16 = 0x8;
+
+const OK: u8 = 0x00;
+const AUTH_SWITCH: u8 = 0xfe;
+const EOF: u8 = 0xfe;
+const LOCAL_INFILE: u8 = 0xfb;
+const ERR: u8 = 0xff;
+
+const COM_QUIT: u8 = 0x01;
+const COM_INIT_DB: u8 = 0x02;
+const COM_QUERY: u8 = 0x03;
+const COM_STATISTICS: u8 = 0x09;
+const COM_STMT_PREPARE: u8 = 0x16;
+const COM_STMT_EXECUTE: u8 = 0x17;
+const COM_STMT_SEND_LONG_DATA: u8 = 0x18;
+const COM_STMT_CLOSE: u8 = 0x19;
+const COM_STMT_RESET: u8 = 0x1a;
+const COM_STMT_FETCH: u8 = 0x1c;
+
+fn command_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        0x00 => "COM_SLEEP",
+        COM_QUIT => "COM_QUIT",
+        COM_INIT_DB => "COM_INIT_DB",
+        COM_QUERY => "COM_QUERY",
+        0x04 => "COM_FIELD_LIST",
+        0x05 => "COM_CREATE_DB",
+        0x06 => "COM_DROP_DB",
+        0x07 => "COM_REFRESH",
+        0x08 => "COM_SHUTDOWN",
+        COM_STATISTICS => "COM_STATISTICS",
+        0x0a => "COM_PROCESS_INFO",
+        0x0c => "COM_PROCESS_KILL",
+        0x0d => "COM_DEBUG",
+        0x0e => "COM_PING",
+        0x11 => "COM_CHANGE_USER",
+        0x12 => "COM_BINLOG_DUMP",
+        0x15 => "COM_REGISTER_SLAVE",
+        COM_STMT_PREPARE => "COM_STMT_PREPARE",
+        COM_STMT_EXECUTE => "COM_STMT_EXECUTE",
+        COM_STMT_SEND_LONG_DATA => "COM_STMT_SEND_LONG_DATA",
+        COM_STMT_CLOSE => "COM_STMT_CLOSE",
+        COM_STMT_RESET => "COM_STMT_RESET",
+        0x1b => "COM_SET_OPTION",
+        COM_STMT_FETCH => "COM_STMT_FETCH",
+        0x1e => "COM_BINLOG_DUMP_GTID",
+        0x1f => "COM_RESET_CONNECTION",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MysqlInfo {
+    pub msg_type: LogMessageType,
+    // a COM_* name, or HANDSHAKE for the login of a connection
+    pub command: &'static str,
+    // truncated to sql_max_length, for executions the prepared statement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    // from the server greeting of a handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_plugin: Option<String>,
+
+    // filled in as the response packets arrive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affected_rows: Option<u64>,
+    // rows of all result sets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+trait MysqlRead {
+    fn lenenc_int(&mut self) -> Result<u64>;
+    fn nul_string(&mut self) -> Result<String>;
+}
+
+impl MysqlRead for Reader<'_> {
+    fn lenenc_int(&mut self) -> Result<u64> {
+        match self.u8()? {
+            0xfc => Ok(self.u16_le()? as u64),
+            0xfd => Ok(read_u32_le(&[self.take(3)?, &[0]].concat()) as u64),
+            0xfe => Ok(read_u64_le(self.take(8)?)),
+            0xfb | 0xff => Err(Error::MysqlLogParseFailed(
+                "invalid length encoded integer".to_owned(),
+            )),
+            n => Ok(n as u64),
+        }
+    }
+
+    fn nul_string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.cstring()?).into_owned())
+    }
+}
+
+// [null bitmap][new params bind flag u8]([type u16][name lenenc]) * count [values],
+// values are in the binary protocol of their type and null ones are not sent
+fn skip_query_attributes(r: &mut Reader, count: usize) -> Result<()> {
+    let null_bitmap = r.take(count.div_ceil(8))?;
+    if r.u8()? != 1 {
+        return Err(Error::MysqlLogParseFailed(
+            "query attributes without types".to_owned(),
+        ));
+    }
+    let mut types = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        types.push(r.u16_le()? as u8);
+        let name_size = r.lenenc_int()? as usize;
+        r.take(name_size)?;
+    }
+    for (i, t) in types.into_iter().enumerate() {
+        if null_bitmap[i / 8] & (1 << (i % 8)) != 0 {
+            continue;
+        }
+        let size = match t {
+            // NULL
+            0x06 => 0,
+            // TINY
+            0x01 => 1,
+            // SHORT, YEAR
+            0x02 | 0x0d => 2,
+            // LONG, INT24, FLOAT
+            0x03 | 0x09 | 0x04 => 4,
+            // DOUBLE, LONGLONG
+            0x05 | 0x08 => 8,
+            // TIMESTAMP, DATE, TIME, DATETIME are prefixed with their length
+            0x07 | 0x0a | 0x0b | 0x0c => r.u8()? as usize,
+            // strings, decimals, blobs and json
+            _ => r.lenenc_int()? as usize,
+        };
+        r.take(size)?;
+    }
+    Ok(())
+}
+
+// [header u8][error code u16]['#' sql state [5]][message]
+fn parse_err(info: &mut MysqlInfo, packet: &[u8]) -> Result<()> {
+    let mut r = Reader { data: packet };
+    r.u8()?;
+    info.error_code = Some(r.u16_le()?);
+    if r.data.first() == Some(&b'#') {
+        r.u8()?;
+        info.sql_state = Some(String::from_utf8_lossy(r.take(5)?).into_owned());
+    }
+    info.error_message = Some(String::from_utf8_lossy(r.rest()).into_owned());
+    info.status = info
+        .sql_state
+        .as_deref()
+        .map_or(L7ResponseStatus::ServerError, sql_state_status);
+    Ok(())
+}
+
+// [header u8][affected rows lenenc][last insert id lenenc][status u16][warnings u16]
+fn parse_ok(info: &mut MysqlInfo, packet: &[u8]) -> Result<u16> {
+    let mut r = Reader { data: packet };
+    r.u8()?;
+    let affected_rows = r.lenenc_int()?;
+    r.lenenc_int()?; // last_insert_id
+    let status = r.u16_le()?;
+    *info.affected_rows.get_or_insert(0) += affected_rows;
+    Ok(status)
+}
+
+// [header u8][warnings u16][status u16]
+fn parse_eof(packet: &[u8]) -> Result<u16> {
+    let mut r = Reader { data: packet };
+    r.u8()?;
+    r.u16_le()?; // warnings
+    r.u16_le()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Phase {
+    // after the server greeting
+    Handshake,
+    Auth,
+    #[default]
+    Command,
+    // the rest of the connection is encrypted
+    Tls,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Response {
+    #[default]
+    First,
+    // column definitions left, with their EOF
+    Columns(u64),
+    Rows,
+}
+
+#[derive(Default)]
+pub struct MysqlLog {
+    streams: PerDirection<Stream>,
+    // the last packet was of MAX_PACKET_SIZE and the next one continues it
+    continued: PerDirection<bool>,
+    phase: Phase,
+    // from the server greeting, until the login is logged
+    server_version: Option<String>,
+    auth_plugin: Option<String>,
+    capabilities: u32,
+    // commands are answered one at a time
+    pending: Option<MysqlInfo>,
+    command: u8,
+    response: Response,
+    // parameter and column definitions following a prepared statement
+    skip_packets: u32,
+    statements: HashMap<u32, String>,
+}
+
+impl MysqlLog {
+    fn parse_packets(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= HEADER_SIZE && self.phase != Phase::Tls {
+            let size = read_u32_le(remain) as usize & MAX_PACKET_SIZE;
+            let seq = remain[3];
+            let total_size = HEADER_SIZE + size;
+            let packet = if remain.len() >= total_size {
+                let packet = &remain[HEADER_SIZE..total_size];
+                remain = &remain[total_size..];
+                packet
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[HEADER_SIZE..]
+            } else {
+                break;
+            };
+            let continuation =
+                mem::replace(&mut self.continued[direction], size == MAX_PACKET_SIZE);
+            if continuation || packet.is_empty() {
+                continue;
+            }
+            let result = match param.direction {
+                PacketDirection::ClientToServer => self.client_packet(seq, packet, param, output),
+                PacketDirection::ServerToClient => self.server_packet(seq, packet, param, output),
+            };
+            if let Err(e) = result {
+                debug!("skip mysql packet: {}", e);
+                last_error = Some(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+        last_error.map_or(Ok(()), Err)
+    }
+
+    fn client_packet(
+        &mut self,
+        seq: u8,
+        packet: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        match self.phase {
+            Phase::Handshake if seq == 1 => return self.handshake_response(packet, param),
+            // auth switch responses and more auth data
+            Phase::Handshake | Phase::Auth | Phase::Tls => return Ok(()),
+            // LOAD DATA LOCAL file contents
+            Phase::Command if seq != 0 => return Ok(()),
+            Phase::Command => (),
+        }
+
+        let command = packet[0];
+        let Some(name) = command_name(command) else {
+            return Err(Error::MysqlLogParseFailed(format!(
+                "unknown command {:#x}",
+                command
+            )));
+        };
+        let mut info = MysqlInfo {
+            msg_type: LogMessageType::Request,
+            command: name,
+            time: param.time,
+            ..Default::default()
+        };
+        let mut r = Reader { data: &packet[1..] };
+        match command {
+            COM_QUERY => {
+                // [parameter count lenenc][parameter set count lenenc][attributes] before the query
+                if self.capabilities & CLIENT_QUERY_ATTRIBUTES != 0 {
+                    let count = r.lenenc_int()? as usize;
+                    r.lenenc_int()?;
+                    if count > 0 {
+                        skip_query_attributes(&mut r, count)?;
+                    }
+                }
+                info.sql = Some(truncate_sql(r.rest(), param.parse_config));
+            }
+            COM_STMT_PREPARE => info.sql = Some(truncate_sql(r.rest(), param.parse_config)),
+            COM_INIT_DB => info.database = Some(String::from_utf8_lossy(r.rest()).into_owned()),
+            COM_STMT_EXECUTE
+            | COM_STMT_SEND_LONG_DATA
+            | COM_STMT_CLOSE
+            | COM_STMT_RESET
+            | COM_STMT_FETCH => {
+                let id = r.u32_le()?;
+                info.statement_id = Some(id);
+                info.sql = match command {
+                    COM_STMT_CLOSE => self.statements.remove(&id),
+                    _ => self.statements.get(&id).cloned(),
+                };
+            }
+            _ => (),
+        }
+        match command {
+            COM_QUIT | COM_STMT_SEND_LONG_DATA | COM_STMT_CLOSE => {
+                // not answered
+                info.msg_type = LogMessageType::Other;
+                output.push(L7ProtocolInfo::MysqlInfo(info));
+            }
+            COM_STMT_FETCH => {
+                self.start(command, info, output);
+                self.response = Response::Rows;
+            }
+            _ => self.start(command, info, output),
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, command: u8, info: MysqlInfo, output: &mut Vec<L7ProtocolInfo>) {
+        if let Some(mut prev) = self.pending.replace(info) {
+            prev.status = L7ResponseStatus::Unknown;
+            output.push(L7ProtocolInfo::MysqlInfo(prev));
+        }
+        self.command = command;
+        self.response = Response::First;
+        self.skip_packets = 0;
+    }
+
+    // [capabilities u32][max packet size u32][charset u8][reserved [23]][user]...
+    fn handshake_response(&mut self, packet: &[u8], param: &ParseParam) -> Result<()> {
+        let mut r = Reader { data: packet };
+        let capabilities = r.u32_le()?;
+        if capabilities & CLIENT_PROTOCOL_41 == 0 {
+            return Err(Error::MysqlLogParseFailed(
+                "pre-4.1 handshake response".to_owned(),
+            ));
+        }
+        self.capabilities &= capabilities;
+        r.take(4 + 1 + 23)?;
+        if r.data.is_empty() && capabilities & CLIENT_SSL != 0 {
+            self.phase = Phase::Tls;
+            return Ok(());
+        }
+        let mut info = MysqlInfo {
+            msg_type: LogMessageType::Request,
+            command: "HANDSHAKE",
+            user: Some(r.nul_string()?),
+            server_version: self.server_version.take(),
+            auth_plugin: self.auth_plugin.take(),
+            time: param.time,
+            ..Default::default()
+        };
+        let auth_size = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+            r.lenenc_int()? as usize
+        } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+            r.u8()? as usize
+        } else {
+            r.nul_string()?;
+            0
+        };
+        r.take(auth_size)?;
+        if capabilities & CLIENT_CONNECT_WITH_DB != 0 {
+            info.database = Some(r.nul_string()?).filter(|d| !d.is_empty());
+        }
+        if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+            info.auth_plugin = r.nul_string().ok().or(info.auth_plugin);
+        }
+        self.pending = Some(info);
+        self.phase = Phase::Auth;
+        Ok(())
+    }
+
+    // [protocol version u8][server version][connection id u32][auth data [8]][filler u8]
+    // [capabilities u16][charset u8][status u16][capabilities u16][auth data length u8]
+    // [reserved [10]][auth data][auth plugin]
+    fn greeting(&mut self, packet: &[u8]) -> Result<()> {
+        let mut r = Reader { data: &packet[1..] };
+        let server_version = r.nul_string()?;
+        r.take(4 + 8 + 1)?;
+        let mut capabilities = r.u16_le()? as u32;
+        r.take(1 + 2)?;
+        capabilities |= (r.u16_le()? as u32) << 16;
+        let auth_size = r.u8()? as usize;
+        r.take(10)?;
+        if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+            r.take(auth_size.saturating_sub(8).max(13))?;
+        }
+        if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+            self.auth_plugin = r.nul_string().ok();
+        }
+        self.server_version = Some(server_version);
+        self.capabilities = capabilities;
+        self.phase = Phase::Handshake;
+        Ok(())
+    }
+
+    fn server_packet(
+        &mut self,
+        seq: u8,
+        packet: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        if seq == 0 && self.pending.is_none() && packet[0] == PROTOCOL_VERSION {
+            return self.greeting(packet);
+        }
+        if self.skip_packets > 0 {
+            self.skip_packets -= 1;
+            return Ok(());
+        }
+        let Some(info) = self.pending.as_mut() else {
+            return Ok(());
+        };
+        if self.phase == Phase::Auth {
+            match packet[0] {
+                OK => self.phase = Phase::Command,
+                ERR => parse_err(info, packet)?,
+                // [header u8][plugin name][plugin data]
+                AUTH_SWITCH => {
+                    info.auth_plugin = Reader { data: &packet[1..] }.nul_string().ok();
+                    return Ok(());
+                }
+                // more auth data
+                _ => return Ok(()),
+            }
+            self.finish(param.time, output);
+            return Ok(());
+        }
+
+        let deprecate_eof = self.capabilities & CLIENT_DEPRECATE_EOF != 0;
+        let is_terminator = packet[0] == EOF && packet.len() < MAX_PACKET_SIZE;
+        let more_results = match self.response {
+            Response::First => match packet[0] {
+                OK if self.command == COM_STMT_PREPARE => {
+                    // [header u8][statement id u32][columns u16][params u16][filler u8][warnings u16]
+                    let mut r = Reader { data: &packet[1..] };
+                    let id = r.u32_le()?;
+                    let columns = r.u16_le()? as u32;
+                    let params = r.u16_le()? as u32;
+                    self.skip_packets = columns + params;
+                    if !deprecate_eof {
+                        self.skip_packets += (columns > 0) as u32 + (params > 0) as u32;
+                    }
+                    info.statement_id = Some(id);
+                    if self.statements.len() < MAX_PENDING_SESSIONS {
+                        self.statements
+                            .insert(id, info.sql.clone().unwrap_or_default());
+                    }
+                    false
+                }
+                OK => parse_ok(info, packet)? & SERVER_MORE_RESULTS_EXISTS != 0,
+                _ if is_terminator => parse_eof(packet)? & SERVER_MORE_RESULTS_EXISTS != 0,
+                ERR => {
+                    parse_err(info, packet)?;
+                    false
+                }
+                // the client sends the file and the server answers with OK
+                LOCAL_INFILE => return Ok(()),
+                _ if self.command == COM_STATISTICS => false,
+                _ => {
+                    let columns = Reader { data: packet }.lenenc_int()?;
+                    self.response = Response::Columns(columns + !deprecate_eof as u64);
+                    info.row_count.get_or_insert(0);
+                    return Ok(());
+                }
+            },
+            Response::Columns(n) => {
+                self.response = if n > 1 {
+                    Response::Columns(n - 1)
+                } else {
+                    Response::Rows
+                };
+                return Ok(());
+            }
+            Response::Rows => match packet[0] {
+                _ if is_terminator && deprecate_eof => {
+                    parse_ok(info, packet)? & SERVER_MORE_RESULTS_EXISTS != 0
+                }
+                _ if is_terminator => parse_eof(packet)? & SERVER_MORE_RESULTS_EXISTS != 0,
+                ERR => {
+                    parse_err(info, packet)?;
+                    false
+                }
+                _ => {
+                    *info.row_count.get_or_insert(0) += 1;
+                    return Ok(());
+                }
+            },
+        };
+        if more_results {
+            self.response = Response::First;
+        } else {
+            self.finish(param.time, output);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, time: u64, output: &mut Vec<L7ProtocolInfo>) {
+        if let Some(mut info) = self.pending.take() {
+            info.msg_type = LogMessageType::Session;
+            info.rrt = time.saturating_sub(info.time);
+            output.push(L7ProtocolInfo::MysqlInfo(info));
+        }
+        self.response = Response::First;
+    }
+
+    fn expire_pending(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|p| now.saturating_sub(p.time) >= SESSION_TIMEOUT)
+        {
+            output.push(self.pending.take().unwrap().into_timeout());
+            self.response = Response::First;
+            self.skip_packets = 0;
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for MysqlLog {
+    // the server greeting opens a connection, a command packet identifies one seen midway
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if payload.len() <= HEADER_SIZE
+            || read_u32_le(payload) as usize & MAX_PACKET_SIZE != payload.len() - HEADER_SIZE
+            || payload[3] != 0
+        {
+            return false;
+        }
+        let packet = &payload[HEADER_SIZE..];
+        match param.direction {
+            PacketDirection::ServerToClient => {
+                packet[0] == PROTOCOL_VERSION && self.greeting(packet).is_ok()
+            }
+            PacketDirection::ClientToServer => matches!(
+                packet[0],
+                COM_QUERY | COM_STMT_PREPARE | COM_STMT_EXECUTE | COM_INIT_DB
+            ),
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        self.expire_pending(param.time, &mut output);
+        if let Err(e) = self.parse_packets(payload, param, &mut output) {
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::MySQL
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, LogParserConfig};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+        let mut p = (payload.len() as u32).to_le_bytes().to_vec();
+        p[3] = seq;
+        p.extend_from_slice(payload);
+        p
+    }
+
+    fn greeting(capabilities: u32) -> Vec<u8> {
+        [
+            &[PROTOCOL_VERSION][..],
+            b"8.0.36\0",
+            &7u32.to_le_bytes(),
+            b"abcdefgh\0",
+            &(capabilities as u16).to_le_bytes(),
+            &[0xff, 2, 0],
+            &((capabilities >> 16) as u16).to_le_bytes(),
+            &[21],
+            &[0; 10],
+            b"ijklmnopqrst\0",
+            b"caching_sha2_password\0",
+        ]
+        .concat()
+    }
+
+    fn handshake_response(capabilities: u32) -> Vec<u8> {
+        [
+            &capabilities.to_le_bytes()[..],
+            &(1u32 << 24).to_le_bytes(),
+            &[0xff],
+            &[0; 23],
+            b"app\0",
+            &[32],
+            &[0x5a; 32],
+            b"orders\0",
+            b"caching_sha2_password\0",
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn login_and_queries() {
+        let capabilities = CLIENT_CONNECT_WITH_DB
+            | CLIENT_PROTOCOL_41
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH;
+        let config = LogParserConfig {
+            sql_max_length: 24,
+            ..Default::default()
+        };
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = MysqlLog::default();
+        let greeting = packet(0, &greeting(capabilities));
+        assert!(MysqlLog::default().check_payload(&greeting, &at(S2C, 0)));
+        parse(&mut parser, &greeting, &at(S2C, 0));
+        parse(
+            &mut parser,
+            &packet(1, &handshake_response(capabilities)),
+            &at(C2S, 10),
+        );
+        // fast auth success, then OK
+        parse(&mut parser, &packet(2, &[0x01, 0x03]), &at(S2C, 20));
+        let ok = [0, 0, 0, 2, 0, 0, 0];
+        let info = parse(&mut parser, &packet(3, &ok), &at(S2C, 30))
+            .pop()
+            .unwrap();
+        assert_eq!(info.command, "HANDSHAKE");
+        assert_eq!(info.server_version.as_deref(), Some("8.0.36"));
+        assert_eq!(info.auth_plugin.as_deref(), Some("caching_sha2_password"));
+        assert_eq!(info.user.as_deref(), Some("app"));
+        assert_eq!(info.database.as_deref(), Some("orders"));
+        assert_eq!((info.status, info.rrt), (L7ResponseStatus::Ok, 20));
+
+        // a result set of two rows in two segments
+        let query = packet(0, b"\x03SELECT id, total FROM orders WHERE id < 3");
+        assert!(MysqlLog::default().check_payload(&query, &at(C2S, 0)));
+        parse(&mut parser, &query, &at(C2S, 100));
+        let eof = [EOF, 0, 0, 2, 0];
+        let result = [
+            packet(1, &[2]),
+            packet(2, b"\x03def\x06orders\x06orders\x06orders\x02id\x02id"),
+            packet(
+                3,
+                b"\x03def\x06orders\x06orders\x06orders\x05total\x05total",
+            ),
+            packet(4, &eof),
+            packet(5, b"\x011\x0512.50"),
+            packet(6, b"\x012\x0599.00"),
+            packet(7, &eof),
+        ]
+        .concat();
+        let (head, tail) = result.split_at(30);
+        assert!(parse(&mut parser, head, &at(S2C, 150)).is_empty());
+        let info = parse(&mut parser, tail, &at(S2C, 160)).pop().unwrap();
+        assert_eq!(info.command, "COM_QUERY");
+        assert_eq!(info.sql.as_deref(), Some("SELECT id, total FROM or"));
+        assert_eq!((info.row_count, info.affected_rows), (Some(2), None));
+        assert_eq!((info.status, info.rrt), (L7ResponseStatus::Ok, 60));
+
+        // OK with affected rows, then an error
+        parse(
+            &mut parser,
+            &packet(0, b"\x03DELETE FROM carts"),
+            &at(C2S, 200),
+        );
+        let info = parse(
+            &mut parser,
+            &packet(1, &[0, 5, 0, 2, 0, 0, 0]),
+            &at(S2C, 210),
+        );
+        assert_eq!(info[0].affected_rows, Some(5));
+        parse(&mut parser, &packet(0, b"\x03SELEC 1"), &at(C2S, 300));
+        let err = b"\xff\x28\x04#42000You have an error in your SQL syntax";
+        let info = parse(&mut parser, &packet(1, err), &at(S2C, 320))
+            .pop()
+            .unwrap();
+        assert_eq!(
+            (info.error_code, info.sql_state.as_deref()),
+            (Some(1064), Some("42000"))
+        );
+        assert_eq!(
+            info.error_message.as_deref(),
+            Some("You have an error in your SQL syntax")
+        );
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+    }
+
+    #[test]
+    fn query_attributes() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = MysqlLog::default();
+        let capabilities = CLIENT_PROTOCOL_41
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH
+            | CLIENT_QUERY_ATTRIBUTES;
+        parse(
+            &mut parser,
+            &packet(0, &greeting(capabilities)),
+            &at(S2C, 0),
+        );
+        let response = [
+            &handshake_response(capabilities)[..69],
+            b"mysql_native_password\0",
+        ]
+        .concat();
+        parse(&mut parser, &packet(1, &response), &at(C2S, 0));
+        parse(&mut parser, &packet(2, &[0, 0, 0, 2, 0, 0, 0]), &at(S2C, 0));
+
+        let ok = packet(1, &[0, 0, 0, 2, 0, 0, 0]);
+        parse(
+            &mut parser,
+            &packet(0, b"\x03\x00\x01SELECT 1"),
+            &at(C2S, 100),
+        );
+        let info = parse(&mut parser, &ok, &at(S2C, 110)).pop().unwrap();
+        assert_eq!(info.sql.as_deref(), Some("SELECT 1"));
+
+        // a string, a longlong and a null tiny
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let query = [
+            &[COM_QUERY, 3, 1, 0b100, 1][..],
+            &[0xfd, 0, 11],
+            b"traceparent",
+            &[0x08, 0, 6],
+            b"tenant",
+            &[0x01, 0, 4],
+            b"none",
+            &[traceparent.len() as u8],
+            traceparent.as_bytes(),
+            &42u64.to_le_bytes(),
+            b"SELECT 2",
+        ]
+        .concat();
+        parse(&mut parser, &packet(0, &query), &at(C2S, 200));
+        let info = parse(&mut parser, &ok, &at(S2C, 230)).pop().unwrap();
+        assert_eq!(info.sql.as_deref(), Some("SELECT 2"));
+        assert_eq!((info.status, info.rrt), (L7ResponseStatus::Ok, 30));
+    }
+
+    #[test]
+    fn prepared_statements() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = MysqlLog::default();
+        let capabilities = CLIENT_PROTOCOL_41
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH
+            | CLIENT_DEPRECATE_EOF;
+        parse(
+            &mut parser,
+            &packet(0, &greeting(capabilities)),
+            &at(S2C, 0),
+        );
+        let response = [
+            &handshake_response(capabilities)[..69],
+            b"mysql_native_password\0",
+        ]
+        .concat();
+        parse(&mut parser, &packet(1, &response), &at(C2S, 0));
+        parse(&mut parser, &packet(2, &[0, 0, 0, 2, 0, 0, 0]), &at(S2C, 0));
+
+        let sql = "UPDATE stock SET count = count - ? WHERE sku = ?";
+        parse(
+            &mut parser,
+            &packet(0, &[&[COM_STMT_PREPARE], sql.as_bytes()].concat()),
+            &at(C2S, 100),
+        );
+        // no columns, two parameters without EOF
+        let prepare_ok = [
+            packet(1, &[0, 9, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]),
+            packet(2, b"\x03def\0\0\0\x01?\0"),
+            packet(3, b"\x03def\0\0\0\x01?\0"),
+        ]
+        .concat();
+        let info = parse(&mut parser, &prepare_ok, &at(S2C, 120))
+            .pop()
+            .unwrap();
+        assert_eq!(
+            (info.command, info.statement_id),
+            ("COM_STMT_PREPARE", Some(9))
+        );
+        assert_eq!(info.sql.as_deref(), Some(sql));
+
+        let execute = [
+            &[COM_STMT_EXECUTE][..],
+            &9u32.to_le_bytes(),
+            &[0, 1, 0, 0, 0, 0, 1],
+        ]
+        .concat();
+        parse(&mut parser, &packet(0, &execute), &at(C2S, 200));
+        let info = parse(
+            &mut parser,
+            &packet(1, &[0, 1, 0, 2, 0, 0, 0]),
+            &at(S2C, 230),
+        )
+        .pop()
+        .unwrap();
+        assert_eq!(
+            (info.command, info.statement_id),
+            ("COM_STMT_EXECUTE", Some(9))
+        );
+        assert_eq!(
+            (info.sql.as_deref(), info.affected_rows),
+            (Some(sql), Some(1))
+        );
+        assert_eq!(info.rrt, 30);
+
+        // a multi-statement call with a result set terminated by OK, then an OK
+        parse(
+            &mut parser,
+            &packet(0, b"\x03CALL stock_report()"),
+            &at(C2S, 300),
+        );
+        let result = [
+            packet(1, &[1]),
+            packet(2, b"\x03def\0\0\0\x03sku\0"),
+            packet(3, b"\x04A-17"),
+            packet(4, &[EOF, 0, 0, 0x0a, 0, 0, 0]),
+            packet(5, &[0, 0, 0, 2, 0, 0, 0]),
+        ]
+        .concat();
+        let info = parse(&mut parser, &result, &at(S2C, 340)).pop().unwrap();
+        assert_eq!((info.row_count, info.affected_rows), (Some(1), Some(0)));
+
+        let close = [&[COM_STMT_CLOSE][..], &9u32.to_le_bytes()].concat();
+        let info = parse(&mut parser, &packet(0, &close), &at(C2S, 400))
+            .pop()
+            .unwrap();
+        assert_eq!(
+            (info.msg_type, info.sql.as_deref()),
+            (LogMessageType::Other, Some(sql))
+        );
+        assert!(parser.statements.is_empty());
+    }
+}
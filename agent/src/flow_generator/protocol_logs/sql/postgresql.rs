@@ -0,0 +1,668 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+use log::debug;
+use public::bytes::read_u32_be;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{sql_state_status, truncate_sql, MAX_PENDING_SESSIONS};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending_in_order, push_pending, L7ParseResult, L7ProtocolInfo,
+    L7ProtocolParserInterface, L7ResponseStatus, LogMessageType, ParseParam, PerDirection, Reader,
+    Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// startup messages are sent before a type byte is, and are small
+const MAX_STARTUP_SIZE: usize = 10000;
+const PROTOCOL_VERSION_3: u32 = 0x0003_0000;
+const SSL_REQUEST: u32 = 80877103;
+const GSSENC_REQUEST: u32 = 80877104;
+const CANCEL_REQUEST: u32 = 80877102;
+
+// frontend
+const QUERY: u8 = b'Q';
+const PARSE: u8 = b'P';
+const BIND: u8 = b'B';
+const EXECUTE: u8 = b'E';
+const CLOSE: u8 = b'C';
+const FUNCTION_CALL: u8 = b'F';
+// backend
+const PARAMETER_STATUS: u8 = b'S';
+const READY_FOR_QUERY: u8 = b'Z';
+const COMMAND_COMPLETE: u8 = b'C';
+const EMPTY_QUERY_RESPONSE: u8 = b'I';
+const PORTAL_SUSPENDED: u8 = b's';
+const ERROR_RESPONSE: u8 = b'E';
+const FUNCTION_CALL_RESPONSE: u8 = b'V';
+
+fn transaction_state(status: u8) -> Option<&'static str> {
+    match status {
+        b'I' => Some("idle"),
+        b'T' => Some("in_transaction"),
+        b'E' => Some("failed_transaction"),
+        _ => None,
+    }
+}
+
+// the row count of a CommandComplete tag such as "INSERT 0 5" or "SELECT 3"
+fn tag_rows(tag: &str) -> Option<u64> {
+    let mut words = tag.split(' ');
+    match words.next()? {
+        "INSERT" | "DELETE" | "UPDATE" | "MERGE" | "SELECT" | "MOVE" | "FETCH" | "COPY" => {
+            words.next_back()?.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PostgresqlInfo {
+    pub msg_type: LogMessageType,
+    // Startup, Query for the simple protocol, Execute for the extended one, or FunctionCall
+    pub request_type: &'static str,
+    // truncated to sql_max_length, for executions the statement bound to the portal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql: Option<String>,
+    // a named prepared statement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_name: Option<String>,
+    // from the startup message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+
+    // filled in as the response messages arrive
+    // the last one for a simple query of several statements
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // from the ReadyForQuery following the statement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_state: Option<&'static str>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+trait PostgresqlRead {
+    fn string(&mut self) -> Result<String>;
+}
+
+impl PostgresqlRead for Reader<'_> {
+    fn string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.cstring()?).into_owned())
+    }
+}
+
+// [field type u8][value]... terminated by a zero
+fn parse_error(info: &mut PostgresqlInfo, body: &[u8]) -> Result<()> {
+    let mut r = Reader { data: body };
+    loop {
+        match r.u8()? {
+            0 => break,
+            b'C' => info.sql_state = Some(r.string()?),
+            b'M' => info.error_message = Some(r.string()?),
+            _ => {
+                r.cstring()?;
+            }
+        }
+    }
+    info.status = info
+        .sql_state
+        .as_deref()
+        .map_or(L7ResponseStatus::ServerError, sql_state_status);
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Phase {
+    // an SSLRequest or GSSENCRequest waits for a single byte answer
+    EncryptionRequested,
+    #[default]
+    Plain,
+    Encrypted,
+}
+
+#[derive(Default)]
+pub struct PostgresqlLog {
+    streams: PerDirection<Stream>,
+    phase: Phase,
+    // statement text by name, the unnamed statement and portal are named ""
+    statements: HashMap<String, String>,
+    // statement name by portal name
+    portals: HashMap<String, String>,
+    // requests in the order the server answers them
+    pending: VecDeque<PostgresqlInfo>,
+    // answered requests waiting for the transaction state of ReadyForQuery
+    completed: Vec<PostgresqlInfo>,
+}
+
+impl PostgresqlLog {
+    fn parse_messages(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut last_error = None;
+        let mut remain = &data[..];
+        if self.phase == Phase::EncryptionRequested
+            && param.direction == PacketDirection::ServerToClient
+            && !remain.is_empty()
+        {
+            // 'S' or 'G', or 'N' to go on unencrypted
+            self.phase = match remain[0] {
+                b'N' => Phase::Plain,
+                _ => Phase::Encrypted,
+            };
+            remain = &remain[1..];
+        }
+        while self.phase != Phase::Encrypted {
+            // frontend messages before the startup completes have no type
+            let typed = remain.first().is_some_and(|&t| t != 0)
+                || param.direction == PacketDirection::ServerToClient;
+            let offset = typed as usize;
+            if remain.len() < offset + 4 {
+                break;
+            }
+            let message_type = remain[0];
+            let size = read_u32_be(&remain[offset..]) as usize;
+            if size < 4 || !typed && size > MAX_STARTUP_SIZE {
+                return Err(Error::PostgresqlLogParseFailed(format!(
+                    "invalid message length {}",
+                    size
+                )));
+            }
+            let total_size = offset + size;
+            let body = if remain.len() >= total_size {
+                let body = &remain[offset + 4..total_size];
+                remain = &remain[total_size..];
+                body
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[offset + 4..]
+            } else {
+                break;
+            };
+            let result = match (param.direction, typed) {
+                (PacketDirection::ClientToServer, false) => {
+                    self.untyped_message(body, param, output)
+                }
+                (PacketDirection::ClientToServer, true) => {
+                    self.frontend_message(message_type, body, param, output)
+                }
+                (PacketDirection::ServerToClient, _) => {
+                    self.backend_message(message_type, body, param, output)
+                }
+            };
+            if let Err(e) = result {
+                debug!("skip postgresql message: {}", e);
+                last_error = Some(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+        last_error.map_or(Ok(()), Err)
+    }
+
+    // [protocol version u32][name value pairs] or a request code
+    fn untyped_message(
+        &mut self,
+        body: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let mut r = Reader { data: body };
+        match r.u32()? {
+            SSL_REQUEST | GSSENC_REQUEST => self.phase = Phase::EncryptionRequested,
+            PROTOCOL_VERSION_3 => {
+                let mut info = PostgresqlInfo {
+                    msg_type: LogMessageType::Request,
+                    request_type: "Startup",
+                    time: param.time,
+                    ..Default::default()
+                };
+                loop {
+                    let name = r.cstring()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let value = Some(r.string()?);
+                    match name {
+                        b"user" => info.user = value,
+                        b"database" => info.database = value,
+                        b"application_name" => info.application_name = value,
+                        _ => (),
+                    }
+                }
+                push_pending(&mut self.pending, info, output);
+            }
+            // answered by closing the connection
+            CANCEL_REQUEST => (),
+            code => {
+                return Err(Error::PostgresqlLogParseFailed(format!(
+                    "unknown startup code {}",
+                    code
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn frontend_message(
+        &mut self,
+        message_type: u8,
+        body: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let mut r = Reader { data: body };
+        let mut info = PostgresqlInfo {
+            msg_type: LogMessageType::Request,
+            time: param.time,
+            ..Default::default()
+        };
+        match message_type {
+            QUERY => {
+                info.request_type = "Query";
+                info.sql = Some(truncate_sql(r.cstring()?, param.parse_config));
+            }
+            // [statement][query][parameter types]
+            PARSE => {
+                let name = r.string()?;
+                let sql = truncate_sql(r.cstring()?, param.parse_config);
+                if self.statements.len() < MAX_PENDING_SESSIONS || name.is_empty() {
+                    self.statements.insert(name, sql);
+                }
+                return Ok(());
+            }
+            // [portal][statement][parameter formats and values][result formats]
+            BIND => {
+                let portal = r.string()?;
+                let statement = r.string()?;
+                if self.portals.len() < MAX_PENDING_SESSIONS || portal.is_empty() {
+                    self.portals.insert(portal, statement);
+                }
+                return Ok(());
+            }
+            // [portal][max rows u32]
+            EXECUTE => {
+                let portal = r.string()?;
+                let statement = self.portals.get(&portal);
+                info.request_type = "Execute";
+                info.sql = statement.and_then(|s| self.statements.get(s)).cloned();
+                info.statement_name = statement.filter(|s| !s.is_empty()).cloned();
+            }
+            // ['S' or 'P'][name]
+            CLOSE => {
+                let target = r.u8()?;
+                let name = r.string()?;
+                match target {
+                    b'S' => self.statements.remove(&name),
+                    _ => self.portals.remove(&name),
+                };
+                return Ok(());
+            }
+            FUNCTION_CALL => info.request_type = "FunctionCall",
+            // Sync, Flush, Describe, password and copy messages
+            _ => return Ok(()),
+        }
+        push_pending(&mut self.pending, info, output);
+        Ok(())
+    }
+
+    fn backend_message(
+        &mut self,
+        message_type: u8,
+        body: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let mut r = Reader { data: body };
+        if message_type == READY_FOR_QUERY {
+            let state = transaction_state(r.u8()?);
+            // the others were skipped after an error in the extended protocol
+            while let Some(mut info) = self.pending.pop_front() {
+                if info.request_type != "Query" && info.request_type != "Startup" {
+                    info.status = L7ResponseStatus::Unknown;
+                }
+                self.complete(info, param.time);
+            }
+            for mut info in self.completed.drain(..) {
+                info.transaction_state = state;
+                output.push(L7ProtocolInfo::PostgresqlInfo(info));
+            }
+            return Ok(());
+        }
+
+        let Some(info) = self.pending.front_mut() else {
+            return Ok(());
+        };
+        // simple queries and startups end at ReadyForQuery
+        let ends = info.request_type == "Execute" || info.request_type == "FunctionCall";
+        match message_type {
+            // [name][value], sent during the startup and after SET of reported parameters
+            PARAMETER_STATUS => {
+                if r.cstring()? == b"server_version" {
+                    info.server_version = Some(r.string()?);
+                }
+                return Ok(());
+            }
+            // [tag]
+            COMMAND_COMPLETE => {
+                let tag = r.string()?;
+                if let Some(rows) = tag_rows(&tag) {
+                    *info.row_count.get_or_insert(0) += rows;
+                }
+                info.command_tag = Some(tag);
+            }
+            ERROR_RESPONSE => {
+                parse_error(info, body)?;
+                // the rest of the extended protocol batch is skipped up to the Sync
+                if !ends {
+                    return Ok(());
+                }
+            }
+            EMPTY_QUERY_RESPONSE | PORTAL_SUSPENDED | FUNCTION_CALL_RESPONSE => (),
+            _ => return Ok(()),
+        }
+        if ends {
+            let info = self.pending.pop_front().unwrap();
+            self.complete(info, param.time);
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, mut info: PostgresqlInfo, time: u64) {
+        info.msg_type = LogMessageType::Session;
+        info.rrt = time.saturating_sub(info.time);
+        self.completed.push(info);
+    }
+}
+
+impl L7ProtocolParserInterface for PostgresqlLog {
+    // a startup message, or a query of a connection seen midway
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.len() < 8 {
+            return false;
+        }
+        match payload[0] {
+            0 => {
+                read_u32_be(payload) as usize == payload.len()
+                    && matches!(
+                        read_u32_be(&payload[4..]),
+                        PROTOCOL_VERSION_3 | SSL_REQUEST | GSSENC_REQUEST
+                    )
+            }
+            QUERY | PARSE => {
+                let size = read_u32_be(&payload[1..]) as usize;
+                let Some(body) = payload.get(5..1 + size) else {
+                    return false;
+                };
+                let mut r = Reader { data: body };
+                match payload[0] {
+                    QUERY => r.cstring().is_ok() && r.data.is_empty(),
+                    _ => r
+                        .cstring()
+                        .and_then(|_| r.cstring())
+                        .and_then(|_| r.u16())
+                        .is_ok(),
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending_in_order(&mut self.pending, param.time, &mut output);
+        if let Err(e) = self.parse_messages(payload, param, &mut output) {
+            self.streams[param.direction].buffer.clear();
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::PostgreSQL
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn at(direction: PacketDirection, time: u64) -> ParseParam<'static> {
+        ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        }
+    }
+
+    fn message(message_type: u8, body: &[u8]) -> Vec<u8> {
+        [
+            &[message_type][..],
+            &(body.len() as u32 + 4).to_be_bytes(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn startup(params: &[&str]) -> Vec<u8> {
+        let body = [
+            &PROTOCOL_VERSION_3.to_be_bytes()[..],
+            params.join("\0").as_bytes(),
+            b"\0\0",
+        ]
+        .concat();
+        [&(body.len() as u32 + 4).to_be_bytes()[..], &body].concat()
+    }
+
+    #[test]
+    fn startup_and_simple_queries() {
+        let mut parser = PostgresqlLog::default();
+        let ssl_request = [&8u32.to_be_bytes()[..], &SSL_REQUEST.to_be_bytes()].concat();
+        assert!(parser.check_payload(&ssl_request, &at(C2S, 0)));
+        parse(&mut parser, &ssl_request, &at(C2S, 0));
+        let startup = startup(&[
+            "user",
+            "app",
+            "database",
+            "orders",
+            "application_name",
+            "billing",
+        ]);
+        // refused, the startup goes on unencrypted
+        parse(&mut parser, b"N", &at(S2C, 5));
+        parse(&mut parser, &startup, &at(C2S, 10));
+        let ready = message(READY_FOR_QUERY, b"I");
+        let response = [
+            message(b'R', &0u32.to_be_bytes()),
+            message(PARAMETER_STATUS, b"server_version\x0016.2\0"),
+            message(b'K', &[0; 8]),
+            ready.clone(),
+        ]
+        .concat();
+        let info = parse(&mut parser, &response, &at(S2C, 50)).pop().unwrap();
+        assert_eq!(info.request_type, "Startup");
+        assert_eq!(info.user.as_deref(), Some("app"));
+        assert_eq!(info.database.as_deref(), Some("orders"));
+        assert_eq!(info.application_name.as_deref(), Some("billing"));
+        assert_eq!(info.server_version.as_deref(), Some("16.2"));
+        assert_eq!((info.status, info.rrt), (L7ResponseStatus::Ok, 40));
+        assert_eq!(info.transaction_state, Some("idle"));
+
+        let query = message(QUERY, b"BEGIN; SELECT id FROM orders LIMIT 2\0");
+        assert!(PostgresqlLog::default().check_payload(&query, &at(C2S, 0)));
+        parse(&mut parser, &query, &at(C2S, 100));
+        let response = [
+            message(COMMAND_COMPLETE, b"BEGIN\0"),
+            message(b'T', b"\0\x01id\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"),
+            message(b'D', b"\0\x01\0\0\0\x017"),
+            message(b'D', b"\0\x01\0\0\0\x018"),
+            message(COMMAND_COMPLETE, b"SELECT 2\0"),
+        ]
+        .concat();
+        let (head, tail) = response.split_at(20);
+        assert!(parse(&mut parser, head, &at(S2C, 120)).is_empty());
+        assert!(parse(&mut parser, tail, &at(S2C, 130)).is_empty());
+        let info = parse(&mut parser, &message(READY_FOR_QUERY, b"T"), &at(S2C, 140))
+            .pop()
+            .unwrap();
+        assert_eq!(
+            info.sql.as_deref(),
+            Some("BEGIN; SELECT id FROM orders LIMIT 2")
+        );
+        assert_eq!(
+            (info.command_tag.as_deref(), info.row_count),
+            (Some("SELECT 2"), Some(2))
+        );
+        assert_eq!(
+            (info.rrt, info.transaction_state),
+            (40, Some("in_transaction"))
+        );
+
+        parse(
+            &mut parser,
+            &message(QUERY, b"SELECT * FROM missing\0"),
+            &at(C2S, 200),
+        );
+        let error = b"SERROR\0VERROR\0C42P01\0Mrelation \"missing\" does not exist\0P15\0\0";
+        let response = [
+            message(ERROR_RESPONSE, error),
+            message(READY_FOR_QUERY, b"E"),
+        ]
+        .concat();
+        let info = parse(&mut parser, &response, &at(S2C, 210)).pop().unwrap();
+        assert_eq!(info.sql_state.as_deref(), Some("42P01"));
+        assert_eq!(
+            info.error_message.as_deref(),
+            Some("relation \"missing\" does not exist")
+        );
+        assert_eq!(info.status, L7ResponseStatus::ClientError);
+        assert_eq!(info.transaction_state, Some("failed_transaction"));
+
+        // the oldest query is reported once too many wait for their response
+        let queries = (0..=MAX_PENDING_SESSIONS)
+            .map(|i| message(QUERY, format!("SELECT {}\0", i).as_bytes()))
+            .collect::<Vec<_>>()
+            .concat();
+        let infos = parse(&mut parser, &queries, &at(C2S, 300));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].sql.as_deref(), Some("SELECT 0"));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+    }
+
+    #[test]
+    fn extended_protocol() {
+        let mut parser = PostgresqlLog::default();
+        let sql = "UPDATE stock SET count = count - $1 WHERE sku = $2";
+        let batch = [
+            message(
+                PARSE,
+                &[b"take_stock\0", sql.as_bytes(), b"\0\0\0"].concat(),
+            ),
+            message(
+                BIND,
+                b"\0take_stock\0\0\0\0\x02\0\0\0\x011\0\0\0\x03A-7\0\0",
+            ),
+            message(b'D', b"P\0"),
+            message(EXECUTE, b"\0\0\0\0\0"),
+            message(PARSE, b"\0SELECT now()\0\0\0"),
+            message(BIND, b"\0\0\0\0\0\0\0\0"),
+            message(EXECUTE, b"\0\0\0\0\0"),
+            message(b'S', b""),
+        ]
+        .concat();
+        assert!(parser.check_payload(&batch, &at(C2S, 0)));
+        parse(&mut parser, &batch, &at(C2S, 100));
+        let response = [
+            message(b'1', b""),
+            message(b'2', b""),
+            message(b'n', b""),
+            message(COMMAND_COMPLETE, b"UPDATE 3\0"),
+            message(b'1', b""),
+            message(b'2', b""),
+            message(b'D', b"\0\x01\0\0\0\x03now"),
+            message(COMMAND_COMPLETE, b"SELECT 1\0"),
+            message(READY_FOR_QUERY, b"I"),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &response, &at(S2C, 150));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].request_type, "Execute");
+        assert_eq!(infos[0].sql.as_deref(), Some(sql));
+        assert_eq!(infos[0].statement_name.as_deref(), Some("take_stock"));
+        assert_eq!((infos[0].row_count, infos[0].rrt), (Some(3), 50));
+        assert_eq!(infos[1].sql.as_deref(), Some("SELECT now()"));
+        assert_eq!(
+            (infos[1].statement_name.as_deref(), infos[1].row_count),
+            (None, Some(1))
+        );
+        assert_eq!(infos[1].transaction_state, Some("idle"));
+
+        // a failed execution skips the rest of the batch
+        let batch = [
+            message(
+                BIND,
+                b"\0take_stock\0\0\0\0\x02\0\0\0\x011\0\0\0\x03A-8\0\0",
+            ),
+            message(EXECUTE, b"\0\0\0\0\0"),
+            message(
+                BIND,
+                b"\0take_stock\0\0\0\0\x02\0\0\0\x011\0\0\0\x03A-9\0\0",
+            ),
+            message(EXECUTE, b"\0\0\0\0\0"),
+            message(b'S', b""),
+        ]
+        .concat();
+        parse(&mut parser, &batch, &at(C2S, 200));
+        let error = b"SERROR\0C23514\0Mnew row violates check constraint\0\0";
+        let response = [
+            message(b'2', b""),
+            message(ERROR_RESPONSE, error),
+            message(READY_FOR_QUERY, b"I"),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &response, &at(S2C, 220));
+        assert_eq!(infos[0].sql_state.as_deref(), Some("23514"));
+        assert_eq!(infos[0].status, L7ResponseStatus::ClientError);
+        assert_eq!(infos[1].status, L7ResponseStatus::Unknown);
+
+        let close = message(CLOSE, b"Stake_stock\0");
+        parse(&mut parser, &close, &at(C2S, 300));
+        assert!(!parser.statements.contains_key("take_stock"));
+    }
+}
@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+
+use log::debug;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::protocol_logs::{
+    expire_pending_in_order, push_pending, L7ParseResult, L7ProtocolInfo,
+    L7ProtocolParserInterface, L7ResponseStatus, LogMessageType, LogParserConfig, ParseParam,
+    PendingRequest, PerDirection, Stream,
+};
+use crate::flow_generator::{Error, Result};
+
+// type lines and inline commands are short, bulk strings are skipped instead of buffered
+const MAX_LINE_SIZE: usize = 16 * 1024;
+// command arguments kept, enough to find the key of XREAD ... STREAMS key
+const MAX_ARGS: usize = 8;
+const MAX_ARG_SIZE: usize = 1024;
+
+// commands whose first argument is a subcommand
+const CONTAINER_COMMANDS: &[&str] = &[
+    "ACL", "CLIENT", "CLUSTER", "COMMAND", "CONFIG", "DEBUG", "FUNCTION", "LATENCY", "MEMORY",
+    "MODULE", "OBJECT", "PUBSUB", "SCRIPT", "SLOWLOG", "XGROUP", "XINFO",
+];
+// commands without keys, some of them with arguments that must not be logged as keys
+const KEYLESS_COMMANDS: &[&str] = &[
+    "AUTH",
+    "BGREWRITEAOF",
+    "BGSAVE",
+    "DBSIZE",
+    "DISCARD",
+    "ECHO",
+    "EXEC",
+    "FLUSHALL",
+    "FLUSHDB",
+    "HELLO",
+    "INFO",
+    "KEYS",
+    "LASTSAVE",
+    "MONITOR",
+    "MULTI",
+    "PING",
+    "PSUBSCRIBE",
+    "PUBLISH",
+    "PUNSUBSCRIBE",
+    "QUIT",
+    "RANDOMKEY",
+    "READONLY",
+    "READWRITE",
+    "RESET",
+    "ROLE",
+    "SAVE",
+    "SCAN",
+    "SELECT",
+    "SHUTDOWN",
+    "SPUBLISH",
+    "SSUBSCRIBE",
+    "SUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "SWAPDB",
+    "TIME",
+    "UNSUBSCRIBE",
+    "UNWATCH",
+    "WAIT",
+];
+const SUBSCRIBE_COMMANDS: &[&str] = &[
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+];
+
+fn first_key<'a>(command: &str, args: &'a [String]) -> Option<&'a str> {
+    let index = match command {
+        _ if KEYLESS_COMMANDS.contains(&command) => return None,
+        // [script][numkeys][key]...
+        "EVAL" | "EVALSHA" | "EVAL_RO" | "EVALSHA_RO" | "FCALL" | "FCALL_RO" => {
+            if args.get(2).map(String::as_str) == Some("0") {
+                return None;
+            }
+            3
+        }
+        // [operation][destkey]
+        "BITOP" => 2,
+        "XREAD" | "XREADGROUP" => {
+            args.iter()
+                .position(|a| a.eq_ignore_ascii_case("STREAMS"))?
+                + 1
+        }
+        "OBJECT" | "MEMORY" | "XGROUP" | "XINFO" => 2,
+        _ if CONTAINER_COMMANDS.contains(&command) => return None,
+        _ => 1,
+    };
+    args.get(index).map(String::as_str)
+}
+
+// the first segment of a key such as user:42:profile, the rest masked as in user:?:?
+fn obfuscate_key(key: &str) -> String {
+    let mut segments = key.split(':');
+    let first = segments.next().unwrap_or_default();
+    match segments.count() {
+        0 => "?".to_owned(),
+        n => format!("{}{}", first, ":?".repeat(n)),
+    }
+}
+
+fn reply_type(kind: u8, null: bool) -> &'static str {
+    match kind {
+        _ if null => "null",
+        b'+' => "simple_string",
+        b'-' => "error",
+        b':' => "integer",
+        b'$' => "bulk_string",
+        b'*' => "array",
+        b'_' => "null",
+        b',' => "double",
+        b'#' => "boolean",
+        b'!' => "blob_error",
+        b'=' => "verbatim_string",
+        b'(' => "big_number",
+        b'%' => "map",
+        b'~' => "set",
+        b'>' => "push",
+        _ => "unknown",
+    }
+}
+
+// by the error prefix, redirections are for the client to follow
+fn error_status(error: &str) -> L7ResponseStatus {
+    match error.split(' ').next().unwrap_or_default() {
+        "ERR" | "WRONGTYPE" | "NOSCRIPT" | "NOAUTH" | "NOPERM" | "WRONGPASS" | "EXECABORT"
+        | "MOVED" | "ASK" | "NOPROTO" => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct RedisInfo {
+    pub msg_type: LogMessageType,
+    // upper-cased, with the subcommand of container commands as in CLIENT SETNAME
+    pub command: String,
+    // masked when redis_key_obfuscation is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+}
+
+// a decoded command or reply, only what is logged is kept
+#[derive(Debug, Default)]
+struct Message {
+    // the type byte of the top level value, attributes preceding it are skipped
+    kind: u8,
+    null: bool,
+    // bulk strings of a top level array or push
+    args: Vec<String>,
+    error: Option<String>,
+    time: u64,
+}
+
+fn parse_int(value: &[u8]) -> Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| {
+            Error::RedisLogParseFailed(format!(
+                "invalid length {:?}",
+                String::from_utf8_lossy(value)
+            ))
+        })
+}
+
+// decodes the values of one direction, which may be cut anywhere
+#[derive(Default)]
+struct Decoder {
+    // bytes of an incomplete line, or of a bulk string to keep, and bytes
+    // left of a bulk string not kept, with its CRLF
+    stream: Stream,
+    // elements left in each aggregate being decoded, and whether it is an attribute
+    levels: Vec<(i64, bool)>,
+    message: Option<Message>,
+}
+
+impl Decoder {
+    // a value ended, returns the message when it was the top level one
+    fn value_done(&mut self) -> Option<Message> {
+        loop {
+            let Some((left, _)) = self.levels.last_mut() else {
+                return self.message.take();
+            };
+            *left -= 1;
+            if *left > 0 {
+                return None;
+            }
+            // attributes are not elements of their parent
+            if self.levels.pop().is_some_and(|(_, attribute)| attribute) {
+                return None;
+            }
+        }
+    }
+
+    // the size of what is decoded, messages decoded before an error are kept
+    fn decode(
+        &mut self,
+        data: &[u8],
+        is_request: bool,
+        time: u64,
+        messages: &mut Vec<Message>,
+    ) -> Result<usize> {
+        let mut pos = 0;
+        while let Some(line_size) = data[pos..].windows(2).position(|w| w == b"\r\n") {
+            let rest = &data[pos..];
+            let line = &rest[..line_size];
+            let token_size = line_size + 2;
+            if line.is_empty() {
+                pos += token_size;
+                continue;
+            }
+            let top = self.levels.is_empty();
+            let message = self.message.get_or_insert_with(|| Message {
+                time,
+                ..Default::default()
+            });
+            let (kind, value) = (line[0], &line[1..]);
+            if top && kind != b'|' {
+                message.kind = kind;
+            }
+            let done = match kind {
+                // inline commands such as PING typed in telnet
+                _ if top && is_request && kind != b'*' => {
+                    message.args = String::from_utf8_lossy(line)
+                        .split_whitespace()
+                        .take(MAX_ARGS)
+                        .map(str::to_owned)
+                        .collect();
+                    true
+                }
+                b'$' | b'=' | b'!' => {
+                    let Ok(len) = usize::try_from(parse_int(value)?) else {
+                        message.null = top;
+                        pos += token_size;
+                        if let Some(message) = self.value_done() {
+                            messages.push(message);
+                        }
+                        continue;
+                    };
+                    let keep = if top {
+                        kind == b'!'
+                    } else {
+                        self.levels.len() == 1
+                            && matches!(message.kind, b'*' | b'>')
+                            && message.args.len() < MAX_ARGS
+                    };
+                    if keep && len <= MAX_ARG_SIZE {
+                        let Some(body) = rest.get(token_size..token_size + len + 2) else {
+                            break;
+                        };
+                        let body = String::from_utf8_lossy(&body[..len]).into_owned();
+                        if top {
+                            message.error = Some(body);
+                        } else {
+                            message.args.push(body);
+                        }
+                    }
+                    let size = token_size + len + 2;
+                    if rest.len() < size {
+                        self.stream.skip = size - rest.len();
+                        pos = data.len();
+                        break;
+                    }
+                    pos += size - token_size;
+                    true
+                }
+                b'*' | b'~' | b'>' | b'%' | b'|' => {
+                    let n = parse_int(value)?;
+                    let n = if kind == b'%' || kind == b'|' {
+                        n.checked_mul(2).ok_or_else(|| {
+                            Error::RedisLogParseFailed(format!("invalid length {}", n))
+                        })?
+                    } else {
+                        n
+                    };
+                    message.null = top && n < 0;
+                    if n > 0 {
+                        self.levels.push((n, kind == b'|'));
+                    }
+                    n <= 0 && kind != b'|'
+                }
+                b'-' => {
+                    if top {
+                        message.error = Some(String::from_utf8_lossy(value).into_owned());
+                    }
+                    true
+                }
+                b'_' | b'+' | b':' | b',' | b'#' | b'(' => true,
+                _ => {
+                    return Err(Error::RedisLogParseFailed(format!(
+                        "invalid type {:#x}",
+                        kind
+                    )))
+                }
+            };
+            pos += token_size;
+            if done {
+                if let Some(message) = self.value_done() {
+                    messages.push(message);
+                }
+            }
+        }
+        if data.len() - pos > MAX_LINE_SIZE {
+            return Err(Error::RedisLogParseFailed("line too long".to_owned()));
+        }
+        Ok(pos)
+    }
+}
+
+#[derive(Default)]
+pub struct RedisLog {
+    decoders: PerDirection<Decoder>,
+    // commands are answered in order
+    pending: VecDeque<RedisInfo>,
+    // RESP2 pub/sub messages are arrays pushed at any time
+    subscribed: bool,
+}
+
+impl RedisLog {
+    fn parse_messages(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        messages: &mut Vec<Message>,
+    ) -> Result<()> {
+        let is_request = param.direction == PacketDirection::ClientToServer;
+        let decoder = &mut self.decoders[param.direction];
+        let skipping = decoder.stream.skip > 0;
+        let data = decoder.stream.data(payload);
+        if skipping && decoder.stream.skip == 0 {
+            messages.extend(decoder.value_done());
+        }
+        match decoder.decode(&data, is_request, param.time, messages) {
+            Ok(consumed) => decoder.stream.buffer = data[consumed..].to_vec(),
+            Err(e) => {
+                // the rest of the segment can not be decoded, keep what is
+                *decoder = Decoder::default();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_command(
+        &mut self,
+        message: Message,
+        config: Option<&LogParserConfig>,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) {
+        let Some(name) = message.args.first() else {
+            return;
+        };
+        let mut command = name.to_ascii_uppercase();
+        let key = first_key(&command, &message.args).map(|k| match config {
+            Some(c) if c.redis_key_obfuscation => obfuscate_key(k),
+            _ => k.to_owned(),
+        });
+        if CONTAINER_COMMANDS.contains(&command.as_str()) {
+            if let Some(subcommand) = message.args.get(1) {
+                command = format!("{} {}", command, subcommand.to_ascii_uppercase());
+            }
+        }
+        if SUBSCRIBE_COMMANDS.contains(&command.as_str()) {
+            self.subscribed = true;
+        }
+        let info = RedisInfo {
+            msg_type: LogMessageType::Request,
+            command,
+            key,
+            time: message.time,
+            ..Default::default()
+        };
+        push_pending(&mut self.pending, info, output);
+    }
+
+    fn handle_reply(&mut self, message: Message, output: &mut Vec<L7ProtocolInfo>) {
+        // pub/sub messages are RESP3 pushes, or arrays once subscribed in RESP2
+        let first = message.args.first().map(|a| a.to_ascii_uppercase());
+        let pushed = match first.as_deref() {
+            _ if message.kind != b'>' && !(self.subscribed && message.kind == b'*') => false,
+            // confirmations are sent once per channel, the first one answers the command
+            Some(c) if SUBSCRIBE_COMMANDS.contains(&c) => {
+                self.pending.front().is_none_or(|p| p.command != c)
+            }
+            Some("MESSAGE" | "PMESSAGE" | "SMESSAGE") => true,
+            // in RESP2 a PING while subscribed is answered with an array too
+            _ => message.kind == b'>',
+        };
+        // replies without a command are pushes too, from MONITOR or a missed SUBSCRIBE
+        if pushed {
+            return;
+        }
+        let Some(mut info) = self.pending.pop_front() else {
+            return;
+        };
+        info.msg_type = LogMessageType::Session;
+        info.rrt = message.time.saturating_sub(info.time);
+        info.reply_type = Some(reply_type(message.kind, message.null));
+        info.status = message
+            .error
+            .as_deref()
+            .map_or(L7ResponseStatus::Ok, error_status);
+        info.error = message.error;
+        output.push(L7ProtocolInfo::RedisInfo(info));
+    }
+}
+
+impl L7ProtocolParserInterface for RedisLog {
+    // *<n>\r\n$<m>\r\n<command>
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.first() != Some(&b'*') {
+            return false;
+        }
+        let mut decoder = Decoder::default();
+        let mut messages = vec![];
+        if decoder.decode(payload, true, 0, &mut messages).is_err() {
+            return false;
+        }
+        // the first command may be cut in a large bulk string
+        messages
+            .first()
+            .or(decoder.message.as_ref())
+            .and_then(|m| m.args.first())
+            .is_some_and(|c| !c.is_empty() && c.bytes().all(|b| b.is_ascii_alphabetic()))
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        expire_pending_in_order(&mut self.pending, param.time, &mut output);
+        let mut messages = vec![];
+        let result = self.parse_messages(payload, param, &mut messages);
+        for message in messages {
+            match param.direction {
+                PacketDirection::ClientToServer => {
+                    self.handle_command(message, param.parse_config, &mut output)
+                }
+                PacketDirection::ServerToClient => self.handle_reply(message, &mut output),
+            }
+        }
+        if let Err(e) = result {
+            debug!("drop redis segment: {}", e);
+            // the replies dropped with the segment can not be told apart from later ones
+            if param.direction == PacketDirection::ServerToClient {
+                output.extend(self.pending.drain(..).map(PendingRequest::into_timeout));
+            }
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::Redis
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::{test_util::parse, MAX_PENDING_SESSIONS};
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn command(args: &[&str]) -> Vec<u8> {
+        let mut c = format!("*{}\r\n", args.len());
+        for arg in args {
+            c.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        c.into_bytes()
+    }
+
+    #[test]
+    fn pipelined_commands() {
+        let config = LogParserConfig::default();
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = RedisLog::default();
+        let big = "x".repeat(100_000);
+        let pipeline = [
+            command(&["SET", "session:8fa2", &big, "EX", "60"]),
+            command(&["get", "session:8fa2"]),
+            command(&["CLIENT", "SETNAME", "cache-1"]),
+            command(&["LPUSH", "queue", "a"]),
+            command(&[
+                "EVALSHA",
+                "e0e1f9fabfc9d4800c877a703b823ac0578ff831",
+                "1",
+                "lock:7",
+                "30",
+            ]),
+            command(&["XREAD", "COUNT", "2", "STREAMS", "events", "0"]),
+            b"PING\r\n".to_vec(),
+        ]
+        .concat();
+        assert!(parser.check_payload(&pipeline, &at(C2S, 0)));
+        for (i, segment) in pipeline.chunks(1460).enumerate() {
+            assert!(parse(&mut parser, segment, &at(C2S, i as u64)).is_empty());
+        }
+        assert_eq!(parser.pending.len(), 7);
+
+        let replies = [
+            &b"+OK\r\n"[..],
+            &format!("${}\r\n{}\r\n", big.len(), big).into_bytes(),
+            b"+OK\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"_\r\n",
+            b"*1\r\n*2\r\n$6\r\nevents\r\n*0\r\n",
+            b"+PONG\r\n",
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in replies.chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 500)));
+        }
+        let summary = infos
+            .iter()
+            .map(|i| (i.command.as_str(), i.key.as_deref(), i.reply_type.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("SET", Some("session:8fa2"), "simple_string"),
+                ("GET", Some("session:8fa2"), "bulk_string"),
+                ("CLIENT SETNAME", None, "simple_string"),
+                ("LPUSH", Some("queue"), "error"),
+                ("EVALSHA", Some("lock:7"), "null"),
+                ("XREAD", Some("events"), "array"),
+                ("PING", None, "simple_string"),
+            ]
+        );
+        assert_eq!(infos[0].rrt, 500);
+        assert_eq!(infos[3].status, L7ResponseStatus::ClientError);
+        assert_eq!(
+            infos[3].error.as_deref(),
+            Some("WRONGTYPE Operation against a key holding the wrong kind of value")
+        );
+        assert!(parser.pending.is_empty());
+    }
+
+    #[test]
+    fn resp3_and_pubsub() {
+        let config = LogParserConfig {
+            redis_key_obfuscation: true,
+            ..Default::default()
+        };
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: Some(&config),
+        };
+        let mut parser = RedisLog::default();
+        let requests = [
+            command(&["HELLO", "3", "AUTH", "default", "secret"]),
+            command(&["HGETALL", "user:42:profile"]),
+            command(&["SUBSCRIBE", "orders", "payments"]),
+            command(&["PING"]),
+        ]
+        .concat();
+        parse(&mut parser, &requests, &at(C2S, 0));
+        let replies = [
+            &b"%2\r\n+server\r\n+redis\r\n+proto\r\n:3\r\n"[..],
+            // attributes are not replies
+            b"|1\r\n+ttl\r\n:60\r\n%1\r\n$4\r\nname\r\n$3\r\nann\r\n",
+            b">3\r\n$9\r\nsubscribe\r\n$6\r\norders\r\n:1\r\n",
+            b">3\r\n$9\r\nsubscribe\r\n$8\r\npayments\r\n:2\r\n",
+            b">3\r\n$7\r\nmessage\r\n$6\r\norders\r\n$2\r\n17\r\n",
+            b"!10\r\nERR failed\r\n",
+        ]
+        .concat();
+        let infos = parse(&mut parser, &replies, &at(S2C, 10));
+        let summary = infos
+            .iter()
+            .map(|i| (i.command.as_str(), i.key.as_deref(), i.reply_type.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("HELLO", None, "map"),
+                ("HGETALL", Some("user:?:?"), "map"),
+                ("SUBSCRIBE", None, "push"),
+                ("PING", None, "blob_error"),
+            ]
+        );
+        assert_eq!(infos[3].error.as_deref(), Some("ERR failed"));
+        assert_eq!(infos[3].status, L7ResponseStatus::ClientError);
+
+        // in RESP2 the confirmations and messages are arrays
+        let mut parser = RedisLog::default();
+        let requests = [
+            command(&["SUBSCRIBE", "orders", "payments"]),
+            command(&["PING"]),
+        ]
+        .concat();
+        parse(&mut parser, &requests, &at(C2S, 0));
+        let replies = [
+            &b"*3\r\n$9\r\nsubscribe\r\n$6\r\norders\r\n:1\r\n"[..],
+            b"*3\r\n$9\r\nsubscribe\r\n$8\r\npayments\r\n:2\r\n",
+            b"*3\r\n$7\r\nmessage\r\n$6\r\norders\r\n$2\r\n17\r\n",
+            b"*2\r\n$4\r\npong\r\n$0\r\n\r\n",
+        ]
+        .concat();
+        let infos = parse(&mut parser, &replies, &at(S2C, 20));
+        assert_eq!(infos.len(), 2);
+        assert_eq!(
+            (infos[0].command.as_str(), infos[0].reply_type),
+            ("SUBSCRIBE", Some("array"))
+        );
+        assert_eq!(
+            (infos[1].command.as_str(), infos[1].reply_type),
+            ("PING", Some("array"))
+        );
+    }
+
+    #[test]
+    fn invalid_replies() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = RedisLog::default();
+        let requests = [
+            command(&["GET", "a"]),
+            command(&["GET", "b"]),
+            command(&["GET", "c"]),
+        ]
+        .concat();
+        assert!(parse(&mut parser, &requests, &at(C2S, 0)).is_empty());
+        // the replies after an invalid type are lost, so are the commands waiting for them
+        let infos = parse(&mut parser, b"$1\r\n1\r\n?\r\n$1\r\n3\r\n", &at(S2C, 10));
+        let summary = infos
+            .iter()
+            .map(|i| (i.key.as_deref(), i.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("a"), L7ResponseStatus::Ok),
+                (Some("b"), L7ResponseStatus::Timeout),
+                (Some("c"), L7ResponseStatus::Timeout),
+            ]
+        );
+        let result = parser.parse_payload(b"%9223372036854775807\r\n", &at(S2C, 20));
+        assert!(matches!(result, Err(Error::RedisLogParseFailed(_))));
+
+        // the oldest command is reported once too many wait for their reply
+        let pings = command(&["PING"]).repeat(MAX_PENDING_SESSIONS + 1);
+        let infos = parse(&mut parser, &pings, &at(C2S, 30));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(
+            (infos[0].command.as_str(), infos[0].time, infos[0].status),
+            ("PING", 30, L7ResponseStatus::Timeout)
+        );
+    }
+}
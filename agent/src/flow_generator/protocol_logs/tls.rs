@@ -0,0 +1,896 @@
+use std::mem;
+
+use log::debug;
+use public::bytes::read_u16_be;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+use x509_parser::parse_x509_certificate;
+
+use super::{
+    L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus, LogMessageType,
+    ParseParam, PerDirection, Reader, Stream, SESSION_TIMEOUT,
+};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::{Error, Result};
+
+const RECORD_HEADER_SIZE: usize = 5;
+// 2^14 of plaintext, plus what encryption may add
+const MAX_RECORD_SIZE: usize = (1 << 14) + 2048;
+// handshake messages may span records, certificate chains being the largest
+const MAX_HANDSHAKE_SIZE: usize = 64 * 1024;
+const MAX_NAME_SIZE: usize = 255;
+// protocols offered by the client that are kept
+const MAX_ALPN_PROTOCOLS: usize = 8;
+
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+const HEARTBEAT: u8 = 24;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_ALPN: u16 = 16;
+const EXT_PRE_SHARED_KEY: u16 = 41;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+
+const SSL_3_0: u16 = 0x0300;
+const TLS_1_0: u16 = 0x0301;
+const TLS_1_2: u16 = 0x0303;
+const TLS_1_3: u16 = 0x0304;
+
+const ALERT_FATAL: u8 = 2;
+
+// a ServerHello with this random is a HelloRetryRequest of TLS 1.3
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+fn version_name(version: u16) -> &'static str {
+    match version {
+        SSL_3_0 => "SSLv3",
+        TLS_1_0 => "TLSv1.0",
+        0x0302 => "TLSv1.1",
+        TLS_1_2 => "TLSv1.2",
+        TLS_1_3 => "TLSv1.3",
+        _ => "unknown",
+    }
+}
+
+// reserved values clients put in lists to keep servers tolerant, as in 0x0a0a
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn cipher_suite_name(suite: u16) -> Option<&'static str> {
+    let name = match suite {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xc009 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+        0xc00a => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0xc023 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+        0xc024 => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384",
+        0xc027 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+        0xc028 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384",
+        0x009e => "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256",
+        0x009f => "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384",
+        0x0033 => "TLS_DHE_RSA_WITH_AES_128_CBC_SHA",
+        0x0039 => "TLS_DHE_RSA_WITH_AES_256_CBC_SHA",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x003c => "TLS_RSA_WITH_AES_128_CBC_SHA256",
+        0x003d => "TLS_RSA_WITH_AES_256_CBC_SHA256",
+        0x000a => "TLS_RSA_WITH_3DES_EDE_CBC_SHA",
+        0x0005 => "TLS_RSA_WITH_RC4_128_SHA",
+        0x0004 => "TLS_RSA_WITH_RC4_128_MD5",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn alert_name(description: u8) -> &'static str {
+    match description {
+        0 => "close_notify",
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        22 => "record_overflow",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        49 => "access_denied",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        71 => "insufficient_security",
+        80 => "internal_error",
+        86 => "inappropriate_fallback",
+        90 => "user_canceled",
+        100 => "no_renegotiation",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        113 => "bad_certificate_status_response",
+        115 => "unknown_psk_identity",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => "unknown",
+    }
+}
+
+// an alert is raised by the side rejecting what its peer sent
+fn alert_status(description: u8, direction: PacketDirection) -> L7ResponseStatus {
+    match (description, direction) {
+        // internal_error
+        (80, PacketDirection::ServerToClient) => L7ResponseStatus::ServerError,
+        (_, PacketDirection::ClientToServer) => L7ResponseStatus::ServerError,
+        _ => L7ResponseStatus::ClientError,
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct TlsInfo {
+    pub msg_type: LogMessageType,
+    // the highest version offered by the client
+    pub client_version: &'static str,
+    // SNI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alpn_offered: Vec<String>,
+
+    // filled in from the ServerHello
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher_suite: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher_suite_name: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    // an earlier session is resumed, without certificates sent
+    pub resumed: bool,
+    // of the server certificate, only sent in clear up to TLS 1.2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_issuer: Option<String>,
+    // unit: seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_not_before: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_not_after: Option<i64>,
+    // days left before the certificate expires at the time of the handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_days_left: Option<i64>,
+    // a fatal alert in clear ending the handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<&'static str>,
+    pub status: L7ResponseStatus,
+    // from the ClientHello to the end of the handshake, unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    #[serde(skip)]
+    negotiated: Option<u16>,
+}
+
+impl TlsInfo {
+    fn client_hello(body: &[u8]) -> Result<Self> {
+        let mut r = Reader { data: body };
+        let legacy_version = r.u16()?;
+        r.take(32)?;
+        let session_id = r.u8()? as usize;
+        r.take(session_id)?;
+        let suites = r.u16()? as usize;
+        r.take(suites)?;
+        let compressions = r.u8()? as usize;
+        r.take(compressions)?;
+
+        let mut info = TlsInfo {
+            msg_type: LogMessageType::Request,
+            client_version: version_name(legacy_version),
+            ..Default::default()
+        };
+        // extensions are absent from the oldest hellos
+        if r.data.is_empty() {
+            return Ok(info);
+        }
+        let size = r.u16()? as usize;
+        let mut extensions = Reader {
+            data: r.take(size)?,
+        };
+        while !extensions.data.is_empty() {
+            let kind = extensions.u16()?;
+            let size = extensions.u16()? as usize;
+            let mut ext = Reader {
+                data: extensions.take(size)?,
+            };
+            match kind {
+                EXT_SERVER_NAME => {
+                    let size = ext.u16()? as usize;
+                    let mut names = Reader {
+                        data: ext.take(size)?,
+                    };
+                    while !names.data.is_empty() {
+                        let name_type = names.u8()?;
+                        let size = names.u16()? as usize;
+                        let name = names.take(size)?;
+                        // host_name
+                        if name_type == 0 && size <= MAX_NAME_SIZE {
+                            info.server_name = Some(String::from_utf8_lossy(name).into_owned());
+                            break;
+                        }
+                    }
+                }
+                EXT_ALPN => {
+                    let size = ext.u16()? as usize;
+                    let mut protocols = Reader {
+                        data: ext.take(size)?,
+                    };
+                    while !protocols.data.is_empty() && info.alpn_offered.len() < MAX_ALPN_PROTOCOLS
+                    {
+                        let size = protocols.u8()? as usize;
+                        let protocol = protocols.take(size)?;
+                        info.alpn_offered
+                            .push(String::from_utf8_lossy(protocol).into_owned());
+                    }
+                }
+                EXT_SUPPORTED_VERSIONS => {
+                    let size = ext.u8()? as usize;
+                    let mut versions = Reader {
+                        data: ext.take(size)?,
+                    };
+                    let mut highest = None;
+                    while !versions.data.is_empty() {
+                        let version = versions.u16()?;
+                        if !is_grease(version) && highest.is_none_or(|h| version > h) {
+                            highest = Some(version);
+                        }
+                    }
+                    if let Some(version) = highest {
+                        info.client_version = version_name(version);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(info)
+    }
+
+    // false for a HelloRetryRequest, the client sending another ClientHello
+    fn server_hello(&mut self, body: &[u8], client_session_id: &[u8]) -> Result<bool> {
+        let mut r = Reader { data: body };
+        let mut version = r.u16()?;
+        if r.take(32)? == HELLO_RETRY_REQUEST {
+            return Ok(false);
+        }
+        let size = r.u8()? as usize;
+        let session_id = r.take(size)?;
+        let suite = r.u16()?;
+        r.u8()?;
+        let mut resumed = false;
+        if !r.data.is_empty() {
+            let size = r.u16()? as usize;
+            let mut extensions = Reader {
+                data: r.take(size)?,
+            };
+            while !extensions.data.is_empty() {
+                let kind = extensions.u16()?;
+                let size = extensions.u16()? as usize;
+                let mut ext = Reader {
+                    data: extensions.take(size)?,
+                };
+                match kind {
+                    EXT_ALPN => {
+                        ext.u16()?;
+                        let size = ext.u8()? as usize;
+                        let protocol = ext.take(size)?;
+                        self.alpn = Some(String::from_utf8_lossy(protocol).into_owned());
+                    }
+                    EXT_SUPPORTED_VERSIONS => version = ext.u16()?,
+                    // TLS 1.3 resumes with a psk, its session id being a dummy one
+                    EXT_PRE_SHARED_KEY => resumed = true,
+                    _ => (),
+                }
+            }
+        }
+        // up to TLS 1.2 the server echoes the session id of the client to resume it
+        if version != TLS_1_3 {
+            resumed = !session_id.is_empty() && session_id == client_session_id;
+        }
+        self.negotiated = Some(version);
+        self.version = Some(version_name(version));
+        self.cipher_suite = Some(suite);
+        self.cipher_suite_name = cipher_suite_name(suite);
+        self.resumed = resumed;
+        Ok(true)
+    }
+
+    // the chain starts with the certificate of the server
+    fn certificate(&mut self, body: &[u8]) -> Result<()> {
+        let mut r = Reader { data: body };
+        r.u24()?;
+        let size = r.u24()?;
+        let der = r.take(size)?;
+        let (_, cert) = parse_x509_certificate(der)
+            .map_err(|e| Error::TlsLogParseFailed(format!("invalid certificate: {}", e)))?;
+        let validity = cert.validity();
+        let not_after = validity.not_after.timestamp();
+        self.cert_subject = Some(cert.subject().to_string());
+        self.cert_issuer = Some(cert.issuer().to_string());
+        self.cert_not_before = Some(validity.not_before.timestamp());
+        self.cert_not_after = Some(not_after);
+        self.cert_days_left = Some((not_after - (self.time / 1_000_000) as i64).div_euclid(86400));
+        Ok(())
+    }
+}
+
+trait TlsRead {
+    fn u24(&mut self) -> Result<usize>;
+}
+
+impl TlsRead for Reader<'_> {
+    fn u24(&mut self) -> Result<usize> {
+        let b = self.take(3)?;
+        Ok((b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+}
+
+#[derive(Default)]
+struct Direction {
+    records: Stream,
+    // handshake messages spanning records, one too large to be buffered is skipped
+    handshake: Stream,
+    // records after a ChangeCipherSpec are encrypted
+    encrypted: bool,
+}
+
+#[derive(Default)]
+pub struct TlsLog {
+    directions: PerDirection<Direction>,
+    // the handshake in progress, from its ClientHello
+    handshake: Option<TlsInfo>,
+    // compared with the one echoed by the server on resumption
+    client_session_id: Vec<u8>,
+    server_hello_seen: bool,
+    // nothing is left in clear once the handshake is logged
+    done: bool,
+}
+
+impl TlsLog {
+    fn parse_records(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.directions[direction].records.data(payload);
+
+        let mut remain = &data[..];
+        while remain.len() >= RECORD_HEADER_SIZE && !self.done {
+            let content_type = remain[0];
+            let version = read_u16_be(&remain[1..]);
+            let size = read_u16_be(&remain[3..]) as usize;
+            if !(CHANGE_CIPHER_SPEC..=HEARTBEAT).contains(&content_type)
+                || version >> 8 != 3
+                || size > MAX_RECORD_SIZE
+            {
+                return Err(Error::TlsLogParseFailed(format!(
+                    "invalid record type {} version {:#06x} size {}",
+                    content_type, version, size
+                )));
+            }
+            let total_size = RECORD_HEADER_SIZE + size;
+            let fragment = if remain.len() >= total_size {
+                let fragment = &remain[RECORD_HEADER_SIZE..total_size];
+                remain = &remain[total_size..];
+                fragment
+            } else if content_type == HANDSHAKE && !self.directions[direction].encrypted {
+                break;
+            } else {
+                // only the type of other records is needed
+                self.directions[direction].records.skip = total_size - remain.len();
+                &mem::take(&mut remain)[RECORD_HEADER_SIZE..]
+            };
+            self.handle_record(content_type, fragment, param, output)?;
+        }
+        if !remain.is_empty() && !self.done {
+            self.directions[direction].records.buffer = remain.to_vec();
+        }
+        Ok(())
+    }
+
+    fn handle_record(
+        &mut self,
+        content_type: u8,
+        fragment: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        match content_type {
+            // TLS 1.3 only sends it for middleboxes, before a HelloRetryRequest is answered too
+            CHANGE_CIPHER_SPEC if self.server_hello_seen && self.negotiated() != Some(TLS_1_3) => {
+                self.directions[direction].encrypted = true;
+                // each side sends its Finished right after
+                if self.directions[direction.reversed()].encrypted {
+                    self.finish(param.time, L7ResponseStatus::Ok, output);
+                }
+            }
+            ALERT
+                if !self.directions[direction].encrypted
+                    && fragment.len() >= 2
+                    && fragment[0] == ALERT_FATAL =>
+            {
+                let description = fragment[1];
+                if let Some(info) = self.handshake.as_mut() {
+                    info.alert = Some(alert_name(description));
+                    self.finish(
+                        param.time,
+                        alert_status(description, param.direction),
+                        output,
+                    );
+                }
+            }
+            HANDSHAKE if !self.directions[direction].encrypted => {
+                self.handle_handshake(fragment, param)?
+            }
+            // the Finished of the client in TLS 1.3 is the first record it encrypts
+            APPLICATION_DATA
+                if param.direction == PacketDirection::ClientToServer
+                    && self.server_hello_seen
+                    && self.negotiated() == Some(TLS_1_3) =>
+            {
+                self.finish(param.time, L7ResponseStatus::Ok, output);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_handshake(&mut self, fragment: &[u8], param: &ParseParam) -> Result<()> {
+        let data = self.directions[param.direction].handshake.data(fragment);
+
+        let mut remain = &data[..];
+        while remain.len() >= 4 {
+            let kind = remain[0];
+            let size = Reader { data: &remain[1..] }.u24()?;
+            let total_size = 4 + size;
+            if remain.len() < total_size {
+                if total_size > MAX_HANDSHAKE_SIZE {
+                    debug!("skip tls handshake message {} of {} bytes", kind, size);
+                    self.directions[param.direction].handshake.skip = total_size - remain.len();
+                    remain = &[];
+                }
+                break;
+            }
+            let body = &remain[4..total_size];
+            remain = &remain[total_size..];
+            if let Err(e) = self.handle_message(kind, body, param) {
+                debug!("skip tls handshake message {}: {}", kind, e);
+            }
+        }
+        self.directions[param.direction].handshake.buffer = remain.to_vec();
+        Ok(())
+    }
+
+    fn handle_message(&mut self, kind: u8, body: &[u8], param: &ParseParam) -> Result<()> {
+        match (kind, param.direction) {
+            (CLIENT_HELLO, PacketDirection::ClientToServer) => {
+                let mut info = TlsInfo::client_hello(body)?;
+                // the one sent again after a HelloRetryRequest starts no new handshake
+                if let Some(first) = self.handshake.as_ref() {
+                    info.time = first.time;
+                } else {
+                    info.time = param.time;
+                }
+                let size = body.get(34).copied().unwrap_or_default() as usize;
+                self.client_session_id = body.get(35..35 + size).unwrap_or_default().to_vec();
+                self.handshake = Some(info);
+            }
+            (SERVER_HELLO, PacketDirection::ServerToClient) => {
+                let Some(info) = self.handshake.as_mut() else {
+                    return Ok(());
+                };
+                if info.server_hello(body, &self.client_session_id)? {
+                    self.server_hello_seen = true;
+                    // the rest of the flight of the server is encrypted
+                    if info.negotiated == Some(TLS_1_3) {
+                        self.directions[param.direction].encrypted = true;
+                    }
+                }
+            }
+            (CERTIFICATE, PacketDirection::ServerToClient) => {
+                if let Some(info) = self.handshake.as_mut() {
+                    info.certificate(body)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn negotiated(&self) -> Option<u16> {
+        self.handshake.as_ref().and_then(|h| h.negotiated)
+    }
+
+    fn finish(&mut self, now: u64, status: L7ResponseStatus, output: &mut Vec<L7ProtocolInfo>) {
+        if let Some(mut info) = self.handshake.take() {
+            info.msg_type = LogMessageType::Session;
+            info.rrt = now.saturating_sub(info.time);
+            info.status = status;
+            output.push(L7ProtocolInfo::TlsInfo(info));
+        }
+        self.done = true;
+        self.directions = Default::default();
+    }
+
+    fn expire_handshake(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        if self
+            .handshake
+            .as_ref()
+            .is_some_and(|h| now.saturating_sub(h.time) >= SESSION_TIMEOUT)
+        {
+            let mut info = self.handshake.take().unwrap();
+            info.status = L7ResponseStatus::Timeout;
+            output.push(L7ProtocolInfo::TlsInfo(info));
+            self.done = true;
+            self.directions = Default::default();
+        }
+    }
+}
+
+impl L7ProtocolParserInterface for TlsLog {
+    // a ClientHello in a handshake record
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer
+            || payload.len() < RECORD_HEADER_SIZE + 4
+            || payload[0] != HANDSHAKE
+            || !(TLS_1_0..=TLS_1_3).contains(&read_u16_be(&payload[1..]))
+            || payload[RECORD_HEADER_SIZE] != CLIENT_HELLO
+        {
+            return false;
+        }
+        let size = read_u16_be(&payload[3..]) as usize;
+        // a handshake message header at least
+        if size < 4 {
+            return false;
+        }
+        let fragment = &payload[RECORD_HEADER_SIZE..payload.len().min(RECORD_HEADER_SIZE + size)];
+        let message_size = Reader {
+            data: &fragment[1..],
+        }
+        .u24()
+        .unwrap_or_default();
+        // a ClientHello spanning records is only checked up to its cipher suites
+        let body = &fragment[4..fragment.len().min(4 + message_size)];
+        match TlsInfo::client_hello(body) {
+            Ok(_) => true,
+            Err(Error::InsufficientPayloadLength) => body.len() > 2 + 32 + 1,
+            Err(_) => false,
+        }
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        self.expire_handshake(param.time, &mut output);
+        if self.done {
+            return Ok(output.into());
+        }
+        if let Err(e) = self.parse_records(payload, param, &mut output) {
+            debug!("reset tls stream: {}", e);
+            self.directions[param.direction] = Direction::default();
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::TLS
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    // self-signed, O=Example, CN=example.com, valid from 2026-10-18T13:35:13Z for 30 days
+    const CERTIFICATE_DER: &str = "MIIBpjCCAUugAwIBAgIUGE58QB+VxNx65CSjS1eOXFCvVjEwCgYIKoZIzj0EAwIwKDEQMA4GA1UECgwHRXhhbXBsZTEUMBIGA1UEAwwLZXhhbXBsZS5jb20wHhcNMjYxMDE4MTMzNTEzWhcNMjYxMTE3MTMzNTEzWjAoMRAwDgYDVQQKDAdFeGFtcGxlMRQwEgYDVQQDDAtleGFtcGxlLmNvbTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABGMrmUjd2RkLv4R6QMnfxkTtWDHFNfgKPGqsiPJ9L8lmSw1ThoYCJOoCH4y4Y5D8LL9mSQ0kbHLgdjmhcGRGpsCjUzBRMB0GA1UdDgQWBBQSL5ptCIQufEmG7KjxhUeC3qm1JjAfBgNVHSMEGDAWgBQSL5ptCIQufEmG7KjxhUeC3qm1JjAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0kAMEYCIQD8NTK1W4L+9Hjo0zdi90spseEcLq3OE1Zts5g2BOZ/QwIhANy0gmFZ3hv68vLuwZxcJw17RisX8Le8ph6R31fnq1pl";
+    const NOT_BEFORE: i64 = 1792330513;
+    const NOT_AFTER: i64 = 1794922513;
+
+    fn u24(n: usize) -> [u8; 3] {
+        [(n >> 16) as u8, (n >> 8) as u8, n as u8]
+    }
+
+    fn record(content_type: u8, fragment: &[u8]) -> Vec<u8> {
+        let mut r = vec![content_type, 3, 3];
+        r.extend((fragment.len() as u16).to_be_bytes());
+        r.extend_from_slice(fragment);
+        r
+    }
+
+    fn handshake(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut m = vec![kind];
+        m.extend(u24(body.len()));
+        m.extend_from_slice(body);
+        m
+    }
+
+    fn extension(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut e = kind.to_be_bytes().to_vec();
+        e.extend((data.len() as u16).to_be_bytes());
+        e.extend_from_slice(data);
+        e
+    }
+
+    fn with_size(size: usize, width: usize, data: &[u8]) -> Vec<u8> {
+        let mut v = size.to_be_bytes()[8 - width..].to_vec();
+        v.extend_from_slice(data);
+        v
+    }
+
+    fn client_hello(
+        session_id: &[u8],
+        server_name: &str,
+        alpn: &[&str],
+        versions: &[u16],
+    ) -> Vec<u8> {
+        let mut body = TLS_1_2.to_be_bytes().to_vec();
+        body.extend([1; 32]);
+        body.extend(with_size(session_id.len(), 1, session_id));
+        body.extend([0, 4, 0x13, 0x01, 0xc0, 0x2f, 1, 0]);
+        let name = with_size(server_name.len(), 2, server_name.as_bytes());
+        let mut names = vec![0];
+        names.extend(name);
+        let mut extensions = extension(EXT_SERVER_NAME, &with_size(names.len(), 2, &names));
+        let protocols: Vec<u8> = alpn
+            .iter()
+            .flat_map(|p| with_size(p.len(), 1, p.as_bytes()))
+            .collect();
+        extensions.extend(extension(
+            EXT_ALPN,
+            &with_size(protocols.len(), 2, &protocols),
+        ));
+        if !versions.is_empty() {
+            let list: Vec<u8> = versions.iter().flat_map(|v| v.to_be_bytes()).collect();
+            extensions.extend(extension(
+                EXT_SUPPORTED_VERSIONS,
+                &with_size(list.len(), 1, &list),
+            ));
+        }
+        body.extend(with_size(extensions.len(), 2, &extensions));
+        handshake(CLIENT_HELLO, &body)
+    }
+
+    fn server_hello(random: [u8; 32], session_id: &[u8], suite: u16, extensions: &[u8]) -> Vec<u8> {
+        let mut body = TLS_1_2.to_be_bytes().to_vec();
+        body.extend(random);
+        body.extend(with_size(session_id.len(), 1, session_id));
+        body.extend(suite.to_be_bytes());
+        body.push(0);
+        body.extend(with_size(extensions.len(), 2, extensions));
+        handshake(SERVER_HELLO, &body)
+    }
+
+    #[test]
+    fn tls12_handshake_with_certificate() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let start = NOT_BEFORE as u64 * 1_000_000;
+        let mut parser = TlsLog::default();
+
+        let hello = record(
+            HANDSHAKE,
+            &client_hello(&[], "example.com", &["h2", "http/1.1"], &[]),
+        );
+        assert!(parser.check_payload(&hello, &at(C2S, start)));
+        assert!(!parser.check_payload(&hello, &at(S2C, start)));
+        for size in 0..4u8 {
+            let record = [HANDSHAKE, 3, 1, 0, size, CLIENT_HELLO, 0, 0, 0x2c, 3, 3];
+            assert!(!parser.check_payload(&record, &at(C2S, start)));
+        }
+        assert!(parse(&mut parser, &hello, &at(C2S, start)).is_empty());
+
+        // the certificate message spans two records, sent in separate packets
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(CERTIFICATE_DER)
+            .unwrap();
+        let chain = with_size(der.len(), 3, &der);
+        let certificate = handshake(CERTIFICATE, &with_size(chain.len(), 3, &chain));
+        let alpn = extension(EXT_ALPN, &with_size(3, 2, &with_size(2, 1, b"h2")));
+        let mut flight = record(
+            HANDSHAKE,
+            &[
+                server_hello([2; 32], &[7; 32], 0xc02f, &alpn),
+                certificate[..100].to_vec(),
+            ]
+            .concat(),
+        );
+        let second = record(
+            HANDSHAKE,
+            &[certificate[100..].to_vec(), handshake(14, &[])].concat(),
+        );
+        flight.extend_from_slice(&second[..50]);
+        assert!(parse(&mut parser, &flight, &at(S2C, start + 1000)).is_empty());
+        assert!(parse(&mut parser, &second[50..], &at(S2C, start + 1000)).is_empty());
+
+        let finished = [
+            record(HANDSHAKE, &handshake(16, &[0; 66])),
+            record(CHANGE_CIPHER_SPEC, &[1]),
+            record(HANDSHAKE, &[0xaa; 40]),
+        ]
+        .concat();
+        assert!(parse(&mut parser, &finished, &at(C2S, start + 2000)).is_empty());
+        let finished = [
+            record(CHANGE_CIPHER_SPEC, &[1]),
+            record(HANDSHAKE, &[0xbb; 40]),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &finished, &at(S2C, start + 3000));
+        assert_eq!(
+            infos,
+            vec![TlsInfo {
+                msg_type: LogMessageType::Session,
+                client_version: "TLSv1.2",
+                server_name: Some("example.com".into()),
+                alpn_offered: vec!["h2".into(), "http/1.1".into()],
+                version: Some("TLSv1.2"),
+                cipher_suite: Some(0xc02f),
+                cipher_suite_name: Some("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"),
+                alpn: Some("h2".into()),
+                cert_subject: Some("O=Example, CN=example.com".into()),
+                cert_issuer: Some("O=Example, CN=example.com".into()),
+                cert_not_before: Some(NOT_BEFORE),
+                cert_not_after: Some(NOT_AFTER),
+                cert_days_left: Some(30),
+                rrt: 3000,
+                time: start,
+                negotiated: Some(TLS_1_2),
+                ..Default::default()
+            }]
+        );
+        // application data is left alone
+        assert!(parse(
+            &mut parser,
+            &record(APPLICATION_DATA, &[0; 64]),
+            &at(C2S, start + 4000)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn tls13_retry_alert_and_timeout() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = TlsLog::default();
+
+        let versions = [0x3a3a, TLS_1_3, TLS_1_2];
+        let hello = client_hello(&[5; 32], "api.example.com", &["h2"], &versions);
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello), &at(C2S, 1000)).is_empty());
+        let supported = extension(EXT_SUPPORTED_VERSIONS, &TLS_1_3.to_be_bytes());
+        let retry = server_hello(HELLO_RETRY_REQUEST, &[5; 32], 0x1301, &supported);
+        let flight = [record(HANDSHAKE, &retry), record(CHANGE_CIPHER_SPEC, &[1])].concat();
+        assert!(parse(&mut parser, &flight, &at(S2C, 2000)).is_empty());
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello), &at(C2S, 3000)).is_empty());
+        let flight = [
+            record(
+                HANDSHAKE,
+                &server_hello([3; 32], &[5; 32], 0x1302, &supported),
+            ),
+            record(APPLICATION_DATA, &[0xcc; 800]),
+        ]
+        .concat();
+        assert!(parse(&mut parser, &flight, &at(S2C, 4000)).is_empty());
+        let finished = [
+            record(CHANGE_CIPHER_SPEC, &[1]),
+            record(APPLICATION_DATA, &[0xdd; 53]),
+        ]
+        .concat();
+        let infos = parse(&mut parser, &finished, &at(C2S, 5000));
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].client_version, "TLSv1.3");
+        assert_eq!(infos[0].version, Some("TLSv1.3"));
+        assert_eq!(infos[0].cipher_suite_name, Some("TLS_AES_256_GCM_SHA384"));
+        assert_eq!(infos[0].server_name.as_deref(), Some("api.example.com"));
+        assert!(!infos[0].resumed);
+        assert_eq!(infos[0].cert_subject, None);
+        assert_eq!(
+            (infos[0].status, infos[0].rrt),
+            (L7ResponseStatus::Ok, 4000)
+        );
+
+        // a server refusing a client stuck on TLS 1.0
+        let mut parser = TlsLog::default();
+        let mut hello = client_hello(&[], "legacy.example.com", &[], &[]);
+        hello[4..6].copy_from_slice(&TLS_1_0.to_be_bytes());
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello), &at(C2S, 0)).is_empty());
+        let infos = parse(
+            &mut parser,
+            &record(ALERT, &[ALERT_FATAL, 70]),
+            &at(S2C, 500),
+        );
+        assert_eq!(infos[0].client_version, "TLSv1.0");
+        assert_eq!(infos[0].alert, Some("protocol_version"));
+        assert_eq!(
+            (infos[0].status, infos[0].rrt),
+            (L7ResponseStatus::ClientError, 500)
+        );
+
+        // the client rejecting the certificate of the server, in a resumed session
+        let mut parser = TlsLog::default();
+        let hello = client_hello(&[9; 32], "a.example.com", &[], &[]);
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello), &at(C2S, 0)).is_empty());
+        let hello = server_hello([4; 32], &[9; 32], 0x0005, &[]);
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello), &at(S2C, 100)).is_empty());
+        let infos = parse(
+            &mut parser,
+            &record(ALERT, &[ALERT_FATAL, 48]),
+            &at(C2S, 200),
+        );
+        assert!(infos[0].resumed);
+        assert_eq!(infos[0].cipher_suite_name, Some("TLS_RSA_WITH_RC4_128_SHA"));
+        assert_eq!(infos[0].alert, Some("unknown_ca"));
+        assert_eq!(infos[0].status, L7ResponseStatus::ServerError);
+
+        let mut parser = TlsLog::default();
+        assert!(parse(&mut parser, &record(HANDSHAKE, &hello_v0()), &at(C2S, 0)).is_empty());
+        let infos = parse(&mut parser, &[], &at(S2C, SESSION_TIMEOUT));
+        assert_eq!(infos[0].status, L7ResponseStatus::Timeout);
+        assert!(parse(&mut parser, &[], &at(S2C, SESSION_TIMEOUT + 1)).is_empty());
+        assert!(parser
+            .parse_payload(b"GET / HTTP/1.1\r\n", &at(C2S, 0))
+            .is_ok());
+        assert!(TlsLog::default()
+            .parse_payload(b"GET / HTTP/1.1\r\n", &at(C2S, 0))
+            .is_err());
+    }
+
+    // a ClientHello without extensions
+    fn hello_v0() -> Vec<u8> {
+        let mut body = TLS_1_0.to_be_bytes().to_vec();
+        body.extend([1; 32]);
+        body.extend([0, 0, 2, 0, 0x0a, 1, 0]);
+        handshake(CLIENT_HELLO, &body)
+    }
+}
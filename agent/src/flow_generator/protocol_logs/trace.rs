@@ -0,0 +1,236 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::Serialize;
+
+use super::LogParserConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceType {
+    // W3C traceparent: {version}-{trace-id}-{parent-id}-{flags}
+    TraceParent,
+    // SkyWalking sw8: {sample}-{base64 trace-id}-{base64 segment-id}-{span-id}-...
+    Sw8,
+    // Jaeger uber-trace-id: {trace-id}:{span-id}:{parent-span-id}:{flags}
+    Uber,
+    // Zipkin multi-header X-B3-TraceId
+    XB3,
+    // Zipkin multi-header X-B3-SpanId, parent from X-B3-ParentSpanId
+    XB3Span,
+    // Zipkin single-header b3: {trace-id}-{span-id}-{sampled}-{parent-span-id}
+    B3,
+    Customize(String),
+}
+
+const B3_PARENT_SPAN_ID: &str = "X-B3-ParentSpanId";
+
+impl From<&str> for TraceType {
+    fn from(key: &str) -> Self {
+        match key.to_ascii_lowercase().as_str() {
+            "traceparent" => Self::TraceParent,
+            "sw8" => Self::Sw8,
+            "uber-trace-id" => Self::Uber,
+            "x-b3-traceid" => Self::XB3,
+            "x-b3-spanid" => Self::XB3Span,
+            "b3" => Self::B3,
+            _ => Self::Customize(key.to_owned()),
+        }
+    }
+}
+
+impl TraceType {
+    pub fn key(&self) -> &str {
+        match self {
+            Self::TraceParent => "traceparent",
+            Self::Sw8 => "sw8",
+            Self::Uber => "uber-trace-id",
+            Self::XB3 => "X-B3-TraceId",
+            Self::XB3Span => "X-B3-SpanId",
+            Self::B3 => "b3",
+            Self::Customize(key) => key,
+        }
+    }
+
+    fn decode_trace_id(&self, value: &str) -> Option<String> {
+        let id = match self {
+            Self::TraceParent => value.split('-').nth(1)?.to_owned(),
+            Self::Sw8 => decode_base64(value.split('-').nth(1)?)?,
+            Self::Uber => uber_fields(value).next()?.to_owned(),
+            Self::B3 => {
+                let mut fields = value.split('-');
+                let trace_id = fields.next()?;
+                // sampling-only form, e.g. `b3: 0`
+                fields.next()?;
+                trace_id.to_owned()
+            }
+            Self::XB3 | Self::XB3Span | Self::Customize(_) => value.trim().to_owned(),
+        };
+        (!id.is_empty()).then_some(id)
+    }
+
+    // returns span id and parent span id when the format carries it
+    fn decode_span_id(&self, value: &str) -> Option<(String, Option<String>)> {
+        let (span_id, parent_span_id) = match self {
+            Self::TraceParent => (value.split('-').nth(2)?.to_owned(), None),
+            Self::Sw8 => {
+                let mut fields = value.split('-').skip(2);
+                let segment_id = decode_base64(fields.next()?)?;
+                (format!("{}-{}", segment_id, fields.next()?), None)
+            }
+            Self::Uber => {
+                let mut fields = uber_fields(value).skip(1);
+                let span_id = fields.next()?.to_owned();
+                let parent = fields.next().filter(|p| *p != "0").map(str::to_owned);
+                (span_id, parent)
+            }
+            Self::B3 => {
+                let mut fields = value.split('-').skip(1);
+                let span_id = fields.next()?.to_owned();
+                (span_id, fields.nth(1).map(str::to_owned))
+            }
+            Self::XB3 | Self::XB3Span | Self::Customize(_) => (value.trim().to_owned(), None),
+        };
+        (!span_id.is_empty()).then_some((span_id, parent_span_id))
+    }
+}
+
+fn decode_base64(value: &str) -> Option<String> {
+    BASE64_STANDARD
+        .decode(value)
+        .ok()
+        .and_then(|v| String::from_utf8(v).ok())
+}
+
+// jaeger clients may send the value url-encoded
+fn uber_fields(value: &str) -> impl Iterator<Item = &str> {
+    let separator = if value.contains(':') { ":" } else { "%3A" };
+    value.split(separator)
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    // `lookup` returns the value of a header, property or attachment by key,
+    // keys should be matched case-insensitively
+    pub fn extract<'a, F>(config: &LogParserConfig, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        let mut context = Self::default();
+        for t in config.trace_types.iter() {
+            if let Some(id) = lookup(t.key()).and_then(|v| t.decode_trace_id(v)) {
+                context.trace_id = Some(id);
+                break;
+            }
+        }
+        for t in config.span_types.iter() {
+            if let Some((id, parent)) = lookup(t.key()).and_then(|v| t.decode_span_id(v)) {
+                context.span_id = Some(id);
+                context.parent_span_id = match t {
+                    TraceType::XB3Span => lookup(B3_PARENT_SPAN_ID).map(str::to_owned),
+                    _ => parent,
+                };
+                break;
+            }
+        }
+        context
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trace_id.is_none() && self.span_id.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(pairs: &[(&str, &str)]) -> TraceContext {
+        TraceContext::extract(&LogParserConfig::default(), |key| {
+            pairs
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| *v)
+        })
+    }
+
+    #[test]
+    fn trace_formats() {
+        let context = extract(&[(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )]);
+        assert_eq!(
+            context.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(context.span_id.as_deref(), Some("b7ad6b7169203331"));
+        assert_eq!(context.parent_span_id, None);
+
+        // trace id `abc`, segment id `seg`
+        let context = extract(&[(
+            "sw8",
+            "1-YWJj-c2Vn-3-c2VydmljZQ==-aW5zdGFuY2U=-L2dldA==-MTAuMC4wLjE6ODA4MA==",
+        )]);
+        assert_eq!(context.trace_id.as_deref(), Some("abc"));
+        assert_eq!(context.span_id.as_deref(), Some("seg-3"));
+
+        let context = extract(&[("uber-trace-id", "5e2f%3Aa1%3Ab2%3A1")]);
+        assert_eq!(context.trace_id.as_deref(), Some("5e2f"));
+        assert_eq!(context.span_id.as_deref(), Some("a1"));
+        assert_eq!(context.parent_span_id.as_deref(), Some("b2"));
+
+        let context = extract(&[("uber-trace-id", "5e2f:a1:0:1")]);
+        assert_eq!(context.parent_span_id, None);
+
+        let context = extract(&[
+            ("x-b3-traceid", "80f198ee56343ba864fe8b2a57d3eff7"),
+            ("x-b3-spanid", "e457b5a2e4d86bd1"),
+            ("x-b3-parentspanid", "05e3ac9a4f6e3b90"),
+        ]);
+        assert_eq!(
+            context.trace_id.as_deref(),
+            Some("80f198ee56343ba864fe8b2a57d3eff7")
+        );
+        assert_eq!(context.span_id.as_deref(), Some("e457b5a2e4d86bd1"));
+        assert_eq!(context.parent_span_id.as_deref(), Some("05e3ac9a4f6e3b90"));
+    }
+
+    #[test]
+    fn configured_keys() {
+        let config = LogParserConfig {
+            trace_types: vec!["b3".into(), "x-request-id".into()],
+            span_types: vec!["b3".into()],
+            ..Default::default()
+        };
+        let lookup = |key: &str| match key {
+            "b3" => Some("80f198ee56343ba8-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90"),
+            _ => None,
+        };
+        let context = TraceContext::extract(&config, lookup);
+        assert_eq!(context.trace_id.as_deref(), Some("80f198ee56343ba8"));
+        assert_eq!(context.span_id.as_deref(), Some("e457b5a2e4d86bd1"));
+        assert_eq!(context.parent_span_id.as_deref(), Some("05e3ac9a4f6e3b90"));
+
+        let context = TraceContext::extract(&config, |key| match key {
+            "b3" => Some("0"),
+            "x-request-id" => Some("req-1"),
+            _ => None,
+        });
+        assert_eq!(context.trace_id.as_deref(), Some("req-1"));
+        assert_eq!(context.span_id, None);
+
+        // keys outside the configured list are ignored
+        let context = TraceContext::extract(&config, |key| match key {
+            "traceparent" => Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+            _ => None,
+        });
+        assert!(context.is_empty());
+    }
+}
@@ -0,0 +1,922 @@
+use std::collections::HashMap;
+use std::mem;
+
+use log::debug;
+use public::bytes::read_u32_be;
+use public::l7_protocol::L7Protocol;
+use serde::Serialize;
+
+use super::{
+    expire_pending, L7ParseResult, L7ProtocolInfo, L7ProtocolParserInterface, L7ResponseStatus,
+    LogMessageType, ParseParam, PendingRequest, PerDirection, Reader, Stream, MAX_PENDING_SESSIONS,
+    SESSION_TIMEOUT,
+};
+use crate::common::flow::PacketDirection;
+use crate::flow_generator::{Error, Result};
+
+// jute.maxbuffer defaults to 1 MiB, raised on clusters keeping large znodes
+const MAX_FRAME_SIZE: usize = 64 << 20;
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_PATH_SIZE: usize = 1024;
+const MAX_MULTI_OPS: usize = 16;
+
+// ConnectRequest: protocolVersion, lastZxidSeen, timeOut, sessionId, passwd[16], readOnly,
+// the last one left out by old clients
+const CONNECT_REQUEST_SIZE: usize = 4 + 8 + 4 + 8 + 4 + 16;
+// ConnectResponse: protocolVersion, timeOut, sessionId, passwd[16], readOnly
+const CONNECT_RESPONSE_SIZE: usize = 4 + 4 + 8 + 4 + 16;
+const PASSWORD_SIZE: usize = 16;
+
+// xids of requests not numbered by the client
+const XID_NOTIFICATION: i32 = -1;
+const XID_PING: i32 = -2;
+
+const OP_CREATE: i32 = 1;
+const OP_DELETE: i32 = 2;
+const OP_EXISTS: i32 = 3;
+const OP_GET_DATA: i32 = 4;
+const OP_SET_DATA: i32 = 5;
+const OP_GET_ACL: i32 = 6;
+const OP_SET_ACL: i32 = 7;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_SYNC: i32 = 9;
+const OP_GET_CHILDREN2: i32 = 12;
+const OP_CHECK: i32 = 13;
+const OP_MULTI: i32 = 14;
+const OP_CREATE2: i32 = 15;
+const OP_CREATE_CONTAINER: i32 = 19;
+const OP_DELETE_CONTAINER: i32 = 20;
+const OP_CREATE_TTL: i32 = 21;
+const OP_MULTI_READ: i32 = 22;
+const OP_ERROR: i32 = -1;
+
+const ERR_RUNTIME_INCONSISTENCY: i32 = -2;
+const ERR_NO_NODE: i32 = -101;
+
+fn op_name(op: i32) -> &'static str {
+    match op {
+        0 => "notification",
+        OP_CREATE => "create",
+        OP_DELETE => "delete",
+        OP_EXISTS => "exists",
+        OP_GET_DATA => "getData",
+        OP_SET_DATA => "setData",
+        OP_GET_ACL => "getACL",
+        OP_SET_ACL => "setACL",
+        OP_GET_CHILDREN => "getChildren",
+        OP_SYNC => "sync",
+        11 => "ping",
+        OP_GET_CHILDREN2 => "getChildren2",
+        OP_CHECK => "check",
+        OP_MULTI => "multi",
+        OP_CREATE2 => "create2",
+        16 => "reconfig",
+        17 => "checkWatches",
+        18 => "removeWatches",
+        OP_CREATE_CONTAINER => "createContainer",
+        OP_DELETE_CONTAINER => "deleteContainer",
+        OP_CREATE_TTL => "createTTL",
+        OP_MULTI_READ => "multiRead",
+        100 => "auth",
+        101 => "setWatches",
+        102 => "sasl",
+        103 => "getEphemerals",
+        104 => "getAllChildrenNumber",
+        105 => "setWatches2",
+        106 => "addWatch",
+        107 => "whoAmI",
+        -10 => "createSession",
+        -11 => "closeSession",
+        _ => "unknown",
+    }
+}
+
+// the requests of these start with a path
+fn has_path(op: i32) -> bool {
+    matches!(op, 1..=9 | 12 | 13 | 15 | 17..=21 | 103 | 104 | 106)
+}
+
+fn error_name(err: i32) -> &'static str {
+    match err {
+        0 => "OK",
+        -1 => "SYSTEMERROR",
+        ERR_RUNTIME_INCONSISTENCY => "RUNTIMEINCONSISTENCY",
+        -3 => "DATAINCONSISTENCY",
+        -4 => "CONNECTIONLOSS",
+        -5 => "MARSHALLINGERROR",
+        -6 => "UNIMPLEMENTED",
+        -7 => "OPERATIONTIMEOUT",
+        -8 => "BADARGUMENTS",
+        -13 => "NEWCONFIGNOQUORUM",
+        -14 => "RECONFIGINPROGRESS",
+        -15 => "UNKNOWNSESSION",
+        -100 => "APIERROR",
+        ERR_NO_NODE => "NONODE",
+        -102 => "NOAUTH",
+        -103 => "BADVERSION",
+        -104 => "NOCHILDRENFOREPHEMERALS",
+        -105 => "NODEEXISTS",
+        -106 => "NOTEMPTY",
+        -107 => "SESSIONEXPIRED",
+        -108 => "INVALIDCALLBACK",
+        -109 => "INVALIDACL",
+        -110 => "AUTHFAILED",
+        -111 => "SESSIONMOVED",
+        -112 => "NOTREADONLY",
+        -113 => "EPHEMERALONLOCALSESSION",
+        -114 => "NOWATCHER",
+        -115 => "REQUESTTIMEOUT",
+        -116 => "RECONFIGDISABLED",
+        -117 => "SESSIONCLOSEDREQUIRESASLAUTH",
+        -118 => "QUOTAEXCEEDED",
+        -119 => "THROTTLEDOP",
+        _ => "UNKNOWN",
+    }
+}
+
+// errors of the api are about what the client asked for, the others about the ensemble
+fn error_status(op: i32, err: i32) -> L7ResponseStatus {
+    match err {
+        0 => L7ResponseStatus::Ok,
+        // how exists tells a node is absent
+        ERR_NO_NODE if op == OP_EXISTS => L7ResponseStatus::Ok,
+        -115 | -119 => L7ResponseStatus::ServerError,
+        _ if err <= -100 => L7ResponseStatus::ClientError,
+        _ => L7ResponseStatus::ServerError,
+    }
+}
+
+fn event_type_name(kind: i32) -> &'static str {
+    match kind {
+        -1 => "None",
+        1 => "NodeCreated",
+        2 => "NodeDeleted",
+        3 => "NodeDataChanged",
+        4 => "NodeChildrenChanged",
+        5 => "DataWatchRemoved",
+        6 => "ChildWatchRemoved",
+        7 => "PersistentWatchRemoved",
+        _ => "Unknown",
+    }
+}
+
+fn keeper_state_name(state: i32) -> &'static str {
+    match state {
+        0 => "Disconnected",
+        3 => "SyncConnected",
+        4 => "AuthFailed",
+        5 => "ConnectedReadOnly",
+        6 => "SaslAuthenticated",
+        7 => "Closed",
+        -112 => "Expired",
+        _ => "Unknown",
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ZookeeperInfo {
+    pub msg_type: LogMessageType,
+    // connect for the ConnectRequest opening a session
+    pub op: &'static str,
+    pub xid: i32,
+    // of the first op of a multi
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    // a watch left by exists, getData or getChildren
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub watch: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub multi_ops: Vec<&'static str>,
+    // of a session, as negotiated by the server, unit: milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_timeout: Option<i32>,
+
+    // filled in when a response is merged into the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zxid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    // of a watcher event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keeper_state: Option<&'static str>,
+    pub status: L7ResponseStatus,
+    // unit: microseconds
+    pub rrt: u64,
+
+    #[serde(skip)]
+    pub time: u64,
+    #[serde(skip)]
+    opcode: i32,
+}
+
+impl ZookeeperInfo {
+    fn merge(&mut self, response: ZookeeperInfo) {
+        self.msg_type = LogMessageType::Session;
+        self.rrt = response.time.saturating_sub(self.time);
+        if response.session_timeout.is_some() {
+            self.session_timeout = response.session_timeout;
+        }
+        self.session_id = response.session_id;
+        self.zxid = response.zxid;
+        self.error_code = response.error_code;
+        self.error = response.error;
+        self.status = response.status;
+    }
+}
+
+trait ZookeeperRead<'a> {
+    fn buffer(&mut self) -> Result<&'a [u8]>;
+    fn path(&mut self) -> Result<String>;
+    fn skip_acl(&mut self) -> Result<()>;
+}
+
+impl<'a> ZookeeperRead<'a> for Reader<'a> {
+    // strings and buffers, -1 for null
+    fn buffer(&mut self) -> Result<&'a [u8]> {
+        let size = self.i32()?;
+        match usize::try_from(size) {
+            Ok(size) => self.take(size),
+            Err(_) if size == -1 => Ok(&[]),
+            Err(_) => Err(Error::ZookeeperLogParseFailed(format!(
+                "invalid buffer size {}",
+                size
+            ))),
+        }
+    }
+
+    fn path(&mut self) -> Result<String> {
+        let path = self.buffer()?;
+        if path.len() > MAX_PATH_SIZE {
+            return Err(Error::ZookeeperLogParseFailed(format!(
+                "invalid path size {}",
+                path.len()
+            )));
+        }
+        Ok(String::from_utf8_lossy(path).into_owned())
+    }
+
+    // vector<ACL{perms, Id{scheme, id}}>
+    fn skip_acl(&mut self) -> Result<()> {
+        let count = self.i32()?.max(0);
+        for _ in 0..count {
+            self.i32()?;
+            self.buffer()?;
+            self.buffer()?;
+        }
+        Ok(())
+    }
+}
+
+fn is_connect_request(message: &[u8]) -> bool {
+    (CONNECT_REQUEST_SIZE..=CONNECT_REQUEST_SIZE + 1).contains(&message.len())
+        && read_u32_be(message) == 0
+        && read_u32_be(&message[24..]) == PASSWORD_SIZE as u32
+}
+
+fn is_connect_response(message: &[u8]) -> bool {
+    (CONNECT_RESPONSE_SIZE..=CONNECT_RESPONSE_SIZE + 1).contains(&message.len())
+        && read_u32_be(message) == 0
+        && read_u32_be(&message[16..]) == PASSWORD_SIZE as u32
+}
+
+fn parse_connect_request(message: &[u8]) -> Result<ZookeeperInfo> {
+    let mut r = Reader { data: message };
+    r.i32()?;
+    r.i64()?;
+    let timeout = r.i32()?;
+    Ok(ZookeeperInfo {
+        msg_type: LogMessageType::Request,
+        op: "connect",
+        session_timeout: Some(timeout),
+        ..Default::default()
+    })
+}
+
+fn parse_connect_response(message: &[u8]) -> Result<ZookeeperInfo> {
+    let mut r = Reader { data: message };
+    r.i32()?;
+    let timeout = r.i32()?;
+    let session_id = r.i64()?;
+    let mut info = ZookeeperInfo {
+        msg_type: LogMessageType::Response,
+        op: "connect",
+        session_timeout: Some(timeout),
+        session_id: Some(format!("{:#x}", session_id)),
+        ..Default::default()
+    };
+    // the session to reconnect to has expired
+    if timeout <= 0 {
+        info.error_code = Some(-107);
+        info.error = Some(error_name(-107));
+        info.status = L7ResponseStatus::ClientError;
+    }
+    Ok(info)
+}
+
+// RequestHeader{xid, type} and the path of the request, what can be read of a
+// message cut at its head is kept
+fn parse_request(message: &[u8]) -> Result<ZookeeperInfo> {
+    let mut r = Reader { data: message };
+    let xid = r.i32()?;
+    let opcode = r.i32()?;
+    let op = op_name(opcode);
+    if op == "unknown" {
+        return Err(Error::ZookeeperLogParseFailed(format!(
+            "invalid opcode {}",
+            opcode
+        )));
+    }
+    let mut info = ZookeeperInfo {
+        msg_type: LogMessageType::Request,
+        op,
+        xid,
+        opcode,
+        ..Default::default()
+    };
+    match opcode {
+        OP_MULTI | OP_MULTI_READ => {
+            let _ = parse_multi_request(&mut r, &mut info);
+        }
+        _ if has_path(opcode) => {
+            info.path = r.path().ok();
+            if matches!(
+                opcode,
+                OP_EXISTS | OP_GET_DATA | OP_GET_CHILDREN | OP_GET_CHILDREN2
+            ) {
+                info.watch = r.u8().is_ok_and(|w| w != 0);
+            }
+        }
+        _ => (),
+    }
+    Ok(info)
+}
+
+// MultiHeader{type, done, err} before each op, ended with a done one
+fn parse_multi_request(r: &mut Reader, info: &mut ZookeeperInfo) -> Result<()> {
+    loop {
+        let opcode = r.i32()?;
+        let done = r.u8()? != 0;
+        r.i32()?;
+        if done || info.multi_ops.len() >= MAX_MULTI_OPS {
+            return Ok(());
+        }
+        info.multi_ops.push(op_name(opcode));
+        let path = r.path()?;
+        info.path.get_or_insert(path);
+        match opcode {
+            OP_CREATE | OP_CREATE2 | OP_CREATE_CONTAINER | OP_CREATE_TTL => {
+                r.buffer()?;
+                r.skip_acl()?;
+                r.i32()?;
+                if opcode == OP_CREATE_TTL {
+                    r.i64()?;
+                }
+            }
+            OP_DELETE | OP_CHECK => {
+                r.i32()?;
+            }
+            OP_SET_DATA => {
+                r.buffer()?;
+                r.i32()?;
+            }
+            OP_GET_DATA | OP_GET_CHILDREN => {
+                r.u8()?;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+// results of a failed multi are all errors, that of the failed op first
+// among the ones rolled back
+fn multi_error(r: &mut Reader) -> Option<i32> {
+    let mut error = None;
+    loop {
+        let opcode = r.i32().ok()?;
+        let done = r.u8().ok()? != 0;
+        r.i32().ok()?;
+        if done || opcode != OP_ERROR {
+            return error;
+        }
+        let err = r.i32().ok()?;
+        if err != 0 && (error.is_none() || error == Some(ERR_RUNTIME_INCONSISTENCY)) {
+            error = Some(err);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ZookeeperLog {
+    streams: PerDirection<Stream>,
+    // by xid, replies may come out of order around pings and notifications
+    pending: HashMap<i32, ZookeeperInfo>,
+    // the ConnectRequest waiting for its response
+    connecting: Option<ZookeeperInfo>,
+}
+
+impl ZookeeperLog {
+    fn parse_messages(
+        &mut self,
+        payload: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        let direction = param.direction;
+        let data = self.streams[direction].data(payload);
+
+        let mut last_error = None;
+        let mut remain = &data[..];
+        while remain.len() >= 4 {
+            let size = read_u32_be(remain) as usize;
+            if !(4..=MAX_FRAME_SIZE).contains(&size) {
+                return Err(Error::ZookeeperLogParseFailed(format!(
+                    "invalid message size {}",
+                    size
+                )));
+            }
+            let total_size = 4 + size;
+            let message = if remain.len() >= total_size {
+                let message = &remain[4..total_size];
+                remain = &remain[total_size..];
+                message
+            } else if remain.len() >= MAX_HEAD_SIZE {
+                self.streams[direction].skip = total_size - remain.len();
+                &mem::take(&mut remain)[4..]
+            } else {
+                break;
+            };
+            let result = match param.direction {
+                PacketDirection::ClientToServer => self.handle_request(message, param, output),
+                PacketDirection::ServerToClient => self.handle_reply(message, param, output),
+            };
+            if let Err(e) = result {
+                debug!("skip zookeeper message: {}", e);
+                last_error = Some(e);
+            }
+        }
+        if !remain.is_empty() {
+            self.streams[direction].buffer = remain.to_vec();
+        }
+
+        match last_error {
+            Some(e) if output.is_empty() => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        message: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        if is_connect_request(message) {
+            let mut info = parse_connect_request(message)?;
+            info.time = param.time;
+            if let Some(prev) = self.connecting.replace(info) {
+                output.push(L7ProtocolInfo::ZookeeperInfo(prev));
+            }
+            return Ok(());
+        }
+        let mut info = parse_request(message)?;
+        // pings are sent every third of the session timeout
+        if info.xid == XID_PING {
+            return Ok(());
+        }
+        info.time = param.time;
+        let key = info.xid;
+        if self.pending.len() >= MAX_PENDING_SESSIONS && !self.pending.contains_key(&key) {
+            output.push(L7ProtocolInfo::ZookeeperInfo(info));
+        } else if let Some(prev) = self.pending.insert(key, info) {
+            output.push(L7ProtocolInfo::ZookeeperInfo(prev));
+        }
+        Ok(())
+    }
+
+    fn handle_reply(
+        &mut self,
+        message: &[u8],
+        param: &ParseParam,
+        output: &mut Vec<L7ProtocolInfo>,
+    ) -> Result<()> {
+        if self.connecting.is_some() && is_connect_response(message) {
+            let mut response = parse_connect_response(message)?;
+            response.time = param.time;
+            let mut request = self.connecting.take().unwrap();
+            request.merge(response);
+            output.push(L7ProtocolInfo::ZookeeperInfo(request));
+            return Ok(());
+        }
+        // ReplyHeader{xid, zxid, err}
+        let mut r = Reader { data: message };
+        let xid = r.i32()?;
+        let zxid = r.i64()?;
+        let err = r.i32()?;
+        match xid {
+            XID_PING => return Ok(()),
+            // WatcherEvent{type, state, path}
+            XID_NOTIFICATION => {
+                let kind = r.i32()?;
+                let state = r.i32()?;
+                output.push(L7ProtocolInfo::ZookeeperInfo(ZookeeperInfo {
+                    msg_type: LogMessageType::Other,
+                    op: op_name(0),
+                    xid,
+                    path: r.path().ok().filter(|p| !p.is_empty()),
+                    event_type: Some(event_type_name(kind)),
+                    keeper_state: Some(keeper_state_name(state)),
+                    time: param.time,
+                    ..Default::default()
+                }));
+                return Ok(());
+            }
+            _ => (),
+        }
+        let Some(mut request) = self.pending.remove(&xid) else {
+            debug!("zookeeper reply of xid {} without a request", xid);
+            return Ok(());
+        };
+        let err = match request.opcode {
+            OP_MULTI if err == 0 => multi_error(&mut r).unwrap_or_default(),
+            _ => err,
+        };
+        let mut response = ZookeeperInfo {
+            msg_type: LogMessageType::Response,
+            zxid: Some(zxid),
+            status: error_status(request.opcode, err),
+            time: param.time,
+            ..Default::default()
+        };
+        if err != 0 {
+            response.error_code = Some(err);
+            response.error = Some(error_name(err));
+        }
+        request.merge(response);
+        output.push(L7ProtocolInfo::ZookeeperInfo(request));
+        Ok(())
+    }
+
+    fn expire_pending(&mut self, now: u64, output: &mut Vec<L7ProtocolInfo>) {
+        if self
+            .connecting
+            .as_ref()
+            .is_some_and(|c| now.saturating_sub(c.time) >= SESSION_TIMEOUT)
+        {
+            output.push(self.connecting.take().unwrap().into_timeout());
+        }
+        expire_pending(&mut self.pending, now, output);
+    }
+}
+
+impl L7ProtocolParserInterface for ZookeeperLog {
+    // a ConnectRequest, or a request with a known opcode
+    fn check_payload(&mut self, payload: &[u8], param: &ParseParam) -> bool {
+        if param.direction != PacketDirection::ClientToServer || payload.len() < 12 {
+            return false;
+        }
+        let size = read_u32_be(payload) as usize;
+        if !(8..=MAX_FRAME_SIZE).contains(&size) {
+            return false;
+        }
+        let message = &payload[4..payload.len().min(4 + size)];
+        if is_connect_request(message) {
+            return true;
+        }
+        // paths are absolute
+        parse_request(message).is_ok_and(|info| {
+            info.xid >= 0
+                && info.opcode > 0
+                && (!has_path(info.opcode) || info.path.is_some_and(|p| p.starts_with('/')))
+        })
+    }
+
+    fn parse_payload(&mut self, payload: &[u8], param: &ParseParam) -> Result<L7ParseResult> {
+        let mut output = vec![];
+        self.expire_pending(param.time, &mut output);
+        if let Err(e) = self.parse_messages(payload, param, &mut output) {
+            debug!("reset zookeeper stream: {}", e);
+            self.streams[param.direction] = Stream::default();
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        Ok(output.into())
+    }
+
+    fn protocol(&self) -> L7Protocol {
+        L7Protocol::ZooKeeper
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_generator::protocol_logs::test_util::parse;
+
+    const C2S: PacketDirection = PacketDirection::ClientToServer;
+    const S2C: PacketDirection = PacketDirection::ServerToClient;
+
+    fn framed(message: Vec<u8>) -> Vec<u8> {
+        let mut f = (message.len() as u32).to_be_bytes().to_vec();
+        f.extend(message);
+        f
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut v = (s.len() as u32).to_be_bytes().to_vec();
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn request(xid: i32, op: i32, body: &[u8]) -> Vec<u8> {
+        let mut m = xid.to_be_bytes().to_vec();
+        m.extend(op.to_be_bytes());
+        m.extend_from_slice(body);
+        framed(m)
+    }
+
+    fn reply(xid: i32, zxid: i64, err: i32, body: &[u8]) -> Vec<u8> {
+        let mut m = xid.to_be_bytes().to_vec();
+        m.extend(zxid.to_be_bytes());
+        m.extend(err.to_be_bytes());
+        m.extend_from_slice(body);
+        framed(m)
+    }
+
+    fn multi_header(op: i32, done: bool, err: i32) -> Vec<u8> {
+        let mut h = op.to_be_bytes().to_vec();
+        h.push(done as u8);
+        h.extend(err.to_be_bytes());
+        h
+    }
+
+    #[test]
+    fn session_and_requests() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = ZookeeperLog::default();
+
+        let connect = [
+            &0i32.to_be_bytes()[..],
+            &0i64.to_be_bytes(),
+            &30000i32.to_be_bytes(),
+            &0i64.to_be_bytes(),
+            &16i32.to_be_bytes(),
+            &[0; 16],
+            &[0],
+        ]
+        .concat();
+        let connect = framed(connect);
+        assert!(parser.check_payload(&connect, &at(C2S, 0)));
+        assert!(parse(&mut parser, &connect, &at(C2S, 0)).is_empty());
+        let accepted = [
+            &0i32.to_be_bytes()[..],
+            &10000i32.to_be_bytes(),
+            &0x1000_0a3b_0000_0001i64.to_be_bytes(),
+            &16i32.to_be_bytes(),
+            &[1; 16],
+            &[0],
+        ]
+        .concat();
+        let infos = parse(&mut parser, &framed(accepted), &at(S2C, 800));
+        assert_eq!(
+            infos,
+            vec![ZookeeperInfo {
+                msg_type: LogMessageType::Session,
+                op: "connect",
+                session_timeout: Some(10000),
+                session_id: Some("0x10000a3b00000001".into()),
+                rrt: 800,
+                ..Default::default()
+            }]
+        );
+
+        let requests = [
+            request(
+                1,
+                OP_GET_DATA,
+                &[&string("/brokers/ids/0")[..], &[1]].concat(),
+            ),
+            request(XID_PING, 11, &[]),
+            request(2, OP_EXISTS, &[&string("/controller")[..], &[1]].concat()),
+            request(
+                3,
+                OP_CREATE,
+                &[
+                    &string("/brokers/ids/1")[..],
+                    &string("{}"),
+                    &1i32.to_be_bytes(),
+                    &31i32.to_be_bytes(),
+                    &string("world"),
+                    &string("anyone"),
+                    &1i32.to_be_bytes(),
+                ]
+                .concat(),
+            ),
+            request(
+                4,
+                OP_SET_DATA,
+                &[&string("/config")[..], &[0; 40_000]].concat(),
+            ),
+            request(
+                5,
+                OP_DELETE,
+                &[&string("/locks")[..], &(-1i32).to_be_bytes()].concat(),
+            ),
+        ]
+        .concat();
+        assert!(parser.check_payload(&requests, &at(C2S, 1000)));
+        for segment in requests.chunks(1460) {
+            assert!(parse(&mut parser, segment, &at(C2S, 1000)).is_empty());
+        }
+        assert_eq!(parser.pending.len(), 5);
+
+        let replies = [
+            reply(XID_PING, 0x40, 0, &[]),
+            reply(2, 0x41, ERR_NO_NODE, &[]),
+            reply(1, 0x41, 0, &[0; 3000]),
+            reply(3, 0x42, -105, &[]),
+            // a watcher event for the watch set by exists
+            reply(
+                XID_NOTIFICATION,
+                -1,
+                0,
+                &[
+                    &1i32.to_be_bytes()[..],
+                    &3i32.to_be_bytes(),
+                    &string("/controller"),
+                ]
+                .concat(),
+            ),
+            reply(4, 0x43, -103, &[]),
+            reply(5, 0x44, -106, &[]),
+        ]
+        .concat();
+        let mut infos = vec![];
+        for segment in replies.chunks(1460) {
+            infos.extend(parse(&mut parser, segment, &at(S2C, 1500)));
+        }
+        let summary = infos
+            .iter()
+            .map(|i| (i.op, i.path.as_deref(), i.error, i.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "exists",
+                    Some("/controller"),
+                    Some("NONODE"),
+                    L7ResponseStatus::Ok
+                ),
+                (
+                    "getData",
+                    Some("/brokers/ids/0"),
+                    None,
+                    L7ResponseStatus::Ok
+                ),
+                (
+                    "create",
+                    Some("/brokers/ids/1"),
+                    Some("NODEEXISTS"),
+                    L7ResponseStatus::ClientError
+                ),
+                (
+                    "notification",
+                    Some("/controller"),
+                    None,
+                    L7ResponseStatus::Ok
+                ),
+                (
+                    "setData",
+                    Some("/config"),
+                    Some("BADVERSION"),
+                    L7ResponseStatus::ClientError
+                ),
+                (
+                    "delete",
+                    Some("/locks"),
+                    Some("NOTEMPTY"),
+                    L7ResponseStatus::ClientError
+                ),
+            ]
+        );
+        assert!(infos[0].watch && infos[1].watch);
+        assert_eq!((infos[1].zxid, infos[1].rrt), (Some(0x41), 500));
+        assert_eq!(infos[3].msg_type, LogMessageType::Other);
+        assert_eq!(
+            (infos[3].event_type, infos[3].keeper_state),
+            (Some("NodeCreated"), Some("SyncConnected"))
+        );
+        assert!(parser.pending.is_empty());
+
+        assert!(parse(
+            &mut parser,
+            &request(6, OP_SYNC, &string("/")),
+            &at(C2S, 2000)
+        )
+        .is_empty());
+        let infos = parse(&mut parser, &[], &at(C2S, 2000 + SESSION_TIMEOUT));
+        assert_eq!(
+            (infos[0].op, infos[0].status),
+            ("sync", L7ResponseStatus::Timeout)
+        );
+    }
+
+    #[test]
+    fn multi_and_expired_session() {
+        let at = |direction, time| ParseParam {
+            direction,
+            time,
+            parse_config: None,
+        };
+        let mut parser = ZookeeperLog::default();
+        let ops = [
+            multi_header(OP_CHECK, false, -1),
+            [&string("/cluster/epoch")[..], &3i32.to_be_bytes()].concat(),
+            multi_header(OP_SET_DATA, false, -1),
+            [
+                &string("/cluster/state")[..],
+                &string("up"),
+                &3i32.to_be_bytes(),
+            ]
+            .concat(),
+            multi_header(OP_DELETE, false, -1),
+            [&string("/cluster/lock")[..], &(-1i32).to_be_bytes()].concat(),
+            multi_header(-1, true, -1),
+        ]
+        .concat();
+        let payload = [request(7, OP_MULTI, &ops), request(8, OP_MULTI, &ops)].concat();
+        assert!(parser.check_payload(&payload, &at(C2S, 0)));
+        assert!(parse(&mut parser, &payload, &at(C2S, 0)).is_empty());
+
+        // each result is an error once one op fails, those after it inconsistent
+        let failed = [
+            multi_header(OP_ERROR, false, 0),
+            0i32.to_be_bytes().to_vec(),
+            multi_header(OP_ERROR, false, -103),
+            (-103i32).to_be_bytes().to_vec(),
+            multi_header(OP_ERROR, false, ERR_RUNTIME_INCONSISTENCY),
+            ERR_RUNTIME_INCONSISTENCY.to_be_bytes().to_vec(),
+            multi_header(-1, true, -1),
+        ]
+        .concat();
+        let succeeded = [
+            multi_header(OP_CHECK, false, 0),
+            multi_header(OP_SET_DATA, false, 0),
+            vec![0; 68],
+            multi_header(OP_DELETE, false, 0),
+            multi_header(-1, true, -1),
+        ]
+        .concat();
+        let replies = [reply(7, 0x50, 0, &failed), reply(8, 0x51, 0, &succeeded)].concat();
+        let mut infos = parse(&mut parser, &replies, &at(S2C, 100));
+        infos.sort_by_key(|i| i.xid);
+        assert_eq!(infos[0].multi_ops, vec!["check", "setData", "delete"]);
+        assert_eq!(infos[0].path.as_deref(), Some("/cluster/epoch"));
+        assert_eq!(
+            (infos[0].error_code, infos[0].status),
+            (Some(-103), L7ResponseStatus::ClientError)
+        );
+        assert_eq!(
+            (infos[1].error, infos[1].status),
+            (None, L7ResponseStatus::Ok)
+        );
+
+        // reconnecting to a session expired in the meantime
+        let mut parser = ZookeeperLog::default();
+        let connect = [
+            &0i32.to_be_bytes()[..],
+            &0x51i64.to_be_bytes(),
+            &30000i32.to_be_bytes(),
+            &0x1000_0a3b_0000_0001i64.to_be_bytes(),
+            &16i32.to_be_bytes(),
+            &[1; 16],
+        ]
+        .concat();
+        assert!(parse(&mut parser, &framed(connect), &at(C2S, 0)).is_empty());
+        let rejected = [&[0; 16][..], &16i32.to_be_bytes(), &[0; 16]].concat();
+        let infos = parse(&mut parser, &framed(rejected), &at(S2C, 10));
+        assert_eq!(
+            (infos[0].error, infos[0].status),
+            (Some("SESSIONEXPIRED"), L7ResponseStatus::ClientError)
+        );
+
+        assert!(!parser.check_payload(b"\x00\x00\x00\x10ruok", &at(C2S, 0)));
+        assert!(!parser.check_payload(&request(1, 42, &[]), &at(C2S, 0)));
+        assert!(!parser.check_payload(&request(1, OP_GET_DATA, &string("nodes")), &at(C2S, 0)));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod common;
 mod error;
+pub mod flow_generator;
 pub mod trident;
 pub mod utils;
 pub mod config;